@@ -1,16 +1,25 @@
 use gtk::prelude::{LabelExt, NotebookExt};
 use gtk::{
     glib,
-    prelude::{BuilderExtManual, ButtonExt, ContainerExt, WidgetExt},
-    Box, Builder, Button, Label, ListBox, ListBoxRow, Notebook, Orientation, Widget,
+    prelude::{
+        AdjustmentExt, BuilderExtManual, ButtonExt, ContainerExt, EditableSignals, EntryExt,
+        ScrolledWindowExt, SpinButtonExt, ToggleButtonExt, WidgetExt,
+    },
+    Adjustment, Box, Builder, Button, CheckButton, Entry, Label, ListBox, ListBoxRow,
+    Notebook, Orientation, ScrolledWindow, SpinButton, Widget,
 };
+use std::cell::RefCell;
+use std::ops::Range;
+use std::time::Duration;
 use packets::{connack::Connack, unsuback::Unsuback};
 use packets::{puback::Puback, publish::Publish, suback::Suback};
 use std::rc::Rc;
 
 use crate::interface::publication_counter::PublicationCounter;
+use crate::interface::saved_connection::ConnectionParams;
+use crate::interface::topic_history::{self, TopicHistory};
 use mqtt_client::{ClientError};
-use mqtt_client::{Observer, Message};
+use mqtt_client::{Message, Observer, PacketSummary};
 
 use super::{
     subscription_list::SubscriptionList,
@@ -19,6 +28,16 @@ use super::{
 
 #[doc(hidden)]
 const PUBLICATIONS_TAB: u32 = 2;
+#[doc(hidden)]
+const RETAINED_BADGE: &str = "[retained]";
+#[doc(hidden)]
+const DUP_BADGE: &str = "[dup]";
+#[doc(hidden)]
+const MAX_MESSAGES_PER_TOPIC: usize = 100;
+#[doc(hidden)]
+const STALLED_TEXT: &str = "Conexion demorada";
+#[doc(hidden)]
+const MAX_PACKET_LOG_ROWS: usize = 500;
 
 /// Observer for the internal client. It sends all messages through
 /// a channel to the main GTK thread.
@@ -42,9 +61,10 @@ impl ClientObserver {
         builder: Builder,
         subs: SubscriptionList,
         pub_counter: PublicationCounter,
+        connection_params: ConnectionParams,
     ) -> ClientObserver {
         let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
-        let internal = InternalObserver::new(builder, subs, pub_counter);
+        let internal = InternalObserver::new(builder, subs, pub_counter, connection_params);
         receiver.attach(None, move |message: Message| {
             internal.message_receiver(message);
             glib::Continue(true)
@@ -60,6 +80,8 @@ struct InternalObserver {
     builder: Builder,
     subs: SubscriptionList,
     pub_counter: PublicationCounter,
+    history: RefCell<TopicHistory>,
+    connection_params: ConnectionParams,
 }
 
 impl InterfaceUtils for InternalObserver {
@@ -75,13 +97,18 @@ impl InternalObserver {
         builder: Builder,
         subs: SubscriptionList,
         pub_counter: PublicationCounter,
+        connection_params: ConnectionParams,
     ) -> Rc<InternalObserver> {
         let internal_observer = Rc::new(Self {
             builder,
             subs,
             pub_counter,
+            history: RefCell::new(TopicHistory::new(MAX_MESSAGES_PER_TOPIC)),
+            connection_params,
         });
         internal_observer.setup_notebook();
+        internal_observer.setup_feed_scroll();
+        internal_observer.setup_feed_controls();
         internal_observer
     }
 
@@ -112,6 +139,38 @@ impl InternalObserver {
                 let dis: Button = self.builder().object("discon_btn").unwrap();
                 dis.clicked();
             }
+            Message::Disconnected(error) => {
+                self.disconnected(error);
+            }
+            Message::Latency(latency) => {
+                self.icon(Icon::Ok);
+                self.status_message(&latency_text(latency));
+            }
+            Message::Stalled => {
+                self.icon(Icon::Error);
+                self.status_message(STALLED_TEXT);
+            }
+            Message::PacketTrace(summary) => {
+                self.add_packet_trace(&summary);
+            }
+        }
+    }
+
+    /// Switches back to the connect menu after the server
+    /// closed the connection, showing whether it was closed
+    /// cleanly or the connection was lost
+    fn disconnected(&self, error: Option<ClientError>) {
+        let dis: Button = self.builder().object("discon_btn").unwrap();
+        dis.clicked();
+        match error {
+            None => {
+                self.icon(Icon::Ok);
+                self.status_message("El servidor cerro la conexion");
+            }
+            Some(error) => {
+                self.icon(Icon::Error);
+                self.status_message(&format!("Se perdio la conexion: {}", error));
+            }
         }
     }
 
@@ -145,21 +204,78 @@ impl InternalObserver {
         }
     }
 
-    /// Adds a new received publish packet to the feed
+    /// Adds a new received publish packet to the history and,
+    /// if it matches the filter of the feed, to the feed
     fn add_publish(&self, publish: Publish) {
+        self.pub_counter.update_new_messages_amount();
+        self.subs.route_publish(&publish);
+        if topic_history::matches(publish.topic_name(), &self.feed_filter()) {
+            self.add_feed_row(&publish);
+            self.trim_feed();
+        }
+        self.history.borrow_mut().add(publish);
+    }
+
+    #[doc(hidden)]
+    /// Adds a row with the given publish at the end of the feed
+    fn add_feed_row(&self, publish: &Publish) {
         let list: ListBox = self.builder.object("sub_msgs").unwrap();
         let row = ListBoxRow::new();
-        row.add(&Self::create_box(&publish));
-        self.pub_counter.update_new_messages_amount();
-        self.subs
-            .add_sub_from_publish(publish.topic_name(), publish.qos());
+        row.add(&Self::create_box(publish));
         list.add(&row);
         list.show_all();
     }
 
+    #[doc(hidden)]
+    /// Returns the topic filter written in 'feed_filter'
+    fn feed_filter(&self) -> String {
+        let filter: Entry = self.builder.object("feed_filter").unwrap();
+        filter.text().to_string()
+    }
+
+    /// Refills the feed with the messages of the history
+    /// that match the filter of the feed
+    fn refresh_feed(&self) {
+        self.remove_all_children_from_listbox("sub_msgs");
+        let filter = self.feed_filter();
+        for publish in self.history.borrow().messages(Some(&filter)) {
+            self.add_feed_row(publish);
+        }
+        self.trim_feed();
+    }
+
+    /// Adds the summary of a sent or received packet to the
+    /// packet log, removing its oldest rows if it is full
+    fn add_packet_trace(&self, summary: &PacketSummary) {
+        let list: ListBox = self.builder.object("packet_log").unwrap();
+        let label = Label::new(Some(&summary.to_string()));
+        label.set_xalign(0.0);
+        let row = ListBoxRow::new();
+        row.add(&label);
+        list.add(&row);
+        list.show_all();
+
+        let rows = list.children();
+        for index in rows_to_evict(rows.len(), MAX_PACKET_LOG_ROWS) {
+            list.remove(&rows[index]);
+        }
+    }
+
+    /// Removes the oldest rows of the feed until it has at most
+    /// as many as the 'feed_limit' spin button allows
+    fn trim_feed(&self) {
+        let list: ListBox = self.builder.object("sub_msgs").unwrap();
+        let limit: SpinButton = self.builder.object("feed_limit").unwrap();
+        let rows = list.children();
+        for index in rows_to_evict(rows.len(), limit.value_as_int().max(0) as usize) {
+            list.remove(&rows[index]);
+        }
+    }
+
     /// Re-enables the interface and shows information
     /// about the result of the connect operation. If
     /// it succeed it switches to the connected/content menu
+    /// and remembers the connection parameters
     fn connected(&self, result: Result<Connack, ClientError>) {
         if let Err(e) = result {
             self.connection_info(None);
@@ -169,7 +285,23 @@ impl InternalObserver {
         } else {
             self.show_content_menu();
             self.icon(Icon::Ok);
-            self.status_message("Conectado");
+            match self.save_connection_params() {
+                Ok(()) => self.status_message("Conectado"),
+                Err(e) => self.status_message(&format!(
+                    "Conectado (no se pudieron guardar los datos de conexion: {})",
+                    e
+                )),
+            }
+        }
+    }
+
+    #[doc(hidden)]
+    /// Stores the parameters of the connection in the
+    /// config directory of the user
+    fn save_connection_params(&self) -> std::io::Result<()> {
+        match ConnectionParams::default_path() {
+            Some(path) => self.connection_params.save(&path),
+            None => Ok(()),
         }
     }
 
@@ -200,6 +332,60 @@ impl InternalObserver {
         });
     }
 
+    #[doc(hidden)]
+    /// Keeps the feed scrolled to the newest message while the
+    /// 'pin_feed' toggle is active. The adjustment emits 'changed'
+    /// when a new row makes the feed taller
+    fn setup_feed_scroll(&self) {
+        let scroll: ScrolledWindow = self.builder.object("sub_msgs_scroll").unwrap();
+        let pin: CheckButton = self.builder.object("pin_feed").unwrap();
+        let adjustment = scroll.vadjustment();
+
+        let pin_clone = pin.clone();
+        adjustment.connect_changed(move |adjustment| {
+            Self::scroll_feed(adjustment, pin_clone.is_active());
+        });
+        pin.connect_toggled(move |pin| {
+            Self::scroll_feed(&adjustment, pin.is_active());
+        });
+    }
+
+    #[doc(hidden)]
+    /// Sets up the button that clears the feed, the spin
+    /// button that limits its length and the entry that
+    /// filters it by topic
+    fn setup_feed_controls(self: &Rc<Self>) {
+        let internal_clone = self.clone();
+        let clear: Button = self.builder.object("clear_feed_btn").unwrap();
+        clear.connect_clicked(move |_| {
+            internal_clone.history.borrow_mut().clear();
+            internal_clone.remove_all_children_from_listbox("sub_msgs");
+        });
+
+        let internal_clone = self.clone();
+        let filter: Entry = self.builder.object("feed_filter").unwrap();
+        filter.connect_changed(move |_| {
+            internal_clone.refresh_feed();
+        });
+
+        let internal_clone = self.clone();
+        let limit: SpinButton = self.builder.object("feed_limit").unwrap();
+        limit.connect_value_changed(move |_| {
+            internal_clone.trim_feed();
+        });
+    }
+
+    #[doc(hidden)]
+    /// Moves the feed to the position given by [`feed_scroll_position`]
+    fn scroll_feed(adjustment: &Adjustment, pinned: bool) {
+        adjustment.set_value(feed_scroll_position(
+            pinned,
+            adjustment.value(),
+            adjustment.upper(),
+            adjustment.page_size(),
+        ));
+    }
+
     #[doc(hidden)]
     /// Updates publications tab label.
     fn handle_switch_notebook_tab(&self, _: &Notebook, _: &Widget, new_page_number: u32) {
@@ -215,14 +401,7 @@ impl InternalObserver {
         let inner_box = Box::new(Orientation::Horizontal, 5);
         let label_topic: Label = Label::new(None);
         label_topic.set_markup(&("<b>• ".to_owned() + publish.topic_name() + "</b>"));
-        let mut qos_msg = format!("- [QoS: {}]", publish.qos() as u8);
-        if publish.retain_flag() {
-            qos_msg.push_str(" (retained)");
-        }
-        if publish.dup_flag() {
-            qos_msg.push_str(" (duplicate)");
-        }
-        let label_qos: Label = Label::new(Some(&qos_msg));
+        let label_qos: Label = Label::new(Some(&publish_badges(publish)));
         let label_payload: Label = Label::new(Some(publish.payload()));
         label_topic.set_line_wrap(true);
         label_qos.set_line_wrap(true);
@@ -234,3 +413,110 @@ impl InternalObserver {
         outer_box
     }
 }
+
+/// Returns the text shown next to the topic of a received
+/// publish in the feed: its QoS and, if applicable, whether
+/// it was retained or is a duplicate
+fn publish_badges(publish: &Publish) -> String {
+    let mut badges = format!("- [QoS: {}]", u8::from(publish.qos()));
+    if publish.retain_flag() {
+        badges.push(' ');
+        badges.push_str(RETAINED_BADGE);
+    }
+    if publish.dup_flag() {
+        badges.push(' ');
+        badges.push_str(DUP_BADGE);
+    }
+    badges
+}
+
+/// Returns the text of the status bar for a connection whose
+/// last PINGREQ took `latency` to be answered
+fn latency_text(latency: Duration) -> String {
+    format!("Conectado ({}ms)", latency.as_millis())
+}
+
+/// Returns the position the feed should be scrolled to after its
+/// content or the pin toggle changes: the bottom of the feed if it
+/// is pinned, or the current position (kept within the feed) if not
+fn feed_scroll_position(pinned: bool, current: f64, upper: f64, page_size: f64) -> f64 {
+    let bottom = (upper - page_size).max(0.0);
+    if pinned {
+        bottom
+    } else {
+        current.max(0.0).min(bottom)
+    }
+}
+
+/// Returns the indices of the rows that must be removed from a
+/// feed with `rows` rows so that it keeps at most `max` of them.
+/// The oldest rows, at the start of the feed, are the ones evicted
+fn rows_to_evict(rows: usize, max: usize) -> Range<usize> {
+    0..rows.saturating_sub(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use packets::{publish::Publish, qos::QoSLevel};
+
+    use std::time::Duration;
+
+    use super::{feed_scroll_position, latency_text, publish_badges, rows_to_evict};
+
+    #[test]
+    fn test_retained_publish_has_retained_badge() {
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel1, true, "topic", "msg", Some(1)).unwrap();
+        assert_eq!(publish_badges(&publish), "- [QoS: 1] [retained]");
+    }
+
+    #[test]
+    fn test_dup_publish_has_dup_badge() {
+        let publish =
+            Publish::new(true, QoSLevel::QoSLevel1, false, "topic", "msg", Some(1)).unwrap();
+        assert_eq!(publish_badges(&publish), "- [QoS: 1] [dup]");
+    }
+
+    #[test]
+    fn test_fresh_publish_has_no_badges() {
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "msg", None).unwrap();
+        assert_eq!(publish_badges(&publish), "- [QoS: 0]");
+    }
+
+    #[test]
+    fn test_pinned_feed_scrolls_to_bottom() {
+        assert_eq!(feed_scroll_position(true, 0.0, 500.0, 100.0), 400.0);
+        assert_eq!(feed_scroll_position(true, 150.0, 500.0, 100.0), 400.0);
+    }
+
+    #[test]
+    fn test_unpinned_feed_keeps_its_position() {
+        assert_eq!(feed_scroll_position(false, 150.0, 500.0, 100.0), 150.0);
+        assert_eq!(feed_scroll_position(false, 0.0, 500.0, 100.0), 0.0);
+    }
+
+    #[test]
+    fn test_feed_shorter_than_page_stays_at_top() {
+        assert_eq!(feed_scroll_position(true, 0.0, 50.0, 100.0), 0.0);
+        assert_eq!(feed_scroll_position(false, 30.0, 50.0, 100.0), 0.0);
+    }
+
+    #[test]
+    fn test_feed_within_limit_evicts_nothing() {
+        assert!(rows_to_evict(0, 10).is_empty());
+        assert!(rows_to_evict(10, 10).is_empty());
+    }
+
+    #[test]
+    fn test_feed_over_limit_evicts_oldest_rows() {
+        assert_eq!(rows_to_evict(11, 10), 0..1);
+        assert_eq!(rows_to_evict(25, 10), 0..15);
+    }
+
+    #[test]
+    fn test_latency_text_is_in_milliseconds() {
+        assert_eq!(latency_text(Duration::from_millis(42)), "Conectado (42ms)");
+        assert_eq!(latency_text(Duration::from_micros(1500)), "Conectado (1ms)");
+    }
+}
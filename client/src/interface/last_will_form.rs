@@ -0,0 +1,97 @@
+use std::convert::TryFrom;
+
+use mqtt_client::ClientError;
+use packets::{
+    connect::{ConnectBuilder, LastWill},
+    qos::QoSLevel,
+    topic_filter::TopicFilter,
+};
+
+#[doc(hidden)]
+const MSG_WILL_WILDCARDS: &str = "El topic del last will no puede contener wildcards";
+
+/// Values of the last will inputs of the connect form
+pub struct LastWillForm {
+    pub topic: String,
+    pub message: String,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+impl LastWillForm {
+    /// Returns the last will described by the form, or None if
+    /// its topic is empty. The topic is the one the message will
+    /// be published to, so it must not contain wildcards
+    pub fn last_will(self) -> Result<Option<LastWill>, ClientError> {
+        if self.topic.trim().is_empty() {
+            return Ok(None);
+        }
+        if self.topic.contains(['+', '#']) {
+            return Err(ClientError::new(MSG_WILL_WILDCARDS));
+        }
+        let topic = TopicFilter::new(self.topic, QoSLevel::try_from(self.qos)?)?;
+        Ok(Some(LastWill::new(topic, self.message, self.retain)))
+    }
+
+    /// Adds the last will described by the form, if any, to
+    /// the given builder
+    pub fn apply(self, builder: ConnectBuilder) -> Result<ConnectBuilder, ClientError> {
+        Ok(match self.last_will()? {
+            Some(last_will) => builder.with_last_will(last_will),
+            None => builder,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packets::{connect::ConnectBuilder, qos::QoSLevel};
+
+    use super::LastWillForm;
+
+    fn form(topic: &str, qos: u8) -> LastWillForm {
+        LastWillForm {
+            topic: topic.to_string(),
+            message: "adios".to_string(),
+            qos,
+            retain: true,
+        }
+    }
+
+    fn builder() -> ConnectBuilder {
+        ConnectBuilder::new("client", 0, true).unwrap()
+    }
+
+    #[test]
+    fn test_form_is_mapped_to_last_will() {
+        let connect = form("status/client", 1)
+            .apply(builder())
+            .unwrap()
+            .build()
+            .unwrap();
+        let last_will = connect.last_will().unwrap();
+        assert_eq!(last_will.topic.name(), "status/client");
+        assert_eq!(last_will.topic.qos(), QoSLevel::QoSLevel1);
+        assert_eq!(last_will.topic_message, "adios");
+        assert!(last_will.retain_flag);
+    }
+
+    #[test]
+    fn test_empty_topic_means_no_will() {
+        for topic in ["", "   "] {
+            let connect = form(topic, 1).apply(builder()).unwrap().build().unwrap();
+            assert!(connect.last_will().is_none());
+        }
+    }
+
+    #[test]
+    fn test_will_topic_with_wildcards_should_fail() {
+        assert!(form("status/+", 0).apply(builder()).is_err());
+        assert!(form("status/#", 0).apply(builder()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_will_qos_should_fail() {
+        assert!(form("status", 3).apply(builder()).is_err());
+    }
+}
@@ -3,8 +3,12 @@ use std::convert::TryFrom;
 use std::rc::Rc;
 
 mod client_observer;
+mod last_will_form;
+mod payload_format;
 mod publication_counter;
+mod saved_connection;
 mod subscription_list;
+mod topic_history;
 mod utils;
 
 use crate::interface::client_observer::ClientObserver;
@@ -14,23 +18,27 @@ use mqtt_client::{Client, ClientError};
 use gtk::gdk::keys::constants::Return;
 use gtk::gdk::EventKey;
 use gtk::glib::GString;
-use gtk::prelude::{ComboBoxTextExt, StackExt, SwitchExt, WidgetExt};
+use gtk::prelude::{
+    ComboBoxExt, ComboBoxTextExt, StackExt, SwitchExt, ToggleButtonExt, WidgetExt,
+};
 use gtk::{
     prelude::{BuilderExtManual, ButtonExt, EntryExt, TextBufferExt},
-    Builder, Button, Entry, Label, Notebook, Switch, TextBuffer,
+    Builder, Button, CheckButton, Entry, Label, Notebook, Switch, TextBuffer,
 };
 use gtk::{ComboBoxText, Inhibit, ListBox, Stack, TextView, Window};
-use packets::connect::{Connect, ConnectBuilder, LastWill};
+use packets::connect::{Connect, ConnectBuilder};
 use packets::topic_filter::TopicFilter;
 
 use crate::interface::publication_counter::PublicationCounter;
 use packets::publish::Publish;
 use packets::qos::QoSLevel;
-use packets::subscribe::Subscribe;
 use packets::unsubscribe::Unsubscribe;
 
-use self::subscription_list::SubscriptionList;
-use self::utils::{Icon, InterfaceUtils};
+use self::last_will_form::LastWillForm;
+use self::payload_format::PayloadFormat;
+use self::saved_connection::ConnectionParams;
+use self::subscription_list::{subscribe_packet, SubscribeForm, SubscriptionList};
+use self::utils::{alert, Icon, InterfaceUtils};
 
 /// Controller for the client. It both creates the
 /// internal client and handles all the user inputs
@@ -56,6 +64,7 @@ impl Controller {
         });
         cont.setup_handlers();
         cont.show_connect_menu();
+        cont.load_connection_params();
         cont
     }
 
@@ -167,8 +176,8 @@ impl Controller {
         );
 
         let connect = self.create_connect_packet()?;
-        let client_observer = self.create_client_observer();
-        let client = Client::new(&full_addr, client_observer, connect)?;
+        let client_observer = self.create_client_observer(self.connection_params());
+        let client = Client::with_packet_trace(&full_addr, client_observer, connect)?;
 
         self.connection_info(Some(&format!(
             "Conectado a {} ({})",
@@ -179,16 +188,69 @@ impl Controller {
         Ok(())
     }
 
+    #[doc(hidden)]
+    /// Returns the parameters of the connect form that are
+    /// remembered for the next connection. The password is only
+    /// included if the 'con_save_psw' check button is active
+    fn connection_params(&self) -> ConnectionParams {
+        let entry_text = |id: &str| {
+            let entry: Entry = self.builder.object(id).unwrap();
+            entry.text().to_string()
+        };
+        let save_password: CheckButton = self.builder.object("con_save_psw").unwrap();
+        ConnectionParams {
+            host: entry_text("con_host"),
+            port: entry_text("con_port"),
+            user_name: entry_text("con_usr"),
+            client_id: entry_text("con_cli"),
+            password: if save_password.is_active() {
+                Some(entry_text("con_psw"))
+            } else {
+                None
+            },
+        }
+    }
+
+    #[doc(hidden)]
+    /// Pre-fills the connect form with the parameters of
+    /// the last successful connection, if there are any.
+    /// The rest of the fields keep their default values
+    fn load_connection_params(&self) {
+        let params =
+            match ConnectionParams::default_path().and_then(|path| ConnectionParams::load(&path)) {
+                Some(params) => params,
+                None => return,
+            };
+        self.set_text_to_entry_box("con_host", &params.host);
+        self.set_text_to_entry_box("con_port", &params.port);
+        self.set_text_to_entry_box("con_usr", &params.user_name);
+        self.set_text_to_entry_box("con_cli", &params.client_id);
+        let save_password: CheckButton = self.builder.object("con_save_psw").unwrap();
+        save_password.set_active(params.password.is_some());
+        self.set_text_to_entry_box("con_psw", &params.password.unwrap_or_default());
+    }
+
     #[doc(hidden)]
     /// Builds a ClientObserver
-    fn create_client_observer(&self) -> ClientObserver {
+    fn create_client_observer(&self, connection_params: ConnectionParams) -> ClientObserver {
         let sub_box: ListBox = self.builder.object("sub_subs").unwrap();
         let unsub_entry: Entry = self.builder.object("unsub_top").unwrap();
+        let filter_entry: Entry = self.builder.object("feed_filter").unwrap();
         let notebook: Notebook = self.builder.object("notebook").unwrap();
         let feed_label: Label = self.builder.object("label_incoming").unwrap();
-        let subs_list = SubscriptionList::new(sub_box, unsub_entry);
+        let sub_form = SubscribeForm {
+            topic_entry: self.builder.object("sub_top").unwrap(),
+            qos_entry: self.builder.object("sub_qos").unwrap(),
+            button: self.builder.object("sub_btn").unwrap(),
+        };
+        let subs_list = SubscriptionList::new(sub_box, unsub_entry, filter_entry, sub_form);
         let publication_counter = PublicationCounter::new(notebook, feed_label);
-        ClientObserver::new(self.builder.clone(), subs_list, publication_counter)
+        ClientObserver::new(
+            self.builder.clone(),
+            subs_list,
+            publication_counter,
+            connection_params,
+        )
     }
 
     #[doc(hidden)]
@@ -237,14 +299,13 @@ impl Controller {
             connect_builder = connect_builder.with_password(&password)?;
         }
 
-        if !last_will_topic.trim().is_empty() {
-            let last_will = LastWill::new(
-                TopicFilter::new(last_will_topic, QoSLevel::try_from(last_will_qos)?)?,
-                last_will_msg,
-                last_will_retain,
-            );
-            connect_builder = connect_builder.with_last_will(last_will);
-        }
+        let last_will = LastWillForm {
+            topic: last_will_topic,
+            message: last_will_msg,
+            qos: last_will_qos,
+            retain: last_will_retain,
+        };
+        connect_builder = last_will.apply(connect_builder)?;
 
         // Return the built connect packet
         Ok(connect_builder.build()?)
@@ -279,9 +340,7 @@ impl Controller {
             .parse::<u8>()
             .unwrap();
 
-        let topic = TopicFilter::new(&topic_entry.text().to_string(), QoSLevel::try_from(qos)?)?;
-
-        let packet = Subscribe::new(vec![topic], rand::random());
+        let packet = subscribe_packet(&topic_entry.text(), qos, rand::random())?;
 
         if let Some(client) = self.client.borrow_mut().as_mut() {
             client.subscribe(packet)?;
@@ -329,15 +388,14 @@ impl Controller {
         }
 
         let retain = retain_switch.is_active();
-        let msg: TextBuffer = self.builder.object("pub_mg_txtbuffer").unwrap();
+        let payload = self.composer_payload()?;
 
         let packet = Publish::new(
             false,
             qos,
             retain,
             &topic_entry.text().to_string(),
-            &msg.text(&msg.start_iter(), &msg.end_iter(), false)
-                .ok_or_else(|| ClientError::new("Se debe completar el campo de mensaje"))?,
+            &payload,
             id,
         )?;
 
@@ -350,6 +408,32 @@ impl Controller {
         Ok(())
     }
 
+    #[doc(hidden)]
+    /// Decodes the message of the publish composer according to
+    /// the format selected in 'pub_fmt'. Since the payload of a
+    /// PUBLISH packet must be UTF-8, the decoded bytes must be too.
+    /// A malformed message is also reported with an alert
+    fn composer_payload(&self) -> Result<String, ClientError> {
+        let msg: TextBuffer = self.builder.object("pub_mg_txtbuffer").unwrap();
+        let format_entry: ComboBoxText = self.builder.object("pub_fmt").unwrap();
+        let input = msg
+            .text(&msg.start_iter(), &msg.end_iter(), false)
+            .ok_or_else(|| ClientError::new("Se debe completar el campo de mensaje"))?;
+
+        let decoded = format_entry
+            .active_id()
+            .map_or(Ok(PayloadFormat::Text), |id| id.parse())
+            .and_then(|format: PayloadFormat| format.decode(&input))
+            .and_then(|bytes| {
+                String::from_utf8(bytes)
+                    .map_err(|_| "El mensaje decodificado no es UTF-8 valido".to_string())
+            });
+        decoded.map_err(|error| {
+            alert(&error);
+            ClientError::new(&error)
+        })
+    }
+
     /// Listener of the Publish button
     /// Tries to build a PUBLISH packet
     /// and send it to the server with the
@@ -445,6 +529,7 @@ impl Controller {
         self.set_buffer_to_text_buffer("con_lw_txtbuffer", "");
         self.set_state_to_switch_box("con_cs", false);
         self.set_state_to_switch_box("con_lw_ret", false);
+        self.load_connection_params();
     }
 
     /// Resets connected screen to its default state
@@ -457,5 +542,6 @@ impl Controller {
         self.set_buffer_to_text_buffer("pub_mg_txtbuffer", "");
         self.remove_all_children_from_listbox("sub_subs");
         self.remove_all_children_from_listbox("sub_msgs");
+        self.remove_all_children_from_listbox("packet_log");
     }
 }
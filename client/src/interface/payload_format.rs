@@ -0,0 +1,159 @@
+use std::str::FromStr;
+
+#[doc(hidden)]
+const MSG_INVALID_HEX: &str = "El mensaje no es hexadecimal valido";
+#[doc(hidden)]
+const MSG_INVALID_BASE64: &str = "El mensaje no es base64 valido";
+#[doc(hidden)]
+const MSG_INVALID_FORMAT: &str = "Formato de mensaje desconocido";
+#[doc(hidden)]
+const BASE64_PADDING: char = '=';
+
+/// Format in which the message of the publish composer is
+/// written, selected with the 'pub_fmt' combo box
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PayloadFormat {
+    Text,
+    Hex,
+    Base64,
+}
+
+impl FromStr for PayloadFormat {
+    type Err = String;
+
+    /// Parses the id of the selected item of the combo box
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        match id {
+            "text" => Ok(PayloadFormat::Text),
+            "hex" => Ok(PayloadFormat::Hex),
+            "base64" => Ok(PayloadFormat::Base64),
+            _ => Err(MSG_INVALID_FORMAT.to_string()),
+        }
+    }
+}
+
+impl PayloadFormat {
+    /// Decodes the composer input into the bytes of the payload.
+    /// Whitespace is ignored in the hex and base64 formats, so
+    /// that long inputs can be split in groups or lines
+    pub fn decode(&self, input: &str) -> Result<Vec<u8>, String> {
+        match self {
+            PayloadFormat::Text => Ok(input.as_bytes().to_vec()),
+            PayloadFormat::Hex => decode_hex(input),
+            PayloadFormat::Base64 => decode_base64(input),
+        }
+    }
+}
+
+#[doc(hidden)]
+fn decode_hex(input: &str) -> Result<Vec<u8>, String> {
+    let digits = input
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).ok_or_else(|| MSG_INVALID_HEX.to_string()))
+        .collect::<Result<Vec<u32>, String>>()?;
+    if digits.len() % 2 != 0 {
+        return Err(MSG_INVALID_HEX.to_string());
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+        .collect())
+}
+
+#[doc(hidden)]
+fn base64_value(c: char) -> Option<u32> {
+    match c {
+        'A'..='Z' => Some(c as u32 - 'A' as u32),
+        'a'..='z' => Some(c as u32 - 'a' as u32 + 26),
+        '0'..='9' => Some(c as u32 - '0' as u32 + 52),
+        '+' => Some(62),
+        '/' => Some(63),
+        _ => None,
+    }
+}
+
+#[doc(hidden)]
+fn decode_base64(input: &str) -> Result<Vec<u8>, String> {
+    let input: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    // El padding es opcional, pero si esta debe completar el ultimo bloque
+    let data = input.trim_end_matches(BASE64_PADDING);
+    let padding = input.len() - data.len();
+    if padding > 2 || (padding > 0 && input.len() % 4 != 0) || data.len() % 4 == 1 {
+        return Err(MSG_INVALID_BASE64.to_string());
+    }
+    let values = data
+        .chars()
+        .map(|c| base64_value(c).ok_or_else(|| MSG_INVALID_BASE64.to_string()))
+        .collect::<Result<Vec<u32>, String>>()?;
+
+    let mut bytes = Vec::with_capacity(values.len() * 3 / 4);
+    for chunk in values.chunks(4) {
+        let block = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |block, (i, value)| block | value << (18 - 6 * i));
+        // Un bloque de n caracteres codifica n - 1 bytes
+        for i in 0..chunk.len() - 1 {
+            bytes.push((block >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PayloadFormat;
+
+    #[test]
+    fn test_text_is_utf8_bytes() {
+        assert_eq!(
+            PayloadFormat::Text.decode("hola ñ").unwrap(),
+            "hola ñ".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_hex_is_decoded() {
+        assert_eq!(
+            PayloadFormat::Hex.decode("00ff 7F\n10").unwrap(),
+            vec![0x00, 0xff, 0x7f, 0x10]
+        );
+        assert!(PayloadFormat::Hex.decode("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_hex_should_be_error() {
+        assert!(PayloadFormat::Hex.decode("abc").is_err());
+        assert!(PayloadFormat::Hex.decode("zz").is_err());
+        assert!(PayloadFormat::Hex.decode("0x10").is_err());
+    }
+
+    #[test]
+    fn test_base64_is_decoded() {
+        assert_eq!(PayloadFormat::Base64.decode("aG9sYQ==").unwrap(), b"hola");
+        assert_eq!(PayloadFormat::Base64.decode("aG9sYQ").unwrap(), b"hola");
+        assert_eq!(PayloadFormat::Base64.decode("aG9s\nYSE=").unwrap(), b"hola!");
+        assert_eq!(
+            PayloadFormat::Base64.decode("AP+/").unwrap(),
+            vec![0x00, 0xff, 0xbf]
+        );
+    }
+
+    #[test]
+    fn test_malformed_base64_should_be_error() {
+        assert!(PayloadFormat::Base64.decode("aG9sY").is_err());
+        assert!(PayloadFormat::Base64.decode("aG9sYQ=").is_err());
+        assert!(PayloadFormat::Base64.decode("aG9sYQ===").is_err());
+        assert!(PayloadFormat::Base64.decode("aG9s*Q==").is_err());
+        assert!(PayloadFormat::Base64.decode("aG=9sYQ=").is_err());
+    }
+
+    #[test]
+    fn test_format_from_combo_box_id() {
+        assert_eq!("text".parse(), Ok(PayloadFormat::Text));
+        assert_eq!("hex".parse(), Ok(PayloadFormat::Hex));
+        assert_eq!("base64".parse(), Ok(PayloadFormat::Base64));
+        assert!("binary".parse::<PayloadFormat>().is_err());
+    }
+}
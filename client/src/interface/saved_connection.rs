@@ -0,0 +1,110 @@
+use std::{env, fs, io, path::Path, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[doc(hidden)]
+const APP_DIR: &str = "rostovfc-mqtt";
+#[doc(hidden)]
+const FILE_NAME: &str = "last_connection.json";
+
+/// Parameters of the last successful connection, used to
+/// pre-fill the connect form. The password is only stored
+/// if the user explicitly asked for it
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectionParams {
+    pub host: String,
+    pub port: String,
+    pub user_name: String,
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl ConnectionParams {
+    /// Serializes the parameters to JSON
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Deserializes the parameters from JSON
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Writes the parameters to the file at `path`,
+    /// creating its directory if needed
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_json()?)
+    }
+
+    /// Reads the parameters from the file at `path`. Returns
+    /// None if it does not exist or is not valid
+    pub fn load(path: &Path) -> Option<Self> {
+        Self::from_json(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Returns the path of the file where the parameters are
+    /// stored, in the config directory of the user
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(config_dir.join(APP_DIR).join(FILE_NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::ConnectionParams;
+
+    fn params(password: Option<&str>) -> ConnectionParams {
+        ConnectionParams {
+            host: "localhost".to_string(),
+            port: "1883".to_string(),
+            user_name: "user".to_string(),
+            client_id: "client".to_string(),
+            password: password.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let params = params(Some("secret"));
+        let json = params.to_json().unwrap();
+        assert_eq!(ConnectionParams::from_json(&json).unwrap(), params);
+    }
+
+    #[test]
+    fn test_password_is_omitted_if_not_saved() {
+        let json = params(None).to_json().unwrap();
+        assert!(!json.contains("password"));
+        assert_eq!(ConnectionParams::from_json(&json).unwrap(), params(None));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join("rostovfc-mqtt-test-save-and-load");
+        let path = dir.join("last_connection.json");
+        let params = params(None);
+        params.save(&path).unwrap();
+        assert_eq!(ConnectionParams::load(&path), Some(params));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_missing_or_invalid_file_is_none() {
+        let dir = std::env::temp_dir().join("rostovfc-mqtt-test-load-invalid");
+        let path = dir.join("last_connection.json");
+        assert_eq!(ConnectionParams::load(&path), None);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(ConnectionParams::load(&path), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
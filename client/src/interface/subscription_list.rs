@@ -1,32 +1,78 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
 
 use gtk::{
-    prelude::{ButtonExt, ContainerExt, EntryExt, LabelExt, WidgetExt},
-    Box, Button, Entry, IconSize, Label, ListBox, Orientation, Widget,
+    prelude::{
+        ButtonExt, ComboBoxExt, ComboBoxTextExt, ContainerExt, EntryExt, LabelExt, WidgetExt,
+    },
+    Box, Button, ComboBoxText, Entry, IconSize, Label, ListBox, Orientation, Widget,
 };
-use packets::{qos::QoSLevel, topic_filter::TopicFilter};
+use mqtt_client::ClientError;
+use packets::{publish::Publish, qos::QoSLevel, subscribe::Subscribe, topic_filter::TopicFilter};
+
+#[doc(hidden)]
+const QOS_OPTIONS: [&str; 3] = ["0", "1", "2"];
+
+/// A subscription shown in the SubsList, along with the
+/// amount of messages routed to it
+struct Subscription {
+    box_: Box,
+    qos: QoSLevel,
+    messages_label: Label,
+    messages: Cell<usize>,
+}
+
+/// Inputs of the subscribe form, used to re-subscribe to
+/// a topic of the SubsList at a different QoS
+pub struct SubscribeForm {
+    pub topic_entry: Entry,
+    pub qos_entry: ComboBoxText,
+    pub button: Button,
+}
+
+impl SubscribeForm {
+    /// Fills the form with the given topic and QoS and
+    /// submits it, sending a new SUBSCRIBE packet
+    fn resubscribe(&self, topic: &str, qos: &str) {
+        self.topic_entry.set_text(topic);
+        self.qos_entry.set_active_id(Some(qos));
+        self.button.clicked();
+    }
+}
 
 pub struct SubscriptionList {
     list: ListBox,
     unsub_entry: Entry,
-    subs: RefCell<HashMap<String, (Box, QoSLevel)>>,
+    filter_entry: Entry,
+    sub_form: Rc<SubscribeForm>,
+    subs: RefCell<HashMap<String, Subscription>>,
 }
 
 impl SubscriptionList {
-    /// Creates a new SubsList given a ListBox and a Entry
-    pub fn new(list: ListBox, unsub_entry: Entry) -> Self {
+    /// Creates a new SubsList given a ListBox, the Entry of
+    /// the topic to unsubscribe from, the Entry of the topic
+    /// filter of the feed and the subscribe form
+    pub fn new(
+        list: ListBox,
+        unsub_entry: Entry,
+        filter_entry: Entry,
+        sub_form: SubscribeForm,
+    ) -> Self {
         Self {
             list,
             unsub_entry,
+            filter_entry,
+            sub_form: Rc::new(sub_form),
             subs: RefCell::new(HashMap::new()),
         }
     }
 
     /// Removes the given topic from the SubsList and updates the view accordingly
     pub fn remove_sub(&self, topic: &str) {
-        if let Some((box_, _)) = self.subs.borrow_mut().remove(topic) {
-            let row: Widget = box_.parent().unwrap();
+        if let Some(sub) = self.subs.borrow_mut().remove(topic) {
+            let row: Widget = sub.box_.parent().unwrap();
             self.list.remove(&row);
             self.list.show_all();
         }
@@ -49,20 +95,47 @@ impl SubscriptionList {
     /// Adds the given topic to the SubsList and updates the view accordingly
     pub fn add_sub(&self, topic: &str, qos: QoSLevel) {
         self.remove_sub(topic);
-        let box_ = self.create_sub_box(topic, qos);
+        let messages_label = Label::new(Some(&Self::messages_text(0)));
+        let box_ = self.create_sub_box(topic, qos, &messages_label);
         self.list.add(&box_);
         self.list.show_all();
-        self.subs
-            .borrow_mut()
-            .insert(topic.to_string(), (box_, qos));
+        self.subs.borrow_mut().insert(
+            topic.to_string(),
+            Subscription {
+                box_,
+                qos,
+                messages_label,
+                messages: Cell::new(0),
+            },
+        );
+    }
+
+    /// Dispatches an incoming PUBLISH to every subscription whose
+    /// filter matches its topic (wildcards included), updating their
+    /// message counters. If none matches (for example, because the
+    /// subscription was made in a previous session), its topic is
+    /// added to the SubsList
+    pub fn route_publish(&self, publish: &Publish) {
+        let mut routed = false;
+        for (filter, sub) in self.subs.borrow().iter() {
+            if publish.matches_filter(filter) {
+                sub.messages.set(sub.messages.get() + 1);
+                sub.messages_label
+                    .set_text(&Self::messages_text(sub.messages.get()));
+                routed = true;
+            }
+        }
+        if !routed {
+            self.add_sub_from_publish(publish.topic_name(), publish.qos());
+        }
     }
 
     /// Adds the given topic to the SubsList and updates the view accordingly.
     /// This function is used in case of any incoming PUBLISH
     pub fn add_sub_from_publish(&self, topic: &str, qos: QoSLevel) {
-        let prev = self.subs.borrow().get(topic).map(|t| t.1);
+        let prev = self.subs.borrow().get(topic).map(|sub| sub.qos);
         if let Some(prev_qos) = prev {
-            if (prev_qos as u8) < qos as u8 {
+            if u8::from(prev_qos) < u8::from(qos) {
                 self.add_sub(topic, qos);
             }
         } else {
@@ -71,12 +144,18 @@ impl SubscriptionList {
     }
 
     #[doc(hidden)]
-    fn create_sub_box(&self, topic: &str, qos: QoSLevel) -> Box {
+    fn messages_text(messages: usize) -> String {
+        format!("({} mensajes)", messages)
+    }
+
+    #[doc(hidden)]
+    fn create_sub_box(&self, topic: &str, qos: QoSLevel, messages_label: &Label) -> Box {
         let outer_box = Box::new(Orientation::Horizontal, 5);
         let topic_label = Label::new(None);
         topic_label.set_markup(&("<b>• ".to_owned() + topic + "</b>"));
         outer_box.add(&topic_label);
-        outer_box.add(&Label::new(Some(&format!("- [QoS {}]", qos as u8))));
+        outer_box.add(&Label::new(Some(&format!("- [{}]", qos))));
+        outer_box.add(messages_label);
 
         // ADD UNSUB BUTTON
         let _topic = topic.to_string();
@@ -88,6 +167,67 @@ impl SubscriptionList {
 
         outer_box.add(&button);
 
+        // ADD RESUBSCRIBE QOS SELECTOR
+        let qos_entry = ComboBoxText::new();
+        for option in QOS_OPTIONS {
+            qos_entry.append(Some(option), option);
+        }
+        qos_entry.set_active_id(Some(&u8::from(qos).to_string()));
+        let _topic = topic.to_string();
+        let button = Button::from_icon_name(Some("view-refresh"), IconSize::Button);
+        button.set_tooltip_text(Some("Volver a suscribirse con el QoS elegido"));
+        let form = self.sub_form.clone();
+        let qos_entry_clone = qos_entry.clone();
+        button.connect_clicked(move |_| {
+            if let Some(qos) = qos_entry_clone.active_id() {
+                form.resubscribe(&_topic, &qos);
+            }
+        });
+
+        outer_box.add(&qos_entry);
+        outer_box.add(&button);
+
+        // ADD FEED FILTER BUTTON
+        let _topic = topic.to_string();
+        let button = Button::from_icon_name(Some("edit-find"), IconSize::Button);
+        button.set_tooltip_text(Some("Ver solo los mensajes de esta suscripcion"));
+        let entry = self.filter_entry.clone();
+        button.connect_clicked(move |_| {
+            entry.set_text(&_topic);
+        });
+
+        outer_box.add(&button);
+
         outer_box
     }
 }
+
+/// Returns the SUBSCRIBE packet that subscribes to `topic` with
+/// the given QoS. Subscribing again to a topic of the SubsList
+/// replaces its subscription, so it is also used to change its QoS
+pub fn subscribe_packet(topic: &str, qos: u8, packet_id: u16) -> Result<Subscribe, ClientError> {
+    let topic = TopicFilter::new(topic, QoSLevel::try_from(qos)?)?;
+    Ok(Subscribe::new(vec![topic], packet_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use packets::qos::QoSLevel;
+
+    use super::subscribe_packet;
+
+    #[test]
+    fn test_resubscribe_at_new_qos() {
+        let subscribe = subscribe_packet("casa/+/temp", 1, 10).unwrap();
+        assert_eq!(subscribe.packet_identifier(), 10);
+        let topics = subscribe.topics();
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].name(), "casa/+/temp");
+        assert_eq!(topics[0].qos(), QoSLevel::QoSLevel1);
+    }
+
+    #[test]
+    fn test_resubscribe_with_invalid_qos_should_fail() {
+        assert!(subscribe_packet("casa", 3, 10).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{HashMap, VecDeque};
+
+use packets::publish::Publish;
+
+/// History of the received messages, indexed by topic, used
+/// to show the feed filtered by a subscription. Each topic
+/// keeps at most a fixed amount of messages, evicting the
+/// oldest ones
+pub struct TopicHistory {
+    buckets: HashMap<String, VecDeque<(u64, Publish)>>,
+    next_seq: u64,
+    max_per_topic: usize,
+}
+
+impl TopicHistory {
+    /// Creates an empty history that keeps at most
+    /// `max_per_topic` messages of each topic
+    pub fn new(max_per_topic: usize) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            next_seq: 0,
+            max_per_topic,
+        }
+    }
+
+    /// Adds a received message to the bucket of its topic
+    pub fn add(&mut self, publish: Publish) {
+        let bucket = self
+            .buckets
+            .entry(publish.topic_name().to_string())
+            .or_default();
+        bucket.push_back((self.next_seq, publish));
+        self.next_seq += 1;
+        while bucket.len() > self.max_per_topic {
+            bucket.pop_front();
+        }
+    }
+
+    /// Returns the messages whose topic matches the topic
+    /// filter `filter` (wildcards included), or all of them if
+    /// there is no filter, in the order they were received
+    pub fn messages(&self, filter: Option<&str>) -> Vec<&Publish> {
+        let mut messages: Vec<&(u64, Publish)> = self
+            .buckets
+            .iter()
+            .filter(|(topic, _)| filter.map_or(true, |filter| matches(topic, filter)))
+            .flat_map(|(_, bucket)| bucket.iter())
+            .collect();
+        messages.sort_by_key(|(seq, _)| *seq);
+        messages.into_iter().map(|(_, publish)| publish).collect()
+    }
+
+    /// Removes every message from the history
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+    }
+}
+
+/// Returns true if a message of `topic` belongs to the feed
+/// filtered by `filter`. An empty filter shows every message
+pub fn matches(topic: &str, filter: &str) -> bool {
+    filter.is_empty() || packets::topic_filter::matches(topic, filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use packets::{publish::Publish, qos::QoSLevel};
+
+    use super::TopicHistory;
+
+    fn publish(topic: &str, payload: &str) -> Publish {
+        Publish::new(false, QoSLevel::QoSLevel0, false, topic, payload, None).unwrap()
+    }
+
+    fn payloads(messages: Vec<&Publish>) -> Vec<&str> {
+        messages.iter().map(|publish| publish.payload()).collect()
+    }
+
+    fn history() -> TopicHistory {
+        let mut history = TopicHistory::new(10);
+        history.add(publish("casa/living", "1"));
+        history.add(publish("casa/cocina", "2"));
+        history.add(publish("oficina", "3"));
+        history.add(publish("casa/living", "4"));
+        history
+    }
+
+    #[test]
+    fn test_no_filter_returns_every_message_in_order() {
+        assert_eq!(payloads(history().messages(None)), ["1", "2", "3", "4"]);
+        assert_eq!(payloads(history().messages(Some(""))), ["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_filter_returns_only_its_topic() {
+        assert_eq!(
+            payloads(history().messages(Some("casa/living"))),
+            ["1", "4"]
+        );
+        assert!(history().messages(Some("garage")).is_empty());
+    }
+
+    #[test]
+    fn test_filter_with_wildcards() {
+        assert_eq!(
+            payloads(history().messages(Some("casa/+"))),
+            ["1", "2", "4"]
+        );
+        assert_eq!(
+            payloads(history().messages(Some("#"))),
+            ["1", "2", "3", "4"]
+        );
+    }
+
+    #[test]
+    fn test_each_topic_keeps_its_newest_messages() {
+        let mut history = TopicHistory::new(2);
+        history.add(publish("a", "1"));
+        history.add(publish("b", "2"));
+        history.add(publish("a", "3"));
+        history.add(publish("a", "4"));
+        assert_eq!(payloads(history.messages(None)), ["2", "3", "4"]);
+        assert_eq!(payloads(history.messages(Some("a"))), ["3", "4"]);
+    }
+
+    #[test]
+    fn test_clear_removes_every_message() {
+        let mut history = history();
+        history.clear();
+        assert!(history.messages(None).is_empty());
+    }
+}
@@ -1,13 +1,42 @@
+use std::str::FromStr;
+
 use tracing::{Level, Subscriber};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
     fmt::{self, writer::MakeWriterExt, MakeWriter},
     prelude::__tracing_subscriber_SubscriberExt,
-    Registry,
+    registry::LookupSpan,
+    Layer, Registry,
 };
 
 const LOG_PREFIX: &str = "log.";
 
+/// Format of the logs written to the standard output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human readable, each event spanning several lines
+    #[default]
+    Pretty,
+    /// Human readable, one line per event
+    Compact,
+    /// One JSON object per line, including the fields of
+    /// the span the event belongs to
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Formato de log desconocido: {}", s)),
+        }
+    }
+}
+
 /// Logger structs. Holds the subscriber guards.
 /// If they were dropped, nothing would be logged.
 pub struct Logger {
@@ -17,6 +46,17 @@ pub struct Logger {
 
 impl Logger {
     pub fn new(log_path: &str, file_level: Level, stdout_level: Level) -> Self {
+        Self::with_format(log_path, file_level, stdout_level, LogFormat::default())
+    }
+
+    /// Like `new()`, but the logs written to the standard output
+    /// use the given format. The log files are always JSON
+    pub fn with_format(
+        log_path: &str,
+        file_level: Level,
+        stdout_level: Level,
+        stdout_format: LogFormat,
+    ) -> Self {
         let file_appender = tracing_appender::rolling::hourly(log_path, LOG_PREFIX);
         let (file, _file_guard) = tracing_appender::non_blocking(file_appender);
         let (stdout, _stdout_guard) = tracing_appender::non_blocking(std::io::stdout());
@@ -24,6 +64,7 @@ impl Logger {
         tracing::subscriber::set_global_default(Self::get_subscriber(
             file.with_max_level(file_level),
             stdout.with_max_level(stdout_level),
+            stdout_format,
         ))
         .expect("Error inicializando el logger");
 
@@ -34,23 +75,30 @@ impl Logger {
     }
 
     /// Sets up the tracing log subscriber and returns it
-    fn get_subscriber<W1, W2>(file: W1, stdout: W2) -> impl Subscriber
+    fn get_subscriber<W1, W2>(file: W1, stdout: W2, stdout_format: LogFormat) -> impl Subscriber
     where
-        W1: for<'writer> MakeWriter<'writer> + 'static,
-        W2: for<'writer> MakeWriter<'writer> + 'static,
+        W1: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+        W2: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
     {
         Registry::default()
-            .with(
-                fmt::Layer::default()
-                    .json()
-                    .with_thread_names(true)
-                    .with_writer(file),
-            )
-            .with(
-                fmt::Layer::default()
-                    .with_thread_names(true)
-                    .pretty()
-                    .with_writer(stdout),
-            )
+            .with(format_layer(LogFormat::Json, file))
+            .with(format_layer(stdout_format, stdout))
+    }
+}
+
+/// Returns a layer that writes every event to `writer`
+/// in the given format, along with the name of its thread
+pub fn format_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = fmt::Layer::default()
+        .with_thread_names(true)
+        .with_writer(writer);
+    match format {
+        LogFormat::Pretty => Box::new(layer.pretty()),
+        LogFormat::Compact => Box::new(layer.compact()),
+        LogFormat::Json => Box::new(layer.json()),
     }
 }
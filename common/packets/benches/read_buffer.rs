@@ -0,0 +1,129 @@
+//! Compares the allocations made when reading packet bodies with
+//! a fresh buffer per packet against the reusable per-thread
+//! scratch buffer used by `read_remaining_bytes`, both for bodies of
+//! different sizes and for many small Publish packets.
+//!
+//! Run with `cargo bench -p packets --bench read_buffer`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Cursor, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use packets::packet_reader::{read_remaining_bytes, RemainingLength};
+use packets::publish::Publish;
+use packets::qos::QoSLevel;
+use packets::traits::MQTTEncoding;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const PACKETS: usize = 100_000;
+const SIZES: [usize; 4] = [16, 256, 1024, 4096];
+const PUBLISHES: usize = 10_000;
+
+fn build_stream() -> Vec<u8> {
+    let mut stream = vec![];
+    for i in 0..PACKETS {
+        let len = SIZES[i % SIZES.len()];
+        stream.extend(RemainingLength::from_uncoded(len).unwrap().encode());
+        stream.extend(vec![0xAB; len]);
+    }
+    stream
+}
+
+/// Lectura sin reutilizar el buffer (un Vec nuevo por paquete)
+fn read_fresh(stream: &mut Cursor<Vec<u8>>) -> usize {
+    let len = RemainingLength::from_encoded(stream).unwrap().decode();
+    let mut vec = vec![0u8; len as usize];
+    stream.read_exact(&mut vec).unwrap();
+    vec.len()
+}
+
+fn read_scratch(stream: &mut Cursor<Vec<u8>>) -> usize {
+    read_remaining_bytes(stream).unwrap().remaining()
+}
+
+fn build_publish_stream() -> Vec<u8> {
+    let mut stream = vec![];
+    for i in 0..PUBLISHES {
+        let publish = Publish::new(
+            false,
+            QoSLevel::QoSLevel1,
+            false,
+            "sensor/temp",
+            "21.5",
+            Some((i % 1000 + 1) as u16),
+        )
+        .unwrap();
+        stream.extend(publish.encode().unwrap());
+    }
+    stream
+}
+
+/// Se descarta el control byte y se lee el cuerpo del Publish
+fn read_fresh_publish(stream: &mut Cursor<Vec<u8>>) -> usize {
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+    read_fresh(stream)
+}
+
+fn read_scratch_publish(stream: &mut Cursor<Vec<u8>>) -> usize {
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+    read_scratch(stream)
+}
+
+fn run(name: &str, stream: Vec<u8>, packets: usize, read: fn(&mut Cursor<Vec<u8>>) -> usize) {
+    let mut stream = Cursor::new(stream);
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut total = 0;
+    for _ in 0..packets {
+        total += read(&mut stream);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    println!(
+        "{:>8}: {} packets ({} bytes) in {:?}, {} allocations",
+        name, packets, total, elapsed, allocations
+    );
+}
+
+fn main() {
+    run("fresh", build_stream(), PACKETS, read_fresh);
+    run("scratch", build_stream(), PACKETS, read_scratch);
+
+    run(
+        "fresh",
+        build_publish_stream(),
+        PUBLISHES,
+        read_fresh_publish,
+    );
+    run(
+        "scratch",
+        build_publish_stream(),
+        PUBLISHES,
+        read_scratch_publish,
+    );
+}
@@ -2,13 +2,11 @@ use std::io::Read;
 
 use super::*;
 use crate::{
-    helpers::{check_packet_type, check_reserved_bits, PacketType},
+    helpers::{check_packet_type, PacketType},
     packet_error::{ErrorKind, PacketError, PacketResult},
     traits::MQTTDecoding,
 };
 
-#[doc(hidden)]
-const CONNACK_RESERVED_BITS: u8 = 0;
 #[doc(hidden)]
 const CONNACK_FIXED_REMAINING_LENGTH: u8 = 0b10;
 
@@ -45,7 +43,6 @@ impl MQTTDecoding for Connack {
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Connack> {
         let buffer = [0u8; 1];
         check_packet_type(control_byte, PacketType::Connack)?;
-        check_reserved_bits(control_byte, CONNACK_RESERVED_BITS)?;
         Connack::verify_remaining_length(buffer, stream)?;
         let session_present = Connack::verify_session_present_flag(buffer, stream)?;
         let return_code = Connack::verify_return_code(buffer, stream)?;
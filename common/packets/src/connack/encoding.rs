@@ -13,11 +13,14 @@ const CONNACK_SESSION_PRESENT_TRUE: u8 = 1;
 const CONNACK_SESSION_PRESENT_FALSE: u8 = 0;
 
 impl MQTTEncoding for Connack {
+    /// Encodes the Connack packet. If the connection was refused,
+    /// the session present flag is always sent as 0, as required
+    /// by the MQTT v3.1.1 protocol [MQTT-3.2.2-4]
     fn encode(&self) -> crate::packet_error::PacketResult<crate::traits::MQTTBytes> {
         let control_byte = build_control_byte(PacketType::Connack, RESERVED_BITS);
         let mut bytes = vec![control_byte, CONNACK_FIXED_REMAINING_LENGTH];
 
-        if self.session_present {
+        if self.session_present && self.return_code == ConnackReturnCode::Accepted {
             bytes.push(CONNACK_SESSION_PRESENT_TRUE)
         } else {
             bytes.push(CONNACK_SESSION_PRESENT_FALSE)
@@ -28,6 +31,8 @@ impl MQTTEncoding for Connack {
 }
 
 impl Connack {
+    /// Creates a Connack packet with the given session present
+    /// flag and return code
     pub fn new(session_present: bool, return_code: ConnackReturnCode) -> Connack {
         Connack {
             session_present,
@@ -1,4 +1,7 @@
-use crate::{packet_error::ErrorKind, traits::MQTTDecoding};
+use crate::{
+    packet_error::ErrorKind,
+    traits::{MQTTDecoding, MQTTEncoding},
+};
 
 use super::*;
 use std::io::Cursor;
@@ -108,3 +111,66 @@ fn test_should_return_valid_connack_packet_with_session_present_1_and_return_cod
     let connack_expected = Connack::new(true, ConnackReturnCode::Accepted);
     assert_eq!(result, connack_expected);
 }
+
+#[test]
+fn test_encode_accepted_connack() {
+    let bytes = Connack::new(true, ConnackReturnCode::Accepted)
+        .encode()
+        .unwrap();
+    assert_eq!(bytes, vec![0x20, 2, 1, 0]);
+    let bytes = Connack::new(false, ConnackReturnCode::Accepted)
+        .encode()
+        .unwrap();
+    assert_eq!(bytes, vec![0x20, 2, 0, 0]);
+}
+
+#[test]
+fn test_encode_refused_connack_never_has_session_present() {
+    let bytes = Connack::new(true, ConnackReturnCode::NotAuthorized)
+        .encode()
+        .unwrap();
+    assert_eq!(bytes, vec![0x20, 2, 0, 5]);
+}
+
+#[test]
+fn test_accepted_connack_round_trip() {
+    for session_present in [true, false] {
+        let connack = Connack::new(session_present, ConnackReturnCode::Accepted);
+        let bytes = connack.encode().unwrap();
+        let mut stream = Cursor::new(bytes[1..].to_vec());
+        let result = Connack::read_from(&mut stream, bytes[0]).unwrap();
+        assert_eq!(result, connack);
+    }
+}
+
+#[test]
+fn test_refused_connack_round_trip() {
+    let cases = [
+        (
+            ConnackReturnCode::UnacceptableProtocolVersion,
+            ErrorKind::UnacceptableProtocolVersion,
+        ),
+        (
+            ConnackReturnCode::IdentifierRejected,
+            ErrorKind::IdentifierRejected,
+        ),
+        (
+            ConnackReturnCode::ServerUnavailable,
+            ErrorKind::ServerUnavailable,
+        ),
+        (
+            ConnackReturnCode::BadUserNameOrPassword,
+            ErrorKind::BadUserNameOrPassword,
+        ),
+        (ConnackReturnCode::NotAuthorized, ErrorKind::NotAuthorized),
+    ];
+    for (return_code, kind) in cases {
+        for session_present in [true, false] {
+            let bytes = Connack::new(session_present, return_code).encode().unwrap();
+            assert_eq!(bytes[3], u8::from(return_code));
+            let mut stream = Cursor::new(bytes[1..].to_vec());
+            let result = Connack::read_from(&mut stream, bytes[0]).unwrap_err();
+            assert_eq!(result.kind(), kind);
+        }
+    }
+}
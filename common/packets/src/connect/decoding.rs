@@ -2,7 +2,7 @@ use std::{convert::TryFrom, io::Read};
 
 use super::*;
 use crate::{
-    helpers::{check_packet_type, check_reserved_bits, PacketType},
+    helpers::{check_packet_type, PacketType},
     packet_error::{ErrorKind, PacketError, PacketResult},
     packet_reader,
     qos::QoSLevel,
@@ -21,46 +21,64 @@ const WILL_QOS: u8 = 0x18;
 
 impl MQTTDecoding for Connect {
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Connect> {
-        let mut bytes = packet_reader::read_remaining_bytes(stream)?;
-        check_packet_type(control_byte, PacketType::Connect)?;
-        check_reserved_bits(control_byte, RESERVED_BITS)?;
-        Connect::verify_protocol(&mut bytes)?;
-        Connect::verify_protocol_level(&mut bytes)?;
-        let mut ret = Connect::get_flags(&mut bytes)?;
-        ret.get_keep_alive(&mut bytes)?;
-        ret.get_client_id(&mut bytes)?;
-        ret.get_will_data(&mut bytes)?;
-        ret.get_auth(&mut bytes)?;
-
-        let mut buf = [0u8; 1];
-        match bytes.read(&mut buf) {
-            Ok(1) | Err(_) => {
-                // Sobraron bytes, no debería
-                return Err(PacketError::new());
-            }
-            Ok(_) => (), // No sobro, perfecto
-        }
-
-        Ok(ret)
+        Connect::read_from_with_compat(stream, control_byte, false)
     }
 }
 
 impl Connect {
-    fn verify_protocol(bytes: &mut impl Read) -> PacketResult<()> {
+    /// Reads a Connect packet, like [`MQTTDecoding::read_from`]. If
+    /// `allow_mqtt_31` is true, it also accepts the protocol name
+    /// "MQIsdp" with protocol level 3, used by MQTT 3.1 clients. The
+    /// packet is decoded the same way as an MQTT 3.1.1 one
+    ///
+    /// # Errors
+    /// If the protocol name is unknown, it returns an error of kind
+    /// [`ErrorKind::InvalidProtocol`]. If the level does not match the
+    /// protocol name, it returns an error of kind
+    /// [`ErrorKind::InvalidProtocolLevel`]
+    pub fn read_from_with_compat<T: Read>(
+        stream: &mut T,
+        control_byte: u8,
+        allow_mqtt_31: bool,
+    ) -> PacketResult<Connect> {
+        // Se chequea antes de leer el resto, para no esperar el
+        // contenido de un paquete que se va a rechazar
+        check_packet_type(control_byte, PacketType::Connect)?;
+        packet_reader::decode_remaining(stream, "Connect", |bytes| {
+            let level = Connect::verify_protocol(bytes, allow_mqtt_31)?;
+            Connect::verify_protocol_level(bytes, level)?;
+            let mut ret = Connect::get_flags(bytes)?;
+            ret.get_keep_alive(bytes)?;
+            ret.get_client_id(bytes)?;
+            ret.get_will_data(bytes)?;
+            ret.get_auth(bytes)?;
+            Ok(ret)
+        })
+    }
+
+    /// Reads the protocol name, returning the protocol level
+    /// that must follow it
+    fn verify_protocol(bytes: &mut impl Read, allow_mqtt_31: bool) -> PacketResult<u8> {
         match Field::new_from_stream(bytes) {
-            Some(mensaje) if mensaje.value != "MQTT" => Err(PacketError::new_kind(
+            Some(mensaje) if mensaje.value == PROTOCOL_NAME => Ok(PROTOCOL_LEVEL_3_1_1),
+            Some(mensaje) if allow_mqtt_31 && mensaje.value == PROTOCOL_NAME_3_1 => {
+                Ok(PROTOCOL_LEVEL_3_1)
+            }
+            Some(_mensaje) => Err(PacketError::new_kind(
                 "Invalid protocol",
                 ErrorKind::InvalidProtocol,
             )),
-            None => Err(PacketError::new()),
-            Some(_mensaje) => Ok(()),
+            None => Err(PacketError::new_kind(
+                "Error at reading protocol name",
+                ErrorKind::ErrorAtReadingPacket,
+            )),
         }
     }
 
-    fn verify_protocol_level(bytes: &mut impl Read) -> PacketResult<()> {
+    fn verify_protocol_level(bytes: &mut impl Read, level: u8) -> PacketResult<()> {
         let mut buf = [0; 1];
         bytes.read_exact(&mut buf)?;
-        if buf[0] != PROTOCOL_LEVEL_3_1_1 {
+        if buf[0] != level {
             return Err(PacketError::new_kind(
                 "Invalid protocol level",
                 ErrorKind::InvalidProtocolLevel,
@@ -70,8 +88,8 @@ impl Connect {
     }
 
     fn get_will(buf: [u8; 1]) -> PacketResult<Option<LastWill>> {
-        let qos = QoSLevel::try_from((buf[0] & WILL_QOS) >> WILL_QOS_SHIFT)?;
         if buf[0] & LAST_WILL_PRESENT != 0 {
+            let qos = QoSLevel::try_from((buf[0] & WILL_QOS) >> WILL_QOS_SHIFT)?;
             return Ok(Some(LastWill {
                 retain_flag: buf[0] & WILL_RETAIN != 0,
                 topic: TopicFilter::new("placeholder", qos)?,
@@ -79,9 +97,10 @@ impl Connect {
             }));
         }
 
-        if buf[0] & WILL_RETAIN != 0 || qos != QoSLevel::QoSLevel0 {
+        // Sin Will Flag, tanto el Will QoS como el Will Retain deben ser 0
+        if buf[0] & (WILL_RETAIN | WILL_QOS) != 0 {
             return Err(PacketError::new_kind(
-                "Invalid flags",
+                "Will QoS and Will Retain must be 0 if the Will Flag is 0",
                 ErrorKind::InvalidFlags,
             ));
         }
@@ -153,8 +172,17 @@ impl Connect {
     }
 
     pub fn new_from_zero(stream: &mut impl Read) -> PacketResult<Connect> {
+        Connect::new_from_zero_with_compat(stream, false)
+    }
+
+    /// Reads a Connect packet, including its control byte. See
+    /// [`Connect::read_from_with_compat`]
+    pub fn new_from_zero_with_compat(
+        stream: &mut impl Read,
+        allow_mqtt_31: bool,
+    ) -> PacketResult<Connect> {
         let mut control_byte_buff: [u8; 1] = [0];
         stream.read_exact(&mut control_byte_buff)?;
-        Connect::read_from(stream, control_byte_buff[0])
+        Connect::read_from_with_compat(stream, control_byte_buff[0], allow_mqtt_31)
     }
 }
@@ -31,7 +31,7 @@ impl MQTTEncoding for Connect {
 impl Connect {
     #[doc(hidden)]
     fn protocol_name(&self) -> MQTTBytes {
-        Field::new_from_string("MQTT")
+        Field::new_from_string(PROTOCOL_NAME)
             .expect("Error inesperado")
             .encode()
     }
@@ -1,4 +1,4 @@
-use crate::topic_filter::TopicFilter;
+use crate::{qos::QoSLevel, topic_filter::TopicFilter};
 
 mod decoding;
 mod encoding;
@@ -7,9 +7,15 @@ use serde::{Deserialize, Serialize};
 #[cfg(test)]
 mod tests;
 
+#[doc(hidden)]
+const PROTOCOL_NAME: &str = "MQTT";
 #[doc(hidden)]
 const PROTOCOL_LEVEL_3_1_1: u8 = 0x04;
 #[doc(hidden)]
+const PROTOCOL_NAME_3_1: &str = "MQIsdp";
+#[doc(hidden)]
+const PROTOCOL_LEVEL_3_1: u8 = 0x03;
+#[doc(hidden)]
 const USER_NAME_PRESENT: u8 = 0x80;
 #[doc(hidden)]
 const PASSWORD_PRESENT: u8 = 0x40;
@@ -77,6 +83,22 @@ impl Connect {
         self.last_will.as_ref()
     }
 
+    /// Get the QoS of the connect's last will, if it has one
+    pub fn will_qos(&self) -> Option<QoSLevel> {
+        self.last_will
+            .as_ref()
+            .map(|last_will| last_will.topic.qos())
+    }
+
+    /// Lowers the QoS of the last will to `max_qos`, if it is
+    /// greater. Used by the server when it does not support the
+    /// QoS requested by the client
+    pub fn set_max_will_qos(&mut self, max_qos: QoSLevel) {
+        if let Some(last_will) = &mut self.last_will {
+            last_will.topic.set_max_qos(max_qos);
+        }
+    }
+
     /// Take the [LastWill] packet, replacing it with
     /// None. If the packet was already None, return None
     pub fn take_last_will(&mut self) -> Option<LastWill> {
@@ -88,6 +110,12 @@ impl Connect {
         self.keep_alive
     }
 
+    /// Set the keep alive, in seconds. Used by the server when it
+    /// overrides the one requested by the client
+    pub fn set_keep_alive(&mut self, keep_alive: u16) {
+        self.keep_alive = keep_alive;
+    }
+
     /// Set the client Id if it is None
     /// If not None, it silently does nothing
     pub fn set_id(&mut self, id: String) {
@@ -95,4 +123,10 @@ impl Connect {
             self.client_id = id;
         }
     }
+
+    /// Replaces the client Id. Used by the server to move the
+    /// session of the client to another namespace
+    pub fn set_client_id(&mut self, id: String) {
+        self.client_id = id;
+    }
 }
@@ -2,6 +2,7 @@ use std::io::Cursor;
 
 use super::*;
 use crate::packet_error::PacketError;
+use crate::packet_reader::encode_remaining_length;
 use crate::qos::QoSLevel;
 use crate::{
     packet_error::ErrorKind,
@@ -23,7 +24,7 @@ fn test_keep_alive() {
     v.append(&mut vec![16u8, 60u8]); //Keep alive
     v.append(&mut Field::new_from_string("id").unwrap().encode());
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -43,7 +44,7 @@ fn test_invalid_protocol() {
     v.append(&mut vec![0u8, 60u8]); //Keep alive
     v.append(&mut Field::new_from_string("id").unwrap().encode());
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -55,6 +56,71 @@ fn test_invalid_protocol() {
     );
 }
 
+fn mqtt_31_connect_bytes(level: u8) -> Cursor<Vec<u8>> {
+    let mut v = Field::new_from_string("MQIsdp").unwrap().encode();
+    v.push(level); // Nivel
+    v.push(0u8); //Flags
+    v.append(&mut vec![0u8, 60u8]); //Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+
+    let mut bytes = encode_remaining_length(v.len());
+    bytes.append(&mut v);
+    Cursor::new(bytes)
+}
+
+#[test]
+fn test_mqtt_31_protocol_accepted_when_allowed() {
+    let mut stream = mqtt_31_connect_bytes(3);
+
+    let connect = Connect::read_from_with_compat(&mut stream, CONNECT_CONTROL_BYTE, true).unwrap();
+    assert_eq!(connect.client_id(), "id");
+    assert_eq!(connect.keep_alive(), 60);
+}
+
+#[test]
+fn test_mqtt_31_protocol_rejected_when_not_allowed() {
+    let mut stream = mqtt_31_connect_bytes(3);
+
+    assert_eq!(
+        Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidProtocol
+    );
+}
+
+#[test]
+fn test_mqtt_31_protocol_with_level_4_is_invalid() {
+    let mut stream = mqtt_31_connect_bytes(4);
+
+    assert_eq!(
+        Connect::read_from_with_compat(&mut stream, CONNECT_CONTROL_BYTE, true)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidProtocolLevel
+    );
+}
+
+#[test]
+fn test_unknown_protocol_rejected_when_mqtt_31_allowed() {
+    let mut v = Field::new_from_string("Not MQTT").unwrap().encode();
+    v.push(3u8); // Nivel
+    v.push(0u8); //Flags
+    v.append(&mut vec![0u8, 60u8]); //Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+
+    let mut bytes = encode_remaining_length(v.len());
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    assert_eq!(
+        Connect::read_from_with_compat(&mut stream, CONNECT_CONTROL_BYTE, true)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidProtocol
+    );
+}
+
 #[test]
 fn test_invalid_protocol_level() {
     let mut v = Field::new_from_string("MQTT").unwrap().encode();
@@ -63,7 +129,7 @@ fn test_invalid_protocol_level() {
     v.append(&mut vec![0u8, 60u8]); //Keep alive
     v.append(&mut Field::new_from_string("id").unwrap().encode());
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -83,7 +149,7 @@ fn test_invalid_reserved_flag() {
     v.append(&mut vec![0u8, 60u8]); //Keep alive
     v.append(&mut Field::new_from_string("id").unwrap().encode());
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -103,7 +169,7 @@ fn test_will_flag_0_topic_message_1() {
     v.append(&mut Field::new_from_string("id").unwrap().encode());
     v.append(&mut vec![0u8, 60u8]); //Keep alive
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -123,7 +189,7 @@ fn test_username_missing_but_needed() {
     v.append(&mut vec![0u8, 60u8]); //Keep alive
     v.append(&mut Field::new_from_string("id").unwrap().encode());
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -139,14 +205,31 @@ fn test_username_present_as_remaining_bytes_should_raise_error() {
     v.append(&mut Field::new_from_string("id").unwrap().encode());
     v.append(&mut Field::new_from_string("unNombre").unwrap().encode());
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    let error_result = Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE).unwrap_err();
+
+    assert_eq!(error_result.kind(), ErrorKind::TrailingBytes);
+}
+
+#[test]
+fn test_truncated_connect_should_raise_unexpected_eof() {
+    let mut v = Field::new_from_string("MQTT").unwrap().encode();
+    v.push(4u8); // Nivel
+    v.push(0); // Flags
+    v.append(&mut vec![0u8, 60u8]); // Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+
+    // El largo indica mas bytes de los que llegan
+    let mut bytes = encode_remaining_length(v.len() + 10);
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
     let error_result = Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE).unwrap_err();
-    let expected = PacketError::new();
 
-    assert_eq!(error_result, expected);
+    assert_eq!(error_result.kind(), ErrorKind::UnexpectedEof);
 }
 
 #[test]
@@ -157,7 +240,7 @@ fn test_connect_clean_session() {
     v.append(&mut vec![0u8, 60u8]); // Keep alive
     v.append(&mut Field::new_from_string("id").unwrap().encode());
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -176,7 +259,7 @@ fn test_will_flag() {
     v.append(&mut Field::new_from_string("soyUnTopic").unwrap().encode());
     v.append(&mut Field::new_from_string("soyUnMensaje").unwrap().encode());
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -188,6 +271,55 @@ fn test_will_flag() {
     assert_eq!(will.topic_message, "soyUnMensaje");
 }
 
+#[test]
+fn test_will_flag_0_will_qos_3() {
+    let mut v = Field::new_from_string("MQTT").unwrap().encode();
+    v.push(4u8); // Nivel
+    v.push(3 << WILL_QOS_SHIFT); // Flags: Will QoS 3 sin Will Flag
+    v.append(&mut vec![0u8, 60u8]); // Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+
+    let mut bytes = encode_remaining_length(v.len());
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    assert_eq!(
+        Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidFlags
+    );
+}
+
+#[test]
+fn test_will_qos() {
+    let mut v = Field::new_from_string("MQTT").unwrap().encode();
+    v.push(4u8); // Nivel
+    v.push(LAST_WILL_PRESENT | ((QoSLevel::QoSLevel2 as u8) << WILL_QOS_SHIFT)); // Flags
+    v.append(&mut vec![0u8, 60u8]); // Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+    v.append(&mut Field::new_from_string("soyUnTopic").unwrap().encode());
+    v.append(&mut Field::new_from_string("soyUnMensaje").unwrap().encode());
+
+    let mut bytes = encode_remaining_length(v.len());
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    let mut packet = Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE).unwrap();
+    assert_eq!(packet.will_qos(), Some(QoSLevel::QoSLevel2));
+
+    packet.set_max_will_qos(QoSLevel::QoSLevel1);
+    assert_eq!(packet.will_qos(), Some(QoSLevel::QoSLevel1));
+    assert_eq!(packet.last_will().unwrap().topic.name(), "soyUnTopic");
+}
+
+#[test]
+fn test_will_qos_without_will() {
+    let mut packet = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+    packet.set_max_will_qos(QoSLevel::QoSLevel0);
+    assert!(packet.will_qos().is_none());
+}
+
 #[test]
 fn test_will_flag_username_password() {
     let mut v = Field::new_from_string("MQTT").unwrap().encode();
@@ -208,7 +340,7 @@ fn test_will_flag_username_password() {
             .encode(),
     );
 
-    let mut bytes = vec![v.len() as u8];
+    let mut bytes = encode_remaining_length(v.len());
     bytes.append(&mut v);
     let mut stream = Cursor::new(bytes);
 
@@ -1,10 +1,10 @@
-use std::io::{self, Read};
+use std::io::Read;
 
 use super::*;
 use crate::{
-    helpers::{check_packet_type, check_reserved_bits, PacketType},
-    packet_error::{PacketError, PacketResult},
-    packet_reader,
+    helpers::{check_packet_type, PacketType},
+    packet_error::{ErrorKind, PacketError, PacketResult},
+    packet_reader::RemainingLength,
     traits::MQTTDecoding,
 };
 
@@ -14,28 +14,25 @@ impl MQTTDecoding for Disconnect {
     /// # Errors
     ///
     /// Returns error if the packet fields do not meet the
-    /// requirements of the MQTT V3.1.1 standard
-    /// (packet type should be Disconnect, reserved bytes should be 0,
-    /// remaining length should be 0)
+    /// requirements of the MQTT V3.1.1 standard:
+    /// - The packet type should be Disconnect
+    /// - The reserved bits should be 0, otherwise the error
+    ///   is of kind [`ErrorKind::InvalidReservedBits`]
+    /// - The remaining length should be 0, otherwise the error is
+    ///   of kind [`ErrorKind::TrailingBytes`]. The rest of the
+    ///   packet is not read from the stream
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Disconnect> {
         check_packet_type(control_byte, PacketType::Disconnect)?;
-        check_reserved_bits(control_byte, RESERVED_BITS)?;
-        let mut packet_bytes = packet_reader::read_remaining_bytes(stream)?;
-        Disconnect::check_packet_end(&mut packet_bytes)?;
-        Ok(Self {})
-    }
-}
-
-impl Disconnect {
-    #[doc(hidden)]
-    fn check_packet_end(packet_bytes: &mut impl Read) -> PacketResult<()> {
-        let mut buff = [0u8; 1];
-        match packet_bytes.read_exact(&mut buff) {
-            Ok(()) => Err(PacketError::new_msg(
-                "El paquete contiene mas bytes de lo esperado",
-            )),
-            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
-            Err(_) => Err(PacketError::new_msg("Error leyendo el paquete")),
+        let remaining_length = RemainingLength::from_encoded(stream)?.decode();
+        if remaining_length != 0 {
+            return Err(PacketError::new_kind(
+                &format!(
+                    "Se recibió Disconnect con remaining_length != 0 ({})",
+                    remaining_length
+                ),
+                ErrorKind::TrailingBytes,
+            ));
         }
+        Ok(Self {})
     }
 }
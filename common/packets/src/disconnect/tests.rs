@@ -22,10 +22,31 @@ fn test_remaining_length_other_than_zero_should_raise_error() {
     let mut stream = Cursor::new(bytes);
     let packet = Disconnect::read_from(&mut stream, control_byte);
     let result = packet.err().unwrap().kind();
-    let expected_error = ErrorKind::UnexpectedEof;
+    let expected_error = ErrorKind::TrailingBytes;
     assert_eq!(result, expected_error);
 }
 
+#[test]
+fn test_remaining_length_other_than_zero_with_body_should_raise_error() {
+    let control_byte = 0b11100000;
+    let bytes = vec![0b10, 0, 0]; // remaining_length y dos bytes de mas
+    let mut stream = Cursor::new(bytes);
+    let packet = Disconnect::read_from(&mut stream, control_byte);
+    let result = packet.err().unwrap().kind();
+    assert_eq!(result, ErrorKind::TrailingBytes);
+    // No lee el cuerpo del paquete
+    assert_eq!(stream.position(), 1);
+}
+
+#[test]
+fn test_truncated_remaining_length_should_raise_error() {
+    let control_byte = 0b11100000;
+    let mut stream = Cursor::new(vec![]);
+    let packet = Disconnect::read_from(&mut stream, control_byte);
+    let result = packet.err().unwrap().kind();
+    assert_eq!(result, ErrorKind::UnexpectedEof);
+}
+
 #[test]
 fn test_invalid_packet_type_should_raise_error() {
     let mut bytes = vec![];
@@ -38,6 +59,17 @@ fn test_invalid_packet_type_should_raise_error() {
     assert_eq!(result, expected_error);
 }
 
+#[test]
+fn test_every_reserved_bit_should_be_zero() {
+    for bit in 0..4 {
+        let control_byte = 0b11100000 | (1 << bit);
+        let mut stream = Cursor::new(vec![0b00000000]);
+        let packet = Disconnect::read_from(&mut stream, control_byte);
+        let result = packet.err().unwrap().kind();
+        assert_eq!(result, ErrorKind::InvalidReservedBits);
+    }
+}
+
 #[test]
 fn test_invalid_reserved_bytes_should_raise_error() {
     let mut bytes = vec![];
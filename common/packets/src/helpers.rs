@@ -21,6 +21,10 @@ const PINGRESP_PACKET_TYPE_BITS: u8 = 13;
 const DISCONNECT_PACKET_TYPE_BITS: u8 = 14;
 
 const RESERVED_BITS_MASK: u8 = 0b00001111;
+/// Reserved bits mandated for PUBREL, SUBSCRIBE and UNSUBSCRIBE
+const RESERVED_BITS_0010: u8 = 0b0010;
+/// Reserved bits mandated for the rest of the packets (except PUBLISH)
+const RESERVED_BITS_0000: u8 = 0b0000;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PacketType {
@@ -43,7 +47,37 @@ pub enum PacketType {
 impl TryFrom<u8> for PacketType {
     type Error = PacketError;
 
+    /// Returns the type of the packet from its control byte.
+    ///
+    /// Besides the type, it validates the reserved bits of the control
+    /// byte (see [MQTT-2.2.2-1]), returning an error of kind
+    /// [`ErrorKind::InvalidReservedBits`] if they are not the expected
+    /// ones. The flags of a PUBLISH packet are not validated here
     fn try_from(control_byte: u8) -> Result<Self, Self::Error> {
+        let packet_type = PacketType::from_type_bits(control_byte)?;
+        if let Some(reserved_bits) = packet_type.reserved_bits() {
+            check_reserved_bits(control_byte, reserved_bits)?;
+        }
+        Ok(packet_type)
+    }
+}
+
+impl PacketType {
+    /// Returns the reserved bits that the control byte of this
+    /// type of packet must have. Returns None for PUBLISH, since
+    /// it uses those bits as flags
+    pub fn reserved_bits(&self) -> Option<u8> {
+        match self {
+            PacketType::Publish => None,
+            PacketType::PubRel | PacketType::Subscribe | PacketType::Unsubscribe => {
+                Some(RESERVED_BITS_0010)
+            }
+            _ => Some(RESERVED_BITS_0000),
+        }
+    }
+
+    #[doc(hidden)]
+    fn from_type_bits(control_byte: u8) -> PacketResult<Self> {
         let packet_type_bytes = (control_byte & PACKET_TYPE_MASK) >> PACKET_TYPE_SHIFT;
         match packet_type_bytes {
             CONNECT_PACKET_TYPE_BITS => Ok(PacketType::Connect),
@@ -149,10 +183,68 @@ impl fmt::Display for PacketType {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
+
     use crate::helpers::PacketType;
+    use crate::packet_error::ErrorKind;
 
     use super::build_control_byte;
 
+    // Todos los tipos de paquete junto con sus bits reservados obligatorios
+    const TYPES_WITH_RESERVED_BITS: [(PacketType, u8); 13] = [
+        (PacketType::Connect, 0b0000),
+        (PacketType::Connack, 0b0000),
+        (PacketType::Puback, 0b0000),
+        (PacketType::PubRec, 0b0000),
+        (PacketType::PubRel, 0b0010),
+        (PacketType::PubComp, 0b0000),
+        (PacketType::Subscribe, 0b0010),
+        (PacketType::Suback, 0b0000),
+        (PacketType::Unsubscribe, 0b0010),
+        (PacketType::Unsuback, 0b0000),
+        (PacketType::PingReq, 0b0000),
+        (PacketType::PingResp, 0b0000),
+        (PacketType::Disconnect, 0b0000),
+    ];
+
+    #[test]
+    fn test_try_from_valid_reserved_bits() {
+        for (packet_type, reserved_bits) in TYPES_WITH_RESERVED_BITS {
+            let control_byte = build_control_byte(packet_type, reserved_bits);
+            assert_eq!(PacketType::try_from(control_byte).unwrap(), packet_type);
+        }
+    }
+
+    #[test]
+    fn test_try_from_invalid_reserved_bits() {
+        for (packet_type, reserved_bits) in TYPES_WITH_RESERVED_BITS {
+            for corrupted_bit in [0b0001, 0b0010, 0b0100, 0b1000] {
+                let control_byte = build_control_byte(packet_type, reserved_bits ^ corrupted_bit);
+                let error = PacketType::try_from(control_byte).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::InvalidReservedBits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_from_publish_accepts_any_flags() {
+        for flags in 0..=0b1111 {
+            let control_byte = build_control_byte(PacketType::Publish, flags);
+            assert_eq!(
+                PacketType::try_from(control_byte).unwrap(),
+                PacketType::Publish
+            );
+        }
+    }
+
+    #[test]
+    fn test_try_from_invalid_packet_type() {
+        for control_byte in [0b0000_0000, 0b1111_0000] {
+            let error = PacketType::try_from(control_byte).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidControlPacketType);
+        }
+    }
+
     #[test]
     fn test_build_connect_control_byte() {
         let control_byte = build_control_byte(PacketType::Connect, 0);
@@ -19,7 +19,18 @@ pub enum ErrorKind {
     InvalidTopicName,
     InvalidReturnCode,
     WouldBlock,
+    Timeout,
     UnexpectedEof,
+    /// The Remaining Length is not a valid variable length encoding,
+    /// exceeds the maximum size, or is too short for the fields the
+    /// packet must have
+    MalformedLength,
+    /// A UTF-8 field (such as a topic name) exceeds 65535 bytes
+    FieldTooLong,
+    /// The packet has more bytes than its content requires
+    TrailingBytes,
+    /// The payload of a Publish exceeds the maximum size allowed
+    PayloadTooLarge,
     UnacceptableProtocolVersion,
     IdentifierRejected,
     ServerUnavailable,
@@ -98,6 +109,9 @@ impl From<io::Error> for PacketError {
             io::ErrorKind::WouldBlock => {
                 PacketError::new_kind(&error.to_string(), ErrorKind::WouldBlock)
             }
+            io::ErrorKind::TimedOut => {
+                PacketError::new_kind(&error.to_string(), ErrorKind::Timeout)
+            }
             _ => PacketError::new_msg(format!("{:?}", error)),
         }
     }
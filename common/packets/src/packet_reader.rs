@@ -1,15 +1,226 @@
-use crate::packet_error::{PacketError, PacketResult};
-use std::io::{Cursor, Read};
+use crate::packet_error::{ErrorKind, PacketError, PacketResult};
+use std::cell::RefCell;
+use std::io::{self, Cursor, Read};
+use std::time::{Duration, Instant};
+use std::{mem, thread};
 
 const MAX_MULTIPLIER: usize = 128 * 128 * 128;
 const MAX_VARIABLE_LENGTH: usize = 268_435_455;
+/// Maximum capacity the scratch buffer of a thread may keep
+/// between packets. Bigger buffers are released after use,
+/// so that a single large packet does not pin its memory
+/// for the whole life of the thread
+pub const MAX_SCRATCH_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    // Buffer reutilizado entre paquetes leidos por el mismo hilo
+    static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Bytes remaining within a packet (variable header and payload).
+///
+/// The underlying buffer is borrowed from a per-thread scratch
+/// buffer, and is given back to it when this structure is dropped.
+/// This way, consecutive packets read by the same thread reuse the
+/// same allocation, which only grows when a packet does not fit in it
+pub struct PacketBytes {
+    cursor: Cursor<Vec<u8>>,
+}
+
+impl PacketBytes {
+    #[doc(hidden)]
+    fn from_scratch(len: usize) -> Self {
+        let mut buf = SCRATCH
+            .try_with(|scratch| mem::take(&mut *scratch.borrow_mut()))
+            .unwrap_or_default();
+        buf.clear();
+        buf.resize(len, 0);
+        Self {
+            cursor: Cursor::new(buf),
+        }
+    }
+
+    /// Returns the amount of bytes that have not been read yet
+    pub fn remaining(&self) -> usize {
+        self.cursor.get_ref().len() - self.cursor.position() as usize
+    }
+
+    /// Checks that every byte of the packet was read. Otherwise,
+    /// it returns an error of kind [`ErrorKind::TrailingBytes`]
+    /// mentioning the name of the packet
+    pub fn check_consumed(&self, packet_name: &str) -> PacketResult<()> {
+        match self.remaining() {
+            0 => Ok(()),
+            left => Err(PacketError::new_kind(
+                format!(
+                    "{} packet has {} more bytes than expected",
+                    packet_name, left
+                ),
+                ErrorKind::TrailingBytes,
+            )),
+        }
+    }
+}
+
+impl Read for PacketBytes {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+impl Drop for PacketBytes {
+    fn drop(&mut self) {
+        let buf = mem::take(self.cursor.get_mut());
+        if buf.capacity() > MAX_SCRATCH_CAPACITY {
+            return;
+        }
+        // Si el hilo esta terminando, el buffer simplemente se libera
+        let _ = SCRATCH.try_with(|scratch| {
+            let mut scratch = scratch.borrow_mut();
+            if buf.capacity() > scratch.capacity() {
+                *scratch = buf;
+            }
+        });
+    }
+}
 
 /// Reads the number of bytes remaining within a stream, including data in the variable header and the payload.
-pub fn read_remaining_bytes<T: Read>(stream: &mut T) -> PacketResult<Cursor<Vec<u8>>> {
+///
+/// The stream is read until the whole remaining length arrives, even if
+/// each read returns fewer bytes than requested (as with a packet split
+/// across several TCP segments). If the stream ends first, an error of
+/// kind [`ErrorKind::UnexpectedEof`] is returned.
+///
+/// The returned bytes are stored in a buffer that is reused by the
+/// following calls made from the same thread (see [`PacketBytes`])
+pub fn read_remaining_bytes<T: Read>(stream: &mut T) -> PacketResult<PacketBytes> {
     let remaining_len = RemainingLength::from_encoded(stream)?.decode();
-    let mut vec = vec![0u8; remaining_len as usize];
-    stream.read_exact(&mut vec)?;
-    Ok(Cursor::new(vec))
+    let mut bytes = PacketBytes::from_scratch(remaining_len as usize);
+    stream.read_exact(bytes.cursor.get_mut())?;
+    Ok(bytes)
+}
+
+/// Reads the remaining bytes of a packet (see [`read_remaining_bytes`])
+/// and decodes them with `decode`, which must consume all of them.
+///
+/// Meant for the packets whose remaining length is fully determined by
+/// their contents, so that each decoder does not need its own check.
+/// If some bytes are left unread, an error of kind
+/// [`ErrorKind::TrailingBytes`] is returned
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Read};
+/// use packets::packet_error::ErrorKind;
+/// use packets::packet_reader::decode_remaining;
+///
+/// let read_u16 = |bytes: &mut packets::packet_reader::PacketBytes| {
+///     let mut buf = [0u8; 2];
+///     bytes.read_exact(&mut buf)?;
+///     Ok(u16::from_be_bytes(buf))
+/// };
+/// let mut stream = Cursor::new(vec![2, 0, 7]);
+/// assert_eq!(decode_remaining(&mut stream, "Test", read_u16).unwrap(), 7);
+///
+/// let mut stream = Cursor::new(vec![3, 0, 7, 0]);
+/// let err = decode_remaining(&mut stream, "Test", read_u16).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::TrailingBytes);
+/// ```
+pub fn decode_remaining<T: Read, P>(
+    stream: &mut T,
+    packet_name: &str,
+    decode: impl FnOnce(&mut PacketBytes) -> PacketResult<P>,
+) -> PacketResult<P> {
+    let mut bytes = read_remaining_bytes(stream)?;
+    let packet = decode(&mut bytes)?;
+    bytes.check_consumed(packet_name)?;
+    Ok(packet)
+}
+
+/// Returns the capacity of the scratch buffer of the current thread.
+/// Useful to check that the buffer is being reused
+pub fn scratch_capacity() -> usize {
+    SCRATCH.with(|scratch| scratch.borrow().capacity())
+}
+
+/// How long a [`DeadlineReader`] waits before retrying a
+/// read that would block
+const DEADLINE_RETRY_SLEEP: Duration = Duration::from_millis(10);
+
+/// Wrapper of a stream that keeps retrying the reads that time
+/// out (or would block) until a deadline is reached.
+///
+/// Useful to read the rest of a packet once its first byte
+/// arrived: a read timeout of the underlying stream does not
+/// lose the bytes already read, while a peer that stops sending
+/// in the middle of a packet cannot block the reader forever.
+/// Once the deadline is reached, reads fail with an error of
+/// kind [`io::ErrorKind::TimedOut`]
+pub struct DeadlineReader<'a, T: Read> {
+    stream: &'a mut T,
+    deadline: Instant,
+}
+
+impl<'a, T: Read> DeadlineReader<'a, T> {
+    /// Creates a reader whose reads fail after the given deadline
+    pub fn new(stream: &'a mut T, deadline: Instant) -> Self {
+        Self { stream, deadline }
+    }
+}
+
+impl<T: Read> Read for DeadlineReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.stream.read(buf) {
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    if Instant::now() >= self.deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "No se recibio el paquete completo a tiempo",
+                        ));
+                    }
+                    thread::sleep(DEADLINE_RETRY_SLEEP);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Encodes a remaining length following the MQTT v3.1.1 variable length
+/// encoding scheme: 7 bits per byte, least significant group first, with
+/// the most significant bit set on every byte but the last one.
+///
+/// The length is not checked against the maximum allowed by the protocol
+/// (see [`RemainingLength::from_uncoded`])
+///
+/// # Examples
+///
+/// ```
+/// use packets::packet_reader::encode_remaining_length;
+///
+/// assert_eq!(encode_remaining_length(127), vec![0x7F]);
+/// assert_eq!(encode_remaining_length(128), vec![0x80, 0x01]);
+/// ```
+pub fn encode_remaining_length(len: usize) -> Vec<u8> {
+    let mut length = len;
+    let mut encoded_len = vec![];
+    loop {
+        let mut encoded_byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            encoded_byte |= 128;
+        }
+        encoded_len.push(encoded_byte);
+        if length == 0 {
+            break;
+        }
+    }
+    encoded_len
 }
 
 /// The Remaining Length is the number of bytes remaining within a stream.
@@ -27,7 +238,10 @@ impl RemainingLength {
     /// This function will return a PacketError if the given length is greater than 256 MB
     pub fn from_uncoded(length: usize) -> PacketResult<Self> {
         if length > MAX_VARIABLE_LENGTH {
-            return Err(PacketError::new_msg("Exceeded max variable length size"));
+            return Err(PacketError::new_kind(
+                "Exceeded max variable length size",
+                ErrorKind::MalformedLength,
+            ));
         }
         Ok(Self {
             length: length as u32,
@@ -48,7 +262,10 @@ impl RemainingLength {
                 break;
             }
             if multiplier as usize > MAX_MULTIPLIER {
-                return Err(PacketError::new_msg("Malformed Remaining Length"));
+                return Err(PacketError::new_kind(
+                    "Malformed Remaining Length",
+                    ErrorKind::MalformedLength,
+                ));
             }
         }
         Ok(Self { length })
@@ -58,20 +275,7 @@ impl RemainingLength {
     ///
     /// Returns a Vec<u8> which contains up to 4 bytes representing the remaining length
     pub fn encode(&self) -> Vec<u8> {
-        let mut length = self.length;
-        let mut encoded_len = vec![];
-        loop {
-            let mut encoded_byte = length % 128;
-            length /= 128;
-            if length > 0 {
-                encoded_byte |= 128;
-            }
-            encoded_len.push(encoded_byte as u8);
-            if length == 0 {
-                break;
-            }
-        }
-        encoded_len
+        encode_remaining_length(self.length as usize)
     }
 
     /// Returns stored remaining length
@@ -82,9 +286,55 @@ impl RemainingLength {
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use std::io::{self, Cursor, Read};
+    use std::time::{Duration, Instant};
+
+    use super::{
+        decode_remaining, encode_remaining_length, read_remaining_bytes, scratch_capacity,
+        DeadlineReader, PacketBytes, RemainingLength, MAX_SCRATCH_CAPACITY,
+    };
+    use crate::packet_error::ErrorKind;
+
+    // Stream que devuelve WouldBlock una cantidad de veces antes de
+    // cada byte, y WouldBlock para siempre cuando se queda sin bytes
+    struct StallingStream {
+        bytes: Cursor<Vec<u8>>,
+        stalls: usize,
+        pending_stalls: usize,
+    }
 
-    use super::RemainingLength;
+    impl StallingStream {
+        fn new(bytes: Vec<u8>, stalls: usize) -> Self {
+            Self {
+                bytes: Cursor::new(bytes),
+                stalls,
+                pending_stalls: stalls,
+            }
+        }
+    }
+
+    impl Read for StallingStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let exhausted = self.bytes.position() as usize == self.bytes.get_ref().len();
+            if self.pending_stalls > 0 || exhausted {
+                self.pending_stalls = self.pending_stalls.saturating_sub(1);
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "stall"));
+            }
+            self.pending_stalls = self.stalls;
+            let len = buf.len().min(1);
+            self.bytes.read(&mut buf[..len])
+        }
+    }
+
+    fn build_body(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn build_packet_body(len: usize) -> Vec<u8> {
+        let mut bytes = RemainingLength::from_uncoded(len).unwrap().encode();
+        bytes.append(&mut build_body(len));
+        bytes
+    }
 
     #[test]
     fn test_encode() {
@@ -104,6 +354,23 @@ mod tests {
         assert_eq!(remaining_length.encode(), vec![0xFF, 0xFF, 0xFF, 0x7F]);
     }
 
+    #[test]
+    fn test_encode_remaining_length_limits() {
+        let cases: [(usize, Vec<u8>); 6] = [
+            (0, vec![0x00]),
+            (127, vec![0x7F]),
+            (128, vec![0x80, 0x01]),
+            (16_383, vec![0xFF, 0x7F]),
+            (16_384, vec![0x80, 0x80, 0x01]),
+            (2_097_151, vec![0xFF, 0xFF, 0x7F]),
+        ];
+        for (len, expected) in cases {
+            assert_eq!(encode_remaining_length(len), expected);
+            let decoded = RemainingLength::from_encoded(&mut Cursor::new(expected)).unwrap();
+            assert_eq!(decoded.decode() as usize, len);
+        }
+    }
+
     #[test]
     fn test_decode_short() {
         let mut bytes = vec![10, 10];
@@ -154,6 +421,113 @@ mod tests {
         let mut stream = Cursor::new(bytes);
         let remaining = RemainingLength::from_encoded(&mut stream);
 
-        assert!(remaining.is_err());
+        assert_eq!(remaining.err().unwrap().kind(), ErrorKind::MalformedLength);
+    }
+
+    #[test]
+    fn test_decode_truncated_length_should_be_unexpected_eof() {
+        let mut stream = Cursor::new(vec![0x80, 0x80]);
+        let remaining = RemainingLength::from_encoded(&mut stream);
+
+        assert_eq!(remaining.err().unwrap().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_remaining_bytes_reuses_buffer_with_varying_sizes() {
+        let sizes = [10, 2000, 0, 1, 500, 2000, 3, 1999];
+        let mut stream = Cursor::new(
+            sizes
+                .iter()
+                .flat_map(|len| build_packet_body(*len))
+                .collect::<Vec<u8>>(),
+        );
+
+        let mut max_len = 0;
+        for len in sizes {
+            let mut bytes = read_remaining_bytes(&mut stream).unwrap();
+            assert_eq!(bytes.remaining(), len);
+            let mut body = vec![];
+            bytes.read_to_end(&mut body).unwrap();
+            assert_eq!(body, build_body(len));
+            drop(bytes);
+            max_len = max_len.max(len);
+            assert!(scratch_capacity() >= max_len);
+        }
+    }
+
+    #[test]
+    fn test_read_remaining_bytes_does_not_keep_big_buffers() {
+        let len = MAX_SCRATCH_CAPACITY + 1;
+        let mut stream = Cursor::new(build_packet_body(len));
+
+        let bytes = read_remaining_bytes(&mut stream).unwrap();
+        assert_eq!(bytes.remaining(), len);
+        drop(bytes);
+        assert!(scratch_capacity() <= MAX_SCRATCH_CAPACITY);
+    }
+
+    #[test]
+    fn test_read_remaining_bytes_with_incomplete_body_should_be_error() {
+        let mut bytes = build_packet_body(100);
+        bytes.truncate(50);
+        let mut stream = Cursor::new(bytes);
+
+        assert_eq!(
+            read_remaining_bytes(&mut stream).err().unwrap().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    fn read_two_bytes(bytes: &mut PacketBytes) -> crate::packet_error::PacketResult<Vec<u8>> {
+        let mut buf = vec![0u8; 2];
+        bytes.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_decode_remaining_consuming_every_byte() {
+        let mut stream = Cursor::new(vec![2, 5, 6, 0xFF]);
+        let body = decode_remaining(&mut stream, "Test", read_two_bytes).unwrap();
+        assert_eq!(body, vec![5, 6]);
+        // No se lee mas alla del paquete
+        assert_eq!(stream.position(), 3);
+    }
+
+    #[test]
+    fn test_decode_remaining_with_trailing_bytes_should_be_error() {
+        let mut stream = Cursor::new(vec![4, 5, 6, 7, 8]);
+        let err = decode_remaining(&mut stream, "Test", read_two_bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TrailingBytes);
+        assert!(err.to_string().contains("2 more bytes"));
+    }
+
+    #[test]
+    fn test_decode_remaining_returns_decoder_error() {
+        let mut stream = Cursor::new(vec![1, 5]);
+        let err = decode_remaining(&mut stream, "Test", read_two_bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_deadline_reader_retries_stalled_reads() {
+        let mut stream = StallingStream::new(build_packet_body(20), 3);
+        let mut reader = DeadlineReader::new(&mut stream, Instant::now() + Duration::from_secs(5));
+        let mut bytes = read_remaining_bytes(&mut reader).unwrap();
+
+        let mut body = Vec::new();
+        bytes.read_to_end(&mut body).unwrap();
+        assert_eq!(body, build_body(20));
+    }
+
+    #[test]
+    fn test_deadline_reader_header_then_stall() {
+        // Llega la longitud restante pero nunca el cuerpo
+        let mut stream = StallingStream::new(vec![10, 1, 2], 0);
+        let start = Instant::now();
+        let mut reader = DeadlineReader::new(&mut stream, start + Duration::from_millis(100));
+        let error = read_remaining_bytes(&mut reader).err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::Timeout);
+        assert!(start.elapsed() >= Duration::from_millis(100));
     }
 }
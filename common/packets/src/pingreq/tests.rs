@@ -47,7 +47,7 @@ fn test_invalid_remaining_length() {
     let mut stream = Cursor::new(remaining_bytes);
     let packet = PingReq::read_from(&mut stream, control_byte);
     let result = packet.err().unwrap().kind();
-    let expected_error = ErrorKind::Other;
+    let expected_error = ErrorKind::TrailingBytes;
     assert_eq!(result, expected_error);
 }
 
@@ -1,10 +1,10 @@
 use crate::{
-    helpers::{check_packet_type, check_reserved_bits, PacketType},
-    packet_error::{PacketError, PacketResult},
+    helpers::{check_packet_type, PacketType},
+    packet_error::PacketResult,
     packet_reader,
     traits::MQTTDecoding,
 };
-use std::io::{self, Read};
+use std::io::Read;
 
 use super::*;
 
@@ -21,18 +21,6 @@ impl MQTTDecoding for PingResp {
         Self: Sized,
     {
         check_packet_type(control_byte, PacketType::PingResp)?;
-        check_reserved_bits(control_byte, RESERVED_BITS)?;
-        let mut bytes = packet_reader::read_remaining_bytes(stream)?;
-        let mut buff = [0];
-        match bytes.read_exact(&mut buff) {
-            Ok(_) => Err(PacketError::new_msg(
-                "Se recibió PingResp con remaining_length != 0",
-            )),
-            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(PingResp {}),
-            Err(err) => Err(PacketError::new_msg(&format!(
-                "Error inesperado: {}",
-                err.to_string()
-            ))),
-        }
+        packet_reader::decode_remaining(stream, "PingResp", |_| Ok(PingResp {}))
     }
 }
@@ -45,7 +45,7 @@ fn test_invalid_remaining_length_should_raise_error() {
     let mut stream = Cursor::new(remaining_bytes);
     let packet = PingResp::read_from(&mut stream, control_byte);
     let result = packet.err().unwrap().kind();
-    let expected_error = ErrorKind::Other;
+    let expected_error = ErrorKind::TrailingBytes;
     assert_eq!(result, expected_error);
 }
 
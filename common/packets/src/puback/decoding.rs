@@ -1,8 +1,8 @@
-use std::io::{self, Read};
+use std::io::Read;
 
 use crate::{
-    helpers::{check_packet_type, check_reserved_bits, PacketType},
-    packet_error::{PacketError, PacketResult},
+    helpers::{check_packet_type, PacketType},
+    packet_error::PacketResult,
     packet_reader,
     traits::MQTTDecoding,
 };
@@ -35,10 +35,9 @@ impl MQTTDecoding for Puback {
     /// ```
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Self> {
         check_packet_type(control_byte, PacketType::Puback)?;
-        check_reserved_bits(control_byte, RESERVED_BITS)?;
-        let mut remaining_bytes = packet_reader::read_remaining_bytes(stream)?;
-        let packet_id = Self::read_packet_id(&mut remaining_bytes);
-        Self::verify_packet_end(&mut remaining_bytes)?;
+        let packet_id = packet_reader::decode_remaining(stream, "Puback", |bytes| {
+            Ok(Self::read_packet_id(bytes))
+        })?;
         Ok(Self { packet_id })
     }
 }
@@ -50,14 +49,4 @@ impl Puback {
         let _ = bytes.read_exact(&mut packet_id_buffer);
         u16::from_be_bytes(packet_id_buffer)
     }
-
-    #[doc(hidden)]
-    fn verify_packet_end(bytes: &mut impl Read) -> PacketResult<()> {
-        let mut buff = [0u8; 1];
-        match bytes.read_exact(&mut buff) {
-            Ok(()) => Err(PacketError::new_msg(MSG_PACKET_MORE_BYTES_THAN_EXPECTED)),
-            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
-            Err(_) => Err(PacketError::new_msg("Error at reading packet")),
-        }
-    }
 }
@@ -3,10 +3,6 @@ mod encoding;
 #[cfg(test)]
 mod tests;
 
-#[doc(hidden)]
-const RESERVED_BITS: u8 = 0;
-#[doc(hidden)]
-const MSG_PACKET_MORE_BYTES_THAN_EXPECTED: &str = "Puback packet contains more bytes than expected";
 #[doc(hidden)]
 const MSG_INVALID_PACKET_ID: &str = "Packet identifier must be greater than zero";
 
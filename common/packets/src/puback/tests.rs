@@ -1,5 +1,5 @@
 use crate::packet_error::{ErrorKind, PacketError};
-use crate::puback::{Puback, MSG_INVALID_PACKET_ID, MSG_PACKET_MORE_BYTES_THAN_EXPECTED};
+use crate::puback::{Puback, MSG_INVALID_PACKET_ID};
 use crate::traits::{MQTTDecoding, MQTTEncoding};
 use std::io::Cursor;
 
@@ -38,9 +38,8 @@ fn test_puback_packet_can_not_have_more_bytes_than_expected() {
     let remaining_length = 3u8;
     let data_buffer: Vec<u8> = vec![remaining_length, 0, 0, 1];
     let mut stream = Cursor::new(data_buffer);
-    let expected_error = PacketError::new_msg(MSG_PACKET_MORE_BYTES_THAN_EXPECTED);
     let result = Puback::read_from(&mut stream, control_byte).unwrap_err();
-    assert_eq!(expected_error, result);
+    assert_eq!(result.kind(), ErrorKind::TrailingBytes);
 }
 
 #[test]
@@ -1,9 +1,12 @@
-use std::{convert::TryInto, io::Read};
+use std::{
+    convert::TryInto,
+    io::{Cursor, Read},
+};
 
 use crate::{
     helpers::{check_packet_type, PacketType},
     packet_error::{ErrorKind, PacketError, PacketResult},
-    packet_reader,
+    packet_reader::RemainingLength,
     traits::MQTTDecoding,
     utf8::Field,
 };
@@ -26,6 +29,7 @@ impl MQTTDecoding for Publish {
     ///
     /// ```
     /// use std::io::Cursor;
+    /// use packets::packet_reader::encode_remaining_length;
     /// use packets::publish::Publish;
     /// use packets::qos::QoSLevel;
     /// use packets::utf8::Field;
@@ -39,8 +43,7 @@ impl MQTTDecoding for Publish {
     ///  remaining_data.append(&mut topic);
     ///  remaining_data.append(&mut packet_id_buf);
     ///  remaining_data.append(&mut payload);
-    ///  let mut bytes = vec![];
-    ///  bytes.push(remaining_data.len() as u8);
+    ///  let mut bytes = encode_remaining_length(remaining_data.len());
     ///  bytes.append(&mut remaining_data);
     ///  let mut stream = Cursor::new(bytes);
     ///  let expected = Publish::new(
@@ -55,31 +58,102 @@ impl MQTTDecoding for Publish {
     ///  assert_eq!(expected, result);
     /// ```
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Publish> {
+        Self::read_publish(stream, control_byte, None)
+    }
+}
+
+impl Publish {
+    /// Reads a Publish packet as [`MQTTDecoding::read_from`] does, but
+    /// fails if its payload is longer than `max_payload_size` bytes.
+    ///
+    /// The size of the payload is known once the topic name and the
+    /// packet identifier are read, so a payload that is too long is
+    /// rejected before it is read from the stream
+    ///
+    /// # Errors
+    ///
+    /// If the payload is too long, it returns an error of kind
+    /// [`ErrorKind::PayloadTooLarge`], and the payload is left unread.
+    /// Otherwise, it fails as [`MQTTDecoding::read_from`]
+    pub fn read_with_max_payload<T: Read>(
+        stream: &mut T,
+        control_byte: u8,
+        max_payload_size: usize,
+    ) -> PacketResult<Publish> {
+        Self::read_publish(stream, control_byte, Some(max_payload_size))
+    }
+
+    #[doc(hidden)]
+    /// Reads a Publish packet, checking the size of its payload
+    /// against `max_payload_size` (if any) before reading it.
+    ///
+    /// If the topic name and the packet identifier do not fit in the
+    /// Remaining Length, it returns an error of kind
+    /// [`ErrorKind::MalformedLength`]
+    fn read_publish<T: Read>(
+        stream: &mut T,
+        control_byte: u8,
+        max_payload_size: Option<usize>,
+    ) -> PacketResult<Publish> {
         let retain_flag = Self::verify_retain_flag(&control_byte);
         let qos_level = Self::verify_qos_level_flag(&control_byte)?;
         let dup_flag = Self::verify_dup_flag(&control_byte, qos_level)?;
         check_packet_type(control_byte, PacketType::Publish)?;
-        let mut remaining_bytes = packet_reader::read_remaining_bytes(stream)?;
-        let topic_name = Self::verify_topic_name(&mut remaining_bytes)?;
-        let packet_id = Self::verify_packet_id(&mut remaining_bytes, &qos_level)?;
-        let payload = Self::read_payload(&mut remaining_bytes)?;
+        let remaining_len = RemainingLength::from_encoded(stream)?.decode() as u64;
+        let mut body = stream.take(remaining_len);
+
+        // Se lee solo el header variable para conocer el largo del payload
+        let mut header_len = 2;
+        if qos_level != QoSLevel::QoSLevel0 {
+            header_len += 2;
+        }
+        if remaining_len < header_len as u64 {
+            return Err(Self::header_too_long(header_len, remaining_len));
+        }
+        let mut topic_len = [0u8; 2];
+        body.read_exact(&mut topic_len)?;
+        header_len += u16::from_be_bytes(topic_len) as usize;
+        if remaining_len < header_len as u64 {
+            return Err(Self::header_too_long(header_len, remaining_len));
+        }
+        let mut header = vec![0; header_len];
+        header[..2].copy_from_slice(&topic_len);
+        body.read_exact(&mut header[2..])?;
+        let mut header = Cursor::new(header);
+        let topic_name = Self::verify_topic_name(&mut header)?;
+        let packet_id = Self::verify_packet_id(&mut header, &qos_level)?;
+
+        let payload_len = body.limit() as usize;
+        if let Some(max_payload_size) = max_payload_size.filter(|max| payload_len > *max) {
+            return Err(PacketError::new_kind(
+                &format!(
+                    "Payload is {} bytes long, the maximum is {}",
+                    payload_len, max_payload_size
+                ),
+                ErrorKind::PayloadTooLarge,
+            ));
+        }
+        let mut payload = vec![0; payload_len];
+        body.read_exact(&mut payload)?;
         Ok(Self {
             packet_id,
             topic_name: topic_name.value,
             qos: qos_level,
             retain_flag,
             dup_flag,
-            payload,
+            payload: String::from_utf8(payload)?,
         })
     }
-}
 
-impl Publish {
     #[doc(hidden)]
-    fn read_payload(bytes: &mut impl Read) -> PacketResult<String> {
-        let mut payload_buf = vec![];
-        let _ = bytes.read_to_end(&mut payload_buf);
-        Ok(String::from_utf8(payload_buf)?)
+    fn header_too_long(header_len: usize, remaining_len: u64) -> PacketError {
+        PacketError::new_kind(
+            &format!(
+                "Variable header is {} bytes long, but the remaining length is {}",
+                header_len, remaining_len
+            ),
+            ErrorKind::MalformedLength,
+        )
     }
 
     #[doc(hidden)]
@@ -112,7 +186,7 @@ impl Publish {
             return Ok(None);
         }
         let mut packet_id_buffer = [0u8; 2];
-        let _ = bytes.read_exact(&mut packet_id_buffer);
+        bytes.read_exact(&mut packet_id_buffer)?;
         let packet_id = u16::from_be_bytes(packet_id_buffer);
         if packet_id == 0 {
             return Err(PacketError::new_msg(MSG_INVALID_PACKET_ID));
@@ -1,6 +1,6 @@
 use crate::{
     helpers::{build_control_byte, PacketType},
-    packet_error::{PacketError, PacketResult},
+    packet_error::{ErrorKind, PacketError, PacketResult},
     packet_reader::RemainingLength,
     traits::{MQTTBytes, MQTTEncoding},
     utf8::Field,
@@ -32,6 +32,7 @@ impl MQTTEncoding for Publish {
     ///   established for UTF-8 fields in MQTT V3.1.1 standard
     /// * topic_name contains wildcard characters
     fn encode(&self) -> PacketResult<MQTTBytes> {
+        Publish::check_packet_id(self.qos, self.packet_id)?;
         let mut bytes = vec![];
         bytes.append(&mut self.fixed_header()?);
         bytes.append(&mut self.variable_header());
@@ -59,16 +60,7 @@ impl Publish {
         topic_message: &str,
         packet_identifier: Option<u16>,
     ) -> PacketResult<Self> {
-        if packet_identifier.is_some() && qos == QoSLevel::QoSLevel0 {
-            return Err(PacketError::new_msg(
-                "Un paquete con QoS 0 no puede tener identificador",
-            ));
-        } else if packet_identifier.is_none() && qos == QoSLevel::QoSLevel1 {
-            return Err(PacketError::new_msg(
-                "Un paquete con QoS 1 debe tener un identificador",
-            ));
-        }
-
+        Publish::check_packet_id(qos, packet_identifier)?;
         Publish::check_topic_name_cannot_contain_wildcard_characters(topic_name)?;
 
         Ok(Self {
@@ -81,6 +73,35 @@ impl Publish {
         })
     }
 
+    /// Returns the packet with its topic name replaced by `topic_name`,
+    /// for example to move it to another namespace. The rest of the
+    /// packet is kept as is
+    ///
+    /// # Errors
+    ///
+    /// Returns error if topic_name contains wildcard characters
+    pub fn with_topic_name(mut self, topic_name: &str) -> PacketResult<Self> {
+        Publish::check_topic_name_cannot_contain_wildcard_characters(topic_name)?;
+        self.topic_name = topic_name.to_string();
+        Ok(self)
+    }
+
+    /// The packet identifier must be present if and only if the
+    /// QoS is greater than 0 (see [MQTT-2.3.1-1] and [MQTT-2.3.1-5])
+    #[doc(hidden)]
+    fn check_packet_id(qos: QoSLevel, packet_identifier: Option<u16>) -> PacketResult<()> {
+        if packet_identifier.is_some() && qos == QoSLevel::QoSLevel0 {
+            return Err(PacketError::new_msg(
+                "Un paquete con QoS 0 no puede tener identificador",
+            ));
+        } else if packet_identifier.is_none() && qos == QoSLevel::QoSLevel1 {
+            return Err(PacketError::new_msg(
+                "Un paquete con QoS 1 debe tener un identificador",
+            ));
+        }
+        Ok(())
+    }
+
     #[doc(hidden)]
     fn check_topic_name_cannot_contain_wildcard_characters(topic_name: &str) -> PacketResult<()> {
         if topic_name.contains(SINGLE_LEVEL_WILDCARD) || topic_name.contains(MULTI_LEVEL_WILDCARD) {
@@ -96,10 +117,8 @@ impl Publish {
         let mut variable_header = vec![];
         variable_header.append(&mut Field::new_from_string(&self.topic_name).unwrap().encode());
         if let Some(packet_identifier) = self.packet_id {
-            if self.qos != QoSLevel::QoSLevel0 {
-                variable_header.push(packet_identifier.to_be_bytes()[0]);
-                variable_header.push(packet_identifier.to_be_bytes()[1]);
-            }
+            variable_header.push(packet_identifier.to_be_bytes()[0]);
+            variable_header.push(packet_identifier.to_be_bytes()[1]);
         }
 
         variable_header
@@ -130,3 +149,87 @@ impl Publish {
         Ok(fixed_header)
     }
 }
+
+/// Builder of [`Publish`] packets, that validates all
+/// of their fields at once when building them
+#[derive(Debug, Clone)]
+pub struct PublishBuilder {
+    topic_name: String,
+    qos: QoSLevel,
+    payload: Vec<u8>,
+    retain_flag: bool,
+    packet_id: Option<u16>,
+}
+
+impl PublishBuilder {
+    /// Creates a PublishBuilder, with an empty payload, the
+    /// retain flag set to false and no packet identifier
+    pub fn new(topic_name: &str, qos: QoSLevel) -> Self {
+        PublishBuilder {
+            topic_name: topic_name.to_owned(),
+            qos,
+            payload: vec![],
+            retain_flag: false,
+            packet_id: None,
+        }
+    }
+
+    pub fn with_payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    pub fn with_retain_flag(mut self, retain_flag: bool) -> Self {
+        self.retain_flag = retain_flag;
+        self
+    }
+
+    pub fn with_packet_id(mut self, packet_id: u16) -> Self {
+        self.packet_id = Some(packet_id);
+        self
+    }
+
+    /// Builds the packet with the received parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns error if:
+    /// * the topic name is empty, contains wildcard characters
+    ///   or exceeds the maximum length of UTF-8 fields
+    /// * the packet identifier is present and the QoS is 0, or
+    ///   it is missing and the QoS is 1
+    /// * the packet identifier is 0
+    /// * the payload is not valid UTF-8
+    pub fn build(self) -> PacketResult<Publish> {
+        if self.topic_name.is_empty() {
+            return Err(PacketError::new_kind(
+                MSG_TOPIC_NAME_ONE_CHAR,
+                ErrorKind::TopicNameMustBeAtLeastOneCharacterLong,
+            ));
+        }
+        if self.topic_name.contains(SINGLE_LEVEL_WILDCARD)
+            || self.topic_name.contains(MULTI_LEVEL_WILDCARD)
+        {
+            return Err(PacketError::new_kind(
+                MSG_TOPIC_WILDCARDS,
+                ErrorKind::TopicNameMustNotHaveWildcards,
+            ));
+        }
+        Field::new_from_string(self.topic_name.as_str())?;
+        Publish::check_packet_id(self.qos, self.packet_id)?;
+        if self.packet_id == Some(0) {
+            return Err(PacketError::new_msg(MSG_INVALID_PACKET_ID));
+        }
+        let payload = String::from_utf8(self.payload)
+            .map_err(|_| PacketError::new_kind(MSG_INVALID_PAYLOAD, ErrorKind::InvalidProtocol))?;
+
+        Ok(Publish {
+            packet_id: self.packet_id,
+            topic_name: self.topic_name,
+            qos: self.qos,
+            retain_flag: self.retain_flag,
+            dup_flag: false,
+            payload,
+        })
+    }
+}
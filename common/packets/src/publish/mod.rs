@@ -1,8 +1,10 @@
+use crate::packet_error::{ErrorKind, PacketError, PacketResult};
 use crate::qos::QoSLevel;
 use serde::{Deserialize, Serialize};
 
 mod decoding;
 mod encoding;
+pub use encoding::PublishBuilder;
 #[cfg(test)]
 mod tests;
 
@@ -11,12 +13,20 @@ const MSG_TOPIC_NAME_ONE_CHAR: &str =
 const MSG_TOPIC_WILDCARDS: &str = "Topic name must not have wildcards";
 const MSG_DUP_FLAG_1_WITH_QOS_LEVEL_0: &str = "It can not be dup flag 1 with QoS level 0";
 
+#[doc(hidden)]
+const TOPIC_LEVEL_SEPARATOR: char = '/';
 #[doc(hidden)]
 const SINGLE_LEVEL_WILDCARD: char = '+';
 #[doc(hidden)]
 const MULTI_LEVEL_WILDCARD: char = '#';
 #[doc(hidden)]
 const MSG_INVALID_PACKET_ID: &str = "Packet identifier must be greater than zero";
+#[doc(hidden)]
+const MSG_INVALID_PAYLOAD: &str = "Payload must be valid UTF-8";
+#[doc(hidden)]
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+#[doc(hidden)]
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 /// Publish packet structure for server/client side
@@ -54,17 +64,52 @@ impl Publish {
     pub fn payload(&self) -> &str {
         &self.payload
     }
+    /// Returns each level of the topic name of a Publish packet.
+    /// Empty levels are valid, so a leading or trailing `/` yields
+    /// an empty first or last level (see [MQTT-4.7.3])
+    pub fn topic_levels(&self) -> impl Iterator<Item = &str> {
+        self.topic_name.split(TOPIC_LEVEL_SEPARATOR)
+    }
+
+    /// Returns true if the topic name of the packet matches the
+    /// topic filter `filter`, wildcards included. Useful to route
+    /// incoming messages to the subscription they belong to
+    /// (see [`topic_filter::matches`](crate::topic_filter::matches))
+    pub fn matches_filter(&self, filter: &str) -> bool {
+        crate::topic_filter::matches(&self.topic_name, filter)
+    }
 
-    #[doc(hidden)]
+    /// Downgrades the QoS of the packet to `max_qos`, if it is
+    /// greater (see [MQTT-3.8.4]). A packet downgraded to QoS 0
+    /// loses its packet identifier and dup flag, since they
+    /// must not be present in a QoS 0 Publish
     pub fn set_max_qos(&mut self, max_qos: QoSLevel) {
         if (max_qos as u8) < (self.qos as u8) {
             self.qos = max_qos;
         }
-        if max_qos == QoSLevel::QoSLevel0 {
-            self.dup_flag = false
+        if self.qos == QoSLevel::QoSLevel0 {
+            self.dup_flag = false;
+            self.packet_id = None;
         }
     }
 
+    /// Checks that the topic name is at most `max_len` bytes long
+    /// (not characters). Otherwise, it returns an error of kind
+    /// [`ErrorKind::InvalidProtocol`]
+    pub fn check_topic_len(&self, max_len: usize) -> PacketResult<()> {
+        if self.topic_name.len() > max_len {
+            return Err(PacketError::new_kind(
+                format!(
+                    "Topic name is {} bytes long, the maximum is {}",
+                    self.topic_name.len(),
+                    max_len
+                ),
+                ErrorKind::InvalidProtocol,
+            ));
+        }
+        Ok(())
+    }
+
     /// Set the publish dup flag.
     pub fn set_dup(&mut self, dup: bool) {
         self.dup_flag = dup;
@@ -78,4 +123,40 @@ impl Publish {
     pub fn set_packet_id(&mut self, packet_id: u16) {
         self.packet_id = Some(packet_id);
     }
+
+    /// Returns a key that identifies the message carried by the packet,
+    /// regardless of the delivery: it only depends on the topic name
+    /// and the payload, not on the packet identifier nor the dup flag.
+    /// Useful to discard the duplicates received after a QoS 1
+    /// redelivery.
+    ///
+    /// The key is a 64-bit FNV-1a hash, so it is the same across runs
+    /// and platforms, and can be persisted. Being a hash, two different
+    /// messages may share a key, although it is very unlikely
+    pub fn dedup_key(&self) -> u64 {
+        // El largo del topic separa ambos campos, para que ("a", "bc")
+        // y ("ab", "c") no tengan la misma clave
+        let topic_len = (self.topic_name.len() as u64).to_be_bytes();
+        [
+            &topic_len[..],
+            self.topic_name.as_bytes(),
+            self.payload.as_bytes(),
+        ]
+        .iter()
+        .flat_map(|bytes| bytes.iter())
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    /// Returns the packet with its packet identifier replaced by
+    /// `packet_id`, so that each delivery of the same message can
+    /// have its own identifier. A QoS 0 packet is returned
+    /// unchanged, since it must not have a packet identifier
+    pub fn with_packet_id(mut self, packet_id: u16) -> Self {
+        if self.qos != QoSLevel::QoSLevel0 {
+            self.packet_id = Some(packet_id);
+        }
+        self
+    }
 }
@@ -1,10 +1,22 @@
 use super::*;
 use crate::packet_error::{ErrorKind, PacketError};
+use crate::packet_reader::encode_remaining_length;
 use crate::publish::Publish;
 use crate::qos::QoSLevel;
 use crate::traits::{MQTTDecoding, MQTTEncoding};
 use crate::utf8::Field;
-use std::io::Cursor;
+use std::io::{self, Cursor, Read};
+
+// Stream que devuelve como maximo un byte por lectura, como
+// un paquete que llega partido en muchos segmentos TCP
+struct OneByteReader(Cursor<Vec<u8>>);
+
+impl Read for OneByteReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(1);
+        self.0.read(&mut buf[..len])
+    }
+}
 
 #[test]
 fn test_dup_flag_0_with_qos_level_different_from_0_should_raise_invalid_dup_flag() {
@@ -50,7 +62,7 @@ fn test_publish_packet_with_qos_level_0_must_not_have_a_packet_id() {
     let mut payload = "mensaje".as_bytes().to_vec();
     remaining_data.append(&mut topic);
     remaining_data.append(&mut payload);
-    let mut bytes = vec![remaining_data.len() as u8];
+    let mut bytes = encode_remaining_length(remaining_data.len());
     bytes.append(&mut remaining_data);
     let mut stream = Cursor::new(bytes);
     let expected = Publish {
@@ -76,7 +88,7 @@ fn test_publish_packet_with_qos_level_1_must_have_a_packet_id() {
     remaining_data.append(&mut packet_id_buf);
     remaining_data.append(&mut payload);
 
-    let mut bytes = vec![remaining_data.len() as u8];
+    let mut bytes = encode_remaining_length(remaining_data.len());
     bytes.append(&mut remaining_data);
     let mut stream = Cursor::new(bytes);
     let expected = Publish {
@@ -100,7 +112,7 @@ fn test_publish_packet_might_have_zero_length_payload() {
     remaining_data.append(&mut topic);
     remaining_data.append(&mut payload);
 
-    let mut bytes = vec![remaining_data.len() as u8];
+    let mut bytes = encode_remaining_length(remaining_data.len());
     bytes.append(&mut remaining_data);
     let mut stream = Cursor::new(bytes);
     let expected = Publish {
@@ -125,7 +137,7 @@ fn test_publish_packet_topics_should_be_case_sensitive() {
     remaining_data.append(&mut topic);
     remaining_data.append(&mut payload);
 
-    let mut bytes = vec![remaining_data.len() as u8];
+    let mut bytes = encode_remaining_length(remaining_data.len());
     bytes.append(&mut remaining_data);
     let mut stream = Cursor::new(bytes);
     let expected = Publish {
@@ -149,7 +161,7 @@ fn test_publish_packet_topic_name_must_be_at_least_one_character_long() {
     remaining_data.append(&mut topic);
     remaining_data.append(&mut payload);
 
-    let mut bytes = vec![remaining_data.len() as u8];
+    let mut bytes = encode_remaining_length(remaining_data.len());
     bytes.append(&mut remaining_data);
     let mut stream = Cursor::new(bytes);
     let expected_error = PacketError::new_kind(
@@ -168,7 +180,7 @@ fn test_topic_name_can_not_have_wildcards() {
     remaining_data.append(&mut topic);
     remaining_data.append(&mut payload);
 
-    let mut bytes = vec![remaining_data.len() as u8];
+    let mut bytes = encode_remaining_length(remaining_data.len());
     bytes.append(&mut remaining_data);
     let mut stream = Cursor::new(bytes);
     let expected_error = ErrorKind::TopicNameMustNotHaveWildcards;
@@ -189,7 +201,7 @@ fn test_publish_packet_can_not_have_packet_id_0() {
     remaining_data.append(&mut packet_id_buf);
     remaining_data.append(&mut payload);
 
-    let mut bytes = vec![remaining_data.len() as u8];
+    let mut bytes = encode_remaining_length(remaining_data.len());
     bytes.append(&mut remaining_data);
     let mut stream = Cursor::new(bytes);
     let expected_error = PacketError::new().kind();
@@ -208,7 +220,7 @@ fn test_max_qos() {
     remaining_data.append(&mut topic);
     remaining_data.append(&mut payload);
 
-    let mut bytes = vec![remaining_data.len() as u8];
+    let mut bytes = encode_remaining_length(remaining_data.len());
     bytes.append(&mut remaining_data);
     let mut stream = Cursor::new(bytes);
     let mut result = Publish::read_from(&mut stream, control_byte).unwrap();
@@ -220,6 +232,27 @@ fn test_max_qos() {
     assert_eq!(result.qos(), QoSLevel::QoSLevel0);
 }
 
+#[test]
+fn test_max_qos_0_removes_packet_id() {
+    let mut publish = Publish::new(
+        true,
+        QoSLevel::QoSLevel1,
+        false,
+        "topic",
+        "message",
+        Some(10),
+    )
+    .unwrap();
+    publish.set_max_qos(QoSLevel::QoSLevel0);
+
+    assert_eq!(publish.qos(), QoSLevel::QoSLevel0);
+    assert!(publish.packet_id().is_none());
+    assert!(!publish.dup_flag());
+    let expected =
+        Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "message", None).unwrap();
+    assert_eq!(publish.encode().unwrap(), expected.encode().unwrap());
+}
+
 #[test]
 fn basic_test() {
     let packet = Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "message", None).unwrap();
@@ -321,6 +354,199 @@ fn test_publish_must_have_packet_identifier_with_qos_1() {
     assert_eq!(result, expected_error);
 }
 
+#[test]
+fn test_encode_qos_0_with_packet_identifier_should_be_error() {
+    let packet = Publish {
+        packet_id: Some(350),
+        topic_name: "topic".to_string(),
+        qos: QoSLevel::QoSLevel0,
+        retain_flag: false,
+        dup_flag: false,
+        payload: "message".to_string(),
+    };
+    assert!(packet.encode().is_err());
+}
+
+#[test]
+fn test_encode_qos_1_without_packet_identifier_should_be_error() {
+    let packet = Publish {
+        packet_id: None,
+        topic_name: "topic".to_string(),
+        qos: QoSLevel::QoSLevel1,
+        retain_flag: false,
+        dup_flag: false,
+        payload: "message".to_string(),
+    };
+    assert!(packet.encode().is_err());
+}
+
+#[test]
+fn test_encode_qos_0_without_packet_identifier() {
+    let packet = Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "message", None).unwrap();
+    assert_eq!(
+        packet.encode().unwrap(),
+        [
+            0b00110000, // control_byte
+            14,         // remaining_length
+            0, 5, // largo topic_name
+            116, 111, 112, 105, 99, // topic
+            109, 101, 115, 115, 97, 103, 101 // message
+        ]
+    );
+}
+
+#[test]
+fn test_encode_payload_longer_than_127_bytes() {
+    let payload = "a".repeat(200);
+    let packet = Publish::new(false, QoSLevel::QoSLevel0, false, "topic", &payload, None).unwrap();
+    let bytes = packet.encode().unwrap();
+    // 7 bytes del topic + 200 del mensaje = 207 = 0b1_1001111
+    assert_eq!(bytes[1..3], [0b11001111, 0b1]);
+    assert_eq!(bytes.len(), 3 + 207);
+
+    let mut stream = Cursor::new(bytes);
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+    let result = Publish::read_from(&mut stream, control_byte[0]).unwrap();
+    assert_eq!(result.payload(), payload);
+}
+
+#[test]
+fn test_decode_one_byte_per_read() {
+    let payload = "mensaje largo ".repeat(20);
+    let packet =
+        Publish::new(false, QoSLevel::QoSLevel1, true, "a/b", &payload, Some(300)).unwrap();
+    let bytes = packet.encode().unwrap();
+    // La longitud restante ocupa mas de un byte
+    assert!(bytes[1] & 0b10000000 != 0);
+
+    let mut stream = OneByteReader(Cursor::new(bytes));
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+    let result = Publish::read_from(&mut stream, control_byte[0]).unwrap();
+    assert_eq!(result, packet);
+    assert_eq!(stream.read(&mut control_byte).unwrap(), 0);
+}
+
+#[test]
+fn test_decode_one_byte_per_read_truncated_should_be_error() {
+    let packet = Publish::new(
+        false,
+        QoSLevel::QoSLevel0,
+        false,
+        "a/b",
+        &"x".repeat(200),
+        None,
+    )
+    .unwrap();
+    let mut bytes = packet.encode().unwrap();
+    bytes.truncate(100);
+
+    let mut stream = OneByteReader(Cursor::new(bytes));
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+    let result = Publish::read_from(&mut stream, control_byte[0])
+        .unwrap_err()
+        .kind();
+    assert_eq!(result, ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_read_with_max_payload_at_the_limit() {
+    for qos in [QoSLevel::QoSLevel0, QoSLevel::QoSLevel1] {
+        let packet_id = if qos == QoSLevel::QoSLevel0 {
+            None
+        } else {
+            Some(7)
+        };
+        let packet = Publish::new(false, qos, false, "a/b", &"x".repeat(200), packet_id).unwrap();
+        let mut stream = Cursor::new(packet.encode().unwrap());
+        let mut control_byte = [0u8];
+        stream.read_exact(&mut control_byte).unwrap();
+
+        let result = Publish::read_with_max_payload(&mut stream, control_byte[0], 200).unwrap();
+        assert_eq!(result, packet);
+    }
+}
+
+#[test]
+fn test_read_with_max_payload_one_byte_over_should_be_error() {
+    for qos in [QoSLevel::QoSLevel0, QoSLevel::QoSLevel1] {
+        let packet_id = if qos == QoSLevel::QoSLevel0 {
+            None
+        } else {
+            Some(7)
+        };
+        let packet = Publish::new(false, qos, false, "a/b", &"x".repeat(201), packet_id).unwrap();
+        let bytes = packet.encode().unwrap();
+        let len = bytes.len();
+        let mut stream = Cursor::new(bytes);
+        let mut control_byte = [0u8];
+        stream.read_exact(&mut control_byte).unwrap();
+
+        let result = Publish::read_with_max_payload(&mut stream, control_byte[0], 200)
+            .unwrap_err()
+            .kind();
+        assert_eq!(result, ErrorKind::PayloadTooLarge);
+        // El payload no se leyo
+        assert_eq!(len - stream.position() as usize, 201);
+    }
+}
+
+#[test]
+fn test_read_with_max_payload_truncated_should_be_error() {
+    let packet = Publish::new(false, QoSLevel::QoSLevel1, false, "a/b", "ab", Some(7)).unwrap();
+    let mut bytes = packet.encode().unwrap();
+    bytes.truncate(6);
+    let mut stream = Cursor::new(bytes);
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+
+    let result = Publish::read_with_max_payload(&mut stream, control_byte[0], 10)
+        .unwrap_err()
+        .kind();
+    assert_eq!(result, ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_topic_longer_than_remaining_length_should_be_error() {
+    // El topic dice tener 10 bytes, pero el Remaining Length es 4
+    let bytes = [4, 0, 10, b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h'];
+    for max_payload_size in [None, Some(10)] {
+        let mut stream = Cursor::new(bytes);
+        let result = match max_payload_size {
+            Some(max) => Publish::read_with_max_payload(&mut stream, 0x30, max),
+            None => Publish::read_from(&mut stream, 0x30),
+        };
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MalformedLength);
+    }
+}
+
+#[test]
+fn test_decode_qos_0_does_not_read_packet_identifier() {
+    let packet = Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "ab", None).unwrap();
+    let mut stream = Cursor::new(packet.encode().unwrap());
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+    let result = Publish::read_from(&mut stream, control_byte[0]).unwrap();
+    assert_eq!(result.packet_id(), None);
+    assert_eq!(result.payload(), "ab");
+}
+
+#[test]
+fn test_decode_qos_1_without_packet_identifier_should_be_error() {
+    let control_byte = 0b110010u8;
+    let mut remaining_data = Field::new_from_string("a/b").unwrap().encode();
+    let mut bytes = encode_remaining_length(remaining_data.len() + 1);
+    bytes.append(&mut remaining_data);
+    bytes.push(1); // Solo la mitad del identificador
+    let mut stream = Cursor::new(bytes);
+    let result = Publish::read_from(&mut stream, control_byte)
+        .unwrap_err()
+        .kind();
+    assert_eq!(result, ErrorKind::MalformedLength);
+}
+
 #[test]
 fn test_topic_name_cannot_contain_single_level_wildcard() {
     let packet = Publish::new(
@@ -373,3 +599,218 @@ fn test_set_retain() {
     packet.set_retain_flag(false);
     assert!(!packet.retain_flag());
 }
+
+#[test]
+fn test_with_packet_id() {
+    let publish = Publish::new(
+        false,
+        QoSLevel::QoSLevel1,
+        false,
+        "topic",
+        "message",
+        Some(10),
+    )
+    .unwrap();
+    let copy = publish.clone().with_packet_id(20);
+
+    assert_eq!(publish.packet_id(), Some(10));
+    assert_eq!(copy.packet_id(), Some(20));
+    assert_eq!(copy.payload(), publish.payload());
+}
+
+#[test]
+fn test_with_packet_id_qos_0_has_no_packet_id() {
+    let publish = Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "message", None)
+        .unwrap()
+        .with_packet_id(20);
+
+    assert!(publish.packet_id().is_none());
+}
+
+#[test]
+fn test_check_topic_len_counts_bytes() {
+    // "ñ" ocupa 2 bytes
+    let publish = Publish::new(false, QoSLevel::QoSLevel0, false, "ñña", "msg", None).unwrap();
+    assert!(publish.check_topic_len(5).is_ok());
+    assert_eq!(
+        publish.check_topic_len(4).unwrap_err().kind(),
+        ErrorKind::InvalidProtocol
+    );
+}
+
+#[test]
+fn test_builder_builds_valid_publish() {
+    let publish = PublishBuilder::new("a/b", QoSLevel::QoSLevel1)
+        .with_payload("mensaje".as_bytes())
+        .with_retain_flag(true)
+        .with_packet_id(7)
+        .build()
+        .unwrap();
+    let expected =
+        Publish::new(false, QoSLevel::QoSLevel1, true, "a/b", "mensaje", Some(7)).unwrap();
+    assert_eq!(publish, expected);
+}
+
+#[test]
+fn test_builder_qos0_without_packet_id_is_valid() {
+    let publish = PublishBuilder::new("a", QoSLevel::QoSLevel0)
+        .build()
+        .unwrap();
+    assert_eq!(publish.packet_id(), None);
+    assert_eq!(publish.payload(), "");
+    assert!(!publish.retain_flag());
+}
+
+#[test]
+fn test_builder_qos0_with_packet_id_should_fail() {
+    let result = PublishBuilder::new("a", QoSLevel::QoSLevel0)
+        .with_packet_id(1)
+        .build();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_builder_qos1_without_packet_id_should_fail() {
+    let result = PublishBuilder::new("a", QoSLevel::QoSLevel1).build();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_builder_packet_id_0_should_fail() {
+    let result = PublishBuilder::new("a", QoSLevel::QoSLevel1)
+        .with_packet_id(0)
+        .build();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_builder_topic_with_wildcards_should_fail() {
+    for topic in ["a/+", "a/#"] {
+        let result = PublishBuilder::new(topic, QoSLevel::QoSLevel0).build();
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::TopicNameMustNotHaveWildcards
+        );
+    }
+}
+
+#[test]
+fn test_builder_empty_topic_should_fail() {
+    let result = PublishBuilder::new("", QoSLevel::QoSLevel0).build();
+    assert_eq!(
+        result.unwrap_err().kind(),
+        ErrorKind::TopicNameMustBeAtLeastOneCharacterLong
+    );
+}
+
+#[test]
+fn test_builder_topic_too_long_should_fail() {
+    let topic = "a".repeat(65536);
+    let result = PublishBuilder::new(&topic, QoSLevel::QoSLevel0).build();
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::FieldTooLong);
+}
+
+#[test]
+fn test_builder_payload_not_utf8_should_fail() {
+    let result = PublishBuilder::new("a", QoSLevel::QoSLevel0)
+        .with_payload(&[0xff, 0xfe])
+        .build();
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidProtocol);
+}
+
+fn topic_levels(topic: &str) -> Vec<String> {
+    Publish::new(false, QoSLevel::QoSLevel0, false, topic, "", None)
+        .unwrap()
+        .topic_levels()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn test_topic_levels_multi_level() {
+    assert_eq!(topic_levels("a/b/c"), vec!["a", "b", "c"]);
+}
+
+#[test]
+fn test_topic_levels_leading_separator_yields_empty_first_level() {
+    assert_eq!(topic_levels("/a"), vec!["", "a"]);
+}
+
+#[test]
+fn test_topic_levels_trailing_separator_yields_empty_last_level() {
+    assert_eq!(topic_levels("a/"), vec!["a", ""]);
+}
+
+#[test]
+fn test_topic_levels_single_level() {
+    assert_eq!(topic_levels("a"), vec!["a"]);
+}
+
+#[test]
+fn test_matches_filter() {
+    let publish = Publish::new(false, QoSLevel::QoSLevel0, false, "a/b/c", "", None).unwrap();
+    assert!(publish.matches_filter("a/b/c"));
+    assert!(publish.matches_filter("a/+/c"));
+    assert!(publish.matches_filter("a/#"));
+    assert!(!publish.matches_filter("a/+"));
+    assert!(!publish.matches_filter("b/#"));
+}
+
+#[test]
+fn test_dedup_key_ignores_packet_id_and_dup_flag() {
+    let original = Publish::new(
+        false,
+        QoSLevel::QoSLevel1,
+        false,
+        "topic",
+        "message",
+        Some(1),
+    )
+    .unwrap();
+    let redelivery = Publish::new(
+        true,
+        QoSLevel::QoSLevel1,
+        false,
+        "topic",
+        "message",
+        Some(2),
+    )
+    .unwrap();
+    assert_eq!(original.dedup_key(), redelivery.dedup_key());
+}
+
+#[test]
+fn test_dedup_key_depends_on_topic_and_payload() {
+    let publish = |topic, payload| {
+        Publish::new(false, QoSLevel::QoSLevel0, false, topic, payload, None)
+            .unwrap()
+            .dedup_key()
+    };
+    assert_ne!(publish("topic", "message"), publish("topic", "other"));
+    assert_ne!(publish("topic", "message"), publish("other", "message"));
+    assert_ne!(publish("a", "bc"), publish("ab", "c"));
+}
+
+#[test]
+fn test_dedup_key_is_stable() {
+    // Si cambia, las claves persistidas dejan de servir
+    let publish = Publish::new(false, QoSLevel::QoSLevel0, false, "a", "", None).unwrap();
+    assert_eq!(publish.dedup_key(), 0xe601_7d3a_248d_eb69);
+}
+
+#[test]
+fn test_with_topic_name_keeps_the_rest_of_the_packet() {
+    let publish = Publish::new(false, QoSLevel::QoSLevel1, true, "data", "21.5", Some(7)).unwrap();
+    let renamed = publish.clone().with_topic_name("tenant/data").unwrap();
+    assert_eq!(renamed.topic_name(), "tenant/data");
+    assert_eq!(renamed.payload(), publish.payload());
+    assert_eq!(renamed.packet_id(), Some(7));
+    assert!(renamed.retain_flag());
+}
+
+#[test]
+fn test_with_topic_name_rejects_wildcards() {
+    let publish = Publish::new(false, QoSLevel::QoSLevel0, false, "data", "", None).unwrap();
+    assert!(publish.clone().with_topic_name("tenant/+").is_err());
+    assert!(publish.with_topic_name("#").is_err());
+}
@@ -1,6 +1,6 @@
 use crate::packet_error::{ErrorKind, PacketError};
 use serde::{Deserialize, Serialize};
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 /// Represents the available QoS levels
@@ -20,6 +20,13 @@ impl From<QoSLevel> for u8 {
     }
 }
 
+impl fmt::Display for QoSLevel {
+    /// Formats the QoS level as "QoS 0", "QoS 1" or "QoS 2"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "QoS {}", u8::from(*self))
+    }
+}
+
 impl TryFrom<u8> for QoSLevel {
     type Error = PacketError;
     fn try_from(value: u8) -> Result<Self, Self::Error> {
@@ -34,3 +41,40 @@ impl TryFrom<u8> for QoSLevel {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::QoSLevel;
+    use crate::packet_error::ErrorKind;
+
+    #[test]
+    fn test_try_from_valid_values() {
+        for (value, qos) in [
+            (0, QoSLevel::QoSLevel0),
+            (1, QoSLevel::QoSLevel1),
+            (2, QoSLevel::QoSLevel2),
+        ] {
+            assert_eq!(QoSLevel::try_from(value).unwrap(), qos);
+            assert_eq!(u8::from(qos), value);
+        }
+    }
+
+    #[test]
+    fn test_try_from_reserved_value_should_be_error() {
+        for value in [3, 4, 255] {
+            assert_eq!(
+                QoSLevel::try_from(value).unwrap_err().kind(),
+                ErrorKind::InvalidQoSLevel
+            );
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(QoSLevel::QoSLevel0.to_string(), "QoS 0");
+        assert_eq!(QoSLevel::QoSLevel1.to_string(), "QoS 1");
+        assert_eq!(QoSLevel::QoSLevel2.to_string(), "QoS 2");
+    }
+}
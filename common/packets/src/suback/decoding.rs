@@ -1,7 +1,7 @@
 use std::io::Read;
 
 use crate::{
-    helpers::{check_packet_type, check_reserved_bits, PacketType},
+    helpers::{check_packet_type, PacketType},
     packet_error::PacketResult,
     packet_reader,
     traits::MQTTDecoding,
@@ -19,7 +19,7 @@ impl MQTTDecoding for Suback {
     /// - Control packet type is different from 9
     /// - Reserved bits are not 0b0000
     /// - Remaining length is greater than 256 MB
-    /// - Any return code does not match any of these 0x00, 0x01, 0x80
+    /// - Any return code does not match any of these 0x00, 0x01, 0x02, 0x80
     ///
     /// # Examples
     ///
@@ -38,7 +38,6 @@ impl MQTTDecoding for Suback {
     /// ```
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Suback> {
         check_packet_type(control_byte, PacketType::Suback)?;
-        check_reserved_bits(control_byte, RESERVED_BITS)?;
         let mut remaining_bytes = packet_reader::read_remaining_bytes(stream)?;
         let subscribe_packet_id = Self::read_packet_id(&mut remaining_bytes);
         let return_codes = Self::read_return_codes(&mut remaining_bytes)?;
@@ -40,7 +40,7 @@ impl Suback {
     ///
     /// # Errors
     ///
-    /// Allowed return codes are 0x00, 0x01, 0x80. If a return code doesn't match any of those, this function returns a [ErrorKind::InvalidReturnCode]
+    /// Allowed return codes are 0x00, 0x01, 0x02, 0x80. If a return code doesn't match any of those, this function returns a [ErrorKind::InvalidReturnCode]
     pub fn new_from_vec(return_codes: Vec<u8>, subscribe_packet_id: u16) -> PacketResult<Self> {
         Self::verify_return_codes_from_vec(&return_codes)?;
         Ok(Self {
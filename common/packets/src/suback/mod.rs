@@ -1,5 +1,8 @@
+use std::convert::TryFrom;
+
 use crate::{
     packet_error::{ErrorKind, PacketError, PacketResult},
+    qos::QoSLevel,
     topic_filter::TopicFilter,
 };
 
@@ -11,13 +14,15 @@ mod tests;
 #[doc(hidden)]
 const RESERVED_BITS: u8 = 0;
 #[doc(hidden)]
-const MSG_INVALID_RETURN_CODE: &str = "Allowed return codes are 0x00, 0x01, 0x80";
+const MSG_INVALID_RETURN_CODE: &str = "Allowed return codes are 0x00, 0x01, 0x02, 0x80";
 #[doc(hidden)]
 const SUCCESS_MAXIMUM_QOS_0: u8 = 0;
 #[doc(hidden)]
 const SUCCESS_MAXIMUM_QOS_1: u8 = 1;
 #[doc(hidden)]
-const FAILURE: u8 = 0x80;
+const SUCCESS_MAXIMUM_QOS_2: u8 = 2;
+#[doc(hidden)]
+pub(crate) const FAILURE: u8 = 0x80;
 
 #[derive(Debug)]
 /// Client/Server side structure for Suback packet
@@ -33,15 +38,34 @@ impl Suback {
         self.subscribe_packet_id
     }
 
-    /// Set the suback's subscribe topics
+    /// Set the suback's subscribe topics, given the topic filters of
+    /// the corresponding Subscribe packet (in the same order).
+    ///
+    /// Each topic keeps the QoS granted by the server, which may be
+    /// lower than the requested one. The topics rejected by the
+    /// server (return code 0x80) are discarded
     pub fn set_topics(&mut self, topics: Vec<TopicFilter>) {
-        self.topics = topics;
+        self.topics = topics
+            .iter()
+            .zip(self.granted_qos())
+            .filter_map(|(topic, granted)| TopicFilter::new(topic.name(), granted?).ok())
+            .collect();
     }
-    /// Get the suback's subscribe topics
+    /// Get the suback's subscribe topics, along with the QoS granted
+    /// to each of them (see [`Suback::set_topics`])
     pub fn topics(&self) -> &Vec<TopicFilter> {
         &self.topics
     }
 
+    /// Returns the QoS granted to each topic filter of the Subscribe
+    /// packet, in the same order. Rejected topic filters are None
+    pub fn granted_qos(&self) -> Vec<Option<QoSLevel>> {
+        self.return_codes
+            .iter()
+            .map(|code| QoSLevel::try_from(*code).ok())
+            .collect()
+    }
+
     #[doc(hidden)]
     fn verify_return_codes_from_vec(return_codes: &[u8]) -> PacketResult<()> {
         for code in return_codes {
@@ -65,6 +89,7 @@ impl Suback {
     fn is_return_code_valid(return_code: &u8) -> bool {
         *return_code == SUCCESS_MAXIMUM_QOS_0
             || *return_code == SUCCESS_MAXIMUM_QOS_1
+            || *return_code == SUCCESS_MAXIMUM_QOS_2
             || *return_code == FAILURE
     }
 }
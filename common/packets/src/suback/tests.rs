@@ -4,7 +4,7 @@ use crate::{
     packet_error::ErrorKind,
     traits::{MQTTDecoding, MQTTEncoding},
 };
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 
 #[doc(hidden)]
 const CONTROL_BYTE_SUBACK: u8 = 0b10010000;
@@ -70,3 +70,67 @@ fn test_stream_with_return_code_3_should_raise_invalid_return_code() {
     let expected_error = ErrorKind::InvalidReturnCode;
     assert_eq!(result, expected_error);
 }
+
+#[test]
+fn test_round_trip_with_mixed_granted_qos() {
+    let suback = Suback::new_from_vec(vec![1, 0, 0x80, 1], 10).unwrap();
+    let mut stream = Cursor::new(suback.encode().unwrap());
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+    let mut decoded = Suback::read_from(&mut stream, control_byte[0]).unwrap();
+
+    assert_eq!(decoded.packet_id(), 10);
+    assert_eq!(
+        decoded.granted_qos(),
+        vec![
+            Some(QoSLevel::QoSLevel1),
+            Some(QoSLevel::QoSLevel0),
+            None,
+            Some(QoSLevel::QoSLevel1)
+        ]
+    );
+
+    // Se pidio QoS 1 en todos, pero el servidor bajo uno y rechazo otro
+    decoded.set_topics(vec![
+        TopicFilter::new("a", QoSLevel::QoSLevel1).unwrap(),
+        TopicFilter::new("b", QoSLevel::QoSLevel1).unwrap(),
+        TopicFilter::new("c", QoSLevel::QoSLevel1).unwrap(),
+        TopicFilter::new("d", QoSLevel::QoSLevel1).unwrap(),
+    ]);
+    let topics: Vec<(&str, QoSLevel)> = decoded
+        .topics()
+        .iter()
+        .map(|topic| (topic.name(), topic.qos()))
+        .collect();
+    assert_eq!(
+        topics,
+        vec![
+            ("a", QoSLevel::QoSLevel1),
+            ("b", QoSLevel::QoSLevel0),
+            ("d", QoSLevel::QoSLevel1)
+        ]
+    );
+}
+
+#[test]
+fn test_round_trip_with_every_return_code() {
+    let suback = Suback::new_from_vec(vec![0, 1, 2, 0x80], 11).unwrap();
+    let encoded = suback.encode().unwrap();
+    assert_eq!(encoded, vec![CONTROL_BYTE_SUBACK, 6, 0, 11, 0, 1, 2, 0x80]);
+
+    let mut stream = Cursor::new(encoded);
+    let mut control_byte = [0u8];
+    stream.read_exact(&mut control_byte).unwrap();
+    let decoded = Suback::read_from(&mut stream, control_byte[0]).unwrap();
+
+    assert_eq!(decoded.packet_id(), 11);
+    assert_eq!(
+        decoded.granted_qos(),
+        vec![
+            Some(QoSLevel::QoSLevel0),
+            Some(QoSLevel::QoSLevel1),
+            Some(QoSLevel::QoSLevel2),
+            None
+        ]
+    );
+}
@@ -2,7 +2,7 @@ use std::io::Read;
 
 use super::*;
 use crate::{
-    helpers::check_reserved_bits,
+    helpers::{check_packet_type, PacketType},
     packet_error::{ErrorKind, PacketError, PacketResult},
     packet_reader,
     traits::MQTTDecoding,
@@ -14,7 +14,7 @@ impl MQTTDecoding for Subscribe {
     /// Returns a PacketError in case the packet is malformed.
     /// It is assumed that the first identifier byte has already been read.
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Subscribe> {
-        check_reserved_bits(control_byte, RESERVED_BITS)?;
+        check_packet_type(control_byte, PacketType::Subscribe)?;
         let mut bytes = packet_reader::read_remaining_bytes(stream)?;
 
         let packet_identifier = Self::get_identifier(&mut bytes)?;
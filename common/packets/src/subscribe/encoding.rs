@@ -1,12 +1,23 @@
 use super::*;
 use crate::{
     helpers::{build_control_byte, PacketType},
-    packet_error::PacketResult,
+    packet_error::{ErrorKind, PacketError, PacketResult},
     packet_reader::RemainingLength,
     topic_filter::TopicFilter,
     traits::{MQTTBytes, MQTTEncoding},
 };
 
+#[doc(hidden)]
+const NO_TOPICS_MSG: &str = "Se intento crear un paquete Subscribe sin topic filters";
+#[doc(hidden)]
+const ZERO_PACKET_ID_MSG: &str = "Se intento crear un paquete Subscribe con packet identifier 0";
+
+/// Subscribe packet builder
+pub struct SubscribeBuilder {
+    #[doc(hidden)]
+    subscribe: Subscribe,
+}
+
 impl MQTTEncoding for Subscribe {
     /// Returns the subscribe packet encoded bytes
     fn encode(&self) -> PacketResult<MQTTBytes> {
@@ -53,3 +64,52 @@ impl Subscribe {
         len
     }
 }
+
+impl SubscribeBuilder {
+    /// Creates a SubscribeBuilder, with the given packet identifier
+    /// and without any topic filter
+    pub fn new(packet_identifier: u16) -> Self {
+        SubscribeBuilder {
+            subscribe: Subscribe {
+                packet_identifier,
+                topics: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds a topic filter to the packet, with the maximum QoS
+    /// the client wants to receive its messages with
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the topic filter is not valid
+    pub fn with_topic(mut self, topic_filter: &str, qos: QoSLevel) -> PacketResult<Self> {
+        self.subscribe
+            .topics
+            .push(TopicFilter::new(topic_filter, qos)?);
+        Ok(self)
+    }
+
+    /// Builds the packet with the received parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns error if no topic filter was added (MQTT-3.8.3-3)
+    /// or if the packet identifier is 0 (MQTT-2.3.1-1)
+    pub fn build(self) -> PacketResult<Subscribe> {
+        if self.subscribe.topics.is_empty() {
+            return Err(PacketError::new_kind(
+                NO_TOPICS_MSG,
+                ErrorKind::InvalidProtocol,
+            ));
+        }
+        if self.subscribe.packet_identifier == 0 {
+            return Err(PacketError::new_kind(
+                ZERO_PACKET_ID_MSG,
+                ErrorKind::InvalidProtocol,
+            ));
+        }
+
+        Ok(self.subscribe)
+    }
+}
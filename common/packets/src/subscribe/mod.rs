@@ -1,9 +1,15 @@
 use std::convert::TryFrom;
 
-use crate::{packet_error::PacketResult, qos::QoSLevel, suback::Suback, topic_filter::TopicFilter};
+use crate::{
+    packet_error::{ErrorKind, PacketError, PacketResult},
+    qos::QoSLevel,
+    suback::{self, Suback},
+    topic_filter::TopicFilter,
+};
 
 mod decoding;
 mod encoding;
+pub use encoding::SubscribeBuilder;
 #[cfg(test)]
 mod tests;
 
@@ -32,11 +38,13 @@ impl Subscribe {
         QoSLevel::try_from(byte)
     }
 
-    /// Creates a response packet (Suback in this case) for this Subscribe packet
+    /// Creates a response packet (Suback in this case) for this Subscribe packet.
+    /// Each topic filter is granted the QoS it requested, so a server that does
+    /// not support every level should call [`Subscribe::set_max_qos`] first
     ///
     /// # Errors
     ///
-    /// Allowed return codes are 0x00, 0x01, 0x80. If a return code doesn't match any of those, this function returns a [crate::packet_error::ErrorKind::InvalidReturnCode]
+    /// Allowed return codes are 0x00, 0x01, 0x02, 0x80. If a return code doesn't match any of those, this function returns a [crate::packet_error::ErrorKind::InvalidReturnCode]
     pub fn response(&self) -> PacketResult<Suback> {
         let mut return_codes = Vec::new();
         for topic in &self.topics {
@@ -45,6 +53,34 @@ impl Subscribe {
         Suback::new_from_vec(return_codes, self.packet_identifier)
     }
 
+    /// Creates a response packet (Suback) that rejects every topic
+    /// filter of this Subscribe packet, with the return code 0x80
+    pub fn failure_response(&self) -> PacketResult<Suback> {
+        Suback::new_from_vec(
+            vec![suback::FAILURE; self.topics.len()],
+            self.packet_identifier,
+        )
+    }
+
+    /// Checks that every topic filter is at most `max_len` bytes
+    /// long (not characters). Otherwise, it returns an error of kind
+    /// [`ErrorKind::InvalidProtocol`]
+    pub fn check_topic_len(&self, max_len: usize) -> PacketResult<()> {
+        for topic in &self.topics {
+            if topic.name().len() > max_len {
+                return Err(PacketError::new_kind(
+                    format!(
+                        "Topic filter is {} bytes long, the maximum is {}",
+                        topic.name().len(),
+                        max_len
+                    ),
+                    ErrorKind::InvalidProtocol,
+                ));
+            }
+        }
+        Ok(())
+    }
+
     #[doc(hidden)]
     /// Sets max QoS for each Topic Filter in a Subscribe packet
     /// This is intended to be used by the server in case some QoS is not yet implemented by it
@@ -1,10 +1,11 @@
 use crate::packet_error::ErrorKind;
+use crate::packet_reader::encode_remaining_length;
 use crate::traits::{MQTTDecoding, MQTTEncoding};
 use crate::utf8::Field;
 
 use super::Subscribe;
 use super::*;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 
 const CONTROL_BYTE: u8 = 0b10000010;
 
@@ -15,7 +16,7 @@ fn test_identifier() {
     v.extend(Field::new_from_string("unTopic").unwrap().encode());
     v.push(1); // QoS level 1
 
-    v.insert(0, v.len() as u8);
+    v.splice(0..0, encode_remaining_length(v.len()));
     let packet = Subscribe::read_from(&mut Cursor::new(v), CONTROL_BYTE).unwrap();
     assert_eq!(packet.packet_identifier(), (123 << 8) + 5);
 }
@@ -25,7 +26,7 @@ fn test_subscribe_with_no_topics_should_raise_error() {
     let mut v: Vec<u8> = Vec::new();
     v.extend_from_slice(&[123, 5]); // identifier
 
-    v.insert(0, v.len() as u8);
+    v.splice(0..0, encode_remaining_length(v.len()));
     let packet = Subscribe::read_from(&mut Cursor::new(v), CONTROL_BYTE);
     let result = packet.err().unwrap().kind();
     let expected_error = ErrorKind::InvalidProtocol;
@@ -43,7 +44,7 @@ fn test_one_topic() {
     );
     v.push(1); // QoS level 1
 
-    v.insert(0, v.len() as u8);
+    v.splice(0..0, encode_remaining_length(v.len()));
     let packet = Subscribe::read_from(&mut Cursor::new(v), CONTROL_BYTE).unwrap();
     assert_eq!(packet.topics().len(), 1);
     assert_eq!(
@@ -62,7 +63,7 @@ fn test_two_topics() {
     v.extend(Field::new_from_string("second").unwrap().encode());
     v.push(0); // QoS level 0
 
-    v.insert(0, v.len() as u8);
+    v.splice(0..0, encode_remaining_length(v.len()));
     let packet = Subscribe::read_from(&mut Cursor::new(v), CONTROL_BYTE).unwrap();
     assert_eq!(packet.topics().len(), 2);
     assert_eq!(packet.topics().first().unwrap().name(), "first");
@@ -71,6 +72,19 @@ fn test_two_topics() {
     assert_eq!(packet.topics()[1].qos(), QoSLevel::QoSLevel0);
 }
 
+#[test]
+fn test_topics_longer_than_127_bytes() {
+    let topic = "x/".repeat(100);
+    let mut v: Vec<u8> = Vec::new();
+    v.extend_from_slice(&[0, 5]); // identifier
+    v.extend(Field::new_from_string(&topic).unwrap().encode());
+    v.push(1); // QoS level 1
+
+    v.splice(0..0, encode_remaining_length(v.len()));
+    let packet = Subscribe::read_from(&mut Cursor::new(v), CONTROL_BYTE).unwrap();
+    assert_eq!(packet.topics().first().unwrap().name(), topic);
+}
+
 #[test]
 fn test_invalid_reserved_flags() {
     let invalid_first = 0b01000011;
@@ -79,7 +93,7 @@ fn test_invalid_reserved_flags() {
     v.extend(Field::new_from_string("unTopic").unwrap().encode());
     v.push(1); // QoS level 1
 
-    v.insert(0, v.len() as u8);
+    v.splice(0..0, encode_remaining_length(v.len()));
     let packet = Subscribe::read_from(&mut Cursor::new(v), invalid_first).unwrap_err();
     let result = packet.kind();
     let expected_error = ErrorKind::InvalidReservedBits;
@@ -93,7 +107,7 @@ fn test_invalid_qos() {
     v.extend(Field::new_from_string("unTopic").unwrap().encode());
     v.push(3); // QoS level 3
 
-    v.insert(0, v.len() as u8);
+    v.splice(0..0, encode_remaining_length(v.len()));
     let packet = Subscribe::read_from(&mut Cursor::new(v), CONTROL_BYTE);
     let result = packet.err().unwrap().kind();
     let expected_error = ErrorKind::InvalidQoSLevel;
@@ -141,3 +155,99 @@ fn test_subscribe_encode_2_topics() {
         ]
     );
 }
+
+#[test]
+fn test_subscribe_builder_multiple_topics() {
+    let subscribe = SubscribeBuilder::new(7)
+        .with_topic("topic1", QoSLevel::QoSLevel0)
+        .unwrap()
+        .with_topic("topic2/+", QoSLevel::QoSLevel1)
+        .unwrap()
+        .build()
+        .unwrap();
+    assert_eq!(subscribe.packet_identifier(), 7);
+    assert_eq!(
+        subscribe.topics(),
+        vec![
+            TopicFilter::new("topic1", QoSLevel::QoSLevel0).unwrap(),
+            TopicFilter::new("topic2/+", QoSLevel::QoSLevel1).unwrap()
+        ]
+    );
+
+    let mut bytes = Cursor::new(subscribe.encode().unwrap());
+    let mut control_byte = [0u8];
+    bytes.read_exact(&mut control_byte).unwrap();
+    let decoded = Subscribe::read_from(&mut bytes, control_byte[0]).unwrap();
+    assert_eq!(decoded.topics(), subscribe.topics());
+}
+
+#[test]
+fn test_subscribe_builder_without_topics_should_raise_error() {
+    let result = SubscribeBuilder::new(7).build();
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidProtocol);
+}
+
+#[test]
+fn test_subscribe_builder_invalid_topic_should_raise_error() {
+    assert!(SubscribeBuilder::new(7)
+        .with_topic("topic/#/invalid", QoSLevel::QoSLevel0)
+        .is_err());
+}
+
+#[test]
+fn test_subscribe_builder_zero_packet_id_should_raise_error() {
+    let result = SubscribeBuilder::new(0)
+        .with_topic("topic", QoSLevel::QoSLevel0)
+        .unwrap()
+        .build();
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidProtocol);
+}
+
+#[test]
+fn test_failure_response_rejects_every_topic() {
+    let topics = vec![
+        TopicFilter::new("a", QoSLevel::QoSLevel1).unwrap(),
+        TopicFilter::new("b", QoSLevel::QoSLevel0).unwrap(),
+    ];
+    let subscribe = Subscribe::new(topics, 7);
+    let suback = subscribe.failure_response().unwrap();
+    assert_eq!(suback.packet_id(), 7);
+    assert_eq!(suback.encode().unwrap(), [0b10010000, 4, 0, 7, 0x80, 0x80]);
+}
+
+#[test]
+fn test_check_topic_len_counts_bytes() {
+    let topics = vec![
+        TopicFilter::new("a", QoSLevel::QoSLevel0).unwrap(),
+        TopicFilter::new("ñ/#", QoSLevel::QoSLevel0).unwrap(),
+    ];
+    let subscribe = Subscribe::new(topics, 7);
+    assert!(subscribe.check_topic_len(4).is_ok());
+    assert_eq!(
+        subscribe.check_topic_len(3).unwrap_err().kind(),
+        ErrorKind::InvalidProtocol
+    );
+}
+
+#[test]
+fn test_response_grants_qos_2() {
+    let topics = vec![
+        TopicFilter::new("a", QoSLevel::QoSLevel2).unwrap(),
+        TopicFilter::new("b", QoSLevel::QoSLevel0).unwrap(),
+    ];
+    let subscribe = Subscribe::new(topics, 7);
+    let suback = subscribe.response().unwrap();
+    assert_eq!(suback.encode().unwrap(), [0b10010000, 4, 0, 7, 2, 0]);
+}
+
+#[test]
+fn test_response_after_set_max_qos_does_not_grant_qos_2() {
+    let topics = vec![
+        TopicFilter::new("a", QoSLevel::QoSLevel2).unwrap(),
+        TopicFilter::new("b", QoSLevel::QoSLevel0).unwrap(),
+    ];
+    let mut subscribe = Subscribe::new(topics, 7);
+    subscribe.set_max_qos(QoSLevel::QoSLevel1);
+    let suback = subscribe.response().unwrap();
+    assert_eq!(suback.encode().unwrap(), [0b10010000, 4, 0, 7, 1, 0]);
+}
@@ -1,16 +1,83 @@
+use std::convert::TryFrom;
+
 use serde::{Deserialize, Serialize};
 
 use crate::packet_error::{ErrorKind, PacketError, PacketResult};
 use crate::qos::QoSLevel;
 use crate::utf8::Field;
 
+#[doc(hidden)]
+const TOPIC_LEVEL_SEPARATOR: char = '/';
+#[doc(hidden)]
+const SINGLE_LEVEL_WILDCARD: &str = "+";
+#[doc(hidden)]
+const MULTI_LEVEL_WILDCARD: &str = "#";
+#[doc(hidden)]
+const SYSTEM_TOPIC_PREFIX: char = '$';
+
+/// Returns true if the topic name `topic` matches the topic filter
+/// `filter`, following the wildcard rules of the protocol (MQTT-4.7):
+///
+/// * `+` matches exactly one level, which may be empty
+/// * `#` matches any number of levels, including the parent level
+///   (so `sport/#` also matches `sport`)
+/// * Topics starting with `$` are not matched by filters starting
+///   with a wildcard
+///
+/// ```
+/// use packets::topic_filter::matches;
+///
+/// assert!(matches("sport/tennis/player1", "sport/tennis/+"));
+/// assert!(matches("sport/tennis/player1/ranking", "sport/#"));
+/// assert!(!matches("sport/tennis/player1/ranking", "sport/tennis/+"));
+/// assert!(!matches("$SYS/monitor", "#"));
+/// ```
+pub fn matches(topic: &str, filter: &str) -> bool {
+    if topic.starts_with(SYSTEM_TOPIC_PREFIX)
+        && (filter.starts_with(SINGLE_LEVEL_WILDCARD) || filter.starts_with(MULTI_LEVEL_WILDCARD))
+    {
+        return false;
+    }
+    let mut topic_levels = topic.split(TOPIC_LEVEL_SEPARATOR);
+    for filter_level in filter.split(TOPIC_LEVEL_SEPARATOR) {
+        if filter_level == MULTI_LEVEL_WILDCARD {
+            return true;
+        }
+        match topic_levels.next() {
+            Some(topic_level)
+                if filter_level == SINGLE_LEVEL_WILDCARD || filter_level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// Topic filter of a subscription. It can only be created through
+/// [`TopicFilter::new`] (deserializing it goes through it too), so
+/// it always complies with the protocol's standard for Topic Filters
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedTopicFilter")]
 pub struct TopicFilter {
     /// Topic for a subscribe packet
     name: Field,
     qos: QoSLevel,
 }
 
+/// Fields of a deserialized [`TopicFilter`], before validating them
+#[derive(Deserialize)]
+struct UncheckedTopicFilter {
+    name: Field,
+    qos: QoSLevel,
+}
+
+impl TryFrom<UncheckedTopicFilter> for TopicFilter {
+    type Error = PacketError;
+
+    fn try_from(unchecked: UncheckedTopicFilter) -> Result<Self, Self::Error> {
+        TopicFilter::new(unchecked.name.value, unchecked.qos)
+    }
+}
+
 impl TopicFilter {
     /// Creates a new topic
     /// Returns PacketError if the topic name is invalid
@@ -62,6 +129,19 @@ impl TopicFilter {
         }
     }
 
+    /// Returns true if the topic name `topic` matches this
+    /// filter (see [`matches`])
+    pub fn matches(&self, topic: &str) -> bool {
+        matches(topic, self.name())
+    }
+
+    /// Checks that the topic filter complies with the protocol's
+    /// standard for Topic Filters. Since every filter is validated
+    /// when created, this only fails for filters built by hand
+    pub fn validate(&self) -> PacketResult<()> {
+        TopicFilter::check_valid_topic_name(self.name())
+    }
+
     /// Validates if the given topic name complies with the protocol's standard for Topic Filters
     /// MQTT-4.7
     fn check_valid_topic_name(topic_name: &str) -> PacketResult<()> {
@@ -72,6 +152,14 @@ impl TopicFilter {
             ));
         }
 
+        // Los strings UTF-8 no pueden contener U+0000 (MQTT-1.5.3-2)
+        if topic_name.contains('\0') {
+            return Err(PacketError::new_kind(
+                "Topic name must not contain null characters",
+                ErrorKind::InvalidTopicName,
+            ));
+        }
+
         if !(topic_name.eq("#"))
             && (topic_name.matches('#').count() > 1
                 || topic_name.contains('#') && !topic_name.ends_with("/#"))
@@ -190,4 +278,108 @@ mod tests {
         let topic = TopicFilter::new("+/+/+/+", QoSLevel::QoSLevel0);
         assert!(topic.is_ok());
     }
+
+    #[test]
+    fn test_invalid_topic_name_null_character() {
+        for name in ["\0", "a/\0/b", "a\0"] {
+            let err = TopicFilter::new(name, QoSLevel::QoSLevel0).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidTopicName);
+        }
+    }
+
+    #[test]
+    fn test_invalid_topic_filter_cases() {
+        for name in ["a/#/b", "a+b", "", "a/\0"] {
+            let err = TopicFilter::new(name, QoSLevel::QoSLevel1).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidTopicName, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_validate_topic_filter() {
+        let topic = TopicFilter::new("a/#", QoSLevel::QoSLevel0).unwrap();
+        assert!(topic.validate().is_ok());
+
+        // Sin pasar por TopicFilter::new
+        let topic = TopicFilter {
+            name: Field::new_from_string("a/#/b").unwrap(),
+            qos: QoSLevel::QoSLevel0,
+        };
+        assert_eq!(
+            topic.validate().unwrap_err().kind(),
+            ErrorKind::InvalidTopicName
+        );
+    }
+
+    #[test]
+    fn test_deserialized_topic_filter_is_validated() {
+        let valid = TopicFilter::new("a/+", QoSLevel::QoSLevel1).unwrap();
+        let json = serde_json::to_string(&valid).unwrap();
+        let deserialized: TopicFilter = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, valid);
+
+        for name in ["a/#/b", "a+b", "", "a/\\u0000"] {
+            let json = json.replace("a/+", name);
+            assert!(
+                serde_json::from_str::<TopicFilter>(&json).is_err(),
+                "{}",
+                json
+            );
+        }
+    }
+
+    // Ejemplos del apendice de wildcards de la especificacion (MQTT-4.7.1)
+    #[test]
+    fn test_multi_level_wildcard_spec_examples() {
+        assert!(matches("sport/tennis/player1", "sport/tennis/player1/#"));
+        assert!(matches(
+            "sport/tennis/player1/ranking",
+            "sport/tennis/player1/#"
+        ));
+        assert!(matches(
+            "sport/tennis/player1/score/wimbledon",
+            "sport/tennis/player1/#"
+        ));
+        assert!(matches("sport", "sport/#"));
+        assert!(matches("sport/tennis/player1", "#"));
+        assert!(!matches("sports/tennis", "sport/#"));
+    }
+
+    #[test]
+    fn test_single_level_wildcard_spec_examples() {
+        assert!(matches("sport/tennis/player1", "sport/tennis/+"));
+        assert!(matches("sport/tennis/player2", "sport/tennis/+"));
+        assert!(!matches("sport/tennis/player1/ranking", "sport/tennis/+"));
+        assert!(!matches("sport", "sport/+"));
+        assert!(matches("sport/", "sport/+"));
+        assert!(matches("/finance", "+/+"));
+        assert!(matches("/finance", "/+"));
+        assert!(!matches("/finance", "+"));
+        assert!(matches("sport/tennis/player1/ranking", "+/tennis/#"));
+        assert!(matches("sport/tennis", "sport/+/#"));
+    }
+
+    #[test]
+    fn test_topics_starting_with_dollar_spec_examples() {
+        assert!(!matches("$SYS/monitor/Clients", "#"));
+        assert!(!matches("$SYS/monitor/Clients", "+/monitor/Clients"));
+        assert!(matches("$SYS/monitor/Clients", "$SYS/#"));
+        assert!(matches("$SYS/monitor/Clients", "$SYS/monitor/+"));
+    }
+
+    #[test]
+    fn test_matches_without_wildcards() {
+        assert!(matches("a/b", "a/b"));
+        assert!(!matches("a/b", "a/c"));
+        assert!(!matches("a/b", "a"));
+        assert!(!matches("a", "a/b"));
+        assert!(!matches("a/b", "A/b"));
+    }
+
+    #[test]
+    fn test_topic_filter_matches() {
+        let filter = TopicFilter::new("a/+/c", QoSLevel::QoSLevel0).unwrap();
+        assert!(filter.matches("a/b/c"));
+        assert!(!filter.matches("a/b/d"));
+    }
 }
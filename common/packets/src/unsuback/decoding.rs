@@ -2,7 +2,7 @@ use std::io::Read;
 
 use super::*;
 use crate::{
-    helpers::{check_packet_type, check_reserved_bits, PacketType},
+    helpers::{check_packet_type, PacketType},
     packet_error::PacketResult,
     packet_reader,
     traits::MQTTDecoding,
@@ -14,9 +14,9 @@ const FIXED_REMAINING_LENGTH: usize = 2;
 impl MQTTDecoding for Unsuback {
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Self> {
         check_packet_type(control_byte, PacketType::Unsuback)?;
-        check_reserved_bits(control_byte, RESERVED_BITS)?;
-        let mut remaining_bytes = packet_reader::read_remaining_bytes(stream)?;
-        let packet_id = Self::read_packet_id(&mut remaining_bytes);
+        let packet_id = packet_reader::decode_remaining(stream, "Unsuback", |bytes| {
+            Ok(Self::read_packet_id(bytes))
+        })?;
         Self::verify_packet_id(&packet_id)?;
         Ok(Self {
             packet_id,
@@ -45,6 +45,16 @@ fn test_valid_unsuback_packet_with_packet_id_1() {
     assert_eq!(expected_id, result.packet_id());
 }
 
+#[test]
+fn test_unsuback_with_more_bytes_than_expected_should_raise_trailing_bytes() {
+    let data_buffer: Vec<u8> = vec![3, 0, 1, 0];
+    let mut stream = Cursor::new(data_buffer);
+    let result = Unsuback::read_from(&mut stream, CONTROL_BYTE)
+        .unwrap_err()
+        .kind();
+    assert_eq!(result, ErrorKind::TrailingBytes);
+}
+
 #[test]
 fn test_valid_unsuback_packet_with_packet_id_0_should_raise_invalid_protocol_error() {
     let control_byte = 0b10110000u8;
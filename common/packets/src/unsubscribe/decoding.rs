@@ -2,7 +2,7 @@ use std::io::Read;
 
 use crate::qos::QoSLevel;
 use crate::{
-    helpers::{check_packet_type, check_reserved_bits, PacketType},
+    helpers::{check_packet_type, PacketType},
     packet_error::{ErrorKind, PacketError, PacketResult},
     packet_reader,
     traits::MQTTDecoding,
@@ -22,12 +22,12 @@ impl MQTTDecoding for Unsubscribe {
     /// - Control packet type is different from 10
     /// - Reserved bits are not 0b0010
     /// - Remaining length is greater than 256 MB
+    /// - The packet ends before its packet identifier
     /// - Topic filter is empty
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Unsubscribe> {
         check_packet_type(control_byte, PacketType::Unsubscribe)?;
-        check_reserved_bits(control_byte, RESERVED_BITS)?;
         let mut remaining_bytes = packet_reader::read_remaining_bytes(stream)?;
-        let packet_id = Self::read_packet_id(&mut remaining_bytes);
+        let packet_id = Self::read_packet_id(&mut remaining_bytes)?;
         let mut topic_filters: Vec<TopicFilter> = Vec::new();
         Self::read_topic_filters(&mut remaining_bytes, &mut topic_filters)?;
         Ok(Unsubscribe {
@@ -39,10 +39,10 @@ impl MQTTDecoding for Unsubscribe {
 
 impl Unsubscribe {
     #[doc(hidden)]
-    fn read_packet_id(bytes: &mut impl Read) -> u16 {
+    fn read_packet_id(bytes: &mut impl Read) -> PacketResult<u16> {
         let mut packet_id_buffer = [0u8; 2];
-        let _ = bytes.read_exact(&mut packet_id_buffer);
-        u16::from_be_bytes(packet_id_buffer)
+        bytes.read_exact(&mut packet_id_buffer)?;
+        Ok(u16::from_be_bytes(packet_id_buffer))
     }
 
     #[doc(hidden)]
@@ -24,6 +24,16 @@ fn test_unsubscribe_packet_with_empty_topic_filter_should_raise_invalid_protocol
     assert_eq!(result, expected_error);
 }
 
+#[test]
+fn test_unsubscribe_packet_without_packet_id_should_raise_unexpected_eof() {
+    let v: Vec<u8> = vec![1, 0]; // remaining length + half of the packet id
+    let mut stream = Cursor::new(v);
+    let result = Unsubscribe::read_from(&mut stream, CONTROL_TYPE_UNSUBSCRIBE)
+        .unwrap_err()
+        .kind();
+    assert_eq!(result, ErrorKind::UnexpectedEof);
+}
+
 #[test]
 fn test_unsubscribe_packet_with_empty_string_as_topic_filter_should_raise_invalid_protocol_error() {
     let control_byte = 0b10100010u8;
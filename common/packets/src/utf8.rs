@@ -2,7 +2,7 @@ use std::io::Read;
 
 use serde::{Deserialize, Serialize};
 
-use crate::packet_error::PacketError;
+use crate::packet_error::{ErrorKind, PacketError};
 
 const MAX_FIELD_LEN: usize = 65535;
 
@@ -32,7 +32,10 @@ impl Field {
     pub fn new_from_string<S: Into<String>>(value: S) -> Result<Self, PacketError> {
         let value = value.into();
         if value.len() > MAX_FIELD_LEN {
-            return Err(PacketError::new_msg("Largo del paquete excedido"));
+            return Err(PacketError::new_kind(
+                "Largo del paquete excedido",
+                ErrorKind::FieldTooLong,
+            ));
         }
         Ok(Self { value })
     }
@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    io,
     sync::mpsc::{self, Receiver, Sender},
     thread::{self, JoinHandle, ThreadId},
 };
@@ -70,6 +71,26 @@ impl ThreadJoiner {
         self.finished_sender.send(Message::Started(handle)).unwrap();
     }
 
+    /// Spawns a new thread with the given name, in which
+    /// it executes the received action
+    ///
+    /// # Errors
+    /// If the thread could not be created, it returns an error
+    pub fn spawn_named<F>(&mut self, name: String, action: F) -> io::Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender_clone = self.finished_sender.clone();
+        let handle = thread::Builder::new().name(name).spawn(move || {
+            let guard = ThreadGuard::new(thread::current().id(), sender_clone);
+            action();
+            drop(guard);
+        })?;
+        trace!("Creando thread {:?} ({:?})", handle.thread().id(), handle.thread().name());
+        self.finished_sender.send(Message::Started(handle)).unwrap();
+        Ok(())
+    }
+
     /// Executes the loop that joins the threads
     fn join_loop(receiver: Receiver<Message>) {
         let mut handles = HashMap::new();
@@ -11,6 +11,22 @@ use threadpool::ThreadPoolError;
 #[derive(Debug)]
 pub struct ClientError {
     msg: String,
+    kind: ClientErrorKind,
+    packet_error: Option<PacketError>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientErrorKind {
+    /// The expected acknowledgement did not arrive in time
+    Timeout,
+    /// A packet received from the server could not be decoded
+    /// (see [`ClientError::packet_error`])
+    MalformedPacket,
+    /// An acknowledgement received from the server has a different
+    /// packet identifier than the packet waiting for it
+    UnexpectedPacketId,
+    Other,
 }
 
 impl Display for ClientError {
@@ -27,15 +43,44 @@ impl Error for ClientError {
 
 impl ClientError {
     pub fn new(msg: &str) -> ClientError {
+        ClientError::new_kind(msg, ClientErrorKind::Other)
+    }
+
+    pub fn new_kind(msg: &str, kind: ClientErrorKind) -> ClientError {
         ClientError {
             msg: msg.to_string(),
+            kind,
+            packet_error: None,
+        }
+    }
+
+    /// Returns an error of kind [`ClientErrorKind::MalformedPacket`]
+    /// for a packet from the server that failed to decode
+    pub fn malformed_packet(err: PacketError) -> ClientError {
+        ClientError {
+            msg: format!("Paquete malformado recibido del servidor: {}", err),
+            kind: ClientErrorKind::MalformedPacket,
+            packet_error: Some(err),
         }
     }
+
+    pub fn kind(&self) -> ClientErrorKind {
+        self.kind
+    }
+
+    /// Returns the packet error that caused this error, if any
+    pub fn packet_error(&self) -> Option<&PacketError> {
+        self.packet_error.as_ref()
+    }
 }
 
 impl From<PacketError> for ClientError {
     fn from(err: PacketError) -> ClientError {
-        ClientError::new(&format!("{}", err))
+        ClientError {
+            msg: format!("{}", err),
+            kind: ClientErrorKind::Other,
+            packet_error: Some(err),
+        }
     }
 }
 
@@ -1,5 +1,6 @@
 use std::{
-    io::{self, Read},
+    convert::TryFrom,
+    io::{self, Cursor, Read},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex,
@@ -8,20 +9,21 @@ use std::{
 };
 
 use packets::{
-    connack::Connack,
-    packet_error::{ErrorKind, PacketError},
-    pingresp::PingResp,
-    traits::MQTTDecoding,
-    unsuback::Unsuback,
+    connack::Connack, helpers::PacketType, packet_error::ErrorKind, packet_reader::RemainingLength,
+    pingresp::PingResp, traits::MQTTDecoding, unsuback::Unsuback,
 };
 use packets::{puback::Puback, publish::Publish, suback::Suback};
 use threadpool::ThreadPool;
 
-use crate::{client::PendingAck, observer::Observer};
+use crate::{
+    client::PendingAck,
+    observer::Observer,
+    packet_summary::{Direction, PacketSummary},
+};
 
 use crate::observer::Message;
 
-use super::{ClientError, STOP_TIMEOUT};
+use super::{ClientError, ClientErrorKind, STOP_TIMEOUT};
 
 /// ReadTimeout trait from which the listener reads the packets
 pub(crate) trait ReadTimeout: Read + Send + Sync + 'static {
@@ -32,26 +34,55 @@ pub(crate) trait ReadTimeout: Read + Send + Sync + 'static {
 /// for receiving all packets from the server, and
 /// acknowledging the ones in which it is required.
 pub(crate) struct ClientListener<T: Observer, R: ReadTimeout, A: AckSender> {
-    stream: R,
+    stream: ReplayStream<R>,
     pending_ack: Arc<Mutex<Option<PendingAck>>>,
     observer: T,
     stop: Arc<AtomicBool>,
     ack_sender: Arc<A>,
     threadpool: ThreadPool,
+    trace: Arc<AtomicBool>,
+}
+
+/// Stream from which the listener reads. To trace a packet, its
+/// remaining bytes are read in advance and then replayed to the
+/// decoder, so that the trace is sent before the packet is handled
+struct ReplayStream<R: Read> {
+    inner: R,
+    replay: Cursor<Vec<u8>>,
 }
 
-enum PacketType {
-    Connect,
-    Connack,
-    Publish,
-    Puback,
-    Subscribe,
-    Suback,
-    Unsubscribe,
-    Unsuback,
-    Pingreq,
-    Pingresp,
-    Disconnect,
+impl<R: Read> ReplayStream<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            replay: Cursor::new(Vec::new()),
+        }
+    }
+
+    /// Reads the remaining length and the rest of the packet whose
+    /// control byte is `header`, and returns all of its bytes. They
+    /// are read again from the stream afterwards
+    fn buffer_packet(&mut self, header: u8) -> Result<Vec<u8>, ClientError> {
+        let remaining_length = RemainingLength::from_encoded(&mut self.inner)
+            .map_err(ClientError::malformed_packet)?;
+        let mut buffer = remaining_length.encode();
+        let mut body = vec![0; remaining_length.decode() as usize];
+        self.inner.read_exact(&mut body)?;
+        buffer.append(&mut body);
+        self.replay = Cursor::new(buffer.clone());
+        buffer.insert(0, header);
+        Ok(buffer)
+    }
+}
+
+impl<R: Read> Read for ReplayStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if (self.replay.position() as usize) < self.replay.get_ref().len() {
+            self.replay.read(buf)
+        } else {
+            self.inner.read(buf)
+        }
+    }
 }
 
 /// Under which errors should the listener send
@@ -65,9 +96,18 @@ const CONNECT_USER_ERRORS: [ErrorKind; 3] = [
 
 // Acknowledge sender for the listener. Every time a packet
 // which requires an acknowledgement is received, the listener
-// will it through this sender.
+// will it through this sender. It is also informed when a
+// PingResp arrives, since it keeps track of the PingReqs sent,
+// and when a Connack or a Puback arrives, since it may have
+// operations blocked waiting for them.
 pub(crate) trait AckSender: Sync + Send + 'static {
     fn send_puback(&self, packet: Puback);
+
+    fn connack_received(&self);
+
+    fn puback_received(&self, packet: Puback);
+
+    fn pingresp_received(&self);
 }
 
 impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
@@ -85,15 +125,24 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
         stream.set_read_timeout(Some(STOP_TIMEOUT))?;
 
         Ok(Self {
-            stream,
+            stream: ReplayStream::new(stream),
             pending_ack,
             observer,
             stop,
             ack_sender,
             threadpool,
+            trace: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    /// Sets the flag that enables the packet trace. While it is
+    /// true, a PacketTrace() message is sent to the observer for
+    /// each packet received, before handling it
+    pub fn with_trace(mut self, trace: Arc<AtomicBool>) -> Self {
+        self.trace = trace;
+        self
+    }
+
     /// Starts the listener. It reads the packets from the stream
     /// and writes the acknowledgements. In case of an internal error,
     /// it will send a Message::InternalError() to the observer and
@@ -108,13 +157,18 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
     /// packet of the same identifier as the one it was received, it then
     /// sets pending_ack to None and sends a Message Subscribed(Ok()),
     /// Unsubscribed(Ok()) or Published(Ok(Some())) appropriately with the
-    /// packet to the observer. In the case of the Puback, if a QoSLevel0
+    /// packet to the observer. If a Suback or Unsuback has a different
+    /// identifier than the pending Subscribe() or Unsubscribe(), it is not
+    /// applied: an InternalError() message of kind
+    /// [`ClientErrorKind::UnexpectedPacketId`](super::ClientErrorKind::UnexpectedPacketId)
+    /// is sent to the observer instead, and the packet stays pending.
+    /// In the case of the Puback, if a QoSLevel0
     /// Publish packet without an id was saved in the pending_ack lock,
     /// the listener will stop and send an InternalError() message to the
     /// observer with the error. In any other case, the packet is ignored.
     ///
-    /// PingResp:  If the pending_ack enum lock contains PingReq(),
-    /// it then sets the lock to None. Else the packet is ignored.
+    /// PingResp: The ack sender is informed that it arrived, so that it
+    /// stops waiting for it.
     ///
     /// Connack: If the connack packet wasn't read successfully and
     /// the error is not in CONNECT_USER_ERRORS, then an InternalError()
@@ -136,6 +190,15 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
     ///
     /// Any other packet will cause the listener to send an InternalError() to
     /// the observer and stop listening.
+    ///
+    /// If a packet can't be decoded, the error sent in the InternalError()
+    /// message is of kind [`ClientErrorKind::MalformedPacket`](super::ClientErrorKind::MalformedPacket),
+    /// and contains the original [`PacketError`](packets::packet_error::PacketError).
+    ///
+    /// If the server closes the connection between packets, the listener
+    /// sends a Disconnected(None) message and stops. If the connection is
+    /// lost (for example, reset by the server), it sends a Disconnected()
+    /// message with the error instead.
     pub fn wait_for_packets(&mut self) {
         while !self.stop.load(Ordering::Relaxed) {
             if let Err(err) = self.try_read_packet() {
@@ -151,6 +214,9 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
 
         match self.stream.read_exact(&mut buf) {
             Ok(()) => {
+                if self.trace.load(Ordering::Relaxed) {
+                    self.trace_packet(buf[0])?;
+                }
                 self.handle_packet(buf[0])?;
                 Ok(())
             }
@@ -161,25 +227,52 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
             {
                 Ok(())
             }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                // El servidor cerro la conexion
+                self.disconnected(None);
+                Ok(())
+            }
+            Err(err)
+                if err.kind() == io::ErrorKind::ConnectionReset
+                    || err.kind() == io::ErrorKind::ConnectionAborted
+                    || err.kind() == io::ErrorKind::BrokenPipe =>
+            {
+                self.disconnected(Some(ClientError::from(err)));
+                Ok(())
+            }
             Err(err) => Err(ClientError::from(err)),
         }
     }
 
+    #[doc(hidden)]
+    fn trace_packet(&mut self, header: u8) -> Result<(), ClientError> {
+        let bytes = self.stream.buffer_packet(header)?;
+        let summary = PacketSummary::from_bytes(Direction::Received, &bytes)?;
+        self.observer.update(Message::PacketTrace(summary));
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    fn disconnected(&self, error: Option<ClientError>) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.observer.update(Message::Disconnected(error));
+    }
+
     #[doc(hidden)]
     fn handle_packet(&mut self, header: u8) -> Result<(), ClientError> {
-        match get_code_type(header >> 4) {
+        match PacketType::try_from(header) {
             Ok(packet) => match packet {
                 PacketType::Publish => self.handle_publish(header),
                 PacketType::Puback => self.handle_puback(header),
                 PacketType::Suback => self.handle_suback(header),
                 PacketType::Unsuback => self.handle_unsuback(header),
-                PacketType::Pingresp => self.handle_pingresp(header),
+                PacketType::PingResp => self.handle_pingresp(header),
                 PacketType::Connack => self.handle_connack(header),
                 _ => Err(ClientError::new("Received an unsupported packet type")),
             },
             Err(error) => {
                 self.observer
-                    .update(Message::InternalError(ClientError::from(error)));
+                    .update(Message::InternalError(ClientError::malformed_packet(error)));
                 Ok(())
             }
         }
@@ -187,7 +280,8 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
 
     #[doc(hidden)]
     fn handle_publish(&mut self, header: u8) -> Result<(), ClientError> {
-        let publish = Publish::read_from(&mut self.stream, header)?;
+        let publish =
+            Publish::read_from(&mut self.stream, header).map_err(ClientError::malformed_packet)?;
         let id_opt = publish.packet_id();
         self.observer.update(Message::Publish(publish));
 
@@ -213,17 +307,19 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
         let expected = matches!(lock.as_ref(), Some(PendingAck::Connect(_)));
         match connack {
             Err(err) if !CONNECT_USER_ERRORS.contains(&err.kind()) => {
-                return Err(ClientError::from(err));
+                return Err(ClientError::malformed_packet(err));
             }
             Err(err) if expected => {
                 // Si o si es uno de los CONNECT_USER_ERRORS
                 lock.take();
+                self.ack_sender.connack_received();
                 self.stop.store(true, Ordering::Relaxed);
                 self.observer
                     .update(Message::Connected(Err(ClientError::from(err))));
             }
             Ok(packet) if expected => {
                 lock.take();
+                self.ack_sender.connack_received();
                 self.observer.update(Message::Connected(Ok(packet)));
             }
             _ => (),
@@ -234,7 +330,8 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
 
     #[doc(hidden)]
     fn handle_suback(&mut self, header: u8) -> Result<(), ClientError> {
-        let mut suback = Suback::read_from(&mut self.stream, header)?;
+        let mut suback =
+            Suback::read_from(&mut self.stream, header).map_err(ClientError::malformed_packet)?;
 
         let mut lock = self.pending_ack.lock()?;
 
@@ -243,15 +340,36 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
                 suback.set_topics(subscribe.topics());
                 lock.take();
                 self.observer.update(Message::Subscribed(Ok(suback)));
+            } else {
+                self.unexpected_packet_id(
+                    "Suback",
+                    subscribe.packet_identifier(),
+                    suback.packet_id(),
+                );
             }
         }
 
         Ok(())
     }
 
+    /// Informs the observer that an acknowledgement arrived with
+    /// a different packet identifier than the expected one, which
+    /// means that the client and the server are out of sync
+    fn unexpected_packet_id(&self, packet: &str, expected: u16, received: u16) {
+        self.observer
+            .update(Message::InternalError(ClientError::new_kind(
+                &format!(
+                    "Se recibio un {} con packet id {}, se esperaba {}",
+                    packet, received, expected
+                ),
+                ClientErrorKind::UnexpectedPacketId,
+            )));
+    }
+
     #[doc(hidden)]
     fn handle_unsuback(&mut self, header: u8) -> Result<(), ClientError> {
-        let mut unsuback = Unsuback::read_from(&mut self.stream, header)?;
+        let mut unsuback =
+            Unsuback::read_from(&mut self.stream, header).map_err(ClientError::malformed_packet)?;
         let mut lock = self.pending_ack.lock()?;
 
         if let Some(PendingAck::Unsubscribe(unsubscribe)) = lock.as_ref() {
@@ -259,6 +377,12 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
                 unsuback.set_topics(unsubscribe.topic_filters());
                 lock.take();
                 self.observer.update(Message::Unsubscribed(Ok(unsuback)));
+            } else {
+                self.unexpected_packet_id(
+                    "Unsuback",
+                    unsubscribe.packet_id(),
+                    unsuback.packet_id(),
+                );
             }
         }
 
@@ -267,7 +391,8 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
 
     #[doc(hidden)]
     fn handle_puback(&mut self, header: u8) -> Result<(), ClientError> {
-        let puback = Puback::read_from(&mut self.stream, header)?;
+        let puback =
+            Puback::read_from(&mut self.stream, header).map_err(ClientError::malformed_packet)?;
 
         let mut lock = self.pending_ack.lock()?;
 
@@ -276,6 +401,7 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
                 if expected_id == puback.packet_id() {
                     lock.take();
                     self.observer.update(Message::Published(Ok(Some(puback))));
+                    return Ok(());
                 }
             } else {
                 return Err(ClientError::new(
@@ -283,45 +409,23 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
                 ));
             }
         }
+        drop(lock);
+        // Puede ser de un publish bloqueado en publish_and_wait()
+        self.ack_sender.puback_received(puback);
 
         Ok(())
     }
 
     #[doc(hidden)]
     fn handle_pingresp(&mut self, header: u8) -> Result<(), ClientError> {
-        let _ = PingResp::read_from(&mut self.stream, header)?;
-
-        let mut lock = self.pending_ack.lock()?;
-
-        if let Some(PendingAck::PingReq(_)) = lock.as_ref() {
-            lock.take();
-        }
+        let _ =
+            PingResp::read_from(&mut self.stream, header).map_err(ClientError::malformed_packet)?;
+        self.ack_sender.pingresp_received();
 
         Ok(())
     }
 }
 
-#[doc(hidden)]
-fn get_code_type(code: u8) -> Result<PacketType, PacketError> {
-    match code {
-        1 => Ok(PacketType::Connect),
-        2 => Ok(PacketType::Connack),
-        3 => Ok(PacketType::Publish),
-        4 => Ok(PacketType::Puback),
-        8 => Ok(PacketType::Subscribe),
-        9 => Ok(PacketType::Suback),
-        10 => Ok(PacketType::Unsubscribe),
-        11 => Ok(PacketType::Unsuback),
-        12 => Ok(PacketType::Pingreq),
-        13 => Ok(PacketType::Pingresp),
-        14 => Ok(PacketType::Disconnect),
-        _ => Err(PacketError::new_kind(
-            &format!("Received invalid packet type: {}", code),
-            ErrorKind::InvalidControlPacketType,
-        )),
-    }
-}
-
 #[cfg(test)]
 mod tests {
 
@@ -331,10 +435,10 @@ mod tests {
     use std::thread;
     use std::time::Duration;
 
-    use crate::client::PendingAck;
+    use crate::client::{ClientErrorKind, PendingAck};
     use crate::observer::Message;
+    use crate::packet_summary::Direction;
     use packets::connect::ConnectBuilder;
-    use packets::pingreq::PingReq;
     use packets::puback::Puback;
     use packets::publish::Publish;
     use packets::qos::QoSLevel;
@@ -374,22 +478,40 @@ mod tests {
 
     struct SenderMock {
         pub times_called: Mutex<u8>,
+        pub pingresps: Mutex<u8>,
     }
 
     impl AckSender for SenderMock {
         fn send_puback(&self, _: Puback) {
             *self.times_called.lock().unwrap() += 1;
         }
+
+        fn connack_received(&self) {}
+
+        fn puback_received(&self, _: Puback) {}
+
+        fn pingresp_received(&self) {
+            *self.pingresps.lock().unwrap() += 1;
+        }
     }
 
     impl SenderMock {
         fn new() -> Arc<Self> {
             Arc::new(Self {
                 times_called: Mutex::new(0),
+                pingresps: Mutex::new(0),
             })
         }
     }
 
+    // Un paquete pendiente que no es confirmado por el que se recibe
+    fn pending_subscribe() -> PendingAck {
+        PendingAck::Subscribe(Subscribe::new(
+            vec![TopicFilter::new("topic", QoSLevel::QoSLevel0).unwrap()],
+            999,
+        ))
+    }
+
     #[test]
     fn test_unsuback() {
         let observer = ObserverMock::new();
@@ -450,12 +572,17 @@ mod tests {
             .iter()
             .position(|packet| matches!(packet, Message::Unsubscribed(Ok(_))));
         assert!(i.is_none());
+        // El error se informa sin aplicar el paquete
+        assert!(msgs.iter().any(|message| matches!(
+            message,
+            Message::InternalError(err) if err.kind() == ClientErrorKind::UnexpectedPacketId
+        )));
     }
 
     #[test]
     fn test_unsuback_unexpected() {
         let observer = ObserverMock::new();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::PingReq(PingReq::new()))));
+        let pending_ack = Arc::new(Mutex::new(Some(pending_subscribe())));
         let stop = Arc::new(AtomicBool::new(false));
         let stream = Cursor::new(vec![0b10110000, 2, 0, 123]);
         let mut listener = ClientListener::new(
@@ -471,7 +598,7 @@ mod tests {
 
         assert!(matches!(
             *pending_ack.lock().unwrap(),
-            Some(PendingAck::PingReq(_))
+            Some(PendingAck::Subscribe(_))
         ));
         let msgs = observer.messages.lock().unwrap();
         let i = msgs
@@ -536,12 +663,17 @@ mod tests {
             .iter()
             .position(|packet| matches!(packet, Message::Subscribed(Ok(_))));
         assert!(i.is_none());
+        // El error se informa sin aplicar el paquete
+        assert!(msgs.iter().any(|message| matches!(
+            message,
+            Message::InternalError(err) if err.kind() == ClientErrorKind::UnexpectedPacketId
+        )));
     }
 
     #[test]
     fn test_suback_unexpected() {
         let observer = ObserverMock::new();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::PingReq(PingReq::new()))));
+        let pending_ack = Arc::new(Mutex::new(Some(pending_subscribe())));
         let stop = Arc::new(AtomicBool::new(false));
         let stream = Cursor::new(vec![0b10010000, 3, 0, 123, 0]);
         let mut listener = ClientListener::new(
@@ -557,7 +689,7 @@ mod tests {
 
         assert!(matches!(
             *pending_ack.lock().unwrap(),
-            Some(PendingAck::PingReq(_))
+            Some(PendingAck::Subscribe(_))
         ));
         let msgs = observer.messages.lock().unwrap();
         let i = msgs
@@ -623,7 +755,7 @@ mod tests {
     #[test]
     fn test_puback_unexpected() {
         let observer = ObserverMock::new();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::PingReq(PingReq::new()))));
+        let pending_ack = Arc::new(Mutex::new(Some(pending_subscribe())));
         let stop = Arc::new(AtomicBool::new(false));
         let stream = Cursor::new(Puback::new(123).unwrap().encode().unwrap());
         let mut listener = ClientListener::new(
@@ -639,7 +771,7 @@ mod tests {
 
         assert!(matches!(
             *pending_ack.lock().unwrap(),
-            Some(PendingAck::PingReq(_))
+            Some(PendingAck::Subscribe(_))
         ));
         let msgs = observer.messages.lock().unwrap();
         let i = msgs
@@ -679,20 +811,22 @@ mod tests {
     #[test]
     fn test_pingresp() {
         let observer = ObserverMock::new();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::PingReq(PingReq::new()))));
+        let pending_ack = Arc::new(Mutex::new(None));
         let stop = Arc::new(AtomicBool::new(false));
         let stream = Cursor::new(vec![0b11010000, 0b00000000]);
+        let sender = SenderMock::new();
         let mut listener = ClientListener::new(
             stream,
             pending_ack.clone(),
             observer,
             stop,
-            SenderMock::new(),
+            sender.clone(),
             ThreadPool::new(1),
         )
         .unwrap();
         listener.wait_for_packets();
 
+        assert_eq!(*sender.pingresps.lock().unwrap(), 1);
         assert!(pending_ack.lock().unwrap().is_none());
     }
 
@@ -728,7 +862,7 @@ mod tests {
     #[test]
     fn test_publish_qos0() {
         let observer = ObserverMock::new();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::PingReq(PingReq::new()))));
+        let pending_ack = Arc::new(Mutex::new(Some(pending_subscribe())));
         let stop = Arc::new(AtomicBool::new(false));
         let publish = Publish::new(false, QoSLevel0, false, "topic", "msg", None).unwrap();
         let stream = Cursor::new(publish.encode().unwrap());
@@ -746,7 +880,7 @@ mod tests {
 
         assert!(matches!(
             *pending_ack.lock().unwrap(),
-            Some(PendingAck::PingReq(_))
+            Some(PendingAck::Subscribe(_))
         ));
         let mut msgs = observer.messages.lock().unwrap();
         assert!(matches!(msgs[0], Message::Publish(_)));
@@ -758,10 +892,43 @@ mod tests {
         assert_eq!(*sender.times_called.lock().unwrap(), 0);
     }
 
+    #[test]
+    fn test_traced_publish() {
+        let observer = ObserverMock::new();
+        let pending_ack = Arc::new(Mutex::new(Some(pending_subscribe())));
+        let stop = Arc::new(AtomicBool::new(false));
+        let publish = Publish::new(false, QoSLevel1, false, "topic", "msg", Some(123)).unwrap();
+        let stream = Cursor::new(publish.encode().unwrap());
+        let sender = SenderMock::new();
+        let mut listener = ClientListener::new(
+            stream,
+            pending_ack,
+            observer.clone(),
+            stop,
+            sender,
+            ThreadPool::new(1),
+        )
+        .unwrap()
+        .with_trace(Arc::new(AtomicBool::new(true)));
+        listener.wait_for_packets();
+
+        let msgs = observer.messages.lock().unwrap();
+        // Primero se informa el paquete y despues se lo procesa normalmente
+        match &msgs[0] {
+            Message::PacketTrace(summary) => {
+                assert_eq!(summary.direction, Direction::Received);
+                assert_eq!(summary.packet_id, Some(123));
+                assert_eq!(summary.payload_size, Some(3));
+            }
+            other => panic!("Se esperaba un PacketTrace, llego {:?}", other),
+        }
+        assert!(matches!(&msgs[1], Message::Publish(received) if *received == publish));
+    }
+
     #[test]
     fn test_publish_qos1() {
         let observer = ObserverMock::new();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::PingReq(PingReq::new()))));
+        let pending_ack = Arc::new(Mutex::new(Some(pending_subscribe())));
         let stop = Arc::new(AtomicBool::new(false));
         let publish = Publish::new(false, QoSLevel1, false, "topic", "msg", Some(123)).unwrap();
         let stream = Cursor::new(publish.encode().unwrap());
@@ -779,7 +946,7 @@ mod tests {
 
         assert!(matches!(
             *pending_ack.lock().unwrap(),
-            Some(PendingAck::PingReq(_))
+            Some(PendingAck::Subscribe(_))
         ));
         let mut msgs = observer.messages.lock().unwrap();
         assert!(matches!(msgs[0], Message::Publish(_)));
@@ -822,7 +989,7 @@ mod tests {
     #[test]
     fn test_connack_unexpected() {
         let observer = ObserverMock::new();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::PingReq(PingReq::new()))));
+        let pending_ack = Arc::new(Mutex::new(Some(pending_subscribe())));
         let stop = Arc::new(AtomicBool::new(false));
         let stream = Cursor::new(vec![32, 2, 1, 0]);
         let mut listener = ClientListener::new(
@@ -838,7 +1005,7 @@ mod tests {
 
         assert!(matches!(
             *pending_ack.lock().unwrap(),
-            Some(PendingAck::PingReq(_))
+            Some(PendingAck::Subscribe(_))
         ));
         let msgs = observer.messages.lock().unwrap();
         let i = msgs
@@ -904,6 +1071,61 @@ mod tests {
         assert!(matches!(msgs[0], Message::InternalError(_)));
     }
 
+    #[test]
+    fn test_truncated_connack_is_malformed_packet() {
+        let observer = ObserverMock::new();
+        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::Connect(
+            ConnectBuilder::new("123", 0, true)
+                .unwrap()
+                .build()
+                .unwrap(),
+        ))));
+        let stop = Arc::new(AtomicBool::new(false));
+        let stream = Cursor::new(vec![32, 2, 1]); // falta el return code
+        let mut listener = ClientListener::new(
+            stream,
+            pending_ack,
+            observer.clone(),
+            stop,
+            SenderMock::new(),
+            ThreadPool::new(1),
+        )
+        .unwrap();
+        listener.wait_for_packets();
+
+        let msgs = observer.messages.lock().unwrap();
+        match &msgs[0] {
+            Message::InternalError(err) => {
+                assert_eq!(err.kind(), ClientErrorKind::MalformedPacket);
+                assert!(err.packet_error().is_some());
+            }
+            other => panic!("Se esperaba InternalError, se recibio {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_end_of_stream_is_clean_disconnection() {
+        let observer = ObserverMock::new();
+        let pending_ack = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let stream = Cursor::new(vec![0b11010000, 0]); // pingresp
+        let mut listener = ClientListener::new(
+            stream,
+            pending_ack,
+            observer.clone(),
+            stop.clone(),
+            SenderMock::new(),
+            ThreadPool::new(1),
+        )
+        .unwrap();
+        listener.wait_for_packets();
+
+        assert!(stop.load(std::sync::atomic::Ordering::Relaxed));
+        let msgs = observer.messages.lock().unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert!(matches!(msgs[0], Message::Disconnected(None)));
+    }
+
     #[test]
     fn test_invalid_packet() {
         let observer = ObserverMock::new();
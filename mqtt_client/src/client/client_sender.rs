@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::io::Write;
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
 use std::time::Duration;
 use std::{thread, time};
 
@@ -14,9 +16,10 @@ use packets::traits::MQTTEncoding;
 use packets::unsubscribe::Unsubscribe;
 
 use crate::observer::{Message, Observer};
+use crate::packet_summary::{Direction, PacketSummary};
 use packets::publish::Publish;
 
-use super::{ClientError, PendingAck};
+use super::{client_error::ClientErrorKind, ClientError, PendingAck};
 use crate::client::client_listener::AckSender;
 
 /// How much time should the sender wait until it tries
@@ -38,6 +41,18 @@ pub(crate) struct ClientSender<T: Observer, W: Write> {
     stream: Mutex<W>,
     pending_ack: Arc<Mutex<Option<PendingAck>>>,
     observer: Arc<T>,
+    /// When the last packet was sent to the server
+    last_sent: Mutex<time::Instant>,
+    /// When the PINGREQ waiting for its PINGRESP was sent, if any
+    pingreq_sent: Mutex<Option<time::Instant>>,
+    /// Whether the packets sent are traced to the observer
+    trace: Arc<AtomicBool>,
+    /// Whether the CONNACK already arrived, notified through connack_cond
+    connack_received: Mutex<bool>,
+    connack_cond: Condvar,
+    /// The PUBACK of each publish blocked in `publish_and_wait()` is
+    /// sent through the channel of its packet identifier
+    awaited_pubacks: Mutex<HashMap<u16, mpsc::Sender<Puback>>>,
 }
 
 impl<T: Observer, W: Write + Send + 'static> AckSender for ClientSender<T, W> {
@@ -46,6 +61,35 @@ impl<T: Observer, W: Write + Send + 'static> AckSender for ClientSender<T, W> {
             self.observer.update(Message::InternalError(e));
         }
     }
+
+    fn connack_received(&self) {
+        if let Ok(mut connack_received) = self.connack_received.lock() {
+            *connack_received = true;
+            self.connack_cond.notify_all();
+        }
+    }
+
+    fn puback_received(&self, puback: Puback) {
+        let waiter = match self.awaited_pubacks.lock() {
+            Ok(mut awaited_pubacks) => awaited_pubacks.remove(&puback.packet_id()),
+            Err(_) => None,
+        };
+        if let Some(waiter) = waiter {
+            // Si ya se dejo de esperar, no hay a quien avisarle
+            let _ = waiter.send(puback);
+        }
+    }
+
+    fn pingresp_received(&self) {
+        let sent = match self.pingreq_sent.lock() {
+            Ok(mut pingreq_sent) => pingreq_sent.take(),
+            Err(_) => None,
+        };
+        if let Some(sent) = sent {
+            let latency = round_trip(sent, time::Instant::now());
+            self.observer.update(Message::Latency(latency));
+        }
+    }
 }
 
 impl<T: Observer, W: Write> ClientSender<T, W> {
@@ -56,9 +100,21 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
             stream: Mutex::new(stream),
             pending_ack: Arc::new(Mutex::new(None)),
             observer: Arc::new(observer),
+            last_sent: Mutex::new(time::Instant::now()),
+            pingreq_sent: Mutex::new(None),
+            trace: Arc::new(AtomicBool::new(false)),
+            connack_received: Mutex::new(false),
+            connack_cond: Condvar::new(),
+            awaited_pubacks: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Gets the flag that enables the packet trace. It is shared
+    /// with the listener, so that both directions are traced
+    pub fn trace(&self) -> Arc<AtomicBool> {
+        self.trace.clone()
+    }
+
     /// Gets the pending_ack lock of the sender. This is used
     /// by the sender after sending a packet to check if it was
     /// acknowledged. If it was, it expects the lock to be
@@ -67,9 +123,35 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
         self.pending_ack.clone()
     }
 
+    #[doc(hidden)]
+    // Escribe un paquete en el stream, registrando cuando se envio
+    fn write_packet(&self, stream: &mut W, bytes: &[u8]) -> Result<(), ClientError> {
+        stream.write_all(bytes)?;
+        *self.last_sent.lock()? = time::Instant::now();
+        if self.trace.load(Ordering::Relaxed) {
+            let summary = PacketSummary::from_bytes(Direction::Sent, bytes)?;
+            self.observer.update(Message::PacketTrace(summary));
+        }
+        Ok(())
+    }
+
+    /// Returns how long ago the last packet was sent to the server
+    pub fn idle_time(&self) -> Result<Duration, ClientError> {
+        Ok(self.last_sent.lock()?.elapsed())
+    }
+
+    /// Returns true if a PINGREQ was sent more than `timeout` ago
+    /// and its PINGRESP has not arrived yet
+    pub fn pingresp_overdue(&self, timeout: Duration) -> Result<bool, ClientError> {
+        Ok(self
+            .pingreq_sent
+            .lock()?
+            .is_some_and(|sent| sent.elapsed() > timeout))
+    }
+
     #[doc(hidden)]
     fn _puback(&self, puback: Puback) -> Result<(), ClientError> {
-        self.stream.lock()?.write_all(&puback.encode()?)?;
+        self.write_packet(&mut *self.stream.lock()?, &puback.encode()?)?;
         Ok(())
     }
 
@@ -81,7 +163,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
             .lock()?
             .replace(PendingAck::Connect(connect));
 
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
 
         if !self.wait_for_ack(&mut lock, &bytes)? {
             return Err(ClientError::new("No se pudo establecer la conexión"));
@@ -113,7 +195,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
             .lock()?
             .replace(PendingAck::Subscribe(subscribe));
 
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
 
         if !self.wait_for_ack(&mut lock, &bytes)? {
             return Err(ClientError::new("No se recibió paquete suback"));
@@ -144,7 +226,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
             *self.pending_ack.lock()? = Some(PendingAck::Publish(publish.clone()));
         }
 
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
 
         publish.set_dup(true);
         let resend_bytes = publish.encode()?;
@@ -182,31 +264,108 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
         }
     }
 
+    /// Sends a PUBLISH packet to the server and blocks until it is
+    /// acknowledged. It waits for the CONNACK before sending it.
+    ///
+    /// If the packet has QoSLevel 1, it waits until the listener receives
+    /// the PUBACK with its packet identifier. It does not use pending_ack,
+    /// so other packets can be sent and acknowledged in the meantime. If
+    /// `timeout` elapses first, an error of kind [`ClientErrorKind::Timeout`]
+    /// is returned. The packet is not resent while waiting.
+    ///
+    /// If the packet has QoSLevel 0, it returns as soon as the packet
+    /// is written and flushed.
+    ///
+    /// Unlike `send_publish()`, the result is returned to the caller
+    /// instead of being sent to the observer.
+    pub fn publish_and_wait(&self, publish: Publish, timeout: Duration) -> Result<(), ClientError> {
+        let deadline = time::Instant::now() + timeout;
+        self.wait_for_connack(deadline)?;
+        let bytes = publish.encode()?;
+
+        let packet_id = match publish.packet_id() {
+            Some(packet_id) if publish.qos() == QoSLevel::QoSLevel1 => packet_id,
+            _ => return self.write_and_flush(&bytes),
+        };
+        let puback = self.await_puback(packet_id)?;
+
+        let result = self.write_and_flush(&bytes).and_then(|_| {
+            let remaining = deadline.saturating_duration_since(time::Instant::now());
+            puback.recv_timeout(remaining).map_err(|_| {
+                ClientError::new_kind(
+                    "No se recibió paquete puback (QoS 1) a tiempo",
+                    ClientErrorKind::Timeout,
+                )
+            })
+        });
+        self.awaited_pubacks.lock()?.remove(&packet_id);
+        result.map(drop)
+    }
+
     #[doc(hidden)]
-    fn _pingreq(&self, pingreq: PingReq) -> Result<(), ClientError> {
+    // Registra que se espera el puback del paquete, devolviendo
+    // por donde se va a recibir
+    fn await_puback(&self, packet_id: u16) -> Result<Receiver<Puback>, ClientError> {
+        let mut awaited_pubacks = self.awaited_pubacks.lock()?;
+        if awaited_pubacks.contains_key(&packet_id) {
+            return Err(ClientError::new(&format!(
+                "Ya se espera el puback del packet id {}",
+                packet_id
+            )));
+        }
+        let (sender, receiver) = mpsc::channel();
+        awaited_pubacks.insert(packet_id, sender);
+        Ok(receiver)
+    }
+
+    #[doc(hidden)]
+    fn write_and_flush(&self, bytes: &[u8]) -> Result<(), ClientError> {
         let mut lock = self.stream.lock()?;
-        let bytes = pingreq.encode()?;
-        self.pending_ack
-            .lock()?
-            .replace(PendingAck::PingReq(pingreq));
+        self.write_packet(&mut lock, bytes)?;
+        lock.flush()?;
+        Ok(())
+    }
 
-        lock.write_all(&bytes)?;
-        if !self.wait_for_ack(&mut lock, &bytes)? {
-            return Err(ClientError::new(
-                "El servidor no respondió al pingreq, ¿esta en línea?",
-            ));
+    #[doc(hidden)]
+    // Espera a que el servidor responda el CONNECT, para no
+    // enviar nada antes de establecer la conexión
+    fn wait_for_connack(&self, deadline: time::Instant) -> Result<(), ClientError> {
+        let mut connack_received = self.connack_received.lock()?;
+        while !*connack_received {
+            let now = time::Instant::now();
+            if now >= deadline {
+                return Err(ClientError::new_kind(
+                    "No se recibió paquete connack a tiempo",
+                    ClientErrorKind::Timeout,
+                ));
+            }
+            connack_received = self
+                .connack_cond
+                .wait_timeout(connack_received, deadline - now)
+                .map_err(|err| ClientError::new(&format!("Error usando lock: {}", err)))?
+                .0;
         }
+        Ok(())
+    }
 
+    #[doc(hidden)]
+    fn _pingreq(&self, pingreq: PingReq) -> Result<(), ClientError> {
+        let mut lock = self.stream.lock()?;
+        let mut pingreq_sent = self.pingreq_sent.lock()?;
+        if pingreq_sent.is_some() {
+            // Todavia se espera la respuesta del anterior
+            return Ok(());
+        }
+        self.write_packet(&mut lock, &pingreq.encode()?)?;
+        pingreq_sent.replace(time::Instant::now());
         Ok(())
     }
 
-    /// Sends a PINGREQ packet to the server.
-    /// After sending the packet, it will set pending_ack to PendingAck::PingReq()
-    /// and will wait until it is None. Every time a RESEND_TIMEOUT duration passes,
-    /// it will resend the package up to a maximum of MAX_RETRIES times, after which
-    /// it fails.
+    /// Sends a PINGREQ packet to the server, unless a previous one
+    /// is still waiting for its PINGRESP. It does not wait for the
+    /// PINGRESP: whether it arrived on time can be checked with
+    /// `pingresp_overdue()`.
     /// If it fails, it sends a Message::InternalError() with the error to the observer
-    /// and pending_ack is set to None.
     pub fn send_pingreq(&self) {
         let pingreq = PingReq::new();
         if let Err(err) = self._pingreq(pingreq) {
@@ -216,7 +375,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
 
     #[doc(hidden)]
     fn _disconnect(&self, disconnect: Disconnect) -> Result<(), ClientError> {
-        self.stream.lock()?.write_all(&disconnect.encode()?)?;
+        self.write_packet(&mut *self.stream.lock()?, &disconnect.encode()?)?;
         Ok(())
     }
 
@@ -236,7 +395,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
         self.pending_ack
             .lock()?
             .replace(PendingAck::Unsubscribe(unsubscribe));
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
 
         if !self.wait_for_ack(&mut lock, &bytes)? {
             return Err(ClientError::new("No se recibió paquete unsuback"));
@@ -277,7 +436,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
                 Some(_) => {
                     let now = time::Instant::now();
                     if last + RESEND_TIMEOUT < now {
-                        unlocked_stream.write_all(resend_bytes)?;
+                        self.write_packet(unlocked_stream, resend_bytes)?;
                         last = time::Instant::now();
                         retries += 1;
                     }
@@ -290,6 +449,18 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
         Ok(false)
     }
 
+    /// Informs the Observer that the connection was lost,
+    /// with a Disconnected message with the given error
+    pub fn send_disconnected(&self, error: ClientError) {
+        self.observer.update(Message::Disconnected(Some(error)));
+    }
+
+    /// Informs the Observer that a PINGRESP is taking
+    /// too long to arrive
+    pub fn send_stalled(&self) {
+        self.observer.update(Message::Stalled);
+    }
+
     /// Sends the specified error to the Observer
     /// as an InternalError message
     pub fn send_error(&self, error: ClientError) {
@@ -297,17 +468,26 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
     }
 }
 
+/// Returns the round-trip time of a packet sent at `sent` whose
+/// response arrived at `received`
+pub(crate) fn round_trip(sent: time::Instant, received: time::Instant) -> Duration {
+    received.saturating_duration_since(sent)
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
         io::{Cursor as IoCursor, Write},
         sync::{atomic::AtomicBool, Arc, Mutex},
         thread,
-        time::Instant,
+        time::{Duration, Instant},
     };
 
     use crate::{
-        client::{client_listener::AckSender, client_sender::MAX_RETRIES, ClientError, PendingAck},
+        client::{
+            client_listener::AckSender, client_sender::MAX_RETRIES, ClientError, ClientErrorKind,
+            PendingAck,
+        },
         observer::Message,
     };
     use packets::{
@@ -316,7 +496,7 @@ mod tests {
     };
     use packets::{puback::Puback, publish::Publish, topic_filter::TopicFilter};
 
-    use super::ClientSender;
+    use super::{round_trip, ClientSender};
 
     #[derive(Clone)]
     struct ObserverMock {
@@ -589,60 +769,85 @@ mod tests {
         let stream = Cursor::new();
         let observer = ObserverMock::new();
 
-        let client_sender = Arc::new(ClientSender::new(stream.clone(), observer.clone()));
+        let client_sender = ClientSender::new(stream.clone(), observer.clone());
         let pending = client_sender.pending_ack();
 
-        let client_sender_clone = client_sender.clone();
-        let handle = thread::spawn(move || {
-            client_sender_clone.send_pingreq();
-        });
-
-        assert!(matches!(
-            take_ack(&client_sender),
-            Some(PendingAck::PingReq(_))
-        ));
-        // Debería haber puesto en el pending_ack un PendingAck::PingReq()
-
-        handle.join().unwrap();
+        client_sender.send_pingreq();
+        assert_eq!(stream.content(), PingReq::new().encode().unwrap());
+        // Debería haber escrito el pingreq en el stream, sin esperar el pingresp
 
         assert!(pending.lock().unwrap().is_none());
-        // Una vez terminó y ya habiendo otro thread sacado el PendingAck, debería
-        // haber quedado en None
+        // El pingreq no ocupa el pending_ack
 
+        client_sender.send_pingreq();
         assert_eq!(stream.content(), PingReq::new().encode().unwrap());
-        // Debería haber escrito el pingreq en el stream
+        // Mientras no llegue el pingresp, no se manda otro
 
-        assert!(observer.messages.lock().unwrap().is_empty());
-        // No le debería haber mandado nada al observer
+        client_sender.pingresp_received();
+        client_sender.send_pingreq();
+        assert_eq!(stream.content(), PingReq::new().encode().unwrap().repeat(2));
+
+        let messages = observer.messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0], Message::Latency(_)));
+        // Solo le debería haber informado la latencia del primer pingreq
     }
 
     #[test]
-    fn test_pingreq_fail() {
-        let stream = Cursor::new();
+    fn test_round_trip() {
+        let sent = Instant::now();
+        let received = sent + Duration::from_millis(42);
+        assert_eq!(round_trip(sent, received), Duration::from_millis(42));
+        assert_eq!(round_trip(sent, sent), Duration::ZERO);
+        // Si los instantes estan invertidos no es negativa
+        assert_eq!(round_trip(received, sent), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_pingresp_sends_latency() {
         let observer = ObserverMock::new();
+        let client_sender = ClientSender::new(Cursor::new(), observer.clone());
 
-        let client_sender = ClientSender::new(stream.clone(), observer.clone());
-        let pending = client_sender.pending_ack();
+        client_sender.pingresp_received();
+        assert!(observer.messages.lock().unwrap().is_empty());
+        // Un pingresp sin pingreq no tiene latencia
 
         client_sender.send_pingreq();
+        thread::sleep(Duration::from_millis(50));
+        client_sender.pingresp_received();
+        let messages = observer.messages.lock().unwrap();
+        assert!(
+            matches!(messages[..], [Message::Latency(latency)] if latency >= Duration::from_millis(50))
+        );
+    }
 
-        assert!(pending.lock().unwrap().is_none());
-        // Al fallar, tiene que dejar vacío el pending_ack
+    #[test]
+    fn test_pingresp_overdue() {
+        let stream = Cursor::new();
+        let client_sender = ClientSender::new(stream, ObserverMock::new());
+        let timeout = std::time::Duration::from_millis(100);
 
-        assert_eq!(
-            stream.content(),
-            PingReq::new()
-                .encode()
-                .unwrap()
-                .repeat(1 + MAX_RETRIES as usize)
-        );
-        // Debería haber escrito el unsubscribe en el stream, con el intento inicial + MAX_RETRIES veces
+        assert!(!client_sender.pingresp_overdue(timeout).unwrap());
+        client_sender.send_pingreq();
+        assert!(!client_sender.pingresp_overdue(timeout).unwrap());
+        thread::sleep(timeout * 2);
+        assert!(client_sender.pingresp_overdue(timeout).unwrap());
+        // No llego el pingresp a tiempo
 
-        assert!(matches!(
-            observer.messages.lock().unwrap()[0],
-            Message::InternalError(_)
-        ));
-        // Debería haber mandado el error al observer
+        client_sender.pingresp_received();
+        assert!(!client_sender.pingresp_overdue(timeout).unwrap());
+    }
+
+    #[test]
+    fn test_idle_time_is_reset_by_any_packet() {
+        let stream = Cursor::new();
+        let client_sender = ClientSender::new(stream, ObserverMock::new());
+        let idle = std::time::Duration::from_millis(100);
+
+        thread::sleep(idle);
+        assert!(client_sender.idle_time().unwrap() >= idle);
+        client_sender.send_disconnect();
+        assert!(client_sender.idle_time().unwrap() < idle);
     }
 
     #[test]
@@ -850,6 +1055,58 @@ mod tests {
         assert_eq!(stream.content(), bytes);
     }
 
+    #[test]
+    fn test_publish_and_wait_does_not_use_pending_ack() {
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel1, false, "topic", "msg", Some(7)).unwrap();
+        let bytes = publish.encode().unwrap();
+
+        let stream = Cursor::new();
+        let client_sender = Arc::new(ClientSender::new(stream.clone(), ObserverMock::new()));
+        let subscribe = Subscribe::new(
+            vec![TopicFilter::new("topic", QoSLevel::QoSLevel0).unwrap()],
+            8,
+        );
+        client_sender
+            .pending_ack()
+            .lock()
+            .unwrap()
+            .replace(PendingAck::Subscribe(subscribe));
+        client_sender.connack_received();
+
+        let client_sender_clone = client_sender.clone();
+        let handle = thread::spawn(move || {
+            client_sender_clone.publish_and_wait(publish, Duration::from_secs(5))
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        // El subscribe sigue esperando su suback
+        assert!(matches!(
+            *client_sender.pending_ack().lock().unwrap(),
+            Some(PendingAck::Subscribe(_))
+        ));
+        client_sender.puback_received(Puback::new(7).unwrap());
+
+        assert!(handle.join().unwrap().is_ok());
+        assert_eq!(stream.content(), bytes);
+    }
+
+    #[test]
+    fn test_publish_and_wait_timeout() {
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel1, false, "topic", "msg", Some(7)).unwrap();
+
+        let client_sender = ClientSender::new(Cursor::new(), ObserverMock::new());
+        client_sender.connack_received();
+
+        let err = client_sender
+            .publish_and_wait(publish, Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(err.kind(), ClientErrorKind::Timeout);
+        // Un puback que llega tarde se ignora
+        client_sender.puback_received(Puback::new(7).unwrap());
+    }
+
     #[test]
     fn test_send_error() {
         let error = ClientError::new("error de prueba");
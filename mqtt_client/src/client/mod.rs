@@ -1,4 +1,4 @@
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::Arc;
 use std::{io, thread};
 use std::{net::TcpStream, time::Duration};
@@ -10,12 +10,13 @@ mod client_sender;
 use client_listener::ClientListener;
 use client_sender::ClientSender;
 use packets::connect::Connect;
-use packets::pingreq::PingReq;
-use packets::subscribe::Subscribe;
+use packets::qos::QoSLevel;
+use packets::subscribe::{Subscribe, SubscribeBuilder};
+use packets::topic_filter::TopicFilter;
 use packets::unsubscribe::Unsubscribe;
 
 use crate::observer::Observer;
-pub use client_error::ClientError;
+pub use client_error::{ClientError, ClientErrorKind};
 use packets::publish::Publish;
 use threadpool::ThreadPool;
 
@@ -27,7 +28,6 @@ use self::client_listener::ReadTimeout;
 pub(crate) enum PendingAck {
     Subscribe(Subscribe),
     Unsubscribe(Unsubscribe),
-    PingReq(PingReq),
     Publish(Publish),
     Connect(Connect),
 }
@@ -38,6 +38,8 @@ pub struct Client<T: Observer> {
     thread_pool: ThreadPool,
     stop: Arc<AtomicBool>,
     sender: Arc<ClientSender<T, TcpStream>>,
+    publish_timeout: Duration,
+    next_packet_id: AtomicU16,
 }
 
 impl ReadTimeout for TcpStream {
@@ -49,17 +51,48 @@ impl ReadTimeout for TcpStream {
 /// How often should the listener and ping sender check to see if they should stop
 pub(crate) const STOP_TIMEOUT: Duration = Duration::from_millis(200);
 
-/// How much to reduce from the given Keep Alive time in orden to have an error margin
-pub(crate) const KEEP_ALIVE_SUBTRACTION: Duration = Duration::from_secs(2);
+/// How long does `publish_and_wait()` wait for the PUBACK
+/// of a QoS 1 packet by default
+pub(crate) const DEFAULT_PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
 
 impl<T: Observer> Client<T> {
     /// Creates a new Client which connects to the TCP Listener on the given address, by
     /// sending the given CONNECT packet.
     /// The client must be initialized with an Observer to receive the different
     /// Messages the client sends after relevant events (defined in the trait Observer).
-    /// If the connect packet has a Keep Alive set, it will automatically send and receive
-    /// the PingReq and PingResp packets
+    /// If the connect packet has a Keep Alive set, it will automatically send a PingReq
+    /// every half of the Keep Alive without any other packet sent. If its PingResp does not
+    /// arrive within another half of the Keep Alive, the connection is considered lost and
+    /// a Disconnected() message with a Timeout error is sent to the Observer
     pub fn new(address: &str, observer: T, connect: Connect) -> Result<Client<T>, ClientError> {
+        Self::start(address, observer, connect, false)
+    }
+
+    /// Creates a new Client like `new()`, but with the packet trace
+    /// enabled from the start, so that the CONNECT and CONNACK packets
+    /// are traced too (see `set_packet_trace()`)
+    pub fn with_packet_trace(
+        address: &str,
+        observer: T,
+        connect: Connect,
+    ) -> Result<Client<T>, ClientError> {
+        Self::start(address, observer, connect, true)
+    }
+
+    /// Enables or disables the packet trace. While it is enabled, a
+    /// PacketTrace() message with the summary of every packet sent or
+    /// received is sent to the Observer
+    pub fn set_packet_trace(&self, enabled: bool) {
+        self.sender.trace().store(enabled, Ordering::Relaxed);
+    }
+
+    #[doc(hidden)]
+    fn start(
+        address: &str,
+        observer: T,
+        connect: Connect,
+        trace: bool,
+    ) -> Result<Client<T>, ClientError> {
         let stream = TcpStream::connect(address)?;
         let mut threads = 3;
         let keep_alive = connect.keep_alive();
@@ -71,7 +104,10 @@ impl<T: Observer> Client<T> {
             thread_pool: ThreadPool::new(threads),
             stop: Arc::new(AtomicBool::new(false)),
             sender: Arc::new(ClientSender::new(stream.try_clone()?, observer.clone())),
+            publish_timeout: DEFAULT_PUBLISH_TIMEOUT,
+            next_packet_id: AtomicU16::new(1),
         };
+        ret.set_packet_trace(trace);
 
         ret.connect(connect, stream, observer)?;
 
@@ -106,6 +142,69 @@ impl<T: Observer> Client<T> {
         Ok(())
     }
 
+    /// Subscribes to every given topic filter, with its maximum QoS, using
+    /// a single SUBSCRIBE packet with a new packet identifier. The result is
+    /// sent to the Observer with one Subscribed() message, whose Suback has
+    /// the topic filters and the granted QoS of each of them (in the same order).
+    ///
+    /// It returns an error if `topics` is empty or any filter is invalid
+    pub fn subscribe_many(&mut self, topics: &[(String, QoSLevel)]) -> Result<(), ClientError> {
+        let mut builder = SubscribeBuilder::new(self.new_packet_id());
+        for (topic, qos) in topics {
+            builder = builder.with_topic(topic, *qos)?;
+        }
+        self.subscribe(builder.build()?)
+    }
+
+    /// Unsubscribes from every given topic filter using a single UNSUBSCRIBE
+    /// packet with a new packet identifier. The result is sent to the Observer
+    /// with one Unsubscribed() message, whose Unsuback has all the filters.
+    ///
+    /// It returns an error if `topics` is empty or any filter is invalid
+    pub fn unsubscribe_many(&mut self, topics: &[String]) -> Result<(), ClientError> {
+        if topics.is_empty() {
+            return Err(ClientError::new(
+                "Se debe especificar al menos un topic para desuscribirse",
+            ));
+        }
+        let topic_filters = topics
+            .iter()
+            .map(|topic| TopicFilter::new(topic, QoSLevel::QoSLevel0))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.unsubscribe(Unsubscribe::new(self.new_packet_id(), topic_filters)?)
+    }
+
+    /// Publishes the given payload on the given topic and blocks until the
+    /// operation is completed.
+    ///
+    /// If `qos` is QoSLevel1, it waits until the corresponding PUBACK packet
+    /// arrives. If it does not arrive before the publish timeout elapses (see
+    /// `set_publish_timeout()`), it returns an error of kind
+    /// [`ClientErrorKind::Timeout`]. If `qos` is QoSLevel0, it returns as soon as
+    /// the packet is sent. Behaviour is undefined for QoSLevel2.
+    ///
+    /// The result is returned directly, so no Published() message is sent to the
+    /// Observer. Other operations can be sent while waiting for the PUBACK.
+    pub fn publish_and_wait(
+        &self,
+        topic: &str,
+        payload: &str,
+        qos: QoSLevel,
+    ) -> Result<(), ClientError> {
+        let packet_id = match qos {
+            QoSLevel::QoSLevel0 => None,
+            _ => Some(self.new_packet_id()),
+        };
+        let publish = Publish::new(false, qos, false, topic, payload, packet_id)?;
+        self.sender.publish_and_wait(publish, self.publish_timeout)
+    }
+
+    /// Sets how long should `publish_and_wait()` wait for the PUBACK
+    /// of a QoS 1 packet before failing
+    pub fn set_publish_timeout(&mut self, timeout: Duration) {
+        self.publish_timeout = timeout;
+    }
+
     /// Sends the given publish packet to the server. The Client then either returns
     /// Err(ClientError) or Ok(()). In the latter case, the result of the operation
     /// is sent to the Observer with a Published() message. If the QoS of the packet
@@ -122,6 +221,17 @@ impl<T: Observer> Client<T> {
         Ok(())
     }
 
+    #[doc(hidden)]
+    // Los ids de paquete validos van de 1 a u16::MAX
+    fn new_packet_id(&self) -> u16 {
+        loop {
+            let id = self.next_packet_id.fetch_add(1, Ordering::Relaxed);
+            if id != 0 {
+                return id;
+            }
+        }
+    }
+
     #[doc(hidden)]
     fn connect(
         &mut self,
@@ -136,7 +246,15 @@ impl<T: Observer> Client<T> {
             self.stop.clone(),
             self.sender.clone(),
             self.thread_pool.clone(),
-        )?;
+        )?
+        .with_trace(self.sender.trace());
+
+        // Queda pendiente desde ahora para que ningun otro paquete
+        // se envie antes que el CONNECT
+        self.sender
+            .pending_ack()
+            .lock()?
+            .replace(PendingAck::Connect(connect.clone()));
 
         let sender = self.sender.clone();
         let stop = self.stop.clone();
@@ -171,17 +289,36 @@ impl<T: Observer> Client<T> {
     fn keep_alive(
         sender: Arc<ClientSender<T, TcpStream>>,
         stop: Arc<AtomicBool>,
-        mut duration: Duration,
+        duration: Duration,
     ) {
-        let mut now = std::time::Instant::now();
-        if duration > KEEP_ALIVE_SUBTRACTION {
-            duration -= KEEP_ALIVE_SUBTRACTION;
-        }
+        // Se deja la mitad del keep alive de margen para la respuesta
+        let interval = duration / 2;
+        let mut stalled = false;
 
-        while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+        while !stop.load(Ordering::Relaxed) {
             thread::sleep(STOP_TIMEOUT);
-            if now.elapsed() > duration {
-                now = std::time::Instant::now();
+            match sender.pingresp_overdue(interval) {
+                Ok(true) => {
+                    stop.store(true, Ordering::Relaxed);
+                    sender.send_disconnected(ClientError::new_kind(
+                        "No se recibio el PINGRESP a tiempo",
+                        ClientErrorKind::Timeout,
+                    ));
+                    break;
+                }
+                Ok(false) => {}
+                Err(err) => sender.send_error(err),
+            }
+            // Se avisa una sola vez por cada PINGRESP demorado
+            match sender.pingresp_overdue(interval / 2) {
+                Ok(true) if !stalled => {
+                    stalled = true;
+                    sender.send_stalled();
+                }
+                Ok(false) => stalled = false,
+                _ => {}
+            }
+            if matches!(sender.idle_time(), Ok(idle) if idle >= interval) {
                 sender.send_pingreq();
             }
         }
@@ -192,7 +329,7 @@ impl<T: Observer> Drop for Client<T> {
     /// The client automatically sends a disconnect packet before dropping and closing the connection.
     /// If this fails, an InternalError is sent to the observer but the connection is closed anyway.
     fn drop(&mut self) {
-        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        self.stop.store(true, Ordering::Relaxed);
         let sender = self.sender.clone();
         if let Err(err) = self.thread_pool.execute(move || {
             sender.send_disconnect();
@@ -203,3 +340,357 @@ impl<T: Observer> Drop for Client<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    use packets::{
+        connack::{Connack, ConnackReturnCode},
+        connect::{Connect, ConnectBuilder},
+        pingreq::PingReq,
+        pingresp::PingResp,
+        puback::Puback,
+        publish::Publish,
+        qos::QoSLevel,
+        suback::Suback,
+        subscribe::Subscribe,
+        traits::{MQTTDecoding, MQTTEncoding},
+        unsuback::Unsuback,
+        unsubscribe::Unsubscribe,
+    };
+
+    use super::{Client, ClientErrorKind};
+    use crate::observer::{Message, Observer};
+
+    #[derive(Clone)]
+    struct ObserverMock;
+
+    impl Observer for ObserverMock {
+        fn update(&self, _: Message) {}
+    }
+
+    #[derive(Clone)]
+    struct RecordingObserver {
+        messages: Arc<Mutex<Vec<Message>>>,
+    }
+
+    impl Observer for RecordingObserver {
+        fn update(&self, message: Message) {
+            self.messages.lock().unwrap().push(message);
+        }
+    }
+
+    // Broker de prueba: acepta una conexion, responde el connect y
+    // lee un publish. Si `ack` es verdadero, responde con el puback.
+    // Devuelve el publish recibido
+    fn stub_broker(ack: bool) -> (String, JoinHandle<Publish>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8];
+
+            stream.read_exact(&mut header).unwrap();
+            Connect::read_from(&mut stream, header[0]).unwrap();
+            let connack = Connack::new(false, ConnackReturnCode::Accepted);
+            stream.write_all(&connack.encode().unwrap()).unwrap();
+
+            stream.read_exact(&mut header).unwrap();
+            let publish = Publish::read_from(&mut stream, header[0]).unwrap();
+            if ack {
+                let puback = Puback::new(publish.packet_id().unwrap()).unwrap();
+                stream.write_all(&puback.encode().unwrap()).unwrap();
+            }
+            // Mantengo la conexion abierta hasta que el cliente se desconecte
+            let _ = stream.read(&mut header);
+            publish
+        });
+        (address, handle)
+    }
+
+    fn connect() -> Connect {
+        ConnectBuilder::new("id", 0, true).unwrap().build().unwrap()
+    }
+
+    #[test]
+    fn test_publish_qos1_acked() {
+        let (address, broker) = stub_broker(true);
+        let client = Client::new(&address, ObserverMock, connect()).unwrap();
+
+        client
+            .publish_and_wait("car/wheels", "wow such wheel", QoSLevel::QoSLevel1)
+            .unwrap();
+        drop(client);
+
+        let publish = broker.join().unwrap();
+        assert_eq!(publish.topic_name(), "car/wheels");
+        assert_eq!(publish.qos(), QoSLevel::QoSLevel1);
+        assert!(publish.packet_id().is_some());
+    }
+
+    #[test]
+    fn test_publish_qos1_timeout() {
+        let (address, broker) = stub_broker(false);
+        let mut client = Client::new(&address, ObserverMock, connect()).unwrap();
+        client.set_publish_timeout(Duration::from_millis(300));
+
+        let start = Instant::now();
+        let error = client
+            .publish_and_wait("car/wheels", "wow such wheel", QoSLevel::QoSLevel1)
+            .unwrap_err();
+        assert_eq!(error.kind(), ClientErrorKind::Timeout);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(client);
+
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn test_publish_qos0_does_not_wait() {
+        let (address, broker) = stub_broker(false);
+        let mut client = Client::new(&address, ObserverMock, connect()).unwrap();
+        client.set_publish_timeout(Duration::from_secs(60));
+
+        client
+            .publish_and_wait("car/wheels", "wow such wheel", QoSLevel::QoSLevel0)
+            .unwrap();
+        drop(client);
+
+        let publish = broker.join().unwrap();
+        assert_eq!(publish.qos(), QoSLevel::QoSLevel0);
+        assert!(publish.packet_id().is_none());
+    }
+
+    #[test]
+    fn test_server_close_notifies_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8];
+            stream.read_exact(&mut header).unwrap();
+            Connect::read_from(&mut stream, header[0]).unwrap();
+            let connack = Connack::new(false, ConnackReturnCode::Accepted);
+            stream.write_all(&connack.encode().unwrap()).unwrap();
+            // Se cierra la conexion del lado del servidor
+        });
+
+        let observer = RecordingObserver {
+            messages: Arc::new(Mutex::new(vec![])),
+        };
+        let _client = Client::new(&address, observer.clone(), connect()).unwrap();
+        broker.join().unwrap();
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            let messages = observer.messages.lock().unwrap();
+            if messages
+                .iter()
+                .any(|message| matches!(message, Message::Disconnected(None)))
+            {
+                return;
+            }
+            drop(messages);
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("No se recibio el mensaje Disconnected");
+    }
+
+    // Broker de prueba: acepta una conexion, responde el connect y
+    // luego un subscribe y un unsubscribe, concediendo QoS 0 a todos
+    // los topics. Devuelve los paquetes recibidos
+    fn subscription_broker() -> (String, JoinHandle<(Subscribe, Unsubscribe)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8];
+
+            stream.read_exact(&mut header).unwrap();
+            Connect::read_from(&mut stream, header[0]).unwrap();
+            let connack = Connack::new(false, ConnackReturnCode::Accepted);
+            stream.write_all(&connack.encode().unwrap()).unwrap();
+
+            stream.read_exact(&mut header).unwrap();
+            let subscribe = Subscribe::read_from(&mut stream, header[0]).unwrap();
+            let return_codes = vec![0; subscribe.topics().len()];
+            let suback = Suback::new_from_vec(return_codes, subscribe.packet_identifier()).unwrap();
+            stream.write_all(&suback.encode().unwrap()).unwrap();
+
+            stream.read_exact(&mut header).unwrap();
+            let unsubscribe = Unsubscribe::read_from(&mut stream, header[0]).unwrap();
+            let unsuback = Unsuback::new(unsubscribe.packet_id()).unwrap();
+            stream.write_all(&unsuback.encode().unwrap()).unwrap();
+
+            let _ = stream.read(&mut header);
+            (subscribe, unsubscribe)
+        });
+        (address, handle)
+    }
+
+    // Espera a que el observer reciba un mensaje que cumpla la condicion
+    fn wait_for_message(observer: &RecordingObserver, condition: fn(&Message) -> bool) {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if observer.messages.lock().unwrap().iter().any(condition) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("No se recibio el mensaje esperado");
+    }
+
+    #[test]
+    fn test_subscribe_many_sends_a_single_packet() {
+        let (address, broker) = subscription_broker();
+        let observer = RecordingObserver {
+            messages: Arc::new(Mutex::new(vec![])),
+        };
+        let mut client = Client::new(&address, observer.clone(), connect()).unwrap();
+        let topics: Vec<String> = (0..5).map(|i| format!("topic/{}", i)).collect();
+
+        client
+            .subscribe_many(
+                &topics
+                    .iter()
+                    .map(|topic| (topic.clone(), QoSLevel::QoSLevel1))
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap();
+        wait_for_message(&observer, |message| {
+            matches!(message, Message::Subscribed(Ok(_)))
+        });
+        client.unsubscribe_many(&topics).unwrap();
+        wait_for_message(&observer, |message| {
+            matches!(message, Message::Unsubscribed(Ok(_)))
+        });
+        drop(client);
+
+        let (subscribe, unsubscribe) = broker.join().unwrap();
+        let subscribed: Vec<String> = subscribe
+            .topics()
+            .iter()
+            .map(|topic| topic.name().to_string())
+            .collect();
+        assert_eq!(subscribed, topics);
+        assert_eq!(unsubscribe.topic_filters().len(), 5);
+        assert_ne!(subscribe.packet_identifier(), unsubscribe.packet_id());
+
+        let messages = observer.messages.lock().unwrap();
+        let suback = messages
+            .iter()
+            .find_map(|message| match message {
+                Message::Subscribed(Ok(suback)) => Some(suback),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(suback.topics().len(), 5);
+        assert_eq!(suback.granted_qos(), vec![Some(QoSLevel::QoSLevel0); 5]);
+        let unsuback = messages
+            .iter()
+            .find_map(|message| match message {
+                Message::Unsubscribed(Ok(unsuback)) => Some(unsuback),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(unsuback.topics().len(), 5);
+    }
+
+    #[test]
+    fn test_subscribe_many_without_topics_should_fail() {
+        let (address, broker) = stub_broker(false);
+        let mut client = Client::new(&address, ObserverMock, connect()).unwrap();
+
+        assert!(client.subscribe_many(&[]).is_err());
+        assert!(client.unsubscribe_many(&[]).is_err());
+        // El broker espera un publish para terminar
+        client
+            .publish_and_wait("topic", "payload", QoSLevel::QoSLevel0)
+            .unwrap();
+        drop(client);
+        broker.join().unwrap();
+    }
+
+    // Broker de prueba: acepta una conexion, responde el connect y
+    // los primeros `answered` PINGREQ. Luego de leer uno mas, deja de
+    // responder hasta que el cliente se desconecte. Devuelve cuando
+    // recibio cada PINGREQ
+    fn ping_broker(answered: usize) -> (String, JoinHandle<Vec<Instant>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8];
+
+            stream.read_exact(&mut header).unwrap();
+            Connect::read_from(&mut stream, header[0]).unwrap();
+            let connack = Connack::new(false, ConnackReturnCode::Accepted);
+            stream.write_all(&connack.encode().unwrap()).unwrap();
+
+            let mut pings = vec![];
+            while pings.len() <= answered {
+                stream.read_exact(&mut header).unwrap();
+                PingReq::read_from(&mut stream, header[0]).unwrap();
+                pings.push(Instant::now());
+                if pings.len() <= answered {
+                    stream
+                        .write_all(&PingResp::new().encode().unwrap())
+                        .unwrap();
+                }
+            }
+            let _ = stream.read(&mut header);
+            pings
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn test_keep_alive_pings_and_disconnects_without_pingresp() {
+        let (address, broker) = ping_broker(3);
+        let observer = RecordingObserver {
+            messages: Arc::new(Mutex::new(vec![])),
+        };
+        let connect = ConnectBuilder::new("id", 1, true).unwrap().build().unwrap();
+        let client = Client::new(&address, observer.clone(), connect).unwrap();
+
+        wait_for_message(
+            &observer,
+            |message| matches!(message, Message::Disconnected(Some(err)) if err.kind() == ClientErrorKind::Timeout),
+        );
+        drop(client);
+
+        let messages = observer.messages.lock().unwrap();
+        let latencies = messages
+            .iter()
+            .filter(|message| matches!(message, Message::Latency(_)))
+            .count();
+        assert_eq!(latencies, 3);
+        // Antes de desconectarse avisa que el ultimo PINGRESP esta demorado
+        let stalled = messages
+            .iter()
+            .position(|message| matches!(message, Message::Stalled))
+            .unwrap();
+        let disconnected = messages
+            .iter()
+            .position(|message| matches!(message, Message::Disconnected(_)))
+            .unwrap();
+        assert!(stalled < disconnected);
+        drop(messages);
+
+        let pings = broker.join().unwrap();
+        assert_eq!(pings.len(), 4);
+        for pair in pings.windows(2) {
+            // Cada medio keep alive, con el margen de la espera del hilo
+            let elapsed = pair[1] - pair[0];
+            assert!(elapsed >= Duration::from_millis(500), "{:?}", elapsed);
+            assert!(elapsed < Duration::from_millis(900), "{:?}", elapsed);
+        }
+    }
+}
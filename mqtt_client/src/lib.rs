@@ -1,4 +1,6 @@
 mod client;
 mod observer;
-pub use crate::client::{Client, ClientError};
+mod packet_summary;
+pub use crate::client::{Client, ClientError, ClientErrorKind};
 pub use crate::observer::*;
+pub use crate::packet_summary::{Direction, PacketSummary};
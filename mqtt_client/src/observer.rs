@@ -1,15 +1,28 @@
+use std::time::Duration;
+
 use packets::{
     connack::Connack, puback::Puback, publish::Publish, suback::Suback, unsuback::Unsuback,
 };
 
-use crate::client::ClientError;
+use crate::{client::ClientError, packet_summary::PacketSummary};
 
 /// Messages for the Observer trait. They are intended
 /// to inform the result of the send operations of the
 /// client, except for the Publish message which should
 /// be sent when the client receives a PUBLISH packet
 /// and the InternalError which is a generic message
-/// for general internal errors
+/// for general internal errors.
+///
+/// Disconnected is sent when the server closes the connection.
+/// It contains None if it was closed cleanly, or the error
+/// if the connection was lost unexpectedly
+///
+/// Latency is sent when a keep alive PINGRESP arrives, with the
+/// time elapsed since its PINGREQ was sent. Stalled is sent when
+/// a PINGRESP takes longer than half of the time it has to arrive
+///
+/// PacketTrace is sent for every packet sent or received, only if
+/// the packet trace of the client is enabled
 #[derive(Debug)]
 pub enum Message {
     Connected(Result<Connack, ClientError>),
@@ -18,6 +31,10 @@ pub enum Message {
     Published(Result<Option<Puback>, ClientError>),
     Publish(Publish),
     InternalError(ClientError),
+    Disconnected(Option<ClientError>),
+    Latency(Duration),
+    Stalled,
+    PacketTrace(PacketSummary),
 }
 
 /// Observer trait for the internal client
@@ -0,0 +1,174 @@
+use std::{convert::TryFrom, fmt};
+
+use packets::{helpers::PacketType, packet_reader::RemainingLength};
+
+use crate::client::{ClientError, ClientErrorKind};
+
+/// Mask of the flags of the control byte
+const FLAGS_MASK: u8 = 0b0000_1111;
+/// Mask of the QoS bits of the flags of a PUBLISH
+const PUBLISH_QOS_MASK: u8 = 0b0000_0110;
+
+/// Whether a traced packet was sent to the server or received from it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Summary of the fixed header and packet identifier of a control
+/// packet, used to debug the traffic of the client (see
+/// [`Message::PacketTrace`](crate::Message::PacketTrace)). It does not
+/// keep the payload of PUBLISH packets, only its size
+#[derive(Debug, Clone, PartialEq)]
+pub struct PacketSummary {
+    pub direction: Direction,
+    pub packet_type: PacketType,
+    pub flags: u8,
+    pub packet_id: Option<u16>,
+    pub remaining_length: usize,
+    pub payload_size: Option<usize>,
+}
+
+impl PacketSummary {
+    /// Builds the summary of the packet encoded in `bytes`, fixed
+    /// header included. It returns an error of kind
+    /// [`ClientErrorKind::MalformedPacket`] if the packet is truncated
+    /// or its fixed header is not valid
+    pub fn from_bytes(direction: Direction, bytes: &[u8]) -> Result<Self, ClientError> {
+        let (&control_byte, mut rest) = bytes.split_first().ok_or_else(truncated)?;
+        let packet_type =
+            PacketType::try_from(control_byte).map_err(ClientError::malformed_packet)?;
+        let remaining_length =
+            RemainingLength::from_encoded(&mut rest).map_err(ClientError::malformed_packet)?;
+        let remaining_length = remaining_length.decode() as usize;
+        let body = rest.get(..remaining_length).ok_or_else(truncated)?;
+        let flags = control_byte & FLAGS_MASK;
+
+        let (packet_id, payload_size) = match packet_type {
+            PacketType::Publish => {
+                let topic_len = read_u16(body, 0)? as usize;
+                let mut header_len = 2 + topic_len;
+                let mut packet_id = None;
+                if flags & PUBLISH_QOS_MASK != 0 {
+                    packet_id = Some(read_u16(body, header_len)?);
+                    header_len += 2;
+                }
+                let payload_size = remaining_length
+                    .checked_sub(header_len)
+                    .ok_or_else(truncated)?;
+                (packet_id, Some(payload_size))
+            }
+            PacketType::Puback
+            | PacketType::PubRec
+            | PacketType::PubRel
+            | PacketType::PubComp
+            | PacketType::Subscribe
+            | PacketType::Suback
+            | PacketType::Unsubscribe
+            | PacketType::Unsuback => (Some(read_u16(body, 0)?), None),
+            _ => (None, None),
+        };
+
+        Ok(Self {
+            direction,
+            packet_type,
+            flags,
+            packet_id,
+            remaining_length,
+            payload_size,
+        })
+    }
+}
+
+impl fmt::Display for PacketSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+        let name = format!("{:?}", self.packet_type).to_uppercase();
+        write!(f, "{} {} flags={:04b}", arrow, name, self.flags)?;
+        if let Some(packet_id) = self.packet_id {
+            write!(f, " id={}", packet_id)?;
+        }
+        write!(f, " len={}", self.remaining_length)?;
+        if let Some(payload_size) = self.payload_size {
+            write!(f, " payload={}B", payload_size)?;
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+fn read_u16(body: &[u8], at: usize) -> Result<u16, ClientError> {
+    match body.get(at..at + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(truncated()),
+    }
+}
+
+#[doc(hidden)]
+fn truncated() -> ClientError {
+    ClientError::new_kind("Paquete truncado", ClientErrorKind::MalformedPacket)
+}
+
+#[cfg(test)]
+mod tests {
+    use packets::{
+        helpers::PacketType, pingreq::PingReq, puback::Puback, publish::Publish, qos::QoSLevel,
+        traits::MQTTEncoding,
+    };
+
+    use super::{Direction, PacketSummary};
+    use crate::client::ClientErrorKind;
+
+    #[test]
+    fn test_publish_summary() {
+        // PUBLISH QoS 1 retenido, topic "a/b", id 10, payload "hola"
+        let bytes = [
+            0x33, 11, 0, 3, b'a', b'/', b'b', 0, 10, b'h', b'o', b'l', b'a',
+        ];
+        let summary = PacketSummary::from_bytes(Direction::Received, &bytes).unwrap();
+        assert_eq!(summary.packet_type, PacketType::Publish);
+        assert_eq!(summary.flags, 0b0011);
+        assert_eq!(summary.packet_id, Some(10));
+        assert_eq!(summary.remaining_length, 11);
+        assert_eq!(summary.payload_size, Some(4));
+        assert_eq!(
+            summary.to_string(),
+            "<- PUBLISH flags=0011 id=10 len=11 payload=4B"
+        );
+    }
+
+    #[test]
+    fn test_summary_does_not_include_payload() {
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "secreto", None).unwrap();
+        let summary =
+            PacketSummary::from_bytes(Direction::Sent, &publish.encode().unwrap()).unwrap();
+        assert_eq!(
+            summary.to_string(),
+            "-> PUBLISH flags=0000 len=14 payload=7B"
+        );
+    }
+
+    #[test]
+    fn test_summary_of_other_packets() {
+        let puback = Puback::new(7).unwrap().encode().unwrap();
+        let summary = PacketSummary::from_bytes(Direction::Received, &puback).unwrap();
+        assert_eq!(summary.to_string(), "<- PUBACK flags=0000 id=7 len=2");
+
+        let pingreq = PingReq::new().encode().unwrap();
+        let summary = PacketSummary::from_bytes(Direction::Sent, &pingreq).unwrap();
+        assert_eq!(summary.to_string(), "-> PINGREQ flags=0000 len=0");
+    }
+
+    #[test]
+    fn test_truncated_packet_should_be_error() {
+        for bytes in [&[][..], &[0x40, 2, 0][..], &[0x32, 4, 0, 1, b'a', 0][..]] {
+            let err = PacketSummary::from_bytes(Direction::Received, bytes).unwrap_err();
+            assert_eq!(err.kind(), ClientErrorKind::MalformedPacket);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Throughput of the publications processed by the server, without the
+//! network stack: the packets are read with `Server::process_packet`
+//! from a [`MemoryStream`], so the measured time is the one spent
+//! decoding the packets, matching their topics against the
+//! subscriptions and dispatching them. The clock stops once every
+//! publication was handled and taken from the dispatch queue (see
+//! `Server::wait_dispatched`).
+//!
+//! The subscribers do not have a connection, so the dispatched
+//! messages are discarded instead of being written to a socket.
+//!
+//! Run it with:
+//!
+//! ```text
+//! cargo bench --features bench --bench publish_throughput
+//! ```
+
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use packets::{
+    publish::Publish, qos::QoSLevel, subscribe::Subscribe, topic_filter::TopicFilter,
+    traits::MQTTEncoding,
+};
+use server::{
+    memory_stream::MemoryStream,
+    traits::{DumpConfig, Login, SubscriptionListener},
+    Config, Server,
+};
+
+/// Messages published on each run
+const MESSAGES: usize = 100_000;
+/// Different topics the messages are published to
+const TOPICS: usize = 100;
+/// Bytes each direction of the in-memory connection can hold
+const PIPE_CAPACITY: usize = 64 * 1024;
+const PACKET_TIMEOUT: Duration = Duration::from_secs(5);
+const THREADPOOL_SIZE: usize = 4;
+
+#[derive(Clone)]
+struct BenchConfig;
+
+impl Config for BenchConfig {
+    fn port(&self) -> u16 {
+        0
+    }
+
+    fn dump_info(&self) -> Option<DumpConfig> {
+        None
+    }
+
+    fn log_path(&self) -> &str {
+        "logs"
+    }
+
+    fn ip(&self) -> &str {
+        "localhost"
+    }
+
+    fn authenticator(&self) -> Option<Box<dyn Login>> {
+        None
+    }
+}
+
+/// Counts the subscriptions processed by the server
+struct SubscriptionCounter(Arc<AtomicUsize>);
+
+impl SubscriptionListener for SubscriptionCounter {
+    fn on_subscribe(&self, _id: &str, _filter: &str, _qos: QoSLevel) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_unsubscribe(&self, _id: &str, _filter: &str) {}
+}
+
+/// Sends `bytes` to the server through a [`MemoryStream`], as if
+/// they came from the client `id`. Returns the number of packets
+/// processed
+fn feed(server: &Arc<Server<BenchConfig>>, id: &str, bytes: Vec<u8>) -> usize {
+    let (mut client, mut connection) = MemoryStream::pair(PIPE_CAPACITY);
+    let writer = thread::spawn(move || client.write_all(&bytes).unwrap());
+    let mut processed = 0;
+    while server
+        .process_packet(&mut connection, id, PACKET_TIMEOUT)
+        .is_ok()
+    {
+        processed += 1;
+    }
+    writer.join().unwrap();
+    processed
+}
+
+/// Subscribes each of the clients to its topic filter
+fn subscribe(server: &Arc<Server<BenchConfig>>, filters: &[String]) {
+    let subscribed = Arc::new(AtomicUsize::new(0));
+    server
+        .add_subscription_listener(Box::new(SubscriptionCounter(subscribed.clone())))
+        .unwrap();
+    for (i, filter) in filters.iter().enumerate() {
+        let topic = TopicFilter::new(filter, QoSLevel::QoSLevel0).unwrap();
+        let subscribe = Subscribe::new(vec![topic], 1);
+        feed(server, &format!("sub-{}", i), subscribe.encode().unwrap());
+    }
+    // Las suscripciones se procesan en el ThreadPool
+    while subscribed.load(Ordering::Relaxed) < filters.len() {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Publishes [`MESSAGES`] messages and prints the throughput
+fn run(name: &str, filters: &[String]) {
+    let server = Server::new(BenchConfig, THREADPOOL_SIZE).unwrap();
+    subscribe(&server, filters);
+
+    let mut bytes = vec![];
+    for i in 0..MESSAGES {
+        let topic = format!("sensores/{}/temperatura", i % TOPICS);
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel0, false, &topic, "21.5", None).unwrap();
+        bytes.append(&mut publish.encode().unwrap());
+    }
+
+    let start = Instant::now();
+    let processed = feed(&server, "publisher", bytes);
+    // Las publicaciones se manejan en el ThreadPool y se despachan
+    // desde otro thread, asi que se espera a que terminen
+    server.wait_dispatched("publisher").unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(processed, MESSAGES);
+    println!(
+        "{:<24} {:>8} mensajes en {:>8.2?} ({:>10.0} mensajes/s)",
+        name,
+        processed,
+        elapsed,
+        processed as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    run("sin suscriptores", &[]);
+    run(
+        "10 suscriptores exactos",
+        &(0..10)
+            .map(|i| format!("sensores/{}/temperatura", i))
+            .collect::<Vec<_>>(),
+    );
+    run(
+        "10 suscriptores wildcard",
+        &(0..10)
+            .map(|i| match i % 2 {
+                0 => "sensores/+/temperatura".to_string(),
+                _ => "sensores/#".to_string(),
+            })
+            .collect::<Vec<_>>(),
+    );
+}
@@ -1,17 +1,19 @@
 use core::fmt;
-use std::time::{Duration, SystemTime};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use std::{io::Write, vec};
 
 use packets::{connect::Connect, qos::QoSLevel, traits::MQTTEncoding};
 use packets::{puback::Puback, publish::Publish};
 use rand::{self};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
 
-use crate::server::UNACK_RESENDING_FREQ;
+use crate::server::mount_point;
 use crate::traits::{Close, Interrupt};
 use crate::{
-    network_connection::NetworkConnection,
+    network_connection::{ByteCounters, NetworkConnection},
     server::{server_error::ServerErrorKind, ClientId, ServerError, ServerResult},
 };
 
@@ -53,9 +55,83 @@ where
     /// occurs, said packet is replaced by the packet
     /// received on the new connection.
     connect: Connect,
-    /// Unacknowledged packets, along with the time they
-    /// were last sent.
-    unacknowledged: Vec<(SystemTime, Publish)>,
+    /// Unacknowledged packets, in the order they were sent
+    unacknowledged: Vec<Unacknowledged>,
+    /// Bytes transferred with the client during the
+    /// current session. They are shared with the current
+    /// connection, which is the one that updates them
+    #[serde(skip, default = "Default::default")]
+    counters: Arc<ByteCounters>,
+    /// Moment in which the last packet was received
+    /// from the client. Used to detect the clients that
+    /// exceeded their Keep Alive
+    #[serde(skip, default = "Instant::now")]
+    last_packet_at: Instant,
+    /// Last packet identifier assigned to a packet sent
+    /// to the client
+    #[serde(default)]
+    last_packet_id: u16,
+    /// True if the user of the current connection can issue
+    /// admin commands (see [`Login::is_admin`](crate::traits::Login::is_admin))
+    #[serde(skip, default)]
+    admin: bool,
+    /// Mount point of the listener of the current connection (see
+    /// [`Config::mount_point`](crate::traits::Config::mount_point)).
+    /// It is kept after a disconnection, so that the packets queued
+    /// meanwhile are delivered without it
+    #[serde(default)]
+    mount_point: Option<String>,
+    /// True if the current connection was closed because a packet
+    /// was not acknowledged after all its retries (see
+    /// [`Client::retry_unacknowledged`])
+    #[serde(skip, default)]
+    retries_exhausted: bool,
+}
+
+/// [`Publish`] packet sent to the client that was not
+/// acknowledged yet
+#[derive(Debug, Serialize, Deserialize)]
+struct Unacknowledged {
+    /// Moment in which the packet was last sent
+    sent_at: SystemTime,
+    publish: Publish,
+    /// Times the packet was sent again on the current
+    /// connection
+    #[serde(skip, default)]
+    retries: u32,
+}
+
+impl Unacknowledged {
+    fn new(publish: Publish) -> Self {
+        Self {
+            sent_at: SystemTime::now(),
+            publish,
+            retries: 0,
+        }
+    }
+}
+
+/// Snapshot of the amount of bytes transferred with
+/// a client during its current session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// Snapshot of a connected client (see
+/// [`Server::connected_clients`](crate::Server::connected_clients))
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientInfo {
+    pub id: ClientId,
+    /// Address of the peer of the current connection
+    pub address: SocketAddr,
+    /// Keep Alive in effect, in seconds. It may be lower than the
+    /// one requested by the client (see [`Config::max_keep_alive`](crate::Config::max_keep_alive))
+    pub keep_alive: u16,
+    pub clean_session: bool,
+    /// Amount of topic filters the client is subscribed to
+    pub subscriptions: usize,
 }
 
 impl<S, I> Client<S, I>
@@ -69,7 +145,13 @@ where
             id: connect.client_id().to_owned(),
             connect,
             unacknowledged: vec![],
+            counters: network_connection.counters().clone(),
             connection: Some(network_connection),
+            last_packet_at: Instant::now(),
+            last_packet_id: 0,
+            admin: false,
+            mount_point: None,
+            retries_exhausted: false,
         }
     }
 
@@ -93,9 +175,31 @@ where
     /// the `send_publish()` method should be used.
     ///
     /// Returns error if the client is disconnected.
-    pub fn send_packet<T: MQTTEncoding>(&mut self, packet: &T) -> ServerResult<()> {
+    ///
+    /// If the write does not finish within the write timeout of the
+    /// connection (see [`Config::write_timeout`](crate::traits::Config::write_timeout)),
+    /// the client is considered unresponsive: its connection is closed,
+    /// so that the thread that reads from it disconnects the client, and
+    /// an error of kind [`ServerErrorKind::Timeout`] is returned
+    pub fn send_packet<T: MQTTEncoding>(&mut self, packet: &T) -> ServerResult<()>
+    where
+        S: Close,
+    {
         if let Some(connection) = &mut self.connection {
-            connection.write_all(&packet.encode()?)?;
+            let result = connection.write_all(&packet.encode()?);
+            if let Err(err) = result {
+                let err = ServerError::from(err);
+                if err.kind() == ServerErrorKind::Timeout {
+                    warn!(
+                        "<{}>: No se pudo escribir a tiempo - Se cierra la conexion",
+                        self.id
+                    );
+                    // Parte del paquete pudo haberse escrito, por lo que
+                    // la conexion ya no se puede seguir usando
+                    connection.close()?;
+                }
+                return Err(err);
+            }
             Ok(())
         } else {
             Err(ServerError::new_kind(
@@ -121,7 +225,8 @@ where
     ///
     /// If `gracefully` is false and the client specified a
     /// LastWill on its last connection, the package to be
-    /// published is returned. Otherwise, it returns None.
+    /// published is returned (inside the mount point of the
+    /// client, if it has one). Otherwise, it returns None.
     ///
     /// If the client was already disconnected, it silently does
     /// nothing.
@@ -143,11 +248,15 @@ where
             } else {
                 packet_identifier = None;
             }
+            let topic = match &self.mount_point {
+                Some(mount_point) => mount_point::mount(mount_point, last_will.topic.name()),
+                None => last_will.topic.name().to_string(),
+            };
             let publish_last_will = Publish::new(
                 false,
                 last_will.topic.qos(),
                 last_will.retain_flag,
-                last_will.topic.name(),
+                &topic,
                 &last_will.topic_message,
                 packet_identifier,
             )
@@ -160,6 +269,26 @@ where
         }
     }
 
+    /// Closes the current connection of the client, without
+    /// taking it. This way, the thread that reads from the
+    /// connection notices it was closed and disconnects the
+    /// client as usual (publishing its Last Will, if any)
+    pub fn close_connection(&mut self) -> ServerResult<()>
+    where
+        S: Close,
+    {
+        if let Some(connection) = &mut self.connection {
+            connection.close()?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the current connection was closed because
+    /// a packet was not acknowledged after all its retries
+    pub fn retries_exhausted(&self) -> bool {
+        self.retries_exhausted
+    }
+
     /// Check that the id of the new connection
     /// matches the id of the client.
     ///
@@ -198,17 +327,34 @@ where
 
         if *new_connect.clean_session() {
             self.unacknowledged = vec![];
+        } else {
+            // La sesion continua, asi que se mantienen los contadores
+            new_connection
+                .counters()
+                .add(self.counters.bytes_read(), self.counters.bytes_written());
+        }
+        self.counters = new_connection.counters().clone();
+        // Cada conexion tiene sus propios reintentos
+        for unacknowledged in &mut self.unacknowledged {
+            unacknowledged.retries = 0;
         }
 
         let last_will = self.disconnect(false)?;
         self.connection = Some(new_connection);
+        self.retries_exhausted = false;
         self.connect = new_connect;
+        self.last_packet_at = Instant::now();
         Ok(last_will)
     }
 
     /// Returns the maximum idle time between communication with
     /// the client before the server decides to disconnect it
     /// (see [MQTT-3.1.2-24])
+    ///
+    /// If the client specified a Keep Alive of 0, the mechanism is
+    /// disabled and None is returned (see [MQTT-3.1.2-23]). Such
+    /// clients are only disconnected when they send a Disconnect
+    /// packet or the connection fails
     pub fn keep_alive(&self) -> Option<Duration> {
         if self.connect.keep_alive() == 0 {
             None
@@ -218,12 +364,60 @@ where
         }
     }
 
+    /// Records that a packet was received from the client
+    pub fn record_activity(&mut self) {
+        self.last_packet_at = Instant::now();
+    }
+
+    /// Returns true if the client is connected and no packet
+    /// was received from it during its Keep Alive (plus the
+    /// given grace period)
+    pub fn keep_alive_expired(&self, grace: Duration) -> bool {
+        match self.keep_alive() {
+            Some(keep_alive) => {
+                self.connected() && self.last_packet_at.elapsed() > keep_alive + grace
+            }
+            None => false,
+        }
+    }
+
     /// Returns the username of the client, if specified.
     /// Otherwise, it returns None.
     pub fn user_name(&self) -> Option<&String> {
         self.connect.user_name()
     }
 
+    /// Returns true if the client can issue admin commands
+    pub fn is_admin(&self) -> bool {
+        self.admin
+    }
+
+    /// Sets whether the client can issue admin commands.
+    /// It is decided on each connection, when the user logs in
+    pub fn set_admin(&mut self, admin: bool) {
+        self.admin = admin;
+    }
+
+    /// Returns the mount point of the client, if it has one
+    pub fn mount_point(&self) -> Option<&str> {
+        self.mount_point.as_deref()
+    }
+
+    /// Sets the mount point of the client. It is decided on each
+    /// connection, by the listener the client connected through
+    pub fn set_mount_point(&mut self, mount_point: Option<String>) {
+        self.mount_point = mount_point;
+    }
+
+    /// Returns the amount of bytes transferred with the
+    /// client during its current session
+    pub fn stats(&self) -> ClientStats {
+        ClientStats {
+            bytes_read: self.counters.bytes_read(),
+            bytes_written: self.counters.bytes_written(),
+        }
+    }
+
     /// Returns the connection id, if the client is connected.
     /// Otherwise, it returns None.
     pub fn connection_id(&self) -> Option<&I> {
@@ -232,84 +426,148 @@ where
 
     /// Removes from the unacknowledged list, the packet whose
     /// *packet_id* matches the *packet_id* of the received [`Puback`]
-    /// packet. If no packet meets this condition, it returns an
-    /// error of kind [`ServerErrorKind::Other`].
+    /// packet.
+    ///
+    /// Returns true if said packet was found. Otherwise, the
+    /// unacknowledged list is left untouched and false is returned
     #[instrument(skip(self, puback) fields(client_id = %self.id, packet_id = %puback.packet_id()))]
-    pub fn acknowledge(&mut self, puback: Puback) -> ServerResult<()> {
+    pub fn acknowledge(&mut self, puback: Puback) -> ServerResult<bool> {
         debug!("Acknowledge");
-        let idx = self.unacknowledged.iter().position(|publish| {
-            puback.packet_id()
-                == publish
-                    .1
-                    .packet_id()
-                    .expect("Se esperaba un paquete con identificador (QoS > 0)")
+        let idx = self.unacknowledged.iter().position(|unacknowledged| {
+            unacknowledged.publish.packet_id() == Some(puback.packet_id())
         });
         if let Some(idx) = idx {
             self.unacknowledged.remove(idx);
         }
-        if self.unacknowledged.is_empty() {
-            match self.keep_alive() {
-                None => {
-                    if let Some(connection) = &mut self.connection {
-                        connection.sleep()?;
-                    }
-                }
-                Some(keep_alive) => {
-                    if let Some(connection) = &mut self.connection {
-                        connection.alert(keep_alive)?;
-                    }
-                }
+        Ok(idx.is_some())
+    }
+
+    /// Makes the reads of the current connection time out with
+    /// the Keep Alive of the client, or never if it is 0. The
+    /// unacknowledged packets do not depend on it, since they are
+    /// sent again by the Keep Alive watchdog
+    pub fn update_read_timeout(&mut self) -> ServerResult<()> {
+        let keep_alive = self.keep_alive();
+        if let Some(connection) = &mut self.connection {
+            match keep_alive {
+                Some(keep_alive) => connection.alert(keep_alive)?,
+                None => connection.sleep()?,
             }
         }
         Ok(())
     }
 
-    /// Sends the packets that have not been acknowledged by
-    /// the client.
-    ///
-    /// `min_elapsed_time` is the minimum time that must have elapsed
-    /// between the last time the packet was sent and the moment the
-    /// method is executed, for the packet to be sent. If it is None,
-    /// 1 packet will be sent.
-    pub fn send_unacknowledged(&mut self, min_elapsed_time: Option<Duration>) -> ServerResult<()> {
-        let now = SystemTime::now();
-        if self.unacknowledged.is_empty() {
-            return Ok(());
+    /// Returns a new packet identifier to send a packet to the
+    /// client. Identifiers of packets still waiting for their
+    /// acknowledgement are never reused (see [MQTT-2.3.1-2])
+    pub fn next_packet_id(&mut self) -> u16 {
+        loop {
+            self.last_packet_id = self.last_packet_id.wrapping_add(1);
+            let packet_id = self.last_packet_id;
+            if packet_id != 0
+                && !self
+                    .unacknowledged
+                    .iter()
+                    .any(|unacknowledged| unacknowledged.publish.packet_id() == Some(packet_id))
+            {
+                return packet_id;
+            }
         }
+    }
 
-        let (last_time_published, publish) = self.unacknowledged.remove(0);
-        if let Some(min_elapsed_time) = min_elapsed_time {
-            if now.duration_since(last_time_published).unwrap() > min_elapsed_time {
-                self.send_packet(&publish)?;
-                self.unacknowledged.insert(0, (now, publish));
-            } else {
-                // No se envio, no actualizo la hora
-                self.unacknowledged
-                    .insert(0, (last_time_published, publish));
+    /// Sends again each unacknowledged packet for which `interval`
+    /// has elapsed since it was last sent. The interval of each
+    /// packet doubles on every retry of it.
+    ///
+    /// Returns false if a packet was already sent again
+    /// `max_retries` times and its last interval elapsed, in which
+    /// case the connection is closed. Otherwise, it returns true
+    pub fn retry_unacknowledged(
+        &mut self,
+        interval: Duration,
+        max_retries: u32,
+    ) -> ServerResult<bool>
+    where
+        S: Close,
+    {
+        let now = SystemTime::now();
+        for idx in 0..self.unacknowledged.len() {
+            let unacknowledged = &self.unacknowledged[idx];
+            let backoff = interval
+                .checked_mul(2u32.saturating_pow(unacknowledged.retries))
+                .unwrap_or(Duration::MAX);
+            let elapsed = now
+                .duration_since(unacknowledged.sent_at)
+                .unwrap_or_default();
+            if elapsed < backoff {
+                continue;
             }
-        } else {
+            if unacknowledged.retries >= max_retries {
+                self.retries_exhausted = true;
+                self.close_connection()?;
+                return Ok(false);
+            }
+            debug!(
+                "Reintento {} del paquete {:?}",
+                unacknowledged.retries + 1,
+                unacknowledged.publish.packet_id()
+            );
+            let publish = unacknowledged.publish.clone();
             self.send_packet(&publish)?;
-            self.unacknowledged.insert(0, (now, publish));
+            let unacknowledged = &mut self.unacknowledged[idx];
+            unacknowledged.sent_at = now;
+            unacknowledged.retries += 1;
         }
-
-        Ok(())
+        Ok(true)
     }
 
     /// Sends a [`Publish`] packet to the client and, if applicable,
     /// adds it to the unacknowledged packet list.
-    pub fn send_publish(&mut self, mut publish: Publish) -> ServerResult<()> {
+    ///
+    /// If the client has a mount point, it is removed from the
+    /// topic of the packet before sending it. The packets outside
+    /// the mount point are discarded.
+    pub fn send_publish(&mut self, mut publish: Publish) -> ServerResult<()>
+    where
+        S: Close,
+    {
+        if let Some(mount_point) = &self.mount_point {
+            match mount_point::unmount(mount_point, publish.topic_name()) {
+                Some(topic) => publish = publish.with_topic_name(&topic)?,
+                None => {
+                    warn!(
+                        "<{}>: Se descarta PUBLISH fuera del mount point ({})",
+                        self.id,
+                        publish.topic_name()
+                    );
+                    return Ok(());
+                }
+            }
+        }
         if self.connected() {
             self.send_packet(&publish)?;
         }
         if publish.qos() == QoSLevel::QoSLevel1 {
             publish.set_dup(true);
-            self.unacknowledged.push((SystemTime::now(), publish));
-            if self.unacknowledged.len() > 1 {
-                if let Some(connection) = &mut self.connection {
-                    connection.alert(UNACK_RESENDING_FREQ)?;
-                }
-            }
+            self.unacknowledged.push(Unacknowledged::new(publish));
         }
         Ok(())
     }
 }
+
+impl<S> Client<S, SocketAddr>
+where
+    S: Write + Interrupt + Send + Sync + 'static,
+{
+    /// Returns a snapshot of the client, with the given amount
+    /// of subscriptions. If it is disconnected, it returns None
+    pub fn info(&self, subscriptions: usize) -> Option<ClientInfo> {
+        self.connection_id().map(|address| ClientInfo {
+            id: self.id.to_owned(),
+            address: *address,
+            keep_alive: self.connect.keep_alive(),
+            clean_session: self.clean_session(),
+            subscriptions,
+        })
+    }
+}
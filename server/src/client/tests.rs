@@ -151,11 +151,11 @@ fn test_publish_saves_packet_in_unacknowledged_if_qos_is_1() {
 
     let mut client = Client::new(connect, network_connection);
     client.send_publish(publish).unwrap();
-    assert_eq!(client.unacknowledged[0].1, publish_copy);
+    assert_eq!(client.unacknowledged[0].publish, publish_copy);
 }
 
 #[test]
-fn test_send_unacknowledged() {
+fn test_retry_unacknowledged() {
     let connect = make_connect(0, true, None);
 
     let publish = make_publish("top", QoSLevel::QoSLevel1);
@@ -167,7 +167,9 @@ fn test_send_unacknowledged() {
 
     let mut client = Client::new(connect, network_connection);
     client.send_publish(publish).unwrap();
-    client.send_unacknowledged(None).unwrap();
+    client
+        .retry_unacknowledged(Duration::ZERO, u32::MAX)
+        .unwrap();
 
     let mut network_connection_copy = client.connection.unwrap().try_clone().unwrap();
 
@@ -183,7 +185,7 @@ fn test_send_unacknowledged() {
 }
 
 #[test]
-fn test_send_unacknowledged_multiple_times() {
+fn test_retry_unacknowledged_multiple_times() {
     let connect = make_connect(0, true, None);
 
     let publish = make_publish("top", QoSLevel::QoSLevel1);
@@ -195,8 +197,12 @@ fn test_send_unacknowledged_multiple_times() {
 
     let mut client = Client::new(connect, network_connection);
     client.send_publish(publish).unwrap();
-    client.send_unacknowledged(None).unwrap();
-    client.send_unacknowledged(None).unwrap();
+    client
+        .retry_unacknowledged(Duration::ZERO, u32::MAX)
+        .unwrap();
+    client
+        .retry_unacknowledged(Duration::ZERO, u32::MAX)
+        .unwrap();
 
     let mut network_connection_copy = client.connection.unwrap().try_clone().unwrap();
 
@@ -228,13 +234,45 @@ fn test_acknowledge_remove_packet_from_list() {
 
     let mut client = Client::new(connect, network_connection);
     client.send_publish(publish).unwrap();
-    client.acknowledge(puback).unwrap();
+    assert!(client.acknowledge(puback).unwrap());
 
     assert!(client.unacknowledged.is_empty());
 }
 
 #[test]
-fn test_send_unacknowledged_inflight_messages_bigger_than_unacknowledged_should_work() {
+fn test_acknowledge_unknown_packet_id_returns_false() {
+    let connect = make_connect(0, true, None);
+
+    let publish = make_publish("top", QoSLevel::QoSLevel1);
+
+    let puback = Puback::new(2).unwrap();
+
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+
+    let mut client = Client::new(connect, network_connection);
+    client.send_publish(publish).unwrap();
+
+    assert!(!client.acknowledge(puback).unwrap());
+    assert_eq!(client.unacknowledged.len(), 1);
+}
+
+#[test]
+fn test_next_packet_id_skips_unacknowledged_ids() {
+    let connect = make_connect(0, true, None);
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+    let mut client = Client::new(connect, network_connection);
+
+    // make_publish usa el packet id 1
+    client
+        .send_publish(make_publish("top", QoSLevel::QoSLevel1))
+        .unwrap();
+
+    assert_eq!(client.next_packet_id(), 2);
+    assert_eq!(client.next_packet_id(), 3);
+}
+
+#[test]
+fn test_retry_unacknowledged_inflight_messages_bigger_than_unacknowledged_should_work() {
     let connect = make_connect(0, true, None);
 
     let publish = make_publish("top", QoSLevel::QoSLevel1);
@@ -246,7 +284,9 @@ fn test_send_unacknowledged_inflight_messages_bigger_than_unacknowledged_should_
 
     let mut client = Client::new(connect, network_connection);
     client.send_publish(publish).unwrap();
-    client.send_unacknowledged(None).unwrap();
+    client
+        .retry_unacknowledged(Duration::ZERO, u32::MAX)
+        .unwrap();
 
     let mut network_connection_copy = client.connection.unwrap().try_clone().unwrap();
 
@@ -262,7 +302,7 @@ fn test_send_unacknowledged_inflight_messages_bigger_than_unacknowledged_should_
 }
 
 #[test]
-fn test_send_unacknowledged_min_elapsed_time_should_not_send_recent_packets() {
+fn test_retry_unacknowledged_min_elapsed_time_should_not_send_recent_packets() {
     let connect = make_connect(0, true, None);
 
     let publish = make_publish("top", QoSLevel::QoSLevel1);
@@ -274,7 +314,7 @@ fn test_send_unacknowledged_min_elapsed_time_should_not_send_recent_packets() {
     let mut client = Client::new(connect, network_connection);
     client.send_publish(publish).unwrap();
     client
-        .send_unacknowledged(Some(Duration::from_secs(5)))
+        .retry_unacknowledged(Duration::from_secs(5), u32::MAX)
         .unwrap();
 
     let mut network_connection_copy = client.connection.unwrap().try_clone().unwrap();
@@ -292,7 +332,7 @@ fn test_send_unacknowledged_min_elapsed_time_should_not_send_recent_packets() {
 }
 
 #[test]
-fn test_send_unacknowledged_min_elapsed_time_should_send_old_packets() {
+fn test_retry_unacknowledged_min_elapsed_time_should_send_old_packets() {
     let connect = make_connect(0, true, None);
 
     let publish = make_publish("top", QoSLevel::QoSLevel1);
@@ -307,7 +347,7 @@ fn test_send_unacknowledged_min_elapsed_time_should_send_old_packets() {
     thread::sleep(Duration::from_millis(150));
 
     client
-        .send_unacknowledged(Some(Duration::from_millis(100)))
+        .retry_unacknowledged(Duration::from_millis(100), u32::MAX)
         .unwrap();
 
     let mut network_connection_copy = client.connection.unwrap().try_clone().unwrap();
@@ -325,7 +365,7 @@ fn test_send_unacknowledged_min_elapsed_time_should_send_old_packets() {
 }
 
 #[test]
-fn test_send_unacknowledged_should_keep_order() {
+fn test_retry_unacknowledged_should_keep_order() {
     let connect = make_connect(0, false, None);
     let publish1 = make_publish("top1", QoSLevel::QoSLevel1);
     let publish2 = make_publish("top2", QoSLevel::QoSLevel1);
@@ -340,10 +380,12 @@ fn test_send_unacknowledged_should_keep_order() {
     // No se deberia enviar ninguno y la cola de unacknowledged
     // queda igual
     client
-        .send_unacknowledged(Some(Duration::from_secs(1)))
+        .retry_unacknowledged(Duration::from_secs(1), u32::MAX)
+        .unwrap();
+    // Se reenvian los paquetes, empezando por el primero
+    client
+        .retry_unacknowledged(Duration::ZERO, u32::MAX)
         .unwrap();
-    // Se envia el primer paquete
-    client.send_unacknowledged(None).unwrap();
 
     let mut network_connection_copy = client.connection.unwrap().try_clone().unwrap();
 
@@ -438,3 +480,52 @@ fn test_reconnect_does_not_work_with_different_client_id() {
     let result = client.reconnect(connect_2, network_connection_2);
     assert_eq!(result.unwrap_err().kind(), ServerErrorKind::Irrecoverable);
 }
+
+#[test]
+fn test_retry_unacknowledged_should_back_off_and_give_up() {
+    let connect = make_connect(0, true, None);
+    let publish = make_publish("top", QoSLevel::QoSLevel1);
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+    let interval = Duration::from_millis(50);
+
+    let mut client = Client::new(connect, network_connection);
+    client.send_publish(publish).unwrap();
+    thread::sleep(Duration::from_millis(80));
+    assert!(client.retry_unacknowledged(interval, 1).unwrap());
+    assert_eq!(client.unacknowledged[0].retries, 1);
+
+    // El segundo intervalo es el doble que el primero
+    thread::sleep(Duration::from_millis(80));
+    assert!(client.retry_unacknowledged(interval, 1).unwrap());
+    thread::sleep(Duration::from_millis(50));
+    assert!(!client.retry_unacknowledged(interval, 1).unwrap());
+}
+
+#[test]
+fn test_retry_unacknowledged_should_back_off_each_packet() {
+    let connect = make_connect(0, true, None);
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+    let interval = Duration::from_millis(50);
+
+    let mut client = Client::new(connect, network_connection);
+    client
+        .send_publish(make_publish("top", QoSLevel::QoSLevel1))
+        .unwrap();
+    thread::sleep(Duration::from_millis(80));
+    client
+        .send_publish(make_publish("top", QoSLevel::QoSLevel1).with_packet_id(2))
+        .unwrap();
+    assert!(client.retry_unacknowledged(interval, 1).unwrap());
+    assert_eq!(client.unacknowledged[0].retries, 1);
+    assert_eq!(client.unacknowledged[1].retries, 0);
+
+    // El segundo paquete se reenvia aunque el primero siga esperando
+    thread::sleep(Duration::from_millis(80));
+    assert!(client.retry_unacknowledged(interval, 1).unwrap());
+    assert_eq!(client.unacknowledged[0].retries, 1);
+    assert_eq!(client.unacknowledged[1].retries, 1);
+
+    client.acknowledge(Puback::new(1).unwrap()).unwrap();
+    assert_eq!(client.unacknowledged.len(), 1);
+    assert_eq!(client.unacknowledged[0].retries, 1);
+}
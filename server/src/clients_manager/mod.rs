@@ -6,21 +6,26 @@ use std::{
     io::{Read, Write},
     ops::DerefMut,
     sync::Mutex,
+    time::Duration,
     vec,
 };
 
-use packets::{connack::ConnackReturnCode, connect::Connect, publish::Publish};
+use packets::{
+    connack::ConnackReturnCode, connect::Connect, disconnect::Disconnect, publish::Publish,
+};
 use serde::{Deserialize, Serialize};
-use tracing::{info, instrument};
+use tracing::{debug, info, instrument};
 
 use crate::{
-    client::Client,
+    client::{Client, ClientStats},
     network_connection::NetworkConnection,
     server::{server_error::ServerErrorKind, ClientId, ClientIdArg, ServerError, ServerResult},
-    traits::{Close, Interrupt, Login, LoginResult},
+    traits::{ClientIdGenerator, Close, Interrupt, Login, LoginResult, UuidGenerator},
 };
 
-const GENERIC_ID_SUFFIX: &str = "__CLIENT__";
+/// Amount of ids generated for a client with an empty client id
+/// before giving up, if all of them are already in use
+const MAX_ID_GENERATION_ATTEMPTS: usize = 10;
 
 /// Structure that manages the clients of the server.
 /// This includes connecting, reconnecting, disconnecting
@@ -43,10 +48,14 @@ where
     /// Login method. If None, clients can connect
     /// without authentication
     login: Option<Box<dyn Login>>,
-    /// Keeps track of how many clients without
-    /// client_id are connected. Useful to assign
-    /// them a unique default ID
-    generic_ids_counter: u32,
+    #[serde(skip, default = "default_id_generator")]
+    /// Generator of the ids assigned to the clients
+    /// that connect without client_id
+    id_generator: Box<dyn ClientIdGenerator>,
+}
+
+fn default_id_generator() -> Box<dyn ClientIdGenerator> {
+    Box::new(UuidGenerator)
 }
 
 /// Information related to the disconnection
@@ -108,11 +117,15 @@ where
         Self {
             clients: HashMap::new(),
             login,
-            generic_ids_counter: 0,
+            id_generator: default_id_generator(),
         }
     }
 
     /// Executes an arbitrary function on a client
+    ///
+    /// If there is no session with the given id (for example, because
+    /// the client disconnected with clean session after the caller got
+    /// its id), it returns an error of kind [`ServerErrorKind::ClientDisconnected`]
     pub fn client_do<F, T>(&self, id: &ClientIdArg, action: F) -> ServerResult<T>
     where
         F: FnOnce(&mut Client<S, I>) -> ServerResult<T>,
@@ -121,16 +134,120 @@ where
             Some(session) => action(session.lock()?.deref_mut()),
             None => Err(ServerError::new_kind(
                 &format!("No existe el cliente con id <{}>", id),
-                ServerErrorKind::ClientNotFound,
+                ServerErrorKind::ClientDisconnected,
             )),
         }
     }
 
+    /// Returns a snapshot of the stats of every client
+    /// with a session in the server
+    pub fn client_stats(&self) -> ServerResult<HashMap<ClientId, ClientStats>> {
+        let mut stats = HashMap::new();
+        for (id, client) in &self.clients {
+            stats.insert(id.to_owned(), client.lock()?.stats());
+        }
+        Ok(stats)
+    }
+
+    /// Closes the connection of every client that exceeded
+    /// its Keep Alive (plus the given grace period). Returns
+    /// the ids of said clients
+    pub fn close_expired_connections(&self, grace: Duration) -> ServerResult<Vec<ClientId>>
+    where
+        S: Close,
+    {
+        let mut expired = vec![];
+        for (id, client) in &self.clients {
+            let mut client = client.lock()?;
+            if client.keep_alive_expired(grace) {
+                client.close_connection()?;
+                expired.push(id.to_owned());
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Sends again the unacknowledged packets of every connected
+    /// client (see [`Client::retry_unacknowledged`]). Returns the
+    /// ids of the clients whose connection was closed because a
+    /// packet ran out of retries
+    pub fn retry_unacknowledged(
+        &self,
+        interval: Duration,
+        max_retries: u32,
+    ) -> ServerResult<Vec<ClientId>>
+    where
+        S: Close,
+    {
+        let mut exhausted = vec![];
+        for (id, client) in &self.clients {
+            let mut client = client.lock()?;
+            if client.connected() && !client.retry_unacknowledged(interval, max_retries)? {
+                exhausted.push(id.to_owned());
+            }
+        }
+        Ok(exhausted)
+    }
+
+    /// Returns the ids of every client with a session
+    /// in the server, sorted
+    pub fn client_ids(&self) -> Vec<ClientId> {
+        let mut ids: Vec<ClientId> = self.clients.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Returns the amount of clients currently connected (that
+    /// is, without counting the disconnected persistent sessions)
+    pub fn connected_count(&self) -> ServerResult<usize> {
+        let mut count = 0;
+        for client in self.clients.values() {
+            if client.lock()?.connected() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns true if there is a client with the given id
+    /// and it can issue admin commands
+    pub fn is_admin(&self, id: &ClientIdArg) -> ServerResult<bool> {
+        match self.clients.get(id) {
+            Some(client) => Ok(client.lock()?.is_admin()),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the mount point of the client with the given id
+    /// (see [`Client::mount_point`]). If there is no such client,
+    /// it returns None
+    pub fn mount_point(&self, id: &ClientIdArg) -> ServerResult<Option<String>> {
+        match self.clients.get(id) {
+            Some(client) => Ok(client.lock()?.mount_point().map(str::to_owned)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if there is a client with the given
+    /// id and it is connected
+    pub fn is_connected(&self, id: &ClientIdArg) -> ServerResult<bool> {
+        match self.clients.get(id) {
+            Some(client) => Ok(client.lock()?.connected()),
+            None => Ok(false),
+        }
+    }
+
     /// Replaces the login method
     pub fn set_auth(&mut self, login: Option<Box<dyn Login>>) {
         self.login = login;
     }
 
+    /// Replaces the generator of the ids assigned to the
+    /// clients that connect without client_id
+    pub fn set_id_generator(&mut self, id_generator: Box<dyn ClientIdGenerator>) {
+        self.id_generator = id_generator;
+    }
+
     /// Tries to disconnect a client. If the client specified
     /// clean_session to false, its information is kept
     /// in (self.clients). Otherwise, it is deleted.
@@ -154,7 +271,7 @@ where
         // de Client Take-Over
         let old_id = match self.client_do(id, |client| Ok(client.connection_id().cloned())) {
             Ok(old_id) => old_id,
-            Err(e) if e.kind() == ServerErrorKind::ClientNotFound => {
+            Err(e) if e.kind() == ServerErrorKind::ClientDisconnected => {
                 return Ok(DisconnectInfo {
                     publish_last_will: None,
                     clean_session: false,
@@ -172,19 +289,9 @@ where
         }
 
         let publish_last_will = self.client_do(id, |session| session.disconnect(gracefully))?;
-        let clean_session;
-        // Si la funcion anterior no devolvio error, entonces existe el cliente
-        if self
-            .clients
-            .get(id)
-            .expect("Error inesperado: no se encontro el cliente en el HashMap")
-            .lock()?
-            .clean_session()
-        {
+        let clean_session = self.client_do(id, |session| Ok(session.clean_session()))?;
+        if clean_session {
             self.clients.remove(id);
-            clean_session = true;
-        } else {
-            clean_session = false;
         }
         Ok(DisconnectInfo {
             publish_last_will,
@@ -226,22 +333,18 @@ where
     /// (login) if a method was specified, and verifies that the
     /// rest of the fields are valid.
     ///
+    /// Returns true if the user can issue admin commands (see
+    /// [`Login::is_admin`]). Without a login method, no client can.
+    ///
     /// If it could not be connected, but it corresponds to
     /// send a Connack to the client, it returns an error of kind
     /// [`ServerErrorKind::ConnectionRefused`]
-    fn check_credentials(&mut self, connect: &Connect) -> ServerResult<()> {
-        if connect.client_id().starts_with(GENERIC_ID_SUFFIX) {
-            return Err(ServerError::new_kind(
-                "ID con prefijo invalido",
-                ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected),
-            ));
-        }
-
+    fn check_credentials(&mut self, connect: &Connect) -> ServerResult<bool> {
         // No precisamos chequear las ids tomadas con check_taken_ids
         // porque en modo sin autenticacion cualquier cliente puede
         // hacer TakeOver
         let login = match &mut self.login {
-            None => return Ok(()),
+            None => return Ok(false),
             Some(path) => path,
         };
         let user_name = match connect.user_name() {
@@ -271,17 +374,30 @@ where
                 "Contraseña invalida",
                 ServerErrorKind::ConnectionRefused(ConnackReturnCode::BadUserNameOrPassword),
             )),
-            LoginResult::Accepted => self.check_taken_ids(connect.client_id(), user_name),
+            LoginResult::Accepted => {
+                let admin = login.is_admin(user_name)?;
+                self.check_taken_ids(connect.client_id(), user_name)?;
+                Ok(admin)
+            }
         }
     }
 
-    /// Creates a new generic ID. Guarantees that this id
-    /// is unique
-    fn new_generic_id(&mut self) -> String {
-        self.generic_ids_counter += 1;
-        let mut generic_id = String::from(GENERIC_ID_SUFFIX);
-        generic_id.push_str(&self.generic_ids_counter.to_string());
-        generic_id
+    /// Creates a new generic ID with the id generator. Guarantees
+    /// that no other client is using this id, by generating another
+    /// one if it is taken. If none of the generated ids is free, it
+    /// returns an error of kind [`ServerErrorKind::ConnectionRefused`]
+    fn new_generic_id(&self) -> ServerResult<String> {
+        for _ in 0..MAX_ID_GENERATION_ATTEMPTS {
+            let id = self.id_generator.generate();
+            if !id.is_empty() && !self.clients.contains_key(&id) {
+                return Ok(id);
+            }
+            debug!("El id generado <{}> ya esta en uso - Se genera otro", id);
+        }
+        Err(ServerError::new_kind(
+            "No se pudo generar un id libre para el cliente",
+            ServerErrorKind::ConnectionRefused(ConnackReturnCode::ServerUnavailable),
+        ))
     }
 
     /// Makes the necessary modifications in the [`Connect`] packet to
@@ -294,7 +410,7 @@ where
                 ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected),
             ))
         } else {
-            let id = self.new_generic_id();
+            let id = self.new_generic_id()?;
             connect.set_id(id);
             Ok(())
         }
@@ -312,16 +428,40 @@ where
     ///
     /// Performs all the necessary checks to ensure that
     /// the session is valid
-    #[instrument(skip(self, network_connection, connect) fields(socket_addr = %network_connection.id(), client_id = %connect.client_id()))]
+    #[cfg(test)]
     pub fn new_session(
+        &mut self,
+        network_connection: NetworkConnection<S, I>,
+        connect: Connect,
+    ) -> ServerResult<ConnectInfo>
+    where
+        S: Close,
+    {
+        self.new_session_purging(network_connection, connect, |_| Ok(()))
+    }
+
+    /// Same as [`ClientsManager::new_session`], but if the client
+    /// connects with clean_session set to true and there was a
+    /// previous session with its id, `purge` is called with that
+    /// id once the new connection was validated and before the
+    /// new session is established. The previous session is
+    /// discarded, along with its pending messages
+    /// (see [MQTT-3.1.2-6]).
+    ///
+    /// This allows the server to remove the subscriptions of the
+    /// previous session before the client can receive anything
+    #[instrument(skip(self, network_connection, connect, purge) fields(socket_addr = %network_connection.id(), client_id = %connect.client_id()))]
+    pub fn new_session_purging<F>(
         &mut self,
         network_connection: NetworkConnection<S, I>,
         mut connect: Connect,
+        purge: F,
     ) -> ServerResult<ConnectInfo>
     where
         S: Close,
+        F: FnOnce(&ClientIdArg) -> ServerResult<()>,
     {
-        self.check_credentials(&connect)?;
+        let admin = self.check_credentials(&connect)?;
 
         if connect.client_id().is_empty() {
             self.process_client_empty_id(&mut connect)?;
@@ -333,14 +473,27 @@ where
 
         // Hay una sesion_presente en el servidor con la misma ID
         if let Some(old_client) = self.clients.get(&id) {
-            info!("Reconectando");
-            takeover_last_will = old_client.lock()?.reconnect(connect, network_connection)?;
-            session_present = true;
+            if *connect.clean_session() {
+                info!("Reconectando con clean_session - Se descarta la sesion anterior");
+                takeover_last_will = old_client.lock()?.disconnect(false)?;
+                purge(&id)?;
+                self.client_add(Client::new(connect, network_connection));
+                // Con clean_session nunca hay sesion presente [MQTT-3.2.2-1]
+                session_present = false;
+            } else {
+                info!("Reconectando");
+                takeover_last_will = old_client.lock()?.reconnect(connect, network_connection)?;
+                session_present = true;
+            }
         } else {
             let client = Client::new(connect, network_connection);
             self.client_add(client);
             session_present = false;
         }
+        self.client_do(&id, |client| {
+            client.set_admin(admin);
+            Ok(())
+        })?;
         Ok(ConnectInfo {
             id,
             session_present,
@@ -348,6 +501,32 @@ where
         })
     }
 
+    /// Disconnects every connected client, sending each of them
+    /// a [`Disconnect`] packet before closing its connection.
+    ///
+    /// Since the clients did not ask to be disconnected, their
+    /// Last Will packets are returned in the [`ShutdownInfo`],
+    /// along with the ids of the sessions that were removed
+    /// because of clean_session
+    #[instrument(skip(self))]
+    pub fn disconnect_all(&mut self, reason: &str) -> ServerResult<ShutdownInfo>
+    where
+        S: Close,
+    {
+        info!("Desconectando a todos los clientes");
+        let disconnect = Disconnect::new();
+        for client in self.clients.values() {
+            let mut client = client.lock()?;
+            if client.connected() {
+                // Si falla, el cliente se desconecta de todas formas
+                if let Err(err) = client.send_packet(&disconnect) {
+                    debug!("<{}>: No se pudo enviar DISCONNECT: {}", client.id(), err);
+                }
+            }
+        }
+        self.shutdown(false)
+    }
+
     pub fn shutdown(&mut self, gracefully: bool) -> ServerResult<ShutdownInfo>
     where
         S: Close,
@@ -10,22 +10,35 @@ use crate::traits::{Login, LoginResult};
 const CACHE_SIZE: usize = 128;
 #[doc(hidden)]
 const SEP: &str = ",";
+/// Value of the optional third field of the users
+/// that can issue admin commands
+#[doc(hidden)]
+const ADMIN_ROLE: &str = "admin";
 
 type Username = String;
 type Password = String;
 
+/// Account of a user, as read from the file
+#[derive(Debug, Clone)]
+struct Account {
+    password: Password,
+    admin: bool,
+}
+
 /// Basic login for the server.
 /// Reads usernames and passwords from
 /// a plain text file whose path is *path*, and
-/// the format is 'username,password'.
+/// the format is 'username,password'. The users
+/// whose line is 'username,password,admin' can
+/// also issue admin commands.
 ///
 /// The first [`CACHE_SIZE`] entries in the file are
 /// stored in memory to speed up the connection on servers
 /// with few users.
 #[derive(Debug)]
 pub struct SimpleLogin {
-    /// Username and Password cache
-    cache: HashMap<Username, Password>,
+    /// Accounts cache
+    cache: HashMap<Username, Account>,
     /// Path of the file from which usernames and passwords
     /// are read. It is [None] if the entire file could be
     /// loaded into memory (that is, the number of users was
@@ -37,16 +50,18 @@ impl std::error::Error for SimpleLogin {}
 
 impl Login for SimpleLogin {
     fn login(&mut self, user_name: &str, password: &str) -> io::Result<LoginResult> {
-        if let Some(real_password) = self.cache.get(user_name) {
-            if password == real_password {
-                Ok(LoginResult::Accepted)
-            } else {
-                Ok(LoginResult::InvalidPassword)
-            }
-        } else {
-            self.search_in_file(user_name, password)
+        match self.account(user_name)? {
+            Some(account) if account.password == password => Ok(LoginResult::Accepted),
+            Some(_) => Ok(LoginResult::InvalidPassword),
+            None => Ok(LoginResult::UsernameNotFound),
         }
     }
+
+    fn is_admin(&mut self, user_name: &str) -> io::Result<bool> {
+        Ok(self
+            .account(user_name)?
+            .is_some_and(|account| account.admin))
+    }
 }
 
 impl SimpleLogin {
@@ -66,13 +81,8 @@ impl SimpleLogin {
                 path.take();
                 break;
             }
-            let (user_name, password) = buf.trim().split_once(SEP).ok_or_else(|| {
-                io::Error::new(io::ErrorKind::InvalidData, "Formato de archivo invalido")
-            })?;
-            if cache
-                .insert(user_name.to_string(), password.to_string())
-                .is_some()
-            {
+            let (user_name, account) = SimpleLogin::parse_line(&buf)?;
+            if cache.insert(user_name.to_string(), account).is_some() {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidData,
                     "Usuario duplicado",
@@ -83,29 +93,47 @@ impl SimpleLogin {
         Ok(Self { cache, path })
     }
 
-    fn search_in_file(&mut self, user_name: &str, password: &str) -> io::Result<LoginResult> {
+    /// Parses a line of the file, with the format
+    /// 'username,password' or 'username,password,admin'
+    fn parse_line(line: &str) -> io::Result<(&str, Account)> {
+        let invalid_format =
+            || io::Error::new(io::ErrorKind::InvalidData, "Formato de archivo invalido");
+        let (user_name, rest) = line.trim().split_once(SEP).ok_or_else(invalid_format)?;
+        let account = match rest.split_once(SEP) {
+            Some((password, ADMIN_ROLE)) => Account {
+                password: password.to_string(),
+                admin: true,
+            },
+            Some(_) => return Err(invalid_format()),
+            None => Account {
+                password: rest.to_string(),
+                admin: false,
+            },
+        };
+        Ok((user_name, account))
+    }
+
+    /// Returns the account of the user, looking for it in
+    /// the file if it is not in the cache
+    fn account(&self, user_name: &str) -> io::Result<Option<Account>> {
+        if let Some(account) = self.cache.get(user_name) {
+            return Ok(Some(account.clone()));
+        }
         let path = match &self.path {
             Some(path) => path,
-            None => return Ok(LoginResult::UsernameNotFound),
+            None => return Ok(None),
         };
 
         let mut reader = BufReader::new(File::open(path)?);
         let mut buf = String::new();
         while reader.read_line(&mut buf)? != 0 {
-            let (found_user_name, found_password) =
-                buf.trim().split_once(SEP).ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::InvalidData, "Formato de archivo invalido")
-                })?;
+            let (found_user_name, account) = SimpleLogin::parse_line(&buf)?;
             if found_user_name == user_name {
-                return if found_password == password {
-                    Ok(LoginResult::Accepted)
-                } else {
-                    Ok(LoginResult::InvalidPassword)
-                };
+                return Ok(Some(account));
             }
             buf.clear();
         }
-        Ok(LoginResult::UsernameNotFound)
+        Ok(None)
     }
 }
 
@@ -178,6 +206,25 @@ fdelu,fdelu",
         assert_eq!(result, LoginResult::UsernameNotFound);
     }
 
+    #[test]
+    fn test_admin_accounts() {
+        let cursor = Cursor::new("root,toor,admin\nfdelu,fdelu");
+        let mut login = SimpleLogin::new_from_stream(cursor, "").unwrap();
+
+        assert_eq!(login.login("root", "toor").unwrap(), LoginResult::Accepted);
+        assert!(login.is_admin("root").unwrap());
+        assert!(!login.is_admin("fdelu").unwrap());
+        assert!(!login.is_admin("NoExiste").unwrap());
+    }
+
+    #[test]
+    fn test_unknown_role_is_invalid_format() {
+        let cursor = Cursor::new("root,toor,superuser");
+        let error = SimpleLogin::new_from_stream(cursor, "").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
     #[test]
     fn test_invalid_file_format() {
         let cursor = invalid_accounts_file();
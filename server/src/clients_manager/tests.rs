@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{sync::Mutex, time::Duration};
 
 use packets::{
     connack::ConnackReturnCode,
@@ -13,9 +13,31 @@ use crate::{
     network_connection::NetworkConnection,
     server::{server_error::ServerErrorKind, ClientIdArg, ServerResult},
     test_helpers::iomock::IOMock,
+    traits::ClientIdGenerator,
 };
 
-use super::{ClientsManager, GENERIC_ID_SUFFIX};
+use super::{ClientsManager, MAX_ID_GENERATION_ATTEMPTS};
+
+/// Generator that returns the given ids in order,
+/// and then always the last one
+#[derive(Debug)]
+struct SequenceGenerator(Mutex<Vec<&'static str>>);
+
+impl ClientIdGenerator for SequenceGenerator {
+    fn generate(&self) -> String {
+        let mut ids = self.0.lock().unwrap();
+        if ids.len() > 1 {
+            ids.remove(0).to_string()
+        } else {
+            ids[0].to_string()
+        }
+    }
+}
+
+fn connect_empty_id(manager: &mut ClientsManager<IOMock, u16>) -> ServerResult<ConnectInfo> {
+    let connect = ConnectBuilder::new("", 0, true).unwrap().build().unwrap();
+    manager.new_session(NetworkConnection::new(0, IOMock::new()), connect)
+}
 
 fn make_manager_with_clients(
     ids: Vec<&ClientIdArg>,
@@ -42,8 +64,6 @@ fn make_manager_with_clients(
 fn test_creation() {
     let manager = ClientsManager::<IOMock, u16>::new(None);
     assert!(manager.clients.is_empty());
-    assert_eq!(manager.generic_ids_counter, 0);
-    assert!(manager.clients.is_empty());
 }
 
 #[test]
@@ -71,7 +91,8 @@ fn test_new_session_with_id() {
 fn test_new_session_empty_id() {
     let manager = make_manager_with_clients(vec![""], true, None).unwrap();
 
-    assert!(manager.clients.contains_key("__CLIENT__1"));
+    assert_eq!(manager.clients.len(), 1);
+    assert!(!manager.clients.contains_key(""));
 }
 
 #[test]
@@ -96,8 +117,44 @@ fn test_multiple_sessions_with_id() {
 fn test_multiple_sessions_empty_id() {
     let manager = make_manager_with_clients(vec!["", ""], true, None).unwrap();
 
-    assert!(manager.clients.contains_key("__CLIENT__1"));
-    assert!(manager.clients.contains_key("__CLIENT__2"));
+    // Cada cliente recibe una id generada distinta
+    assert_eq!(manager.clients.len(), 2);
+    assert!(!manager.clients.contains_key(""));
+}
+
+#[test]
+fn test_empty_id_uses_id_generator() {
+    let mut manager = ClientsManager::<IOMock, u16>::new(None);
+    manager.set_id_generator(Box::new(SequenceGenerator(Mutex::new(vec!["generated"]))));
+    connect_empty_id(&mut manager).unwrap();
+
+    assert!(manager.clients.contains_key("generated"));
+}
+
+#[test]
+fn test_empty_id_generates_again_if_taken() {
+    let mut manager = make_manager_with_clients(vec!["taken"], true, None).unwrap();
+    manager.set_id_generator(Box::new(SequenceGenerator(Mutex::new(vec![
+        "taken", "taken", "free",
+    ]))));
+    connect_empty_id(&mut manager).unwrap();
+
+    assert!(manager.clients.contains_key("taken"));
+    assert!(manager.clients.contains_key("free"));
+}
+
+#[test]
+fn test_empty_id_fails_if_every_generated_id_is_taken() {
+    let mut manager = make_manager_with_clients(vec!["taken"], true, None).unwrap();
+    let mut ids = vec!["taken"; MAX_ID_GENERATION_ATTEMPTS];
+    ids.push("free");
+    manager.set_id_generator(Box::new(SequenceGenerator(Mutex::new(ids))));
+
+    assert_eq!(
+        connect_empty_id(&mut manager).unwrap_err().kind(),
+        ServerErrorKind::ConnectionRefused(ConnackReturnCode::ServerUnavailable)
+    );
+    assert_eq!(manager.clients.len(), 1);
 }
 
 #[test]
@@ -120,6 +177,29 @@ fn test_authorized_session() {
     assert!(manager.clients.contains_key("client_id"));
 }
 
+#[test]
+fn test_admin_session() {
+    let mut manager = ClientsManager::<IOMock, u16>::new(Some(Box::new(
+        SimpleLogin::new("tests/files/test_accounts.csv").unwrap(),
+    )));
+    for (id, user_name, password) in [("admin", "root", "toor"), ("client", "user", "pass")] {
+        let connect = ConnectBuilder::new(id, 0, true)
+            .unwrap()
+            .with_user_name(user_name)
+            .unwrap()
+            .with_password(password)
+            .unwrap()
+            .build()
+            .unwrap();
+        let network_connection = NetworkConnection::new(0, IOMock::new());
+        manager.new_session(network_connection, connect).unwrap();
+    }
+
+    assert!(manager.is_admin("admin").unwrap());
+    assert!(!manager.is_admin("client").unwrap());
+    assert!(!manager.is_admin("unknown").unwrap());
+}
+
 #[test]
 fn test_invalid_username_should_fail() {
     let iomock = IOMock::new();
@@ -204,15 +284,6 @@ fn test_ids_collision_should_fail() {
     );
 }
 
-#[test]
-fn test_connect_with_forbidden_id_should_fail() {
-    let result = make_manager_with_clients(vec![GENERIC_ID_SUFFIX], true, None);
-    assert_eq!(
-        result.unwrap_err().kind(),
-        ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected)
-    );
-}
-
 #[test]
 fn test_takeover() {
     let iomock_1 = IOMock::new();
@@ -490,3 +561,85 @@ fn test_disconnect_non_persistent_session_should_not_be_remembered() {
 
     assert!(!connect_info.session_present);
 }
+
+#[test]
+fn test_disconnect_all_honors_clean_session() {
+    let mut manager = make_manager_with_clients(vec!["a", "b"], false, None).unwrap();
+    let connect = ConnectBuilder::new("c", 0, true).unwrap().build().unwrap();
+    manager
+        .new_session(NetworkConnection::new(2, IOMock::new()), connect)
+        .unwrap();
+
+    let info = manager.disconnect_all("mantenimiento").unwrap();
+
+    assert_eq!(info.clean_session_ids, vec!["c".to_owned()]);
+    assert!(manager.clients.contains_key("a"));
+    assert!(manager.clients.contains_key("b"));
+    assert!(!manager.clients.contains_key("c"));
+    for client in manager.clients.values() {
+        assert!(!client.lock().unwrap().connected());
+    }
+}
+
+#[test]
+fn test_connected_count_ignores_disconnected_sessions() {
+    let mut manager = make_manager_with_clients(vec!["a", "b", "c"], false, None).unwrap();
+    manager
+        .disconnect("b", NetworkConnection::new(1, IOMock::new()), true)
+        .unwrap();
+
+    assert_eq!(manager.connected_count().unwrap(), 2);
+    assert!(manager.is_connected("a").unwrap());
+    assert!(!manager.is_connected("b").unwrap());
+    assert!(!manager.is_connected("d").unwrap());
+}
+
+#[test]
+fn test_clean_session_reconnect_should_purge_previous_session() {
+    let mut manager = make_manager_with_clients(vec!["a"], false, None).unwrap();
+    let mut purged = vec![];
+
+    let connect = ConnectBuilder::new("a", 0, true).unwrap().build().unwrap();
+    manager
+        .new_session_purging(NetworkConnection::new(1, IOMock::new()), connect, |id| {
+            purged.push(id.to_owned());
+            Ok(())
+        })
+        .unwrap();
+
+    assert_eq!(purged, vec!["a".to_owned()]);
+    assert!(manager.is_connected("a").unwrap());
+}
+
+#[test]
+fn test_persistent_or_new_session_should_not_purge() {
+    let mut manager = make_manager_with_clients(vec!["a"], false, None).unwrap();
+    let mut purged = vec![];
+
+    let connect = ConnectBuilder::new("a", 0, false).unwrap().build().unwrap();
+    manager
+        .new_session_purging(NetworkConnection::new(1, IOMock::new()), connect, |id| {
+            purged.push(id.to_owned());
+            Ok(())
+        })
+        .unwrap();
+    let connect = ConnectBuilder::new("b", 0, true).unwrap().build().unwrap();
+    manager
+        .new_session_purging(NetworkConnection::new(2, IOMock::new()), connect, |id| {
+            purged.push(id.to_owned());
+            Ok(())
+        })
+        .unwrap();
+
+    assert!(purged.is_empty());
+}
+
+#[test]
+fn test_client_do_on_missing_client_is_client_disconnected() {
+    let manager = make_manager_with_clients(vec!["client_id"], true, None).unwrap();
+    let result = manager.client_do("other_id", |client| Ok(client.keep_alive()));
+    assert_eq!(
+        result.unwrap_err().kind(),
+        ServerErrorKind::ClientDisconnected
+    );
+}
@@ -1,27 +1,69 @@
 use std::{
     collections::HashMap,
-    fs::File,
+    fmt,
+    fs::{self, File},
     io::{BufRead, BufReader, Read},
+    path::PathBuf,
+    str::FromStr,
     time::Duration,
 };
 
+use logger::LogFormat;
+use serde::de::DeserializeOwned;
+use toml::value::{Table, Value};
 use tracing::Level;
 
 use crate::{
     clients_manager::simple_login::SimpleLogin,
-    traits::{Config, Login},
+    server::{server_error::ServerErrorKind, ServerError, ServerResult},
+    traits::{
+        BridgeConfig, Config, DumpConfig, Login, OverloadPolicy, DEFAULT_CONNECT_TIMEOUT,
+        DEFAULT_DISPATCH_QUEUE_LEN, DEFAULT_LISTEN_BACKLOG, DEFAULT_MAX_PENDING_CONNECTIONS,
+        DEFAULT_MAX_RETRIES, DEFAULT_MAX_TOPIC_LEN, DEFAULT_PACKET_READ_TIMEOUT,
+        DEFAULT_RETRY_INTERVAL, DEFAULT_WRITE_TIMEOUT,
+    },
 };
 
 /// Config struct contains information which is needed from a Server
 #[derive(Debug, Clone)]
 pub struct FileConfig {
     port: u16,
-    dump_info: Option<(String, Duration)>,
+    dump_info: Option<DumpConfig>,
     log_path: String,
     accounts_path: Option<String>,
     ip: String,
+    bind_address: Option<String>,
+    dual_stack: bool,
+    dispatch_queue_len: Option<usize>,
+    overload_policy: Option<OverloadPolicy>,
+    will_delay: Option<Duration>,
+    retained_delivery_window: Option<Duration>,
+    mount_point: Option<String>,
+    bridge_port: Option<u16>,
+    packet_read_timeout: Option<Duration>,
+    strict_protocol: bool,
+    allow_mqtt_31: bool,
+    no_local: bool,
+    max_clients: Option<usize>,
+    max_client_threads: Option<usize>,
+    listen_backlog: Option<u32>,
+    connect_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_pending_connections: Option<usize>,
+    max_topic_len: Option<usize>,
+    max_payload_size: Option<usize>,
+    retry_interval: Option<Duration>,
+    max_retries: Option<u32>,
+    max_keep_alive: Option<u16>,
+    max_retained: Option<usize>,
+    max_retained_bytes: Option<usize>,
+    bridges: Vec<BridgeConfig>,
     log_file_level: Level,
     log_stdout_level: Level,
+    log_format: LogFormat,
+    threadpool_size: usize,
+    /// Keys of the file that were not recognized
+    unknown_keys: Vec<String>,
 }
 
 const PORT_KEY: &str = "port";
@@ -30,8 +72,46 @@ const DUMP_TIME_KEY: &str = "dump_time";
 const LOG_PATH_KEY: &str = "log_path";
 const ACCOUNTS_PATH_KEY: &str = "accounts_path";
 const IP_KEY: &str = "ip";
+const BIND_ADDRESS_KEY: &str = "bind_address";
+const DUAL_STACK_KEY: &str = "dual_stack";
+const DUMP_COMPRESS_KEY: &str = "dump_compress";
+const DISPATCH_QUEUE_LEN_KEY: &str = "dispatch_queue_len";
+const OVERLOAD_POLICY_KEY: &str = "overload_policy";
+const WILL_DELAY_KEY: &str = "will_delay";
+const RETAINED_DELIVERY_WINDOW_KEY: &str = "retained_delivery_window";
+const MOUNT_POINT_KEY: &str = "mount_point";
+const BRIDGE_PORT_KEY: &str = "bridge_port";
+const PACKET_READ_TIMEOUT_KEY: &str = "packet_read_timeout";
+const STRICT_PROTOCOL_KEY: &str = "strict_protocol";
+const ALLOW_MQTT_31_KEY: &str = "allow_mqtt_31";
+const NO_LOCAL_KEY: &str = "no_local";
+const MAX_CLIENTS_KEY: &str = "max_clients";
+const MAX_CLIENT_THREADS_KEY: &str = "max_client_threads";
+const LISTEN_BACKLOG_KEY: &str = "listen_backlog";
+const CONNECT_TIMEOUT_KEY: &str = "connect_timeout";
+const WRITE_TIMEOUT_KEY: &str = "write_timeout";
+const MAX_PENDING_CONNECTIONS_KEY: &str = "max_pending_connections";
+const MAX_TOPIC_LEN_KEY: &str = "max_topic_len";
+const MAX_PAYLOAD_SIZE_KEY: &str = "max_payload_size";
+const RETRY_INTERVAL_KEY: &str = "retry_interval";
+const MAX_RETRIES_KEY: &str = "max_retries";
+const MAX_KEEP_ALIVE_KEY: &str = "max_keep_alive";
+const MAX_RETAINED_KEY: &str = "max_retained";
+const MAX_RETAINED_BYTES_KEY: &str = "max_retained_bytes";
 const LOG_FILE_LEVEL_KEY: &str = "log_file_level";
 const LOG_STDOUT_LEVEL_KEY: &str = "log_stdout_level";
+const LOG_FORMAT_KEY: &str = "log_format";
+const THREADPOOL_SIZE_KEY: &str = "threadpool_size";
+const DUMP_INTERVAL_KEY: &str = "dump_interval";
+const SERVER_TABLE: &str = "server";
+const BRIDGE_TABLE: &str = "bridge";
+
+/// Threadpool size used if the configuration does not specify one
+pub const DEFAULT_THREADPOOL_SIZE: usize = 8;
+// Valores por defecto de las claves opcionales del formato TOML
+const DEFAULT_IP: &str = "localhost";
+const DEFAULT_LOG_PATH: &str = "logs";
+const DEFAULT_LOG_LEVEL: Level = Level::INFO;
 
 const SEP: &str = "=";
 
@@ -43,6 +123,21 @@ impl FileConfig {
     /// Each line of the file must consist of `field=value`:
     /// port, dump_path, dump_time, log_path, ip
     ///
+    /// Optionally, it can also specify bind_address (if not
+    /// specified, the server listens on ip), dual_stack and
+    /// dump_compress (true or false, false by default), dispatch_queue_len,
+    /// overload_policy (backpressure, drop_oldest or drop_newest),
+    /// will_delay (in seconds), retained_delivery_window (in milliseconds),
+    /// mount_point, bridge_port,
+    /// packet_read_timeout (in seconds),
+    /// strict_protocol, allow_mqtt_31 and no_local (true or false, false by default),
+    /// max_clients, max_client_threads,
+    /// listen_backlog, connect_timeout and write_timeout (in seconds),
+    /// max_pending_connections, max_topic_len and max_payload_size (in bytes),
+    /// retry_interval (in seconds), max_retries, max_keep_alive (in seconds),
+    /// max_retained, max_retained_bytes, threadpool_size and
+    /// log_format (pretty, compact or json, pretty by default)
+    ///
     /// # Errors
     /// If the file following the path does not have the correct format, this function returns None
     pub fn new(path: &str) -> Option<FileConfig> {
@@ -66,11 +161,19 @@ impl FileConfig {
             })
             .collect::<Option<HashMap<_, _>>>()?;
 
+        let dump_compress = match config.remove(DUMP_COMPRESS_KEY) {
+            Some(dump_compress) => dump_compress.parse().ok()?,
+            None => false,
+        };
         let dump_info;
         let dump_path = config.remove(DUMP_PATH_KEY)?;
         if !dump_path.is_empty() {
             let dump_time = Duration::from_secs(config.remove(DUMP_TIME_KEY)?.parse().ok()?);
-            dump_info = Some((dump_path, dump_time));
+            dump_info = Some(DumpConfig {
+                path: PathBuf::from(dump_path),
+                interval: dump_time,
+                compress: dump_compress,
+            });
         } else {
             dump_info = None;
         }
@@ -81,11 +184,235 @@ impl FileConfig {
             log_path: config.remove(LOG_PATH_KEY)?,
             accounts_path: config.remove(ACCOUNTS_PATH_KEY),
             ip: config.remove(IP_KEY)?,
+            bind_address: config.remove(BIND_ADDRESS_KEY),
+            dual_stack: match config.remove(DUAL_STACK_KEY) {
+                Some(dual_stack) => dual_stack.parse().ok()?,
+                None => false,
+            },
+            dispatch_queue_len: match config.remove(DISPATCH_QUEUE_LEN_KEY) {
+                Some(len) => Some(len.parse().ok()?),
+                None => None,
+            },
+            overload_policy: match config.remove(OVERLOAD_POLICY_KEY) {
+                Some(policy) => Some(policy.parse().ok()?),
+                None => None,
+            },
+            will_delay: match config.remove(WILL_DELAY_KEY) {
+                Some(secs) => Some(Duration::from_secs(secs.parse().ok()?)),
+                None => None,
+            },
+            retained_delivery_window: match config.remove(RETAINED_DELIVERY_WINDOW_KEY) {
+                Some(millis) => Some(Duration::from_millis(millis.parse().ok()?)),
+                None => None,
+            },
+            mount_point: config.remove(MOUNT_POINT_KEY),
+            bridge_port: match config.remove(BRIDGE_PORT_KEY) {
+                Some(port) => Some(port.parse().ok()?),
+                None => None,
+            },
+            packet_read_timeout: match config.remove(PACKET_READ_TIMEOUT_KEY) {
+                Some(secs) => Some(Duration::from_secs(secs.parse().ok()?)),
+                None => None,
+            },
+            strict_protocol: match config.remove(STRICT_PROTOCOL_KEY) {
+                Some(strict_protocol) => strict_protocol.parse().ok()?,
+                None => false,
+            },
+            allow_mqtt_31: match config.remove(ALLOW_MQTT_31_KEY) {
+                Some(allow_mqtt_31) => allow_mqtt_31.parse().ok()?,
+                None => false,
+            },
+            no_local: match config.remove(NO_LOCAL_KEY) {
+                Some(no_local) => no_local.parse().ok()?,
+                None => false,
+            },
+            max_clients: match config.remove(MAX_CLIENTS_KEY) {
+                Some(max_clients) => Some(max_clients.parse().ok()?),
+                None => None,
+            },
+            max_client_threads: match config.remove(MAX_CLIENT_THREADS_KEY) {
+                Some(max_threads) => Some(max_threads.parse().ok()?),
+                None => None,
+            },
+            listen_backlog: match config.remove(LISTEN_BACKLOG_KEY) {
+                Some(backlog) => Some(backlog.parse().ok()?),
+                None => None,
+            },
+            connect_timeout: match config.remove(CONNECT_TIMEOUT_KEY) {
+                Some(secs) => Some(Duration::from_secs(secs.parse().ok()?)),
+                None => None,
+            },
+            write_timeout: match config.remove(WRITE_TIMEOUT_KEY) {
+                Some(secs) => Some(Duration::from_secs(secs.parse().ok()?)),
+                None => None,
+            },
+            max_pending_connections: match config.remove(MAX_PENDING_CONNECTIONS_KEY) {
+                Some(max_pending) => Some(max_pending.parse().ok()?),
+                None => None,
+            },
+            max_topic_len: match config.remove(MAX_TOPIC_LEN_KEY) {
+                Some(max_len) => Some(max_len.parse().ok()?),
+                None => None,
+            },
+            max_payload_size: match config.remove(MAX_PAYLOAD_SIZE_KEY) {
+                Some(max_size) => Some(max_size.parse().ok()?),
+                None => None,
+            },
+            retry_interval: match config.remove(RETRY_INTERVAL_KEY) {
+                Some(secs) => Some(Duration::from_secs(secs.parse().ok()?)),
+                None => None,
+            },
+            max_retries: match config.remove(MAX_RETRIES_KEY) {
+                Some(max_retries) => Some(max_retries.parse().ok()?),
+                None => None,
+            },
+            max_keep_alive: match config.remove(MAX_KEEP_ALIVE_KEY) {
+                Some(secs) => Some(secs.parse().ok()?),
+                None => None,
+            },
+            max_retained: match config.remove(MAX_RETAINED_KEY) {
+                Some(max_retained) => Some(max_retained.parse().ok()?),
+                None => None,
+            },
+            max_retained_bytes: match config.remove(MAX_RETAINED_BYTES_KEY) {
+                Some(max_bytes) => Some(max_bytes.parse().ok()?),
+                None => None,
+            },
+            // Los bridges solo se pueden configurar en formato TOML
+            bridges: vec![],
+            unknown_keys: vec![],
             log_file_level: config.remove(LOG_FILE_LEVEL_KEY)?.parse().ok()?,
             log_stdout_level: config.remove(LOG_STDOUT_LEVEL_KEY)?.parse().ok()?,
+            log_format: match config.remove(LOG_FORMAT_KEY) {
+                Some(format) => format.parse().ok()?,
+                None => LogFormat::default(),
+            },
+            threadpool_size: match config.remove(THREADPOOL_SIZE_KEY) {
+                Some(size) => size.parse().ok()?,
+                None => DEFAULT_THREADPOOL_SIZE,
+            },
+        })
+    }
+
+    /// Returns a Config struct based on a TOML file
+    ///
+    /// The file must have a `[server]` table. The only required
+    /// key is `port`. It may also specify `bind_address`,
+    /// `dump_path` and `dump_interval` (in seconds, required if
+    /// `dump_path` is specified), `threadpool_size`, and the rest
+    /// of the keys of the `field=value` format (see `new()`)
+    ///
+    /// Each `[[bridge]]` table adds a bridge to a remote broker (see
+    /// [`BridgeConfig`]), with the keys `remote` (required), `name`,
+    /// `topics_in`, `topics_out`, `user_name` and `password`
+    ///
+    /// Unknown keys are ignored, and can be obtained afterwards
+    /// with `unknown_keys()` to report them
+    ///
+    /// # Errors
+    /// If the file cannot be read or does not have the correct format,
+    /// this function returns an error of kind [`ServerErrorKind::InvalidConfig`]
+    pub fn from_toml(path: &str) -> ServerResult<FileConfig> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| invalid_config(format!("No se pudo leer el archivo {}: {}", path, e)))?;
+        FileConfig::from_toml_str(&content)
+    }
+
+    /// Returns a Config struct from the content of a TOML file
+    /// (see `from_toml()`)
+    pub fn from_toml_str(content: &str) -> ServerResult<FileConfig> {
+        let mut root: Table =
+            toml::from_str(content).map_err(|e| invalid_config(format!("TOML invalido: {}", e)))?;
+        let mut table = match root.remove(SERVER_TABLE) {
+            Some(Value::Table(table)) => table,
+            Some(_) => return Err(invalid_config("[server] debe ser una tabla".to_string())),
+            None => return Err(invalid_config("Falta la tabla [server]".to_string())),
+        };
+        let bridges = take_toml(&mut root, BRIDGE_TABLE)?.unwrap_or_default();
+        let mut unknown_keys: Vec<String> = root.keys().cloned().collect();
+
+        let port = take_toml(&mut table, PORT_KEY)?
+            .ok_or_else(|| invalid_config(format!("Falta la clave obligatoria <{}>", PORT_KEY)))?;
+        let dump_compress = take_toml(&mut table, DUMP_COMPRESS_KEY)?.unwrap_or(false);
+        let dump_info = match take_toml::<String>(&mut table, DUMP_PATH_KEY)? {
+            Some(dump_path) => {
+                let dump_interval = take_toml(&mut table, DUMP_INTERVAL_KEY)?.ok_or_else(|| {
+                    invalid_config(format!(
+                        "La clave <{}> es obligatoria si se especifica <{}>",
+                        DUMP_INTERVAL_KEY, DUMP_PATH_KEY
+                    ))
+                })?;
+                Some(DumpConfig {
+                    path: PathBuf::from(dump_path),
+                    interval: Duration::from_secs(dump_interval),
+                    compress: dump_compress,
+                })
+            }
+            None => None,
+        };
+
+        let config = FileConfig {
+            port,
+            dump_info,
+            log_path: take_toml(&mut table, LOG_PATH_KEY)?
+                .unwrap_or_else(|| DEFAULT_LOG_PATH.to_string()),
+            accounts_path: take_toml(&mut table, ACCOUNTS_PATH_KEY)?,
+            ip: take_toml(&mut table, IP_KEY)?.unwrap_or_else(|| DEFAULT_IP.to_string()),
+            bind_address: take_toml(&mut table, BIND_ADDRESS_KEY)?,
+            dual_stack: take_toml(&mut table, DUAL_STACK_KEY)?.unwrap_or(false),
+            dispatch_queue_len: take_toml(&mut table, DISPATCH_QUEUE_LEN_KEY)?,
+            overload_policy: take_toml(&mut table, OVERLOAD_POLICY_KEY)?,
+            will_delay: take_toml(&mut table, WILL_DELAY_KEY)?.map(Duration::from_secs),
+            retained_delivery_window: take_toml(&mut table, RETAINED_DELIVERY_WINDOW_KEY)?
+                .map(Duration::from_millis),
+            mount_point: take_toml(&mut table, MOUNT_POINT_KEY)?,
+            bridge_port: take_toml(&mut table, BRIDGE_PORT_KEY)?,
+            packet_read_timeout: take_toml(&mut table, PACKET_READ_TIMEOUT_KEY)?
+                .map(Duration::from_secs),
+            strict_protocol: take_toml(&mut table, STRICT_PROTOCOL_KEY)?.unwrap_or(false),
+            allow_mqtt_31: take_toml(&mut table, ALLOW_MQTT_31_KEY)?.unwrap_or(false),
+            no_local: take_toml(&mut table, NO_LOCAL_KEY)?.unwrap_or(false),
+            max_clients: take_toml(&mut table, MAX_CLIENTS_KEY)?,
+            max_client_threads: take_toml(&mut table, MAX_CLIENT_THREADS_KEY)?,
+            listen_backlog: take_toml(&mut table, LISTEN_BACKLOG_KEY)?,
+            connect_timeout: take_toml(&mut table, CONNECT_TIMEOUT_KEY)?.map(Duration::from_secs),
+            write_timeout: take_toml(&mut table, WRITE_TIMEOUT_KEY)?.map(Duration::from_secs),
+            max_pending_connections: take_toml(&mut table, MAX_PENDING_CONNECTIONS_KEY)?,
+            max_topic_len: take_toml(&mut table, MAX_TOPIC_LEN_KEY)?,
+            max_payload_size: take_toml(&mut table, MAX_PAYLOAD_SIZE_KEY)?,
+            retry_interval: take_toml(&mut table, RETRY_INTERVAL_KEY)?.map(Duration::from_secs),
+            max_retries: take_toml(&mut table, MAX_RETRIES_KEY)?,
+            max_keep_alive: take_toml(&mut table, MAX_KEEP_ALIVE_KEY)?,
+            max_retained: take_toml(&mut table, MAX_RETAINED_KEY)?,
+            max_retained_bytes: take_toml(&mut table, MAX_RETAINED_BYTES_KEY)?,
+            bridges,
+            unknown_keys: vec![],
+            log_file_level: take_toml_level(&mut table, LOG_FILE_LEVEL_KEY)?,
+            log_stdout_level: take_toml_level(&mut table, LOG_STDOUT_LEVEL_KEY)?,
+            log_format: take_toml_parsed(&mut table, LOG_FORMAT_KEY)?.unwrap_or_default(),
+            threadpool_size: take_toml(&mut table, THREADPOOL_SIZE_KEY)?
+                .unwrap_or(DEFAULT_THREADPOOL_SIZE),
+        };
+
+        unknown_keys.extend(table.keys().cloned());
+        Ok(FileConfig {
+            unknown_keys,
+            ..config
         })
     }
 
+    /// Returns the keys of the configuration file that were not
+    /// recognized, and therefore ignored
+    pub fn unknown_keys(&self) -> &[String] {
+        &self.unknown_keys
+    }
+
+    /// Returns the size of the threadpool used to
+    /// process the packets
+    pub fn threadpool_size(&self) -> usize {
+        self.threadpool_size
+    }
+
     /// Returns the file log level
     pub fn log_file_level(&self) -> Level {
         self.log_file_level
@@ -97,15 +424,51 @@ impl FileConfig {
     }
 }
 
+#[doc(hidden)]
+fn invalid_config(msg: String) -> ServerError {
+    ServerError::new_kind(&msg, ServerErrorKind::InvalidConfig)
+}
+
+#[doc(hidden)]
+// Saca la clave de la tabla, devolviendo error si tiene un tipo invalido
+fn take_toml<T: DeserializeOwned>(table: &mut Table, key: &str) -> ServerResult<Option<T>> {
+    match table.remove(key) {
+        Some(value) => value
+            .try_into()
+            .map(Some)
+            .map_err(|e| invalid_config(format!("Valor invalido para <{}>: {}", key, e))),
+        None => Ok(None),
+    }
+}
+
+#[doc(hidden)]
+// Saca la clave de la tabla, parseando el string que contiene
+fn take_toml_parsed<T>(table: &mut Table, key: &str) -> ServerResult<Option<T>>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match take_toml::<String>(table, key)? {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| invalid_config(format!("Valor invalido para <{}>: {}", key, e))),
+        None => Ok(None),
+    }
+}
+
+#[doc(hidden)]
+fn take_toml_level(table: &mut Table, key: &str) -> ServerResult<Level> {
+    Ok(take_toml_parsed(table, key)?.unwrap_or(DEFAULT_LOG_LEVEL))
+}
+
 impl Config for FileConfig {
     fn port(&self) -> u16 {
         self.port
     }
 
-    fn dump_info(&self) -> Option<(&str, Duration)> {
-        self.dump_info
-            .as_ref()
-            .map(|dump_info| (dump_info.0.as_str(), dump_info.1))
+    fn dump_info(&self) -> Option<DumpConfig> {
+        self.dump_info.clone()
     }
 
     fn log_path(&self) -> &str {
@@ -116,6 +479,119 @@ impl Config for FileConfig {
         &self.ip
     }
 
+    fn bind_address(&self) -> &str {
+        self.bind_address.as_deref().unwrap_or(&self.ip)
+    }
+
+    fn dual_stack(&self) -> bool {
+        self.dual_stack
+    }
+
+    fn dispatch_queue_len(&self) -> usize {
+        match self.dispatch_queue_len {
+            Some(len) => len,
+            None => DEFAULT_DISPATCH_QUEUE_LEN,
+        }
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        self.overload_policy.unwrap_or_default()
+    }
+
+    fn will_delay(&self) -> Option<Duration> {
+        self.will_delay
+    }
+
+    fn retained_delivery_window(&self) -> Option<Duration> {
+        self.retained_delivery_window
+    }
+
+    fn mount_point(&self) -> Option<&str> {
+        self.mount_point.as_deref()
+    }
+
+    fn packet_read_timeout(&self) -> Duration {
+        self.packet_read_timeout
+            .unwrap_or(DEFAULT_PACKET_READ_TIMEOUT)
+    }
+
+    fn strict_protocol(&self) -> bool {
+        self.strict_protocol
+    }
+
+    fn allow_mqtt_31(&self) -> bool {
+        self.allow_mqtt_31
+    }
+
+    fn no_local(&self) -> bool {
+        self.no_local
+    }
+
+    fn max_clients(&self) -> Option<usize> {
+        self.max_clients
+    }
+
+    fn max_client_threads(&self) -> Option<usize> {
+        self.max_client_threads
+    }
+
+    fn listen_backlog(&self) -> u32 {
+        self.listen_backlog.unwrap_or(DEFAULT_LISTEN_BACKLOG)
+    }
+
+    fn connect_timeout(&self) -> Duration {
+        self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    fn write_timeout(&self) -> Duration {
+        self.write_timeout.unwrap_or(DEFAULT_WRITE_TIMEOUT)
+    }
+
+    fn max_pending_connections(&self) -> usize {
+        self.max_pending_connections
+            .unwrap_or(DEFAULT_MAX_PENDING_CONNECTIONS)
+    }
+
+    fn max_topic_len(&self) -> usize {
+        self.max_topic_len.unwrap_or(DEFAULT_MAX_TOPIC_LEN)
+    }
+
+    fn max_payload_size(&self) -> Option<usize> {
+        self.max_payload_size
+    }
+
+    fn retry_interval(&self) -> Duration {
+        self.retry_interval.unwrap_or(DEFAULT_RETRY_INTERVAL)
+    }
+
+    fn max_retries(&self) -> u32 {
+        self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES)
+    }
+
+    fn max_keep_alive(&self) -> Option<u16> {
+        self.max_keep_alive
+    }
+
+    fn max_retained(&self) -> Option<usize> {
+        self.max_retained
+    }
+
+    fn max_retained_bytes(&self) -> Option<usize> {
+        self.max_retained_bytes
+    }
+
+    fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    fn bridges(&self) -> &[BridgeConfig] {
+        &self.bridges
+    }
+
+    fn bridge_port(&self) -> Option<u16> {
+        self.bridge_port
+    }
+
     fn authenticator(&self) -> Option<Box<dyn Login>> {
         let login = SimpleLogin::new(self.accounts_path.as_ref()?).ok()?;
         Some(Box::new(login))
@@ -124,12 +600,17 @@ impl Config for FileConfig {
 
 #[cfg(test)]
 mod tests {
-    use std::{io::Cursor, time::Duration};
+    use std::{io::Cursor, path::PathBuf, time::Duration};
 
     use tracing::Level;
 
-    use crate::config::FileConfig;
-    use crate::traits::Config;
+    use crate::config::{FileConfig, DEFAULT_THREADPOOL_SIZE};
+    use crate::server::server_error::ServerErrorKind;
+    use crate::traits::{
+        Config, DumpConfig, LogFormat, OverloadPolicy, DEFAULT_CONNECT_TIMEOUT,
+        DEFAULT_LISTEN_BACKLOG, DEFAULT_MAX_PENDING_CONNECTIONS, DEFAULT_MAX_RETRIES,
+        DEFAULT_MAX_TOPIC_LEN, DEFAULT_RETRY_INTERVAL, DEFAULT_WRITE_TIMEOUT,
+    };
 
     #[test]
     fn test_valid_file() {
@@ -146,8 +627,11 @@ log_stdout_level=info",
 
         let config = FileConfig::new_from_file(cursor).unwrap();
         assert_eq!(config.port(), 8080);
-        assert_eq!(config.dump_info().unwrap().0, "foo.txt");
-        assert_eq!(config.dump_info().unwrap().1, Duration::from_secs(10));
+        assert_eq!(config.dump_info().unwrap().path, PathBuf::from("foo.txt"));
+        assert_eq!(
+            config.dump_info().unwrap().interval,
+            Duration::from_secs(10)
+        );
         assert_eq!(config.log_path(), "bar.txt");
         assert!(config.authenticator().is_none());
         assert_eq!(config.ip(), "localhost");
@@ -170,8 +654,11 @@ accounts_path=
 
         let config = FileConfig::new_from_file(cursor).unwrap();
         assert_eq!(config.port(), 8080);
-        assert_eq!(config.dump_info().unwrap().0, "foo.txt");
-        assert_eq!(config.dump_info().unwrap().1, Duration::from_secs(10));
+        assert_eq!(config.dump_info().unwrap().path, PathBuf::from("foo.txt"));
+        assert_eq!(
+            config.dump_info().unwrap().interval,
+            Duration::from_secs(10)
+        );
         assert_eq!(config.log_path(), "bar.txt");
         assert!(config.authenticator().is_none());
         assert_eq!(config.ip(), "localhost");
@@ -211,6 +698,28 @@ log_stdout_level=trace",
         assert!(FileConfig::new_from_file(cursor).is_none());
     }
 
+    #[test]
+    fn test_dump_info() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=dumps/dump.json.gz
+dump_time=45
+dump_compress=true
+log_path=bar.txt
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        let dump_info = config.dump_info().unwrap();
+
+        assert_eq!(dump_info.path, PathBuf::from("dumps/dump.json.gz"));
+        assert_eq!(dump_info.interval, Duration::from_secs(45));
+        assert!(dump_info.compress);
+        assert_eq!(config.dump_interval(), Duration::from_secs(45));
+    }
+
     #[test]
     fn test_no_dump_info() {
         let cursor = Cursor::new(
@@ -232,4 +741,263 @@ log_stdout_level=trace",
         assert!(config.authenticator().is_none());
         assert_eq!(config.ip(), "localhost");
     }
+
+    #[test]
+    fn test_bind_address() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+bind_address=::
+dual_stack=true
+dispatch_queue_len=16
+overload_policy=drop_oldest
+will_delay=5
+retained_delivery_window=250
+mount_point=tenantA
+bridge_port=8883
+strict_protocol=true
+allow_mqtt_31=true
+no_local=true
+max_clients=100
+max_client_threads=200
+listen_backlog=4096
+connect_timeout=3
+write_timeout=7
+max_pending_connections=8
+max_topic_len=256
+max_payload_size=1024
+retry_interval=4
+max_retries=2
+max_keep_alive=60
+max_retained=1000
+max_retained_bytes=65536
+log_format=compact
+log_file_level=warn
+log_stdout_level=trace",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.ip(), "localhost");
+        assert_eq!(config.bind_address(), "::");
+        assert!(config.dual_stack());
+        assert_eq!(config.dispatch_queue_len(), 16);
+        assert_eq!(config.overload_policy(), OverloadPolicy::DropOldest);
+        assert_eq!(config.will_delay(), Some(Duration::from_secs(5)));
+        assert_eq!(
+            config.retained_delivery_window(),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(config.mount_point(), Some("tenantA"));
+        assert_eq!(config.bridge_port(), Some(8883));
+        assert!(config.strict_protocol());
+        assert!(config.allow_mqtt_31());
+        assert!(config.no_local());
+        assert_eq!(config.max_clients(), Some(100));
+        assert_eq!(config.max_client_threads(), Some(200));
+        assert_eq!(config.listen_backlog(), 4096);
+        assert_eq!(config.connect_timeout(), Duration::from_secs(3));
+        assert_eq!(config.write_timeout(), Duration::from_secs(7));
+        assert_eq!(config.max_pending_connections(), 8);
+        assert_eq!(config.max_topic_len(), 256);
+        assert_eq!(config.max_payload_size(), Some(1024));
+        assert_eq!(config.retry_interval(), Duration::from_secs(4));
+        assert_eq!(config.max_retries(), 2);
+        assert_eq!(config.max_keep_alive(), Some(60));
+        assert_eq!(config.max_retained(), Some(1000));
+        assert_eq!(config.max_retained_bytes(), Some(65536));
+        assert_eq!(config.log_format(), LogFormat::Compact);
+    }
+
+    #[test]
+    fn test_bind_address_defaults_to_ip() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=0.0.0.0
+log_file_level=warn
+log_stdout_level=trace",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.bind_address(), "0.0.0.0");
+        assert!(!config.dual_stack());
+        assert_eq!(config.overload_policy(), OverloadPolicy::Backpressure);
+        assert!(config.will_delay().is_none());
+        assert!(config.retained_delivery_window().is_none());
+        assert!(config.mount_point().is_none());
+        assert!(config.bridge_port().is_none());
+        assert!(!config.strict_protocol());
+        assert!(!config.allow_mqtt_31());
+        assert!(!config.no_local());
+        assert!(config.max_clients().is_none());
+        assert!(config.max_client_threads().is_none());
+        assert_eq!(config.listen_backlog(), DEFAULT_LISTEN_BACKLOG);
+        assert_eq!(config.connect_timeout(), DEFAULT_CONNECT_TIMEOUT);
+        assert_eq!(config.write_timeout(), DEFAULT_WRITE_TIMEOUT);
+        assert_eq!(
+            config.max_pending_connections(),
+            DEFAULT_MAX_PENDING_CONNECTIONS
+        );
+        assert_eq!(config.max_topic_len(), DEFAULT_MAX_TOPIC_LEN);
+        assert!(config.max_payload_size().is_none());
+        assert_eq!(config.retry_interval(), DEFAULT_RETRY_INTERVAL);
+        assert_eq!(config.max_retries(), DEFAULT_MAX_RETRIES);
+        assert!(config.max_keep_alive().is_none());
+        assert!(config.max_retained().is_none());
+        assert!(config.max_retained_bytes().is_none());
+    }
+
+    #[test]
+    fn test_invalid_dual_stack() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+dual_stack=quizas
+log_file_level=warn
+log_stdout_level=trace",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_none());
+    }
+
+    #[test]
+    fn test_toml_valid() {
+        let config = FileConfig::from_toml_str(
+            r#"
+[server]
+port = 1883
+bind_address = "0.0.0.0"
+dump_path = "dump.json"
+dump_interval = 30
+threadpool_size = 4
+overload_policy = "drop_newest"
+log_format = "json"
+clave_desconocida = "se ignora"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.port(), 1883);
+        assert_eq!(config.bind_address(), "0.0.0.0");
+        assert_eq!(
+            config.dump_info(),
+            Some(DumpConfig {
+                path: PathBuf::from("dump.json"),
+                interval: Duration::from_secs(30),
+                compress: false,
+            })
+        );
+        assert_eq!(config.threadpool_size(), 4);
+        assert_eq!(config.overload_policy(), OverloadPolicy::DropNewest);
+        assert_eq!(config.log_file_level(), Level::INFO);
+        assert_eq!(config.log_format(), LogFormat::Json);
+        assert!(config.authenticator().is_none());
+        assert_eq!(config.unknown_keys(), ["clave_desconocida"]);
+    }
+
+    #[test]
+    fn test_toml_bridges() {
+        let config = FileConfig::from_toml_str(
+            r#"
+[server]
+port = 1883
+
+[[bridge]]
+remote = "10.0.0.2:1883"
+topics_in = ["sensores/#"]
+topics_out = ["comandos/+"]
+
+[[bridge]]
+remote = "10.0.0.3:1883"
+name = "respaldo"
+"#,
+        )
+        .unwrap();
+
+        let bridges = config.bridges();
+        assert_eq!(bridges.len(), 2);
+        assert_eq!(bridges[0].remote, "10.0.0.2:1883");
+        assert_eq!(bridges[0].topics_in, vec!["sensores/#"]);
+        assert_eq!(bridges[0].topics_out, vec!["comandos/+"]);
+        assert!(bridges[0].name.is_none());
+        assert_eq!(bridges[1].name.as_deref(), Some("respaldo"));
+        assert!(bridges[1].topics_in.is_empty());
+    }
+
+    #[test]
+    fn test_toml_bridge_without_remote() {
+        let error = FileConfig::from_toml_str("[server]\nport = 1883\n\n[[bridge]]\nname = \"a\"")
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ServerErrorKind::InvalidConfig);
+    }
+
+    #[test]
+    fn test_toml_defaults() {
+        let config = FileConfig::from_toml_str("[server]\nport = 1883").unwrap();
+
+        assert!(config.dump_info().is_none());
+        assert_eq!(config.ip(), "localhost");
+        assert_eq!(config.bind_address(), "localhost");
+        assert_eq!(config.threadpool_size(), DEFAULT_THREADPOOL_SIZE);
+        assert_eq!(config.log_format(), LogFormat::Pretty);
+        assert!(config.bridges().is_empty());
+    }
+
+    #[test]
+    fn test_toml_invalid_log_format() {
+        let error =
+            FileConfig::from_toml_str("[server]\nport = 1883\nlog_format = \"xml\"").unwrap_err();
+
+        assert_eq!(error.kind(), ServerErrorKind::InvalidConfig);
+        assert!(error.to_string().contains("log_format"));
+    }
+
+    #[test]
+    fn test_toml_missing_port() {
+        let error = FileConfig::from_toml_str(
+            r#"
+[server]
+bind_address = "0.0.0.0"
+"#,
+        )
+        .unwrap_err();
+
+        assert_eq!(error.kind(), ServerErrorKind::InvalidConfig);
+        assert!(error.to_string().contains("port"));
+    }
+
+    #[test]
+    fn test_toml_dump_path_without_interval() {
+        let error = FileConfig::from_toml_str(
+            r#"
+[server]
+port = 1883
+dump_path = "dump.json"
+"#,
+        )
+        .unwrap_err();
+
+        assert_eq!(error.kind(), ServerErrorKind::InvalidConfig);
+    }
+
+    #[test]
+    fn test_toml_invalid_port() {
+        let error = FileConfig::from_toml_str("[server]\nport = 70000").unwrap_err();
+
+        assert_eq!(error.kind(), ServerErrorKind::InvalidConfig);
+    }
 }
@@ -1,40 +1,102 @@
-use std::io::Read;
+use std::{
+    io::Read,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
 
-use tracing::info;
+use tracing::{info, warn};
 
+pub use crate::client::{ClientInfo, ClientStats};
 use crate::config::FileConfig;
-use logger::Logger;
-pub use crate::server::{Server, ServerController};
+pub use crate::server::server_error::{ServerError, ServerErrorKind};
+pub use crate::server::{
+    DumpState, JsonFileBackend, Server, ServerController, StateSummary, SubscriptionSummary,
+};
 pub use crate::traits::Config;
+use logger::Logger;
 
 mod client;
 mod clients_manager;
 mod config;
+#[cfg(any(test, feature = "bench"))]
+pub mod memory_stream;
 mod network_connection;
 mod server;
 mod test_helpers;
 mod topic_handler;
 pub mod traits;
 
-/// Initializes the server
+/// Extension of the configuration files in TOML format
+const TOML_EXTENSION: &str = ".toml";
+
+/// How often [`init`] checks whether the server should stop
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns, as JSON, a summary of the state stored in the
+/// dump file (see [`Server::export_state`])
+pub fn export_state(dump_path: &str) -> Result<String, ServerError> {
+    let json_str = JsonFileBackend::decode(std::fs::read(dump_path)?)?;
+    let summary = Server::<FileConfig>::export_state(&json_str)?;
+    Ok(serde_json::to_string_pretty(&summary)?)
+}
+
+/// Initializes the server.
+///
+/// If the path has the `.toml` extension, the configuration is
+/// read as TOML, with a `[server]` table and a `[[bridge]]` table
+/// for each bridge. Otherwise, it is read in the `field=value`
+/// format. Unknown keys are ignored, logging a warning.
+///
+/// The server stops when ENTER is pressed (or the standard input
+/// is closed), or when SIGINT or SIGTERM is received. In every case
+/// it shuts down gracefully, dumping its state before exiting
 pub fn init(config_path: &str) {
-    let config = FileConfig::new(config_path).expect("Error cargando la configuracion");
+    let config = if config_path.ends_with(TOML_EXTENSION) {
+        FileConfig::from_toml(config_path)
+            .unwrap_or_else(|e| panic!("Error cargando la configuracion: {}", e))
+    } else {
+        FileConfig::new(config_path).expect("Error cargando la configuracion")
+    };
 
-    let _logger = Logger::new(
+    let _logger = Logger::with_format(
         config.log_path(),
         config.log_file_level(),
         config.log_stdout_level(),
+        config.log_format(),
     );
+    for key in config.unknown_keys() {
+        warn!(
+            "Se ignora la clave desconocida <{}> de la configuracion",
+            key
+        );
+    }
 
-    let threadpool_size = 8;
+    let threadpool_size = config.threadpool_size();
     let server = Server::new(config, threadpool_size).expect("Error iniciando el servidor");
     let controller = server
         .run()
         .expect("Error iniciando ejecución del servidor");
 
+    controller
+        .shutdown_on_signals()
+        .expect("Error instalando el manejo de señales");
+
     info!("Presione [ENTER] para detener la ejecucion del servidor");
 
-    let mut buf = [0u8; 1];
-    std::io::stdin().read_exact(&mut buf).unwrap_or(());
+    let enter_pressed = Arc::new(AtomicBool::new(false));
+    let enter_pressed_copy = enter_pressed.clone();
+    thread::spawn(move || {
+        let mut buf = [0u8; 1];
+        std::io::stdin().read_exact(&mut buf).unwrap_or(());
+        enter_pressed_copy.store(true, Ordering::Relaxed);
+    });
+    while !controller.is_shutting_down() && !enter_pressed.load(Ordering::Relaxed) {
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+    info!("Deteniendo el servidor");
     drop(controller);
 }
@@ -1,6 +1,9 @@
 use std::env;
 
-use server::init;
+use server::{export_state, init};
+
+/// Prints a summary of a dump file instead of running the server
+const EXPORT_STATE_COMMAND: &str = "export-state";
 
 fn get_config_path(default_path: Option<String>) -> String {
     let args: Vec<String> = env::args().collect();
@@ -13,6 +16,17 @@ fn get_config_path(default_path: Option<String>) -> String {
     panic!("Error: Debe especificar la ruta al archivo de configuración")
 }
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 2 && args[1] == EXPORT_STATE_COMMAND {
+        match export_state(&args[2]) {
+            Ok(summary) => println!("{}", summary),
+            Err(e) => {
+                eprintln!("Error leyendo el DUMP: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     let config_path: String = get_config_path(Some("./config.txt".to_string()));
     init(&config_path);
 }
@@ -0,0 +1,243 @@
+//! In-memory transport, used to exercise the server without the
+//! overhead of the network stack (for example, to benchmark it).
+//!
+//! Only available with the `bench` feature
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::traits::Interrupt;
+
+/// Bytes of one direction of a [`MemoryStream`]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    capacity: usize,
+    closed: bool,
+}
+
+/// One direction of a [`MemoryStream`]: a bounded ring buffer
+/// shared by the writing end and the reading end
+struct Pipe {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+impl Pipe {
+    fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(PipeState {
+                bytes: VecDeque::with_capacity(capacity),
+                capacity,
+                closed: false,
+            }),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PipeState> {
+        // Ningun metodo deja el estado a medio modificar
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.readable.notify_all();
+        self.writable.notify_all();
+    }
+}
+
+/// One end of an in-memory connection, created with
+/// [`MemoryStream::pair`]. What is written to one end is read
+/// from the other one, like the two ends of a TCP connection.
+///
+/// Each direction holds at most a fixed amount of bytes: writes
+/// block while it is full, and reads block while it is empty
+/// (or until the read timeout expires). Once an end is dropped,
+/// the other one reads the remaining bytes and then the end of
+/// the stream, and its writes fail with
+/// [`io::ErrorKind::BrokenPipe`]
+pub struct MemoryStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Option<Duration>,
+}
+
+impl MemoryStream {
+    /// Creates the two ends of a connection, each direction
+    /// holding up to `capacity` bytes
+    pub fn pair(capacity: usize) -> (MemoryStream, MemoryStream) {
+        let capacity = capacity.max(1);
+        let (a_to_b, b_to_a) = (Pipe::new(capacity), Pipe::new(capacity));
+        let a = MemoryStream {
+            incoming: b_to_a.clone(),
+            outgoing: a_to_b.clone(),
+            read_timeout: None,
+        };
+        let b = MemoryStream {
+            incoming: a_to_b,
+            outgoing: b_to_a,
+            read_timeout: None,
+        };
+        (a, b)
+    }
+
+    /// Makes the reads that find no bytes fail after `timeout`
+    /// with [`io::ErrorKind::WouldBlock`], like a socket with a
+    /// read timeout. If None, they block until a byte arrives
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.incoming.lock();
+        while state.bytes.is_empty() && !state.closed {
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WouldBlock,
+                            "No hay bytes para leer",
+                        ));
+                    }
+                    self.incoming
+                        .readable
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .incoming
+                    .readable
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+        let len = buf.len().min(state.bytes.len());
+        for (dst, src) in buf.iter_mut().zip(state.bytes.drain(..len)) {
+            *dst = src;
+        }
+        drop(state);
+        self.incoming.writable.notify_all();
+        Ok(len)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.outgoing.lock();
+        while state.bytes.len() == state.capacity && !state.closed {
+            state = self
+                .outgoing
+                .writable
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        if state.closed {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+        }
+        let len = buf.len().min(state.capacity - state.bytes.len());
+        state.bytes.extend(&buf[..len]);
+        drop(state);
+        self.outgoing.readable.notify_all();
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Interrupt for MemoryStream {
+    fn alert(&mut self, when: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(when));
+        Ok(())
+    }
+
+    fn sleep(&mut self) -> io::Result<()> {
+        self.set_read_timeout(None);
+        Ok(())
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        thread,
+        time::Duration,
+    };
+
+    use super::MemoryStream;
+
+    #[test]
+    fn test_bytes_written_are_read_from_the_other_end() {
+        let (mut a, mut b) = MemoryStream::pair(16);
+        a.write_all(b"ping").unwrap();
+        b.write_all(b"pong").unwrap();
+
+        let mut buf = [0u8; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        a.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[test]
+    fn test_writes_larger_than_capacity_wait_for_the_reader() {
+        let (mut a, mut b) = MemoryStream::pair(8);
+        let sent: Vec<u8> = (0..=255).collect();
+        let expected = sent.clone();
+        let writer = thread::spawn(move || a.write_all(&sent).unwrap());
+
+        let mut received = vec![0u8; expected.len()];
+        b.read_exact(&mut received).unwrap();
+        writer.join().unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_read_timeout_is_would_block() {
+        let (_a, mut b) = MemoryStream::pair(8);
+        b.set_read_timeout(Some(Duration::from_millis(10)));
+        let err = b.read(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_dropped_end_is_end_of_stream() {
+        let (mut a, mut b) = MemoryStream::pair(8);
+        a.write_all(b"ab").unwrap();
+        drop(a);
+
+        let mut received = vec![];
+        b.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"ab");
+        let err = b.write(b"c").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
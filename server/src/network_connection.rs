@@ -1,5 +1,10 @@
 use std::{
     io::{self},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
@@ -8,12 +13,45 @@ use crate::{
     traits::{Close, Interrupt, TryClone},
 };
 
+/// Amount of bytes transferred through a connection.
+///
+/// It is shared between a [`NetworkConnection`] and its
+/// copies, so both the reading and the writing end of
+/// the connection update the same counters
+#[derive(Debug, Default)]
+pub struct ByteCounters {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl ByteCounters {
+    /// Returns the amount of bytes read from the client
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Returns the amount of bytes written to the client
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Adds the given amounts to the counters. Useful to
+    /// carry over the counters of a previous connection
+    /// of the same session
+    pub fn add(&self, bytes_read: u64, bytes_written: u64) {
+        self.bytes_read.fetch_add(bytes_read, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes_written, Ordering::Relaxed);
+    }
+}
+
 /// Information related to the current session of
 /// the client
 #[derive(Debug)]
 pub struct NetworkConnection<S, I> {
     id: I,
     stream: S,
+    counters: Arc<ByteCounters>,
 }
 
 impl<S, I> NetworkConnection<S, I> {
@@ -26,17 +64,31 @@ impl<S, I> NetworkConnection<S, I> {
     pub fn id(&self) -> &I {
         &self.id
     }
+    pub fn counters(&self) -> &Arc<ByteCounters> {
+        &self.counters
+    }
+}
+
+impl<S> NetworkConnection<S, SocketAddr> {
+    /// Returns the address of the peer of the connection
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.id
+    }
 }
 
 impl<S: io::Read, I> io::Read for NetworkConnection<S, I> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.stream.read(buf)
+        let read = self.stream.read(buf)?;
+        self.counters.add(read as u64, 0);
+        Ok(read)
     }
 }
 
 impl<S: io::Write, I> io::Write for NetworkConnection<S, I> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.stream.write(buf)
+        let written = self.stream.write(buf)?;
+        self.counters.add(0, written as u64);
+        Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -56,14 +108,23 @@ impl<S: Interrupt, I> Interrupt for NetworkConnection<S, I> {
 
 impl<S, I> NetworkConnection<S, I> {
     pub fn new(id: I, stream: S) -> Self {
-        Self { id, stream }
+        Self {
+            id,
+            stream,
+            counters: Arc::new(ByteCounters::default()),
+        }
     }
 
+    /// Flushes the pending bytes and then closes the stream, so
+    /// the last packets sent (for example, a Puback) are not lost.
+    /// The stream is closed even if the flush fails
     pub fn close(&mut self) -> io::Result<()>
     where
         S: Close,
     {
-        self.stream.close()
+        let flushed = self.stream.flush();
+        self.stream.close()?;
+        flushed
     }
 
     pub fn try_clone(&self) -> ServerResult<Self>
@@ -80,6 +141,7 @@ impl<S, I> NetworkConnection<S, I> {
         Ok(NetworkConnection {
             id: self.id,
             stream,
+            counters: self.counters.clone(),
         })
     }
 }
@@ -0,0 +1,231 @@
+use packets::{publish::Publish, qos::QoSLevel};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use super::{
+    mount_point, poison::RwLockOrRecover, ClientId, ClientIdArg, Server, ServerError, ServerResult,
+};
+use crate::client::ClientInfo;
+use crate::traits::Config;
+
+/// Prefix of the topics of the admin commands. A command is issued by
+/// publishing to `$admin/<command>`, and its response is published to
+/// `$admin/<command>/response/<request_id>`
+pub const ADMIN_TOPIC_PREFIX: &str = "$admin";
+
+/// Topic level that separates the command from the request
+/// id in the topics of the responses
+const RESPONSE_LEVEL: &str = "response";
+
+/// Returns true if the topic (or topic filter) belongs
+/// to the admin commands namespace
+pub fn is_admin_topic(topic: &str) -> bool {
+    topic == ADMIN_TOPIC_PREFIX
+        || topic
+            .strip_prefix(ADMIN_TOPIC_PREFIX)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Payload of the [`Publish`] of an admin command, in JSON
+#[derive(Debug, Deserialize)]
+struct AdminRequest {
+    /// Last level of the topic the response is published to
+    request_id: String,
+    /// Client the command refers to, if it needs one
+    #[serde(default)]
+    client_id: Option<String>,
+}
+
+impl<C: Config> Server<C> {
+    /// Runs the admin command published by the client, and publishes
+    /// its response. The payload must be a JSON object with the
+    /// `request_id` and, for the `disconnect` command, the `client_id`
+    /// to disconnect. The supported commands are:
+    ///
+    /// * `list_clients`: the connected clients (see [`Server::connected_clients`])
+    /// * `stats`: the amount of clients, topics and publications
+    /// * `disconnect`: closes the connection of a client, as if it was lost
+    ///
+    /// The commands of a client with a mount point (see
+    /// [`Config::mount_point`]) only refer to the clients and topics
+    /// of its mount point, and its response is published inside it.
+    ///
+    /// If the command fails, the response is a JSON object with an
+    /// `error`. The publications of the clients that can not issue admin
+    /// commands (see [`Login::is_admin`](crate::traits::Login::is_admin)),
+    /// or that are not valid requests, are discarded. Either way, they
+    /// are never delivered to the subscribers of the topic
+    pub(super) fn handle_admin_command(
+        &self,
+        publish: &Publish,
+        id: &ClientIdArg,
+    ) -> ServerResult<()> {
+        if !self.clients_manager.read_or_recover().is_admin(id)? {
+            warn!("Comando de administracion de un cliente no autorizado");
+            return Ok(());
+        }
+        let command = match publish
+            .topic_name()
+            .strip_prefix(ADMIN_TOPIC_PREFIX)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            // Las respuestas y demas topics con mas niveles se ignoran
+            Some(command) if !command.is_empty() && !command.contains('/') => command,
+            _ => {
+                debug!("Se descarta publicacion a {}", publish.topic_name());
+                return Ok(());
+            }
+        };
+        let request = match serde_json::from_str::<AdminRequest>(publish.payload()) {
+            Ok(request) if !request.request_id.is_empty() => request,
+            Ok(_) => {
+                warn!("Comando de administracion <{}> sin request_id", command);
+                return Ok(());
+            }
+            Err(err) => {
+                warn!("Comando de administracion <{}> invalido: {}", command, err);
+                return Ok(());
+            }
+        };
+        info!("Comando de administracion <{}>", command);
+
+        let mount_point = self.clients_manager.read_or_recover().mount_point(id)?;
+        let response = self
+            .run_admin_command(command, &request, mount_point.as_deref())
+            .unwrap_or_else(|err| json!({ "error": err.to_string() }));
+        let mut topic = format!(
+            "{}/{}/{}/{}",
+            ADMIN_TOPIC_PREFIX, command, RESPONSE_LEVEL, request.request_id
+        );
+        if let Some(mount_point) = &mount_point {
+            topic = mount_point::mount(mount_point, &topic);
+        }
+        // El packet id se reemplaza por el de cada suscriptor
+        match Publish::new(
+            false,
+            QoSLevel::QoSLevel1,
+            false,
+            &topic,
+            &response.to_string(),
+            Some(1),
+        ) {
+            Ok(response) => self.broadcast_publish(response, None),
+            Err(err) => {
+                warn!("request_id invalido: {}", err);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the ids of the sessions of the clients of the mount
+    /// point. Without one, it returns every session
+    fn mounted_sessions(&self, mount_point: Option<&str>) -> ServerResult<Vec<ClientId>> {
+        let clients_manager = self.clients_manager.read_or_recover();
+        let mut sessions = vec![];
+        for id in clients_manager.client_ids() {
+            if mount_point.is_none() || clients_manager.mount_point(&id)?.as_deref() == mount_point
+            {
+                sessions.push(id);
+            }
+        }
+        Ok(sessions)
+    }
+
+    /// Returns the connected clients of the mount point, with
+    /// the ids seen by its clients. Without one, it returns
+    /// every connected client (see [`Server::connected_clients`])
+    fn mounted_clients(&self, mount_point: Option<&str>) -> ServerResult<Vec<ClientInfo>> {
+        let mount_point = match mount_point {
+            Some(mount_point) => mount_point,
+            None => return self.connected_clients(),
+        };
+        let sessions = self.mounted_sessions(Some(mount_point))?;
+        Ok(self
+            .connected_clients()?
+            .into_iter()
+            .filter(|client| sessions.contains(&client.id))
+            .map(|mut client| {
+                client.id = mount_point::client_id(mount_point, &client.id).to_owned();
+                client
+            })
+            .collect())
+    }
+
+    /// Runs an admin command, returning its response
+    fn run_admin_command(
+        &self,
+        command: &str,
+        request: &AdminRequest,
+        mount_point: Option<&str>,
+    ) -> ServerResult<Value> {
+        match command {
+            "list_clients" => Ok(json!(self.mounted_clients(mount_point)?)),
+            "stats" => {
+                let topic_stats = self.topic_stats()?;
+                let topic_stats: Vec<u64> = topic_stats
+                    .into_iter()
+                    .filter(|(topic, _)| match mount_point {
+                        Some(mount_point) => mount_point::unmount(mount_point, topic).is_some(),
+                        None => true,
+                    })
+                    .map(|(_, published)| published)
+                    .collect();
+                Ok(json!({
+                    "connected_clients": self.mounted_clients(mount_point)?.len(),
+                    "sessions": self.mounted_sessions(mount_point)?.len(),
+                    "topics": topic_stats.len(),
+                    "published": topic_stats.iter().sum::<u64>(),
+                }))
+            }
+            "disconnect" => {
+                let client_id = request
+                    .client_id
+                    .as_deref()
+                    .ok_or_else(|| ServerError::new_msg("Falta el client_id"))?;
+                let client_id = match mount_point {
+                    Some(mount_point) => {
+                        let session_id = mount_point::session_id(mount_point, client_id);
+                        let sessions = self.mounted_sessions(Some(mount_point))?;
+                        // Los ids asignados por el servidor no tienen el mount point
+                        if sessions.contains(&session_id) {
+                            session_id
+                        } else if sessions.iter().any(|id| id == client_id) {
+                            client_id.to_owned()
+                        } else {
+                            return Ok(json!({ "disconnected": false }));
+                        }
+                    }
+                    None => client_id.to_owned(),
+                };
+                let client_id = client_id.as_str();
+                let clients_manager = self.clients_manager.read_or_recover();
+                let disconnected = clients_manager.is_connected(client_id)?;
+                if disconnected {
+                    // El thread del cliente lo desconecta al notar el cierre
+                    clients_manager.client_do(client_id, |client| client.close_connection())?;
+                }
+                Ok(json!({ "disconnected": disconnected }))
+            }
+            _ => Err(ServerError::new_msg(format!(
+                "Comando desconocido: {}",
+                command
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_admin_topic;
+
+    #[test]
+    fn test_admin_topics() {
+        assert!(is_admin_topic("$admin"));
+        assert!(is_admin_topic("$admin/stats"));
+        assert!(is_admin_topic("$admin/#"));
+        assert!(!is_admin_topic("$administrator/stats"));
+        assert!(!is_admin_topic("admin/stats"));
+        assert!(!is_admin_topic("#"));
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    convert::TryFrom,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use packets::{
+    connack::Connack,
+    connect::ConnectBuilder,
+    helpers::PacketType,
+    puback::Puback,
+    publish::Publish,
+    suback::Suback,
+    subscribe::Subscribe,
+    topic_filter::TopicFilter,
+    traits::{MQTTDecoding, MQTTEncoding},
+};
+use tracing::{debug, error, info};
+
+use super::{poison::LockOrRecover, ServerError, ServerErrorKind, ServerResult, MAX_QOS};
+use crate::traits::BridgeConfig;
+
+/// Prefix of the client ids used by the bridges. They are only
+/// accepted through the listener of the bridges (see
+/// [`Config::bridge_port`](crate::Config::bridge_port))
+pub const BRIDGE_ID_PREFIX: &str = "$bridge/";
+
+/// Maximum time to wait for the connection, Connack and Suback of each broker
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time to wait before the first reconnection. It is doubled
+/// after each failed attempt, up to [`MAX_RECONNECT_DELAY`]
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// How often to check if the server is shutting down while
+/// waiting to reconnect
+const SHUTDOWN_CHECK: Duration = Duration::from_millis(100);
+
+/// Packet identifier of the Subscribe sent by the bridge
+const SUBSCRIBE_PACKET_ID: u16 = 1;
+
+/// Returns true if the client id belongs to a bridge
+pub fn is_bridge_id(id: &str) -> bool {
+    id.starts_with(BRIDGE_ID_PREFIX)
+}
+
+/// Write half of one of the connections of a bridge, shared
+/// between the thread that reads from it (which sends the
+/// acknowledgements) and the one that forwards messages to it
+struct BridgeWriter {
+    stream: Mutex<TcpStream>,
+}
+
+impl BridgeWriter {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            stream: Mutex::new(stream),
+        }
+    }
+
+    fn send<T: MQTTEncoding>(&self, packet: &T) -> ServerResult<()> {
+        self.stream.lock_or_recover().write_all(&packet.encode()?)?;
+        Ok(())
+    }
+
+    fn close(&self) {
+        let _ = self.stream.lock_or_recover().shutdown(Shutdown::Both);
+    }
+}
+
+/// Starts a bridge between the server whose bridges listen on
+/// `local_addr` and the remote broker of `config`, on a new thread.
+///
+/// The bridge connects to both brokers as a client, with the id
+/// [`BRIDGE_ID_PREFIX`] followed by its name, subscribes on the remote
+/// broker to `topics_in` and on the local one to `topics_out`, and
+/// republishes every message it receives on the other broker, with
+/// the QoS it was delivered with. The brokers do not deliver the
+/// messages back to the bridge that published them, so they do not
+/// loop between the brokers.
+///
+/// If the connection to any of the brokers fails or is closed, it
+/// reconnects, waiting longer after each failed attempt. It ends
+/// when `shutdown` is set
+pub fn start(
+    config: BridgeConfig,
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+) -> ServerResult<()> {
+    let name = config.name.clone().unwrap_or_else(|| config.remote.clone());
+    thread::Builder::new()
+        .name(format!("bridge {}", name))
+        .spawn(move || run(&config, &name, loopback(local_addr), &shutdown))?;
+    Ok(())
+}
+
+#[doc(hidden)]
+fn run(config: &BridgeConfig, name: &str, local_addr: SocketAddr, shutdown: &AtomicBool) {
+    let mut delay = INITIAL_RECONNECT_DELAY;
+    while !shutdown.load(Ordering::Relaxed) {
+        match connect_both(config, name, local_addr) {
+            Ok((local, remote)) => {
+                info!("Bridge <{}> conectado a {}", name, config.remote);
+                delay = INITIAL_RECONNECT_DELAY;
+                if let Err(err) = forward_both(local, remote, name) {
+                    error!("Error en el bridge <{}>: {}", name, err);
+                }
+                info!("Bridge <{}> desconectado", name);
+            }
+            Err(_) if shutdown.load(Ordering::Relaxed) => return,
+            Err(err) => {
+                error!("Error conectando el bridge <{}>: {}", name, err);
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+        // Se duerme de a intervalos cortos para notar rapido el apagado
+        let reconnect_at = Instant::now() + delay;
+        while Instant::now() < reconnect_at && !shutdown.load(Ordering::Relaxed) {
+            thread::sleep(SHUTDOWN_CHECK);
+        }
+    }
+}
+
+#[doc(hidden)]
+fn connect_both(
+    config: &BridgeConfig,
+    name: &str,
+    local_addr: SocketAddr,
+) -> ServerResult<(TcpStream, TcpStream)> {
+    let client_id = format!("{}{}", BRIDGE_ID_PREFIX, name);
+    let remote = connect(
+        config.remote.as_str(),
+        &client_id,
+        config,
+        &config.topics_in,
+    )?;
+    let local = connect(local_addr, &client_id, config, &config.topics_out)?;
+    Ok((local, remote))
+}
+
+/// Forwards the messages between both connections until
+/// any of them is closed
+fn forward_both(local: TcpStream, remote: TcpStream, name: &str) -> ServerResult<()> {
+    let remote_writer = Arc::new(BridgeWriter::new(remote.try_clone()?));
+    let local_writer = Arc::new(BridgeWriter::new(local.try_clone()?));
+
+    let (from, to) = (local_writer.clone(), remote_writer.clone());
+    let outgoing_name = name.to_owned();
+    let outgoing = thread::Builder::new()
+        .name(format!("bridge {} out", name))
+        .spawn(move || forward(local, &from, &to, &outgoing_name))?;
+    forward(remote, &remote_writer, &local_writer, name);
+    let _ = outgoing.join();
+    Ok(())
+}
+
+/// Returns the loopback address if `addr` is unspecified (such as
+/// 0.0.0.0), so that the bridge can connect to the local server
+fn loopback(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        let ip: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        };
+        addr.set_ip(ip);
+    }
+    addr
+}
+
+/// Connects to a broker and subscribes to `topics` with [`MAX_QOS`],
+/// waiting for the Connack and the Suback
+fn connect<A: ToSocketAddrs>(
+    addr: A,
+    client_id: &str,
+    config: &BridgeConfig,
+    topics: &[String],
+) -> ServerResult<TcpStream> {
+    let mut stream = connect_any(addr)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let mut builder = ConnectBuilder::new(client_id, 0, true)?;
+    if let Some(user_name) = &config.user_name {
+        builder = builder.with_user_name(user_name)?;
+    }
+    if let Some(password) = &config.password {
+        builder = builder.with_password(password)?;
+    }
+    stream.write_all(&builder.build()?.encode()?)?;
+    let mut control = [0u8];
+    stream.read_exact(&mut control)?;
+    Connack::read_from(&mut stream, control[0])?;
+
+    if !topics.is_empty() {
+        let mut filters = vec![];
+        for topic in topics {
+            filters.push(TopicFilter::new(topic, MAX_QOS)?);
+        }
+        let subscribe = Subscribe::new(filters, SUBSCRIBE_PACKET_ID);
+        stream.write_all(&subscribe.encode()?)?;
+        stream.read_exact(&mut control)?;
+        let suback = Suback::read_from(&mut stream, control[0])?;
+        if suback.granted_qos().iter().any(Option::is_none) {
+            return Err(ServerError::new_kind(
+                "El broker rechazo alguna suscripcion del bridge",
+                ServerErrorKind::Other,
+            ));
+        }
+    }
+
+    stream.set_read_timeout(None)?;
+    Ok(stream)
+}
+
+/// Connects to the first address of `addr` that accepts the connection
+fn connect_any<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "La direccion del broker no resuelve a ninguna IP",
+    );
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// Reads the packets of `stream` (whose write half is `from`), and
+/// republishes through `to` the messages it receives. When the
+/// connection is closed, it also closes the other one
+fn forward(mut stream: TcpStream, from: &BridgeWriter, to: &BridgeWriter, name: &str) {
+    if let Err(err) = forward_packets(&mut stream, from, to, name) {
+        if err.kind() != ServerErrorKind::ClientDisconnected {
+            error!("Error reenviando mensajes del bridge: {}", err);
+        }
+    }
+    from.close();
+    to.close();
+}
+
+#[doc(hidden)]
+fn forward_packets(
+    stream: &mut TcpStream,
+    from: &BridgeWriter,
+    to: &BridgeWriter,
+    name: &str,
+) -> ServerResult<()> {
+    let mut control = [0u8];
+    let mut last_packet_id: u16 = 0;
+    loop {
+        if let Err(err) = stream.read_exact(&mut control) {
+            return match err.kind() {
+                io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted => Ok(()),
+                _ => Err(err.into()),
+            };
+        }
+        match PacketType::try_from(control[0])? {
+            PacketType::Publish => {
+                let publish = Publish::read_from(stream, control[0])?;
+                if let Some(packet_id) = publish.packet_id() {
+                    from.send(&Puback::new(packet_id)?)?;
+                }
+                // Cada conexion tiene sus propios packet ids, que no
+                // pueden ser 0
+                last_packet_id = last_packet_id.checked_add(1).unwrap_or(1);
+                debug!(
+                    "Bridge <{}> reenviando PUBLISH a {}",
+                    name,
+                    publish.topic_name()
+                );
+                to.send(&forwarded(publish, last_packet_id))?;
+            }
+            // Confirmaciones de lo que el otro sentido del bridge
+            // reenvio a este broker
+            PacketType::Puback => {
+                Puback::read_from(stream, control[0])?;
+            }
+            packet_type => {
+                return Err(ServerError::new_kind(
+                    &format!("Paquete inesperado en el bridge: {:?}", packet_type),
+                    ServerErrorKind::ProtocolViolation,
+                ));
+            }
+        }
+    }
+}
+
+/// Returns the message as it should be republished by the bridge,
+/// with the identifier `packet_id` if it has QoS 1. It keeps the QoS
+/// it was delivered with, which is the lowest between the one it was
+/// published with and the one granted to the bridge
+fn forwarded(mut publish: Publish, packet_id: u16) -> Publish {
+    // Es una nueva entrega, aunque el broker la haya reenviado
+    publish.set_dup(false);
+    publish.with_packet_id(packet_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use packets::{publish::Publish, qos::QoSLevel};
+
+    use super::forwarded;
+
+    #[test]
+    fn test_messages_are_forwarded_with_their_qos() {
+        let mut publish = Publish::new(
+            false,
+            QoSLevel::QoSLevel1,
+            false,
+            "topic",
+            "payload",
+            Some(3),
+        )
+        .unwrap();
+        publish.set_dup(true);
+        let forwarded_publish = forwarded(publish, 7);
+        assert_eq!(forwarded_publish.qos(), QoSLevel::QoSLevel1);
+        assert_eq!(forwarded_publish.packet_id(), Some(7));
+        assert!(!forwarded_publish.dup_flag());
+        assert_eq!(forwarded_publish.payload(), "payload");
+
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "payload", None).unwrap();
+        let forwarded_publish = forwarded(publish, 8);
+        assert_eq!(forwarded_publish.qos(), QoSLevel::QoSLevel0);
+        assert_eq!(forwarded_publish.packet_id(), None);
+    }
+}
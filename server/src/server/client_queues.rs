@@ -0,0 +1,223 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    panic::{self, AssertUnwindSafe},
+    sync::{Condvar, Mutex},
+};
+
+use tracing::error;
+
+use super::{ClientId, ClientIdArg, ServerError, ServerResult};
+
+/// Job to be executed on behalf of a client
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Maximum amount of jobs of a client waiting to be executed.
+/// Once reached, `push()` blocks until one of them is executed
+const MAX_PENDING_JOBS: usize = 32;
+
+#[doc(hidden)]
+#[derive(Default)]
+struct QueuesState {
+    queues: HashMap<ClientId, VecDeque<Job>>,
+    /// First error returned by a job of each client, not yet
+    /// taken by the thread that reads from its connection
+    errors: HashMap<ClientId, ServerError>,
+}
+
+/// Queues of pending jobs of each client.
+///
+/// It allows processing the packets of different clients
+/// concurrently, while the packets of the same client are
+/// processed one at a time, in the order they were received
+/// (see [MQTT-4.6.0-1] and [MQTT-4.6.0-2]).
+///
+/// A client has a queue only while it has a job in flight.
+/// When a job is pushed and there is no queue, whoever
+/// pushed it is responsible for calling `run_pending()`.
+///
+/// Each queue is bounded, so that a client whose jobs can not
+/// make progress (for example, because the dispatch queue is
+/// full) stops being read from until they do
+#[derive(Default)]
+pub struct ClientQueues {
+    state: Mutex<QueuesState>,
+    /// Notified every time a job is taken from a queue
+    changed: Condvar,
+}
+
+impl ClientQueues {
+    /// Creates a new [`ClientQueues`], without any
+    /// pending job
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a job to the queue of the client. If the queue is
+    /// full, it blocks until there is room for it.
+    ///
+    /// Returns true if the client had no job in flight. In
+    /// that case, `run_pending()` must be called so that the
+    /// job is executed
+    pub fn push(&self, id: &ClientIdArg, job: Job) -> ServerResult<bool> {
+        let mut state = self.state.lock()?;
+        while state
+            .queues
+            .get(id)
+            .is_some_and(|queue| queue.len() >= MAX_PENDING_JOBS)
+        {
+            state = self.changed.wait(state)?;
+        }
+        match state.queues.get_mut(id) {
+            Some(queue) => {
+                queue.push_back(job);
+                Ok(false)
+            }
+            None => {
+                state
+                    .queues
+                    .insert(id.to_owned(), VecDeque::from(vec![job]));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Executes, in order, the jobs of the client until
+    /// its queue is empty.
+    ///
+    /// If a job panics, the panic is logged and the following
+    /// jobs are executed anyway. Otherwise, the queue would be
+    /// left in flight and the client would never be served again
+    pub fn run_pending(&self, id: &ClientIdArg) -> ServerResult<()> {
+        loop {
+            let job = {
+                let mut state = self.state.lock()?;
+                let job = state.queues.get_mut(id).and_then(|queue| queue.pop_front());
+                if job.is_none() {
+                    state.queues.remove(id);
+                }
+                self.changed.notify_all();
+                match job {
+                    Some(job) => job,
+                    None => return Ok(()),
+                }
+            };
+            // No se ejecuta con el lock tomado, para no
+            // bloquear a los demas clientes
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                error!("<{}>: Panic procesando un paquete", id);
+            }
+        }
+    }
+
+    /// Blocks until the client has no job in flight
+    pub fn wait_idle(&self, id: &ClientIdArg) -> ServerResult<()> {
+        let mut state = self.state.lock()?;
+        while state.queues.contains_key(id) {
+            state = self.changed.wait(state)?;
+        }
+        Ok(())
+    }
+
+    /// Records the error returned by a job of the client. Only
+    /// the first one is kept until it is taken
+    pub fn fail(&self, id: &ClientIdArg, err: ServerError) -> ServerResult<()> {
+        self.state
+            .lock()?
+            .errors
+            .entry(id.to_owned())
+            .or_insert(err);
+        Ok(())
+    }
+
+    /// Takes the error returned by a job of the client, if any
+    pub fn take_error(&self, id: &ClientIdArg) -> ServerResult<Option<ServerError>> {
+        Ok(self.state.lock()?.errors.remove(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use super::{ClientQueues, ServerError};
+
+    #[test]
+    fn test_only_first_push_starts_the_queue() {
+        let queues = ClientQueues::new();
+        assert!(queues.push("id", Box::new(|| ())).unwrap());
+        assert!(!queues.push("id", Box::new(|| ())).unwrap());
+        assert!(queues.push("other_id", Box::new(|| ())).unwrap());
+    }
+
+    #[test]
+    fn test_jobs_run_in_order() {
+        let queues = ClientQueues::new();
+        let results = Arc::new(Mutex::new(vec![]));
+        for i in 0..10 {
+            let results = results.clone();
+            queues
+                .push("id", Box::new(move || results.lock().unwrap().push(i)))
+                .unwrap();
+        }
+        queues.run_pending("id").unwrap();
+        assert_eq!(*results.lock().unwrap(), (0..10).collect::<Vec<_>>());
+
+        // Al vaciarse la cola, el siguiente push vuelve a iniciarla
+        assert!(queues.push("id", Box::new(|| ())).unwrap());
+    }
+
+    #[test]
+    fn test_panicking_job_does_not_stop_the_queue() {
+        let queues = ClientQueues::new();
+        let results = Arc::new(Mutex::new(vec![]));
+        queues
+            .push("id", Box::new(|| panic!("Job con panic")))
+            .unwrap();
+        let results_copy = results.clone();
+        queues
+            .push("id", Box::new(move || results_copy.lock().unwrap().push(1)))
+            .unwrap();
+
+        queues.run_pending("id").unwrap();
+        assert_eq!(*results.lock().unwrap(), vec![1]);
+        assert!(queues.push("id", Box::new(|| ())).unwrap());
+    }
+    #[test]
+    fn test_wait_idle_waits_for_the_jobs_in_flight() {
+        let queues = Arc::new(ClientQueues::new());
+        let results = Arc::new(Mutex::new(vec![]));
+        let results_copy = results.clone();
+        queues
+            .push(
+                "id",
+                Box::new(move || {
+                    thread::sleep(Duration::from_millis(100));
+                    results_copy.lock().unwrap().push(1)
+                }),
+            )
+            .unwrap();
+        let queues_copy = queues.clone();
+        let runner = thread::spawn(move || queues_copy.run_pending("id").unwrap());
+
+        queues.wait_idle("id").unwrap();
+        assert_eq!(*results.lock().unwrap(), vec![1]);
+        queues.wait_idle("other_id").unwrap();
+        runner.join().unwrap();
+    }
+
+    #[test]
+    fn test_only_first_error_is_kept() {
+        let queues = ClientQueues::new();
+        queues.fail("id", ServerError::new_msg("primero")).unwrap();
+        queues.fail("id", ServerError::new_msg("segundo")).unwrap();
+
+        let err = queues.take_error("id").unwrap().unwrap();
+        assert_eq!(err.to_string(), "primero");
+        assert!(queues.take_error("id").unwrap().is_none());
+        assert!(queues.take_error("other_id").unwrap().is_none());
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+
+use tracing::error;
+
+use super::ServerResult;
+
+#[doc(hidden)]
+struct TimerState<T> {
+    /// Entries waiting for their deadline. The sequence number
+    /// keeps the ones with the same deadline in the order they
+    /// were scheduled
+    pending: BTreeMap<(Instant, u64), T>,
+    next_seq: u64,
+    stopped: bool,
+}
+
+impl<T> Default for TimerState<T> {
+    fn default() -> Self {
+        Self {
+            pending: BTreeMap::new(),
+            next_seq: 0,
+            stopped: false,
+        }
+    }
+}
+
+/// Calls a callback with each scheduled entry once its deadline
+/// is reached.
+///
+/// The timer runs on its own thread, which sleeps until the
+/// next deadline (or until entries are scheduled or cancelled).
+/// It does not run until `start()` is called, and it stops
+/// when the [`DeadlineTimer`] is dropped
+pub struct DeadlineTimer<T> {
+    state: Arc<(Mutex<TimerState<T>>, Condvar)>,
+}
+
+impl<T> Default for DeadlineTimer<T> {
+    fn default() -> Self {
+        Self {
+            state: Arc::new((Mutex::new(TimerState::default()), Condvar::new())),
+        }
+    }
+}
+
+impl<T: Send + 'static> DeadlineTimer<T> {
+    /// Spawns the timer thread with the given name. When the
+    /// deadline of an entry is reached, it is removed and
+    /// `on_expire` is called with it
+    pub fn start<F>(&self, name: &str, on_expire: F) -> io::Result<()>
+    where
+        F: Fn(T) + Send + 'static,
+    {
+        let state = self.state.clone();
+        let thread_name = name.to_owned();
+        thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || Self::timer_loop(state, &thread_name, on_expire))?;
+        Ok(())
+    }
+
+    /// Schedules the given entries, each one with its deadline
+    pub fn schedule<E>(&self, entries: E) -> ServerResult<()>
+    where
+        E: IntoIterator<Item = (Instant, T)>,
+    {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock()?;
+        for (deadline, entry) in entries {
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.pending.insert((deadline, seq), entry);
+        }
+        condvar.notify_one();
+        Ok(())
+    }
+
+    /// Removes the entries for which `predicate` returns true.
+    /// Returns true if any was removed
+    pub fn cancel<P>(&self, mut predicate: P) -> ServerResult<bool>
+    where
+        P: FnMut(&T) -> bool,
+    {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock()?;
+        let count = state.pending.len();
+        state.pending.retain(|_, entry| !predicate(entry));
+        let cancelled = state.pending.len() != count;
+        condvar.notify_one();
+        Ok(cancelled)
+    }
+
+    /// Removes and returns all the entries whose deadline was
+    /// not reached yet
+    pub fn take_pending(&self) -> ServerResult<Vec<T>> {
+        let (lock, _condvar) = &*self.state;
+        let pending = std::mem::take(&mut lock.lock()?.pending);
+        Ok(pending.into_values().collect())
+    }
+
+    #[doc(hidden)]
+    fn timer_loop<F>(state: Arc<(Mutex<TimerState<T>>, Condvar)>, name: &str, on_expire: F)
+    where
+        F: Fn(T),
+    {
+        let (lock, condvar) = &*state;
+        let mut guard = match lock.lock() {
+            Ok(guard) => guard,
+            Err(e) => {
+                error!("Error iniciando el timer {}: {}", name, e);
+                return;
+            }
+        };
+        while !guard.stopped {
+            let now = Instant::now();
+            let mut expired = vec![];
+            while let Some(entry) = guard.pending.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                expired.push(entry.remove());
+            }
+            if !expired.is_empty() {
+                // No se llama al callback con el lock tomado, para no
+                // bloquear a quienes agregan o cancelan entradas
+                drop(guard);
+                for entry in expired {
+                    on_expire(entry);
+                }
+                guard = match lock.lock() {
+                    Ok(guard) => guard,
+                    Err(e) => {
+                        error!("Error en el timer {}: {}", name, e);
+                        return;
+                    }
+                };
+                continue;
+            }
+
+            let next_deadline = guard.pending.keys().next().map(|(deadline, _)| *deadline);
+            let result = match next_deadline {
+                Some(deadline) => condvar
+                    .wait_timeout(guard, deadline.saturating_duration_since(now))
+                    .map(|(guard, _)| guard)
+                    .map_err(|e| e.to_string()),
+                None => condvar.wait(guard).map_err(|e| e.to_string()),
+            };
+            guard = match result {
+                Ok(guard) => guard,
+                Err(e) => {
+                    error!("Error en el timer {}: {}", name, e);
+                    return;
+                }
+            };
+        }
+    }
+}
+
+impl<T> Drop for DeadlineTimer<T> {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.state;
+        if let Ok(mut state) = lock.lock() {
+            state.stopped = true;
+        }
+        condvar.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc::channel,
+        time::{Duration, Instant},
+    };
+
+    use super::DeadlineTimer;
+
+    #[test]
+    fn test_cancelled_entries_do_not_expire() {
+        let timer = DeadlineTimer::default();
+        let (sender, receiver) = channel();
+        timer
+            .start("test_timer", move |entry| sender.send(entry).unwrap())
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_millis(100);
+        timer
+            .schedule(vec![(deadline, "cancelled"), (deadline, "expired")])
+            .unwrap();
+        assert!(timer.cancel(|entry| *entry == "cancelled").unwrap());
+        assert!(!timer.cancel(|entry| *entry == "unknown").unwrap());
+
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap(),
+            "expired"
+        );
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::{
+    topic_handler::{topic_handler_error::TopicHandlerError, Message, MessageSink},
+    traits::OverloadPolicy,
+};
+
+use super::poison::LockOrRecover;
+
+/// Bounded queue of the messages waiting to be sent to the
+/// subscribers by the publish dispatcher.
+///
+/// When it is full, what happens with a new message depends
+/// on its [`OverloadPolicy`] (see [`Config::overload_policy`](crate::Config::overload_policy))
+pub struct DispatchQueue {
+    messages: Mutex<VecDeque<Message>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: OverloadPolicy,
+    dropped: AtomicU64,
+}
+
+impl DispatchQueue {
+    /// Creates an empty queue that holds up to `capacity`
+    /// messages (at least one)
+    pub fn new(capacity: usize, policy: OverloadPolicy) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            messages: Mutex::new(VecDeque::with_capacity(capacity)),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity,
+            policy,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds a message to the queue. If it is full, the message
+    /// is handled according to the [`OverloadPolicy`] of the queue
+    pub fn push(&self, message: Message) {
+        let mut messages = self.messages.lock_or_recover();
+        if messages.len() >= self.capacity {
+            match self.policy {
+                OverloadPolicy::Backpressure => {
+                    while messages.len() >= self.capacity {
+                        messages = self
+                            .not_full
+                            .wait(messages)
+                            .unwrap_or_else(|err| err.into_inner());
+                    }
+                }
+                OverloadPolicy::DropOldest => {
+                    if let Some(oldest) = messages.pop_front() {
+                        let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                        warn!(
+                            "Cola de despacho llena - Se descarta el mensaje mas antiguo (topic: {}, descartados: {})",
+                            oldest.packet.topic_name(),
+                            dropped
+                        );
+                    }
+                }
+                OverloadPolicy::DropNewest => {
+                    let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    warn!(
+                        "Cola de despacho llena - Se descarta el mensaje nuevo (topic: {}, descartados: {})",
+                        message.packet.topic_name(),
+                        dropped
+                    );
+                    return;
+                }
+            }
+        }
+        messages.push_back(message);
+        self.not_empty.notify_one();
+    }
+
+    /// Takes the oldest message of the queue, waiting up to
+    /// `timeout` for one to arrive. Returns None if the queue
+    /// is still empty after that
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<Message> {
+        let messages = self.messages.lock_or_recover();
+        let (mut messages, _) = self
+            .not_empty
+            .wait_timeout_while(messages, timeout, |messages| messages.is_empty())
+            .unwrap_or_else(|err| err.into_inner());
+        let message = messages.pop_front();
+        if message.is_some() {
+            self.not_full.notify_one();
+        }
+        message
+    }
+
+    /// Returns true if there are no messages waiting to be
+    /// taken by the dispatcher
+    #[cfg(feature = "bench")]
+    pub fn is_empty(&self) -> bool {
+        self.messages.lock_or_recover().is_empty()
+    }
+
+    /// Returns the amount of messages dropped because
+    /// the queue was full
+    #[cfg(test)]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl MessageSink for DispatchQueue {
+    fn send(&self, message: Message) -> Result<(), TopicHandlerError> {
+        self.push(message);
+        Ok(())
+    }
+}
+
+/// [`MessageSink`] that discards the messages addressed to the
+/// client that published them, and passes the rest on to `sink`
+/// (see [`Config::no_local`](crate::Config::no_local) and
+/// [`Config::bridges`](crate::Config::bridges))
+pub struct SkipOrigin<'a, S: MessageSink> {
+    pub sink: S,
+    pub origin: &'a str,
+}
+
+impl<S: MessageSink> MessageSink for SkipOrigin<'_, S> {
+    fn send(&self, message: Message) -> Result<(), TopicHandlerError> {
+        if message.client_id == self.origin {
+            return Ok(());
+        }
+        self.sink.send(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+        time::Duration,
+    };
+
+    use packets::{publish::Publish, qos::QoSLevel};
+
+    use super::{DispatchQueue, SkipOrigin};
+    use crate::{
+        topic_handler::{Message, MessageSink},
+        traits::OverloadPolicy,
+    };
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn message(topic: &str) -> Message {
+        message_for("cliente", topic)
+    }
+
+    fn message_for(client_id: &str, topic: &str) -> Message {
+        Message {
+            client_id: client_id.to_string(),
+            packet: Publish::new(false, QoSLevel::QoSLevel0, false, topic, "payload", None)
+                .unwrap(),
+        }
+    }
+
+    fn pop_topics(queue: &DispatchQueue) -> Vec<String> {
+        let mut topics = vec![];
+        while let Some(message) = queue.pop_timeout(TIMEOUT) {
+            topics.push(message.packet.topic_name().to_string());
+        }
+        topics
+    }
+
+    #[test]
+    fn test_drop_oldest_under_full_queue() {
+        let queue = DispatchQueue::new(2, OverloadPolicy::DropOldest);
+        queue.push(message("a"));
+        queue.push(message("b"));
+        queue.push(message("c"));
+
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(pop_topics(&queue), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_drop_newest_under_full_queue() {
+        let queue = DispatchQueue::new(2, OverloadPolicy::DropNewest);
+        queue.push(message("a"));
+        queue.push(message("b"));
+        queue.push(message("c"));
+
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(pop_topics(&queue), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_backpressure_under_full_queue_blocks_until_there_is_room() {
+        let queue = Arc::new(DispatchQueue::new(1, OverloadPolicy::Backpressure));
+        queue.push(message("a"));
+
+        let (sender, receiver) = mpsc::channel();
+        let queue_copy = queue.clone();
+        let handle = thread::spawn(move || {
+            queue_copy.push(message("b"));
+            sender.send(()).unwrap();
+        });
+
+        // La cola esta llena, asi que el push queda bloqueado
+        assert!(receiver.recv_timeout(TIMEOUT).is_err());
+        assert_eq!(queue.pop_timeout(TIMEOUT).unwrap().packet.topic_name(), "a");
+        receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(queue.dropped(), 0);
+        assert_eq!(pop_topics(&queue), vec!["b"]);
+    }
+
+    #[test]
+    fn test_skip_origin_discards_messages_for_the_publisher() {
+        let queue = DispatchQueue::new(4, OverloadPolicy::Backpressure);
+        let sink = SkipOrigin {
+            sink: &queue,
+            origin: "publicador",
+        };
+        sink.send(message_for("publicador", "a")).unwrap();
+        sink.send(message_for("otro", "b")).unwrap();
+
+        assert_eq!(pop_topics(&queue), vec!["b"]);
+    }
+
+    #[test]
+    fn test_pop_timeout_on_empty_queue() {
+        let queue = DispatchQueue::new(1, OverloadPolicy::Backpressure);
+        assert!(queue.pop_timeout(TIMEOUT).is_none());
+    }
+}
@@ -1,68 +1,161 @@
 use std::{
-    fs::{self},
-    io::{self},
+    collections::BTreeMap,
     net::{SocketAddr, TcpStream},
-    path::MAIN_SEPARATOR,
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc, Mutex, RwLock,
+    },
 };
 
+use packets::qos::QoSLevel;
+use serde::Serialize;
 use serde_json::json;
 use threadpool::ThreadPool;
-use tracing::debug;
+use tracing::error;
 
 use crate::{clients_manager::ClientsManager, topic_handler::TopicHandler, Config, Server};
 
-use super::{server_error::ServerErrorKind, ServerError, ServerResult};
+use super::{
+    client_queues::ClientQueues, dispatch_queue::DispatchQueue, persistence::DumpState,
+    retained_scheduler::RetainedScheduler, server_error::ServerErrorKind,
+    will_scheduler::WillScheduler, ClientId, ServerError, ServerResult,
+};
+
+/// Human-readable summary of the state stored in a dump
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StateSummary {
+    /// Ids of the clients that had a session when
+    /// the dump was made, sorted
+    pub clients: Vec<ClientId>,
+    /// Subscriptions of each client
+    pub subscriptions: BTreeMap<ClientId, Vec<SubscriptionSummary>>,
+    /// Amount of retained messages
+    pub retained_messages: usize,
+}
+
+/// Subscription of a client, as shown in a [`StateSummary`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubscriptionSummary {
+    pub topic_filter: String,
+    pub qos: QoSLevel,
+}
 
 impl<C: Config> Server<C> {
+    /// Returns a summary of the state stored in the JSON of a dump,
+    /// without creating a server (nor binding any socket)
+    pub fn export_state(json_str: &str) -> ServerResult<StateSummary> {
+        let (topic_handler, mut clients_manager) =
+            Server::<C>::restore_from_state(DumpState::parse(json_str)?)?;
+        let clients = clients_manager.get_mut()?.client_ids();
+        let (subscriptions, retained_messages) = Self::subscriptions_summary(&topic_handler)?;
+        Ok(StateSummary {
+            clients,
+            subscriptions,
+            retained_messages,
+        })
+    }
+
+    /// Returns the subscriptions of each client, along with
+    /// the amount of retained messages
+    fn subscriptions_summary(
+        topic_handler: &TopicHandler,
+    ) -> ServerResult<(BTreeMap<ClientId, Vec<SubscriptionSummary>>, usize)> {
+        let (subscriptions, retained_messages) = topic_handler.state()?;
+        let subscriptions = subscriptions
+            .into_iter()
+            .map(|(id, subscriptions)| {
+                let subscriptions = subscriptions
+                    .into_iter()
+                    .map(|(topic_filter, qos)| SubscriptionSummary { topic_filter, qos })
+                    .collect();
+                (id, subscriptions)
+            })
+            .collect();
+        Ok((subscriptions, retained_messages))
+    }
+
+    /// Creates a server from the state stored in the persistence
+    /// backend specified in the configuration
+    /// (see [`Config::persistence_backend`]).
+    ///
+    /// Returns None if there is no stored state, or if it is
+    /// corrupt (in which case it is logged and ignored)
     pub fn try_restore(config: &C, threadpool_size: usize) -> ServerResult<Option<Arc<Server<C>>>> {
-        let dump_path = match config.dump_info() {
-            Some(dump_info) => dump_info.0,
+        let persistence = match config.persistence_backend() {
+            Some(persistence) => persistence,
             None => return Ok(None),
         };
 
-        let json_str = match fs::read_to_string(dump_path) {
-            Ok(json_str) => json_str,
-            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
-            Err(err) => return Err(ServerError::from(err)),
+        let restored = persistence
+            .load()
+            .and_then(|state| state.map(Server::<C>::restore_from_state).transpose());
+        let (topic_handler, mut clients_manager) = match restored {
+            Ok(Some(restored)) => restored,
+            Ok(None) => return Ok(None),
+            Err(err) if err.kind() == ServerErrorKind::DumpError => {
+                error!("El DUMP esta corrupto ({}) - Se ignora", err);
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
         };
-
-        let (topic_handler, mut clients_manager) = Server::<C>::restore_from_json(&json_str)?;
         let shutdown_info = clients_manager.get_mut()?.shutdown(false)?;
         clients_manager.get_mut()?.set_auth(config.authenticator());
+        clients_manager
+            .get_mut()?
+            .set_id_generator(config.client_id_generator());
         for client_id in shutdown_info.clean_session_ids {
             topic_handler.remove_client(&client_id)?;
         }
+        topic_handler.compact()?;
 
+        topic_handler.set_retained_budget(config.max_retained(), config.max_retained_bytes())?;
+        let dispatch_queue = Arc::new(DispatchQueue::new(
+            config.dispatch_queue_len(),
+            config.overload_policy(),
+        ));
         let server = Server {
             clients_manager,
             config: config.clone(),
             topic_handler,
             pool: Mutex::new(ThreadPool::new(threadpool_size)),
+            dispatch_queue: dispatch_queue.clone(),
+            will_scheduler: WillScheduler::new(),
+            retained_scheduler: RetainedScheduler::new(),
+            client_queues: ClientQueues::new(),
+            connection_listeners: RwLock::new(vec![]),
+            subscription_listeners: RwLock::new(vec![]),
+            persistence: Some(persistence),
+            draining: AtomicBool::new(false),
+            drain_deadline: Mutex::new(None),
+            pending_connections: AtomicUsize::new(0),
+            client_threads: AtomicUsize::new(0),
         };
         let server = Arc::new(server);
+        server.start_publish_dispatcher(dispatch_queue)?;
+        server.start_will_scheduler()?;
+        server.start_retained_scheduler()?;
         for (id, last_will) in shutdown_info.last_will_packets {
             server.send_last_will(last_will, &id)?;
         }
         Ok(Some(server))
     }
 
-    fn restore_from_json(
-        json_str: &str,
+    fn restore_from_state(
+        state: DumpState,
     ) -> ServerResult<(TopicHandler, RwLock<ClientsManager<TcpStream, SocketAddr>>)> {
-        let json: serde_json::Value = match serde_json::from_str(json_str) {
-            Ok(json) => json,
-            Err(err) => {
-                return Err(ServerError::new_kind(
-                    &err.to_string(),
-                    ServerErrorKind::DumpError,
-                ))
-            }
-        };
-
-        if let serde_json::Value::Object(mut obj) = json {
-            let topic_handler = obj.remove("topic_handler").unwrap();
-            let clients_manager = obj.remove("clients_manager").unwrap();
+        if let serde_json::Value::Object(mut obj) = state.into_json() {
+            let (topic_handler, clients_manager) =
+                match (obj.remove("topic_handler"), obj.remove("clients_manager")) {
+                    (Some(topic_handler), Some(clients_manager)) => {
+                        (topic_handler, clients_manager)
+                    }
+                    _ => {
+                        return Err(ServerError::new_kind(
+                            "Faltan campos en el DUMP",
+                            ServerErrorKind::DumpError,
+                        ))
+                    }
+                };
             Ok((
                 serde_json::from_value(topic_handler).map_err(|err| {
                     ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError)
@@ -72,28 +165,37 @@ impl<C: Config> Server<C> {
                 })?,
             ))
         } else {
-            panic!("Invalid json");
+            Err(ServerError::new_kind(
+                "El DUMP no es un objeto JSON",
+                ServerErrorKind::DumpError,
+            ))
         }
     }
 
+    /// Persists the state of the server through the persistence
+    /// backend, if specified in the configuration
+    /// (see [`Config::persistence_backend`])
+    ///
+    /// Besides the state needed to restore the server, it includes
+    /// the subscriptions of each client (topic filter and QoS), to
+    /// ease debugging. They are ignored when restoring
     pub fn dump(&self) -> ServerResult<()> {
-        if let Some(dump_info) = self.config.dump_info() {
-            debug!("DUMP");
+        if let Some(persistence) = &self.persistence {
             let topic_handler = serde_json::to_value(&self.topic_handler).map_err(|err| {
                 ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError)
             })?;
             let clients_manager = serde_json::to_value(&self.clients_manager).map_err(|err| {
                 ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError)
             })?;
-            let json = json!({
+            let (subscriptions, _) = Self::subscriptions_summary(&self.topic_handler)?;
+            let subscriptions = serde_json::to_value(subscriptions).map_err(|err| {
+                ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError)
+            })?;
+            persistence.save(&DumpState::new(json!({
                 "topic_handler": topic_handler,
-                "clients_manager": clients_manager
-            });
-
-            if let Some((folder, _)) = dump_info.0.rsplit_once(MAIN_SEPARATOR) {
-                fs::create_dir_all(folder)?;
-            }
-            fs::write(dump_info.0, serde_json::to_string_pretty(&json)?)?;
+                "clients_manager": clients_manager,
+                "subscriptions": subscriptions
+            })))?;
         }
         Ok(())
     }
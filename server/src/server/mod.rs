@@ -1,19 +1,20 @@
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     io::{self, Read, Write},
-    net::{SocketAddr, TcpListener, TcpStream},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
     sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc::{self, Receiver, Sender},
-        Arc, Mutex, RwLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
     },
-    thread::{self},
-    time::{Duration, SystemTime},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+use socket2::{Domain, Protocol, Socket, Type};
 use thread_joiner::ThreadJoiner;
 use threadpool::ThreadPool;
-use tracing::{debug, error, info, instrument, trace, warn};
+use tracing::{debug, error, field, info, info_span, instrument, trace, warn};
 
 use packets::{
     connack::{Connack, ConnackReturnCode},
@@ -26,30 +27,47 @@ use packets::{
     unsuback::Unsuback, unsubscribe::Unsubscribe,
 };
 
+mod admin;
+pub(crate) mod bridge;
+mod client_queues;
+mod deadline_timer;
+mod dispatch_queue;
 mod dump;
+pub(crate) mod mount_point;
 mod packet_processing;
+mod persistence;
+mod poison;
+mod retained_scheduler;
 mod server_controller;
 pub mod server_error;
+mod will_scheduler;
 
 pub use server_error::ServerError;
 
-/// Maximum time between the client connection and the sending
-/// of the [`Connect`] packet
-const CONNECTION_WAIT_TIMEOUT: Duration = Duration::from_secs(180);
-/// How often unacknowledged packets are sent
-pub const UNACK_RESENDING_FREQ: Duration = Duration::from_millis(500);
+/// Maximum QoS supported by the server. The publications, subscriptions
+/// and last wills with a greater QoS are downgraded to it
+pub const MAX_QOS: QoSLevel = QoSLevel::QoSLevel1;
 /// How long the server sleeps between each failed TCP connection
 /// attempt
 const ACCEPT_SLEEP_DUR: Duration = Duration::from_millis(100);
-/// Minimum time since the last sending of a packet for
-/// it to be resent. This prevents very recent packets
-/// from being resent
-const MIN_ELAPSED_TIME: Option<Duration> = Some(Duration::from_millis(2000));
+/// How often the Keep Alive watchdog looks for expired clients
+/// and unacknowledged packets to send again
+const KEEP_ALIVE_CHECK: Duration = Duration::from_millis(100);
+/// Extra time the Keep Alive watchdog waits before closing a
+/// connection, so that the clients that are not stuck reading
+/// a packet are disconnected by their own thread
+const KEEP_ALIVE_WATCHDOG_GRACE: Duration = Duration::from_millis(500);
+/// How often the dump timer checks if the server was shut down
+const DUMP_TIMER_CHECK: Duration = Duration::from_millis(100);
+/// How often the publish dispatcher checks if the server was
+/// dropped while there are no messages to dispatch
+const DISPATCH_CHECK: Duration = Duration::from_millis(100);
 
 use packets::publish::Publish;
 use packets::qos::QoSLevel;
 
 use crate::{
+    client::{ClientInfo, ClientStats},
     clients_manager::{ClientsManager, ConnectInfo},
     network_connection::NetworkConnection,
     server::server_error::ServerErrorKind,
@@ -57,7 +75,14 @@ use crate::{
     traits::*,
 };
 
+use self::client_queues::ClientQueues;
+use self::dispatch_queue::DispatchQueue;
+pub use self::dump::{StateSummary, SubscriptionSummary};
+pub use self::persistence::{DumpState, JsonFileBackend};
+use self::poison::{LockOrRecover, RwLockOrRecover};
+use self::retained_scheduler::RetainedScheduler;
 pub use self::server_controller::ServerController;
+use self::will_scheduler::WillScheduler;
 
 pub type ServerResult<T> = Result<T, ServerError>;
 #[doc(hidden)]
@@ -65,6 +90,24 @@ pub type ClientId = String;
 #[doc(hidden)]
 pub type ClientIdArg = str;
 
+/// How the clients accepted by a listener are handled
+#[derive(Debug, Clone, Default)]
+struct ListenerOptions {
+    /// Mount point of its clients (see [`Config::mount_point`])
+    mount_point: Option<String>,
+    /// Whether bridges can connect through it (see [`Config::bridge_port`])
+    bridges: bool,
+}
+
+/// Decrements a counter when dropped
+struct CountGuard<'a>(&'a AtomicUsize);
+
+impl Drop for CountGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 /// Represents a Server that complies with the
 /// MQTT V3.1.1 protocol
 ///
@@ -109,8 +152,41 @@ pub struct Server<C: Config> {
     topic_handler: TopicHandler,
     /// Threadpool used to process packets received from clients
     /// The only ones that are not processed in the Threadpool
-    /// are the [`Connect`] and [`Disconnect`] packets.
+    /// are the [`Connect`], [`Disconnect`] and [`Publish`] packets.
     pool: Mutex<ThreadPool>,
+    /// Packets of each client waiting to be processed in the
+    /// Threadpool. The packets of a client are processed one
+    /// at a time, in the order they were received
+    client_queues: ClientQueues,
+    /// Bounded queue through which the [`TopicHandler`] sends the
+    /// packets to be published to the publish dispatcher thread
+    /// (see [`Config::dispatch_queue_len`] and [`Config::overload_policy`])
+    dispatch_queue: Arc<DispatchQueue>,
+    /// Last Will packets whose publication is delayed
+    /// (see [`Config::will_delay`])
+    will_scheduler: WillScheduler,
+    /// Retained messages whose delivery is spread over a time
+    /// window (see [`Config::retained_delivery_window`])
+    retained_scheduler: RetainedScheduler,
+    /// Listeners notified when a client connects or disconnects
+    connection_listeners: RwLock<Vec<Box<dyn ConnectionListener + Send + Sync>>>,
+    /// Listeners notified when a client subscribes or unsubscribes
+    subscription_listeners: RwLock<Vec<Box<dyn SubscriptionListener + Send + Sync>>>,
+    /// Backend in which the state of the server is persisted
+    /// (see [`Config::persistence_backend`])
+    persistence: Option<Box<dyn PersistenceBackend>>,
+    /// True once the server started shutting down or draining.
+    /// From then on, new clients are refused
+    draining: AtomicBool,
+    /// Moment until which the server waits for the connected clients
+    /// to disconnect, once it started draining (see [`Server::drain`])
+    drain_deadline: Mutex<Option<Instant>>,
+    /// Amount of accepted connections that did not send their
+    /// [`Connect`] packet yet (see [`Config::max_pending_connections`])
+    pending_connections: AtomicUsize,
+    /// Amount of threads handling clients
+    /// (see [`Config::max_client_threads`])
+    client_threads: AtomicUsize,
 }
 
 impl<C: Config> Server<C> {
@@ -125,12 +201,36 @@ impl<C: Config> Server<C> {
                 } else {
                     warn!("No se encontro un archivo de DUMP - Creando servidor en blanco");
 
+                    let topic_handler = TopicHandler::new();
+                    topic_handler
+                        .set_retained_budget(config.max_retained(), config.max_retained_bytes())
+                        .ok()?;
+                    let dispatch_queue = Arc::new(DispatchQueue::new(
+                        config.dispatch_queue_len(),
+                        config.overload_policy(),
+                    ));
+                    let mut clients_manager = ClientsManager::new(config.authenticator());
+                    clients_manager.set_id_generator(config.client_id_generator());
                     let server = Arc::new(Self {
-                        clients_manager: RwLock::new(ClientsManager::new(config.authenticator())),
+                        clients_manager: RwLock::new(clients_manager),
+                        persistence: config.persistence_backend(),
                         config,
-                        topic_handler: TopicHandler::new(),
+                        topic_handler,
                         pool: Mutex::new(ThreadPool::new(threadpool_size)),
+                        dispatch_queue: dispatch_queue.clone(),
+                        will_scheduler: WillScheduler::new(),
+                        retained_scheduler: RetainedScheduler::new(),
+                        client_queues: ClientQueues::new(),
+                        connection_listeners: RwLock::new(vec![]),
+                        subscription_listeners: RwLock::new(vec![]),
+                        draining: AtomicBool::new(false),
+                        drain_deadline: Mutex::new(None),
+                        pending_connections: AtomicUsize::new(0),
+                        client_threads: AtomicUsize::new(0),
                     });
+                    server.start_publish_dispatcher(dispatch_queue).ok()?;
+                    server.start_will_scheduler().ok()?;
+                    server.start_retained_scheduler().ok()?;
                     Some(server)
                 }
             }
@@ -143,17 +243,112 @@ impl<C: Config> Server<C> {
     /// Returns a ServerController that can be used to stop the server
     ///
     /// This method does not return until the server initializes everything
-    /// necessary to start accepting connections
+    /// necessary to start accepting connections. If the bind address
+    /// specified in the configuration is invalid, it returns an error of
+    /// kind [`ServerErrorKind::InvalidConfig`]
     #[instrument(skip(self) fields(ip = %self.config.ip(), port = %self.config.port()))]
-    pub fn run(self: Arc<Self>) -> io::Result<ServerController> {
+    pub fn run(self: Arc<Self>) -> ServerResult<ServerController> {
+        let listener = self.bind()?;
+        self.run_on(listener)
+    }
+
+    /// Run the server in a new thread, accepting connections from an
+    /// already bound listener instead of binding to the address of the
+    /// configuration (which is then ignored, as well as
+    /// [`Config::listen_backlog`] and [`Config::dual_stack`]).
+    ///
+    /// Useful to know the address of the server before it starts, or to
+    /// receive the listener from a process manager (such as systemd
+    /// socket activation). Otherwise, it behaves as [`Server::run`]
+    #[instrument(skip(self, listener))]
+    pub fn run_on(self: Arc<Self>, listener: TcpListener) -> ServerResult<ServerController> {
+        let mount_point = self.config.mount_point().map(str::to_owned);
+        self.run_on_listeners(vec![(listener, mount_point)])
+    }
+
+    /// Run the server in a new thread, accepting connections from
+    /// several already bound listeners, each with its own mount point
+    /// (which replaces [`Config::mount_point`]). For example, to serve
+    /// each tenant on its own port, isolated from the others.
+    ///
+    /// The listener of the bridges (see [`Config::bridge_port`]) is
+    /// bound apart from the given ones, and its address is returned by
+    /// [`ServerController::bridge_addr`]. Otherwise, it behaves as
+    /// [`Server::run_on`].
+    ///
+    /// If there are no listeners, or a mount point is invalid or nested
+    /// inside another one (see [`Config::mount_point`]), it returns an
+    /// error of kind [`ServerErrorKind::InvalidConfig`]
+    #[instrument(skip(self, listeners))]
+    pub fn run_on_listeners(
+        self: Arc<Self>,
+        listeners: Vec<(TcpListener, Option<String>)>,
+    ) -> ServerResult<ServerController> {
         let shutdown_bool = Arc::new(AtomicBool::new(false));
         let shutdown_bool_copy = shutdown_bool.clone();
-        let (started_sender, started_receiver) = mpsc::channel();
+
+        let mount_points: Vec<&str> = listeners
+            .iter()
+            .filter_map(|(_, mount_point)| mount_point.as_deref())
+            .collect();
+        mount_point::validate_disjoint(&mount_points)?;
+        let mut local_addrs = vec![];
+        for (listener, mount_point) in &listeners {
+            if let Some(mount_point) = mount_point {
+                mount_point::validate(mount_point)?;
+            }
+            listener.set_nonblocking(true)?;
+            let local_addr = listener.local_addr()?;
+            match mount_point {
+                Some(mount_point) => {
+                    info!("Escuchando en {} (mount point {})", local_addr, mount_point)
+                }
+                None => info!("Escuchando en {}", local_addr),
+            }
+            local_addrs.push(local_addr);
+        }
+        if local_addrs.is_empty() {
+            return Err(ServerError::new_kind(
+                "No hay listeners",
+                ServerErrorKind::InvalidConfig,
+            ));
+        }
+        let mut listeners: Vec<_> = listeners
+            .into_iter()
+            .map(|(listener, mount_point)| {
+                let options = ListenerOptions {
+                    mount_point,
+                    bridges: false,
+                };
+                (listener, options)
+            })
+            .collect();
+
+        let mut bridge_addr = None;
+        if let Some(listener) = self.bind_bridge_listener()? {
+            listener.set_nonblocking(true)?;
+            let addr = listener.local_addr()?;
+            info!("Escuchando bridges en {}", addr);
+            let options = ListenerOptions {
+                mount_point: None,
+                bridges: true,
+            };
+            listeners.push((listener, options));
+            bridge_addr = Some(addr);
+        }
+
+        let mut timers = vec![self.start_keep_alive_watchdog(shutdown_bool.clone())?];
+        timers.extend(self.start_dump_timer(shutdown_bool.clone())?);
+        if let Some(bridge_addr) = bridge_addr {
+            for bridge_config in self.config.bridges() {
+                bridge::start(bridge_config.clone(), bridge_addr, shutdown_bool.clone())?;
+            }
+        }
 
         let server_handle = thread::Builder::new()
             .name("server_loop".to_owned())
             .spawn(move || {
-                if let Err(err) = self.server_loop(shutdown_bool, started_sender) {
+                if let Err(err) = self.server_loop(listeners, shutdown_bool, timers) {
                     error!(
                         "Error inesperado del servidor: {} - Se recomienda apagarlo",
                         err.to_string()
@@ -161,13 +356,218 @@ impl<C: Config> Server<C> {
                 }
             })?;
         trace!("Creando thread {:?}", server_handle.thread().id());
-        started_receiver.recv().unwrap_or_else(|e| {
-            error!("Error iniciando el servidor: {}", e);
-        });
-        let server_controller = ServerController::new(shutdown_bool_copy, server_handle);
+        let server_controller =
+            ServerController::new(shutdown_bool_copy, server_handle, local_addrs, bridge_addr);
         Ok(server_controller)
     }
 
+    /// Returns the socket address on which the server should listen,
+    /// built from the bind address and port of the configuration.
+    ///
+    /// The bind address can be an IPv4 or IPv6 literal, or a host name
+    /// (in which case the first address it resolves to is used). If
+    /// it is neither, it returns an error of kind [`ServerErrorKind::InvalidConfig`]
+    fn bind_socket_addr(&self) -> ServerResult<SocketAddr> {
+        let bind_address = self.config.bind_address();
+        let port = self.config.port();
+        if let Ok(ip) = bind_address.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, port));
+        }
+        // No es un literal, se intenta resolver como nombre de host
+        (bind_address, port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| {
+                ServerError::new_kind(
+                    format!("Direccion de bind invalida: <{}>", bind_address),
+                    ServerErrorKind::InvalidConfig,
+                )
+            })
+    }
+
+    /// Starts the thread that persists the state of the server every
+    /// [`Config::dump_interval`]. It stops when `shutdown_bool` is set.
+    ///
+    /// If the dump is disabled, or the interval is zero, no thread is
+    /// started and None is returned
+    fn start_dump_timer(
+        self: &Arc<Self>,
+        shutdown_bool: Arc<AtomicBool>,
+    ) -> ServerResult<Option<JoinHandle<()>>> {
+        let interval = self.config.dump_interval();
+        if self.persistence.is_none() || interval.is_zero() {
+            return Ok(None);
+        }
+        let server = Arc::downgrade(self);
+        let handle = thread::Builder::new()
+            .name("dump_timer".to_owned())
+            .spawn(move || Self::dump_timer_loop(server, shutdown_bool, interval))?;
+        Ok(Some(handle))
+    }
+
+    #[doc(hidden)]
+    fn dump_timer_loop(server: Weak<Self>, shutdown_bool: Arc<AtomicBool>, interval: Duration) {
+        let mut last_dump = Instant::now();
+        while !shutdown_bool.load(Ordering::Relaxed) {
+            // Se duerme de a intervalos cortos para notar rapido el apagado
+            thread::sleep(DUMP_TIMER_CHECK.min(interval));
+            if last_dump.elapsed() < interval {
+                continue;
+            }
+            match server.upgrade() {
+                Some(server) => server
+                    .dump()
+                    .unwrap_or_else(|e| error!("Error realizando el Dump: {}", e)),
+                None => return,
+            }
+            last_dump = Instant::now();
+        }
+    }
+
+    /// Spawns the thread that disconnects the clients that did
+    /// not send any packet during 1.5 times their Keep Alive
+    /// (see [MQTT-3.1.2-24]).
+    ///
+    /// It does not depend on the read timeout of the connection,
+    /// so a client that sends a packet slowly (one byte at a time)
+    /// is also disconnected.
+    ///
+    /// The same thread sends again the packets the clients did not
+    /// acknowledge (see [`Config::retry_interval`]), since the thread
+    /// that reads from a client may be blocked without a timeout
+    fn start_keep_alive_watchdog(
+        self: &Arc<Self>,
+        shutdown_bool: Arc<AtomicBool>,
+    ) -> ServerResult<JoinHandle<()>> {
+        let server = Arc::downgrade(self);
+        let handle = thread::Builder::new()
+            .name("keep_alive_watchdog".to_owned())
+            .spawn(move || Self::keep_alive_watchdog_loop(server, shutdown_bool))?;
+        Ok(handle)
+    }
+
+    #[doc(hidden)]
+    fn keep_alive_watchdog_loop(server: Weak<Self>, shutdown_bool: Arc<AtomicBool>) {
+        while !shutdown_bool.load(Ordering::Relaxed) {
+            thread::sleep(KEEP_ALIVE_CHECK);
+            let server = match server.upgrade() {
+                Some(server) => server,
+                None => return,
+            };
+            let result = server
+                .clients_manager
+                .read_or_recover()
+                .close_expired_connections(KEEP_ALIVE_WATCHDOG_GRACE);
+            match result {
+                Ok(expired) => {
+                    for id in expired {
+                        warn!("<{}>: KeepAlive Timeout", id);
+                    }
+                }
+                Err(e) => error!("Error en el watchdog de KeepAlive: {}", e),
+            }
+            let max_retries = server.config.max_retries();
+            let result = server
+                .clients_manager
+                .read_or_recover()
+                .retry_unacknowledged(server.config.retry_interval(), max_retries);
+            match result {
+                Ok(exhausted) => {
+                    for id in exhausted {
+                        warn!(
+                            "<{}>: El cliente no confirmo un paquete luego de {} reintentos",
+                            id, max_retries
+                        );
+                    }
+                }
+                Err(e) => error!("Error reenviando paquetes: {}", e),
+            }
+        }
+    }
+
+    /// Starts the timer that publishes the delayed Last Will packets
+    /// (see [`Config::will_delay`])
+    fn start_will_scheduler(self: &Arc<Self>) -> ServerResult<()> {
+        let server = Arc::downgrade(self);
+        self.will_scheduler.start(move |id, last_will| {
+            if let Some(server) = server.upgrade() {
+                server
+                    .send_last_will(last_will, id)
+                    .unwrap_or_else(|e| error!("Error publicando el Last Will: {}", e));
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Starts the timer that delivers the retained messages whose
+    /// delivery is spread (see [`Config::retained_delivery_window`])
+    fn start_retained_scheduler(self: &Arc<Self>) -> ServerResult<()> {
+        let server = Arc::downgrade(self);
+        self.retained_scheduler.start(move |id, topic| {
+            if let Some(server) = server.upgrade() {
+                server.deliver_retained(id, topic).unwrap_or_else(|e| {
+                    if e.kind() != ServerErrorKind::ClientDisconnected {
+                        error!("Error enviando retained message: {}", e);
+                    }
+                });
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Creates the TCP listener of the server, bound to the address
+    /// specified in the configuration.
+    ///
+    /// If the address is IPv6, the listener only accepts IPv6
+    /// connections, unless dual stack is enabled in the configuration
+    fn bind(&self) -> ServerResult<TcpListener> {
+        let socket_addr = self.bind_socket_addr()?;
+        let listener = self.create_listener(socket_addr).map_err(|e| {
+            ServerError::new_msg(format!("No se pudo escuchar en {}: {}", socket_addr, e))
+        })?;
+        Ok(listener)
+    }
+
+    /// Binds the listener through which the bridges connect (see
+    /// [`Config::bridge_port`]), on the bind address of the configuration.
+    ///
+    /// If there is no port configured, it is only bound if the server
+    /// has bridges, on the loopback address and a port assigned by the OS
+    fn bind_bridge_listener(&self) -> ServerResult<Option<TcpListener>> {
+        let socket_addr = match self.config.bridge_port() {
+            Some(port) => SocketAddr::new(self.bind_socket_addr()?.ip(), port),
+            None if !self.config.bridges().is_empty() => {
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)
+            }
+            None => return Ok(None),
+        };
+        let listener = self.create_listener(socket_addr).map_err(|e| {
+            ServerError::new_msg(format!("No se pudo escuchar en {}: {}", socket_addr, e))
+        })?;
+        Ok(Some(listener))
+    }
+
+    #[doc(hidden)]
+    fn create_listener(&self, socket_addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(
+            Domain::for_address(socket_addr),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        if socket_addr.is_ipv6() {
+            // La libreria estandar no permite configurar IPV6_V6ONLY
+            socket.set_only_v6(!self.config.dual_stack())?;
+        }
+        // Mismo comportamiento que TcpListener::bind
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&socket_addr.into())?;
+        let backlog = i32::try_from(self.config.listen_backlog()).unwrap_or(i32::MAX);
+        socket.listen(backlog)?;
+        Ok(socket.into())
+    }
+
     /// Receives the [`Connect`] packet from a client, connects it to the
     /// server and sets its network_connection read Timeout with the Keep Alive Timeout
     /// provided by the client in the [`Connect`] packet.
@@ -185,70 +585,215 @@ impl<C: Config> Server<C> {
     /// [`ServerErrorKind::ConnectionRefused`], with the return code that the Connack
     /// must contain. If the error it returns is not of that kind, a Connack should
     /// not be send.
+    ///
+    /// The client gets the mount point of the listener it connected
+    /// through, if it has one (see [`Config::mount_point`]). Its
+    /// session is then kept apart from the ones of the clients of
+    /// other mount points, even if they use the same client id. To
+    /// do so, client ids with null characters are rejected.
+    ///
+    /// The client ids of the bridges are only accepted through the
+    /// listener of the bridges (see [`Config::bridge_port`]). Otherwise,
+    /// it returns an error of kind [`ServerErrorKind::ConnectionRefused`]
+    /// with return code [`ConnackReturnCode::IdentifierRejected`]
     #[instrument(skip(self, network_connection))]
     fn connect_client(
         self: &Arc<Self>,
         network_connection: &mut NetworkConnection<TcpStream, SocketAddr>,
+        listener: ListenerOptions,
     ) -> ServerResult<ConnectInfo> {
         debug!("Conectando cliente");
-        let connect = self.wait_for_connect(network_connection)?;
-        let clean_session = *connect.clean_session();
-        network_connection.alert(UNACK_RESENDING_FREQ)?;
-        let connect_info = self
-            .clients_manager
-            .write()?
-            .new_session(network_connection.try_clone()?, connect)?;
-        if connect_info.session_present && clean_session {
-            self.topic_handler.remove_client(&connect_info.id)?;
+        let connect = self.wait_for_connect(network_connection);
+        self.pending_connections.fetch_sub(1, Ordering::Relaxed);
+        let mut connect = connect?;
+        if bridge::is_bridge_id(connect.client_id()) && !listener.bridges {
+            return Err(ServerError::new_kind(
+                format!(
+                    "Solo los bridges pueden usar el prefijo {}",
+                    bridge::BRIDGE_ID_PREFIX
+                ),
+                ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected),
+            ));
+        }
+        if connect
+            .client_id()
+            .contains(mount_point::SESSION_ID_SEPARATOR)
+        {
+            return Err(ServerError::new_kind(
+                "El client id no puede contener caracteres nulos",
+                ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected),
+            ));
+        }
+        let mount_point = listener.mount_point;
+        if let Some(mount_point) = &mount_point {
+            // Cada mount point tiene sus propias sesiones
+            let session_id = mount_point::session_id(mount_point, connect.client_id());
+            connect.set_client_id(session_id);
+        }
+        self.clamp_keep_alive(&mut connect);
+        Self::clamp_will_qos(&mut connect);
+        let connect_info = {
+            // El chequeo y el alta se hacen con el mismo lock, para que
+            // dos clientes no puedan ocupar el ultimo lugar a la vez
+            let mut clients_manager = self.clients_manager.write_or_recover();
+            self.check_available(&clients_manager, connect.client_id())?;
+            // Las suscripciones de la sesion anterior se eliminan antes
+            // de establecer la nueva, para que no reciba nada de ellas
+            let connect_info = clients_manager.new_session_purging(
+                network_connection.try_clone()?,
+                connect,
+                |id| Ok(self.topic_handler.remove_client(id)?),
+            )?;
+            clients_manager.client_do(&connect_info.id, |client| {
+                client.set_mount_point(mount_point);
+                client.update_read_timeout()
+            })?;
+            connect_info
+        };
+        if self.will_scheduler.cancel(&connect_info.id)? {
+            debug!("Reconexion antes del delay - Se cancela el Last Will");
         }
         Ok(connect_info)
     }
 
+    /// Clamps the Keep Alive requested by the client to the one allowed
+    /// by the server (see [`Config::max_keep_alive`]). The clamped value
+    /// is the one stored in the session, and therefore the one enforced
+    fn clamp_keep_alive(&self, connect: &mut Connect) {
+        if let Some(max_keep_alive) = self.config.max_keep_alive() {
+            if connect.keep_alive() > max_keep_alive {
+                info!(
+                    "Keep Alive de {} segundos excede el maximo - Se usa {}",
+                    connect.keep_alive(),
+                    max_keep_alive
+                );
+                connect.set_keep_alive(max_keep_alive);
+            }
+        }
+    }
+
+    /// Downgrades the QoS of the last will of the client to [`MAX_QOS`],
+    /// so that the stored last will is the one that will be published
+    fn clamp_will_qos(connect: &mut Connect) {
+        if let Some(will_qos) = connect.will_qos() {
+            if will_qos as u8 > MAX_QOS as u8 {
+                info!(
+                    "Last Will con {} excede el maximo - Se usa {}",
+                    will_qos, MAX_QOS
+                );
+                connect.set_max_will_qos(MAX_QOS);
+            }
+        }
+    }
+
+    /// Checks that the server can accept a new client. That is, it is
+    /// not shutting down and the limit of connected clients (see
+    /// [`Config::max_clients`]) is not exceeded. A client that takes
+    /// over its own session does not count as a new one.
+    ///
+    /// Otherwise, it returns an error of kind
+    /// [`ServerErrorKind::ConnectionRefused`] with return code
+    /// [`ConnackReturnCode::ServerUnavailable`]
+    fn check_available(
+        &self,
+        clients_manager: &ClientsManager<TcpStream, SocketAddr>,
+        id: &ClientIdArg,
+    ) -> ServerResult<()> {
+        let unavailable = |msg: &str| {
+            Err(ServerError::new_kind(
+                msg,
+                ServerErrorKind::ConnectionRefused(ConnackReturnCode::ServerUnavailable),
+            ))
+        };
+        if self.draining.load(Ordering::Relaxed) {
+            return unavailable("El servidor se esta apagando");
+        }
+        if let Some(max_clients) = self.config.max_clients() {
+            if clients_manager.connected_count()? >= max_clients
+                && !clients_manager.is_connected(id)?
+            {
+                return unavailable("Se alcanzo el maximo de clientes conectados");
+            }
+        }
+        Ok(())
+    }
+
     /// Process a client until it disconnects. This includes receiving the
     /// packets that the client send, processing them, and sending the corresponding
     /// acknowledgements. It does not disconnect the client.
     ///
-    /// Returns true if the client should be disconnected gracefully.
-    /// If it returns false or error, it should be disconnected
-    /// ungracefully
+    /// Returns the reason why the client should be disconnected.
+    /// Only if it is [`DisconnectReason::Gracefully`], it should be
+    /// disconnected gracefully. Otherwise, its Last Will is published,
+    /// whether it closed the connection or it was closed by the server
+    /// [MQTT-3.1.2-8]
     #[instrument(skip(self, id, network_connection))]
     fn client_loop(
         self: &Arc<Self>,
         id: &ClientIdArg,
         network_connection: &mut NetworkConnection<TcpStream, SocketAddr>,
-    ) -> ServerResult<bool> {
-        let mut last_activity = SystemTime::now();
+    ) -> ServerResult<DisconnectReason> {
+        // El Keep Alive se cuenta desde que se envio el Connack
         let keep_alive_opt = self
             .clients_manager
-            .read()?
-            .client_do(id, |client| Ok(client.keep_alive()))?;
+            .read_or_recover()
+            .client_do(id, |client| {
+                client.record_activity();
+                Ok(client.keep_alive())
+            })?;
+        let packet_timeout = match keep_alive_opt {
+            Some(keep_alive) => keep_alive.min(self.config.packet_read_timeout()),
+            None => self.config.packet_read_timeout(),
+        };
 
         loop {
-            match self.process_packet(network_connection, id) {
+            match self.process_packet(network_connection, id, packet_timeout) {
                 Ok(packet_type) => {
-                    last_activity = SystemTime::now();
                     if packet_type == PacketType::Disconnect {
-                        return Ok(true);
+                        return Ok(DisconnectReason::Gracefully);
                     }
-                    continue;
-                }
-                Err(err) if err.kind() == ServerErrorKind::Timeout => {
                     self.clients_manager
-                        .read()?
-                        .client_do(id, |client| client.send_unacknowledged(MIN_ELAPSED_TIME))?;
+                        .read_or_recover()
+                        .client_do(id, |client| {
+                            client.record_activity();
+                            client.update_read_timeout()
+                        })?;
+                    continue;
                 }
+                Err(err) if err.kind() == ServerErrorKind::Idle => (),
                 Err(err) => {
-                    if err.kind() != ServerErrorKind::ClientDisconnected {
-                        error!("Error inesperado: {}", err);
+                    // El watchdog cierra la conexion si se agotan los reintentos
+                    if self
+                        .clients_manager
+                        .read_or_recover()
+                        .client_do(id, |client| Ok(client.retries_exhausted()))?
+                    {
+                        return Ok(DisconnectReason::RetriesExhausted);
+                    }
+                    let reason = err.disconnect_reason();
+                    match (reason, err.kind()) {
+                        // Cerrar la conexion sin DISCONNECT es esperable, no es un error
+                        (_, ServerErrorKind::ClientDisconnected) => {
+                            info!("El cliente cerro la conexion sin enviar DISCONNECT")
+                        }
+                        (DisconnectReason::PacketTimeout, _) => {
+                            warn!("Paquete incompleto: {}", err)
+                        }
+                        (DisconnectReason::ProtocolViolation, _) => {
+                            warn!("Violacion del protocolo: {}", err)
+                        }
+                        _ => error!("Error inesperado: {}", err),
                     }
-                    return Ok(false);
+                    return Ok(reason);
                 }
             }
-            if let Some(keep_alive) = keep_alive_opt {
-                if SystemTime::now().duration_since(last_activity)? > keep_alive {
-                    warn!("KeepAlive Timeout");
-                    return Ok(false);
-                }
+            if self
+                .clients_manager
+                .read_or_recover()
+                .client_do(id, |client| Ok(client.keep_alive_expired(Duration::ZERO)))?
+            {
+                warn!("KeepAlive Timeout");
+                return Ok(DisconnectReason::KeepAliveTimeout);
             }
         }
     }
@@ -263,6 +808,11 @@ impl<C: Config> Server<C> {
     ///
     /// In case a Client TakeOver occurs and the previous session had LastWill,
     /// it is also published.
+    ///
+    /// A client may send packets right after its [`Connect`], without waiting
+    /// for the [`Connack`]. Since the [`Connect`] is read without consuming
+    /// any byte past its end, those packets remain in the connection and are
+    /// processed in order once the [`Connack`] is sent.
     #[instrument(skip(self, connect_info, network_connection) fields(client_id = %connect_info.id))]
     fn manage_successful_connection(
         self: &Arc<Self>,
@@ -278,12 +828,32 @@ impl<C: Config> Server<C> {
         if let Some(last_will) = connect_info.takeover_last_will {
             self.send_last_will(last_will, &connect_info.id)?;
         }
+        let socket_addr = *network_connection.id();
+        for listener in self.connection_listeners.read()?.iter() {
+            listener.on_connect(&connect_info.id, socket_addr);
+        }
         let disconnect_info;
-        let gracefully = self
+        let reason = self
             .client_loop(&connect_info.id, &mut network_connection)
-            .unwrap_or(false);
-        debug!("Cliente desconectado (Gracefully: {})", gracefully);
-        disconnect_info = self.clients_manager.write()?.disconnect(
+            .unwrap_or(DisconnectReason::ConnectionLost);
+        // Los paquetes leidos antes de la desconexion se terminan
+        // de procesar antes de desconectarlo
+        self.client_queues.wait_idle(&connect_info.id)?;
+        if let Some(err) = self.client_queues.take_error(&connect_info.id)? {
+            debug!(
+                "Error procesando un paquete durante la desconexion: {}",
+                err
+            );
+        }
+        let gracefully = reason == DisconnectReason::Gracefully;
+        let counters = network_connection.counters().clone();
+        info!(
+            bytes_read = counters.bytes_read(),
+            bytes_written = counters.bytes_written(),
+            "Cliente desconectado (Gracefully: {})",
+            gracefully
+        );
+        disconnect_info = self.clients_manager.write_or_recover().disconnect(
             &connect_info.id,
             network_connection,
             gracefully,
@@ -292,14 +862,23 @@ impl<C: Config> Server<C> {
             self.topic_handler.remove_client(&connect_info.id)?;
         }
         if let Some(last_will) = disconnect_info.publish_last_will {
-            self.send_last_will(last_will, &connect_info.id)?;
+            match self.config.will_delay() {
+                Some(delay) => self
+                    .will_scheduler
+                    .schedule(&connect_info.id, last_will, delay)?,
+                None => self.send_last_will(last_will, &connect_info.id)?,
+            }
+        }
+        for listener in self.connection_listeners.read()?.iter() {
+            listener.on_disconnect(&connect_info.id, reason);
         }
         Ok(())
     }
 
     /// Send a [`Connack`] to the client if the connection failed due to one
-    /// of the errors listed in section `3.2.2.3` of the MQTT v3.1.1 protocol
-    /// Otherwise, it returns a [`ServerError`]
+    /// of the errors listed in section `3.2.2.3` of the MQTT v3.1.1 protocol.
+    /// If the client violated the protocol, the connection is closed without
+    /// sending anything. Otherwise, it returns a [`ServerError`]
     #[instrument(skip(self, network_connection, error))]
     fn manage_failed_connection(
         &self,
@@ -312,19 +891,34 @@ impl<C: Config> Server<C> {
                 network_connection.write_all(&Connack::new(false, return_code).encode()?)?;
                 Ok(())
             }
+            // No corresponde enviar un Connack, se cierra la conexion
+            ServerErrorKind::ProtocolViolation | ServerErrorKind::Timeout => {
+                warn!("Conexion rechazada: {}", error);
+                network_connection.close()?;
+                Ok(())
+            }
             _ => Err(error),
         }
     }
 
     // Metodo usado para procesar los errores mas facilmente
     #[doc(hidden)]
-    #[instrument(skip(self, network_connection) name="run_client", fields(socket_addr = %network_connection.id()))]
+    /// Every event logged while handling the client belongs to a span
+    /// with its address and, once its [`Connect`] is received, its id
     fn _run_client(
         self: Arc<Self>,
         mut network_connection: NetworkConnection<TcpStream, SocketAddr>,
+        listener: ListenerOptions,
     ) -> ServerResult<()> {
-        match self.connect_client(&mut network_connection) {
+        let span = info_span!(
+            "client",
+            peer_addr = %network_connection.peer_addr(),
+            client_id = field::Empty
+        );
+        let _entered = span.enter();
+        match self.connect_client(&mut network_connection, listener) {
             Ok(connect_info) => {
+                span.record("client_id", connect_info.id.as_str());
                 self.manage_successful_connection(connect_info, network_connection)?
             }
             Err(err) => self.manage_failed_connection(network_connection, err)?,
@@ -332,80 +926,277 @@ impl<C: Config> Server<C> {
         Ok(())
     }
 
-    /// Creates a new thread in which the client will be handled. Adds that
-    /// thread to the list of threads pending to be joined
-    #[instrument(skip(self, network_connection, thread_joiner), fields(socket_addr = %network_connection.id()))]
+    /// Creates a new thread in which the client will be handled, named
+    /// after the address of the client. Adds that thread to the list of
+    /// threads pending to be joined
+    #[instrument(skip(self, network_connection, listener, thread_joiner), fields(peer_addr = %network_connection.peer_addr()))]
     fn run_client(
         self: &Arc<Self>,
         network_connection: NetworkConnection<TcpStream, SocketAddr>,
+        listener: ListenerOptions,
         thread_joiner: &mut ThreadJoiner,
     ) -> ServerResult<()> {
         let sv_copy = self.clone();
-        thread_joiner.spawn(move || {
-            sv_copy._run_client(network_connection).unwrap_or_else(|e| {
-                // Si llega un error a este punto ya no se puede solucionar
-                if e.kind() != ServerErrorKind::ClientDisconnected
-                    || e.kind() != ServerErrorKind::ClientNotFound
-                {
-                    error!("Error no manejado: {}", e);
-                }
-            });
+        let name = format!("client {}", network_connection.peer_addr());
+        self.client_threads.fetch_add(1, Ordering::Relaxed);
+        let spawned = thread_joiner.spawn_named(name, move || {
+            // Se descuenta aunque el thread termine con panic
+            let _thread_count = CountGuard(&sv_copy.client_threads);
+            sv_copy
+                .clone()
+                ._run_client(network_connection, listener)
+                .unwrap_or_else(|e| {
+                    // Si llega un error a este punto ya no se puede solucionar
+                    if e.kind() != ServerErrorKind::ClientDisconnected {
+                        error!("Error no manejado: {}", e);
+                    }
+                });
         });
+        if spawned.is_err() {
+            // La conexion se descarta sin llegar a connect_client(),
+            // por lo que tambien deja de estar pendiente
+            self.client_threads.fetch_sub(1, Ordering::Relaxed);
+            self.pending_connections.fetch_sub(1, Ordering::Relaxed);
+        }
+        spawned?;
         Ok(())
     }
 
+    /// Returns true if the limit of threads handling clients
+    /// (see [`Config::max_client_threads`]) was reached
+    fn client_threads_exhausted(&self) -> bool {
+        match self.config.max_client_threads() {
+            Some(max_threads) => self.client_threads.load(Ordering::Relaxed) >= max_threads,
+            None => false,
+        }
+    }
+
     /// Accepts clients and processes them as log as a shutdown signal is not
     /// received from the [ServerController] corresponding to this server
-    #[instrument(skip(self, shutdown_bool, started_sender) fields(ip = %self.config.ip(), port = %self.config.port()))]
+    ///
+    /// Each listener is polled in turn, accepting at most one client
+    /// from each of them on every round
+    #[instrument(skip(self, listeners, shutdown_bool) fields(ip = %self.config.ip(), port = %self.config.port()))]
     fn server_loop(
         self: Arc<Self>,
+        listeners: Vec<(TcpListener, ListenerOptions)>,
         shutdown_bool: Arc<AtomicBool>,
-        started_sender: Sender<()>,
+        timers: Vec<JoinHandle<()>>,
     ) -> ServerResult<()> {
-        let listener = TcpListener::bind(format!("{}:{}", self.config.ip(), self.config.port()))?;
-        let mut time_last_dump = SystemTime::now();
-        let dump_info_opt = self.config.dump_info();
-        started_sender.send(())?;
-
         let mut thread_joiner = ThreadJoiner::new();
-        listener.set_nonblocking(true)?;
-        while !shutdown_bool.load(Ordering::Relaxed) {
-            match self.accept_client(&listener) {
-                Ok(connection_stream) => {
-                    let socket_addr = *connection_stream.id();
-                    self.run_client(connection_stream, &mut thread_joiner)
-                        .unwrap_or_else(|e| error!("{}: Error - {}", socket_addr, e));
-                }
-                Err(e) if e.kind() == ServerErrorKind::Idle => {
-                    thread::sleep(ACCEPT_SLEEP_DUR);
-                }
-                Err(e) => {
-                    error!("Error de nueva conexion: {}", e);
+        'accept: while !shutdown_bool.load(Ordering::Relaxed) {
+            if self.drained() {
+                info!("Drenado finalizado");
+                break;
+            }
+            let mut accepted = false;
+            for (listener, options) in &listeners {
+                if self.pending_connections.load(Ordering::Relaxed)
+                    >= self.config.max_pending_connections()
+                    || self.client_threads_exhausted()
+                {
+                    // Las nuevas conexiones esperan en el backlog hasta
+                    // que se libere un lugar
                     break;
                 }
-            }
-            if let Some(dump_info) = dump_info_opt {
-                if SystemTime::now().duration_since(time_last_dump).unwrap() >= dump_info.1 {
-                    self.dump()?;
-                    time_last_dump = SystemTime::now();
+                match self.accept_client(listener) {
+                    Ok(connection_stream) => {
+                        accepted = true;
+                        let socket_addr = *connection_stream.id();
+                        self.run_client(connection_stream, options.clone(), &mut thread_joiner)
+                            .unwrap_or_else(|e| error!("{}: Error - {}", socket_addr, e));
+                    }
+                    Err(e) if e.kind() == ServerErrorKind::Idle => (),
+                    Err(e) => {
+                        error!("Error de nueva conexion: {}", e);
+                        break 'accept;
+                    }
                 }
             }
+            if !accepted {
+                thread::sleep(ACCEPT_SLEEP_DUR);
+            }
         }
 
+        // Si se salio del loop por un error, se detienen los timers igualmente
+        shutdown_bool.store(true, Ordering::Relaxed);
+        for timer in timers {
+            let name = timer.thread().name().unwrap_or_default().to_owned();
+            if timer.join().is_err() {
+                error!("El thread {} termino con panic", name);
+            }
+        }
         self.shutdown()
     }
 
+    /// Returns a snapshot of the amount of bytes read from and
+    /// written to each client with a session in the server.
+    ///
+    /// The counters are kept while the session lasts (including
+    /// reconnections with clean session set to false), and are
+    /// reset when the client connects with clean session set
+    /// to true
+    pub fn client_stats(&self) -> ServerResult<HashMap<ClientId, ClientStats>> {
+        self.clients_manager.read_or_recover().client_stats()
+    }
+
+    /// Returns a snapshot of every connected client, sorted by id.
+    ///
+    /// The clients are read with a read lock, so new connections
+    /// can still be accepted meanwhile (they are only delayed
+    /// while the snapshot is taken)
+    pub fn connected_clients(&self) -> ServerResult<Vec<ClientInfo>> {
+        // Se leen antes de tomar el lock de los clientes
+        let (subscriptions, _) = self.topic_handler.state()?;
+        let clients_manager = self.clients_manager.read_or_recover();
+        let mut clients = vec![];
+        for id in clients_manager.client_ids() {
+            let count = subscriptions.get(&id).map_or(0, Vec::len);
+            if let Some(info) = clients_manager.client_do(&id, |client| Ok(client.info(count)))? {
+                clients.push(info);
+            }
+        }
+        Ok(clients)
+    }
+
+    /// Registers a listener that is notified every time a client
+    /// connects to or disconnects from the server. Listeners are
+    /// called from the thread of the client, in the order they
+    /// were registered
+    pub fn add_connection_listener(
+        &self,
+        listener: Box<dyn ConnectionListener + Send + Sync>,
+    ) -> ServerResult<()> {
+        self.connection_listeners.write()?.push(listener);
+        Ok(())
+    }
+
+    /// Registers a listener that is notified every time a client
+    /// subscribes to or unsubscribes from a topic filter. Listeners
+    /// are called from the thread of the client, in the order they
+    /// were registered
+    pub fn add_subscription_listener(
+        &self,
+        listener: Box<dyn SubscriptionListener + Send + Sync>,
+    ) -> ServerResult<()> {
+        self.subscription_listeners.write()?.push(listener);
+        Ok(())
+    }
+
+    /// Returns the clients that would receive a [`Publish`] sent
+    /// to the given topic, along with the QoS of each matching
+    /// subscription. Nothing is published
+    pub fn matching_subscribers(&self, topic: &str) -> ServerResult<Vec<(ClientId, QoSLevel)>> {
+        Ok(self.topic_handler.matching_subscribers(topic)?)
+    }
+
+    /// Returns the amount of packets published to each topic.
+    ///
+    /// Only the topics that have subscribers or were published
+    /// to within the last minutes are included, up to a fixed
+    /// amount of them
+    pub fn topic_stats(&self) -> ServerResult<HashMap<String, u64>> {
+        Ok(self.topic_handler.topic_stats()?)
+    }
+
+    /// Blocks until every packet read from the client with the given
+    /// id was handled, and the messages it published were taken from
+    /// the dispatch queue. The dispatch of the last one may still be
+    /// in progress. Useful to also measure the dispatch of the
+    /// messages in the benchmarks
+    #[cfg(feature = "bench")]
+    pub fn wait_dispatched(&self, id: &ClientIdArg) -> ServerResult<()> {
+        self.client_queues.wait_idle(id)?;
+        while !self.dispatch_queue.is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+
+    /// Removes every subscription and retained message, for example
+    /// to reset the server without restarting it. The clients stay
+    /// connected (and keep their sessions), but they do not receive
+    /// anything until they subscribe again
+    pub fn clear_topics(&self) -> ServerResult<()> {
+        Ok(self.topic_handler.clear()?)
+    }
+
+    /// Removes every subscription of the client with the given id,
+    /// which stays connected. The retained messages are kept
+    pub fn clear_client_subscriptions(&self, id: &ClientIdArg) -> ServerResult<()> {
+        Ok(self.topic_handler.clear_client(id)?)
+    }
+
+    /// Starts draining the server, for example before replacing it
+    /// with a new instance. New clients are refused with the return
+    /// code 0x03 (Server unavailable), while the connected ones keep
+    /// being served.
+    ///
+    /// Once every client disconnected, or after `timeout`, the server
+    /// shuts down as if its [`ServerController`] was dropped (see
+    /// [`ServerController::wait`])
+    pub fn drain(&self, timeout: Duration) {
+        info!("Drenando servidor");
+        self.draining.store(true, Ordering::Relaxed);
+        *self.drain_deadline.lock_or_recover() = Some(Instant::now() + timeout);
+    }
+
+    /// Returns true if the server is draining and, either every
+    /// client disconnected, or the drain deadline passed
+    fn drained(&self) -> bool {
+        match *self.drain_deadline.lock_or_recover() {
+            Some(deadline) if Instant::now() >= deadline => {
+                warn!("Se alcanzo el limite del drenado - Se desconectan los clientes restantes");
+                true
+            }
+            Some(_) => {
+                let connected = self.clients_manager.read_or_recover().connected_count();
+                matches!(connected, Ok(0))
+            }
+            None => false,
+        }
+    }
+
+    /// Disconnects every connected client, for example to
+    /// perform maintenance tasks. The server keeps accepting
+    /// new connections.
+    ///
+    /// The sessions of the clients with clean_session set to
+    /// true are removed, and the Last Will of the clients that
+    /// specified one is published (honoring `will_delay`)
+    pub fn disconnect_all(self: &Arc<Self>, reason: &str) -> ServerResult<()> {
+        let disconnect_info = self
+            .clients_manager
+            .write_or_recover()
+            .disconnect_all(reason)?;
+        for client_id in disconnect_info.clean_session_ids {
+            self.topic_handler.remove_client(&client_id)?;
+        }
+        for (id, last_will) in disconnect_info.last_will_packets {
+            match self.config.will_delay() {
+                Some(delay) => self.will_scheduler.schedule(&id, last_will, delay)?,
+                None => self.send_last_will(last_will, &id)?,
+            }
+        }
+        Ok(())
+    }
+
     /// Shuts down the server and performs various cleanups
     /// Sends the last will of all connected clients
     fn shutdown(self: &Arc<Self>) -> ServerResult<()> {
         info!("Apagando servidor");
-        let shutdown_info = self.clients_manager.write()?.shutdown(false)?;
+        self.draining.store(true, Ordering::Relaxed);
+        let shutdown_info = self.clients_manager.write_or_recover().shutdown(false)?;
         for client_id in shutdown_info.clean_session_ids {
             self.topic_handler.remove_client(&client_id)?;
         }
         for (id, last_will) in shutdown_info.last_will_packets {
             self.send_last_will(last_will, &id)?;
         }
+        // Los Last Will demorados se publican antes de apagar
+        for (id, last_will) in self.will_scheduler.take_pending()? {
+            self.send_last_will(last_will, &id)?;
+        }
         Ok(())
     }
 
@@ -428,7 +1219,11 @@ impl<C: Config> Server<C> {
                 Err(ServerError::from(error))
             }
             Ok((stream, socket_addr)) => {
-                stream.set_read_timeout(Some(CONNECTION_WAIT_TIMEOUT))?;
+                stream.set_read_timeout(Some(self.config.connect_timeout()))?;
+                // Aplica tambien a las copias del stream, con las que
+                // se envian los paquetes al cliente
+                stream.set_write_timeout(Some(self.config.write_timeout()))?;
+                self.pending_connections.fetch_add(1, Ordering::Relaxed);
                 Ok(NetworkConnection::new(socket_addr, stream))
             }
         }
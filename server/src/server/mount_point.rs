@@ -0,0 +1,160 @@
+use super::{
+    admin::{is_admin_topic, ADMIN_TOPIC_PREFIX},
+    ServerError, ServerErrorKind, ServerResult,
+};
+
+/// Separator between the mount point and the topic of the client
+const TOPIC_LEVEL_SEPARATOR: char = '/';
+/// Separator between the mount point and the client id in the id of
+/// a session. Since it cannot be part of a client id (see
+/// [MQTT-1.5.3-2]), the ids of the sessions never collide
+pub const SESSION_ID_SEPARATOR: char = '\0';
+
+/// Returns the topic (or topic filter) of a client as it is stored in
+/// the server, that is, inside the mount point of its listener (see
+/// [`Config::mount_point`](crate::traits::Config::mount_point)).
+///
+/// The topics of the admin commands are mounted right after their
+/// prefix (`$admin/tenantA/...`), so that they keep starting with
+/// `$` and wildcards like `#` do not match them
+pub fn mount(mount_point: &str, topic: &str) -> String {
+    if is_admin_topic(topic) {
+        let rest = &topic[ADMIN_TOPIC_PREFIX.len()..];
+        format!(
+            "{}{}{}{}",
+            ADMIN_TOPIC_PREFIX, TOPIC_LEVEL_SEPARATOR, mount_point, rest
+        )
+    } else {
+        format!("{}{}{}", mount_point, TOPIC_LEVEL_SEPARATOR, topic)
+    }
+}
+
+/// Returns the topic as it is seen by the clients of the mount point.
+/// If the topic does not belong to the mount point, it returns None
+pub fn unmount(mount_point: &str, topic: &str) -> Option<String> {
+    if let Some(rest) = topic
+        .strip_prefix(ADMIN_TOPIC_PREFIX)
+        .and_then(|rest| rest.strip_prefix(TOPIC_LEVEL_SEPARATOR))
+    {
+        let rest = rest.strip_prefix(mount_point)?;
+        if rest.is_empty() || rest.starts_with(TOPIC_LEVEL_SEPARATOR) {
+            return Some(format!("{}{}", ADMIN_TOPIC_PREFIX, rest));
+        }
+        return None;
+    }
+    topic
+        .strip_prefix(mount_point)?
+        .strip_prefix(TOPIC_LEVEL_SEPARATOR)
+        .map(str::to_owned)
+}
+
+/// Returns the id of the session of a client of the mount point, so
+/// that clients of different mount points can use the same client id
+/// without taking over each other's session. An empty id is kept
+/// empty, so that the server assigns one
+pub fn session_id(mount_point: &str, client_id: &str) -> String {
+    if client_id.is_empty() {
+        return String::new();
+    }
+    format!("{}{}{}", mount_point, SESSION_ID_SEPARATOR, client_id)
+}
+
+/// Returns the client id of a session of the mount point (see
+/// [`session_id`]), as it is seen by its clients. The ids assigned
+/// by the server are returned as they are
+pub fn client_id<'a>(mount_point: &str, session_id: &'a str) -> &'a str {
+    session_id
+        .strip_prefix(mount_point)
+        .and_then(|rest| rest.strip_prefix(SESSION_ID_SEPARATOR))
+        .unwrap_or(session_id)
+}
+
+/// Checks that the mount point can be used as the first levels of
+/// a topic. That is, it is not empty and has neither wildcards nor
+/// null characters. Otherwise, it returns an error of kind
+/// [`ServerErrorKind::InvalidConfig`]
+pub fn validate(mount_point: &str) -> ServerResult<()> {
+    if mount_point.is_empty() || mount_point.contains(['+', '#', '\0']) {
+        return Err(ServerError::new_kind(
+            format!("Mount point invalido: <{}>", mount_point),
+            ServerErrorKind::InvalidConfig,
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that no mount point is nested inside another one, since
+/// their topics would collide (`a` with `b/c` and `a/b` with `c`
+/// are both `a/b/c`). Otherwise, it returns an error of kind
+/// [`ServerErrorKind::InvalidConfig`]. The same mount point can be
+/// used by several listeners
+pub fn validate_disjoint(mount_points: &[&str]) -> ServerResult<()> {
+    for outer in mount_points {
+        for inner in mount_points {
+            let nested = inner
+                .strip_prefix(outer)
+                .is_some_and(|rest| rest.starts_with(TOPIC_LEVEL_SEPARATOR));
+            if nested {
+                return Err(ServerError::new_kind(
+                    format!("El mount point <{}> esta dentro de <{}>", inner, outer),
+                    ServerErrorKind::InvalidConfig,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client_id, mount, session_id, unmount, validate, validate_disjoint};
+
+    #[test]
+    fn test_mount_and_unmount() {
+        assert_eq!(mount("tenantA", "data"), "tenantA/data");
+        assert_eq!(mount("tenantA", "#"), "tenantA/#");
+        assert_eq!(unmount("tenantA", "tenantA/data").unwrap(), "data");
+        assert_eq!(unmount("tenantA", "tenantAB/data"), None);
+        assert_eq!(unmount("tenantA", "tenantB/data"), None);
+        assert_eq!(unmount("tenantA", "$admin/stats"), None);
+    }
+
+    #[test]
+    fn test_admin_topics_are_mounted_after_their_prefix() {
+        assert_eq!(mount("tenantA", "$admin/stats"), "$admin/tenantA/stats");
+        assert_eq!(mount("tenantA", "$admin/#"), "$admin/tenantA/#");
+        assert_eq!(
+            unmount("tenantA", "$admin/tenantA/stats/response/1").unwrap(),
+            "$admin/stats/response/1"
+        );
+        assert_eq!(unmount("tenantA", "$admin/tenantB/stats"), None);
+        assert_eq!(unmount("tenantA", "$admin/tenantAB/stats"), None);
+    }
+
+    #[test]
+    fn test_session_ids() {
+        assert_eq!(session_id("tenantA", "client"), "tenantA\0client");
+        assert_eq!(session_id("tenantA", ""), "");
+        assert_eq!(client_id("tenantA", "tenantA\0client"), "client");
+        assert_eq!(client_id("tenantA", "generated"), "generated");
+        assert_ne!(session_id("a", "b/c"), session_id("a/b", "c"));
+    }
+
+    #[test]
+    fn test_nested_mount_points() {
+        assert!(validate_disjoint(&["tenantA", "tenantB", "tenantA"]).is_ok());
+        assert!(validate_disjoint(&["tenant", "tenantA"]).is_ok());
+        assert!(validate_disjoint(&["a", "a/b"]).is_err());
+        assert!(validate_disjoint(&["a/b/c", "a/b"]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_mount_points() {
+        assert!(validate("tenantA").is_ok());
+        assert!(validate("tenants/a").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate("tenant/+").is_err());
+        assert!(validate("#").is_err());
+        assert!(validate("tenant\0").is_err());
+    }
+}
@@ -1,32 +1,78 @@
-use packets::{packet_error::ErrorKind, pingresp::PingResp};
+use std::time::Instant;
 
-use super::*;
+use packets::{
+    packet_error::ErrorKind, packet_reader::DeadlineReader, pingresp::PingResp,
+    topic_filter::TopicFilter,
+};
+
+use super::{admin::is_admin_topic, dispatch_queue::SkipOrigin, mount_point, *};
 
 impl<C: Config> Server<C> {
     /// Submit a job to the ThreadPool
+    ///
+    /// The jobs of the same client are executed one at a time,
+    /// in the order they were submitted, while the jobs of
+    /// different clients can run concurrently. If the client
+    /// already has too many jobs pending, it blocks until one
+    /// of them is executed.
+    ///
+    /// If the job fails, the connection of the client is closed,
+    /// and the error is returned by the next call to
+    /// [`Server::process_packet`] for the client
     fn to_threadpool<F>(self: &Arc<Self>, action: F, id: &ClientIdArg) -> ServerResult<()>
     where
         F: FnOnce(Arc<Self>, &ClientId) -> ServerResult<()> + Send + 'static,
     {
         let sv_copy = self.clone();
         let id_copy = id.to_owned();
-        self.pool.lock()?.execute(move || {
+        let job = Box::new(move || {
+            let server = sv_copy.clone();
             action(sv_copy, &id_copy).unwrap_or_else(|e| {
-                if e.kind() != ServerErrorKind::ClientNotFound
-                    && e.kind() != ServerErrorKind::ClientDisconnected
-                {
-                    error!("{}", e);
+                if e.kind() != ServerErrorKind::ClientDisconnected {
+                    server.fail_client(&id_copy, e);
                 }
             });
-        })?;
+        });
+        // Si el cliente ya tenia un trabajo en curso, el worker
+        // que lo esta ejecutando se encarga tambien de este
+        if self.client_queues.push(id, job)? {
+            let sv_copy = self.clone();
+            let id_copy = id.to_owned();
+            self.pool.lock_or_recover().execute(move || {
+                sv_copy
+                    .client_queues
+                    .run_pending(&id_copy)
+                    .unwrap_or_else(|e| error!("{}", e));
+            })?;
+        }
         Ok(())
     }
 
+    /// Records the error of a job of the client, and closes its
+    /// connection so that the thread reading from it notices
+    fn fail_client(&self, id: &ClientIdArg, err: ServerError) {
+        self.client_queues
+            .fail(id, err)
+            .unwrap_or_else(|e| error!("{}", e));
+        // El cliente puede haberse desconectado mientras tanto
+        let _ = self
+            .clients_manager
+            .read_or_recover()
+            .client_do(id, |client| client.close_connection());
+    }
+
     /// Reads a packet from the stream and processes it.
     ///
     /// The first byte of the packet must have already been read, and
     /// corresponds to the *control_byte* parameter.
     ///
+    /// The packet is read from the calling thread, while its handling
+    /// goes through the queue of the client (see `to_threadpool()`), so
+    /// that the packets of a client are handled in the order they were
+    /// sent. The Disconnect needs no handling, but whoever reads it should
+    /// wait for the pending packets of the client (see
+    /// [`ClientQueues::wait_idle`]) before disconnecting it
+    ///
     /// Returns the type of package that was read
     fn process_packet_given_control_byte<T: Read>(
         self: &Arc<Self>,
@@ -37,17 +83,33 @@ impl<C: Config> Server<C> {
         let packet_type = PacketType::try_from(control_byte)?;
         match packet_type {
             PacketType::Publish => {
-                let publish = Publish::read_from(stream, control_byte)?;
+                let publish = match self.config.max_payload_size() {
+                    Some(max_size) => {
+                        Publish::read_with_max_payload(stream, control_byte, max_size)
+                    }
+                    None => Publish::read_from(stream, control_byte),
+                }
+                .map_err(|err| match err.kind() {
+                    ErrorKind::PayloadTooLarge | ErrorKind::MalformedLength => {
+                        ServerError::new_kind(
+                            &format!("<{}>: Publish rechazado: {}", id, err),
+                            ServerErrorKind::ProtocolViolation,
+                        )
+                    }
+                    _ => err.into(),
+                })?;
+                publish.check_topic_len(self.config.max_topic_len())?;
+                // Si la cola de despacho esta llena, la cola del cliente se
+                // llena tambien, y se deja de leer de su conexion
                 self.to_threadpool(|server, id| server.handle_publish(publish, id), id)?;
             }
             PacketType::Puback => {
-                let packet = Puback::read_from(stream, control_byte)?;
-                self.clients_manager
-                    .read()?
-                    .client_do(id, |client| client.acknowledge(packet))?;
+                let puback = Puback::read_from(stream, control_byte)?;
+                self.to_threadpool(|server, id| server.handle_puback(puback, id), id)?;
             }
             PacketType::Subscribe => {
                 let subscribe = Subscribe::read_from(stream, control_byte)?;
+                subscribe.check_topic_len(self.config.max_topic_len())?;
                 self.to_threadpool(|server, id| server.handle_subscribe(subscribe, id), id)?;
             }
             PacketType::Unsubscribe => {
@@ -56,12 +118,30 @@ impl<C: Config> Server<C> {
             }
             PacketType::PingReq => {
                 let _packet = PingReq::read_from(stream, control_byte)?;
-                self.clients_manager
-                    .read()?
-                    .client_do(id, |client| client.send_packet(&PingResp::new()))?;
+                self.to_threadpool(
+                    |server, id| {
+                        server
+                            .clients_manager
+                            .read_or_recover()
+                            .client_do(id, |client| client.send_packet(&PingResp::new()))
+                    },
+                    id,
+                )?;
             }
             PacketType::Disconnect => {
-                let _packet = Disconnect::read_from(stream, control_byte)?;
+                // Un Disconnect mal formado no es una desconexion ordenada
+                let _packet =
+                    Disconnect::read_from(stream, control_byte).map_err(|err| {
+                        match err.kind() {
+                            ErrorKind::InvalidReservedBits | ErrorKind::TrailingBytes => {
+                                ServerError::new_kind(
+                                    &format!("<{}>: Disconnect invalido: {}", id, err),
+                                    ServerErrorKind::ProtocolViolation,
+                                )
+                            }
+                            _ => err.into(),
+                        }
+                    })?;
             }
             _ => {
                 return Err(ServerError::new_kind(
@@ -78,127 +158,326 @@ impl<C: Config> Server<C> {
     ///
     /// In case the client associated with the stream has disconnected,
     /// it returns an error of kin [`ServerErrorKind::ClientDisconnected`]
+    ///
+    /// If there is no packet to be read (the read of the first byte
+    /// timed out), it returns an error of kind [`ServerErrorKind::Idle`].
+    /// Once the first byte arrives, the rest of the packet must arrive
+    /// within `packet_timeout`. Otherwise, it returns an error of kind
+    /// [`ServerErrorKind::Timeout`]. To enforce it even if the reads of
+    /// the stream did not time out, they are left timing out with
+    /// `packet_timeout`, so the caller should restore their timeout
+    ///
+    /// Since every client is read from its own thread, the packet
+    /// body is read into the per-thread scratch buffer of the packets
+    /// crate, which is reused across all the packets of the client
+    ///
+    /// If the handling of a previous packet of the client failed, that
+    /// error is returned instead (see `to_threadpool()`)
     #[instrument(skip(self, stream, id))]
-    pub fn process_packet<T: Read>(
+    pub fn process_packet<T: Read + Interrupt>(
+        self: &Arc<Self>,
+        stream: &mut T,
+        id: &ClientIdArg,
+        packet_timeout: Duration,
+    ) -> ServerResult<PacketType> {
+        let result = self.read_packet(stream, id, packet_timeout);
+        // Si fallo el manejo de un paquete anterior, se cerro la
+        // conexion, asi que ese es el error que importa
+        match self.client_queues.take_error(id)? {
+            Some(err) => Err(err),
+            None => result,
+        }
+    }
+
+    #[doc(hidden)]
+    fn read_packet<T: Read + Interrupt>(
         self: &Arc<Self>,
         stream: &mut T,
         id: &ClientIdArg,
+        packet_timeout: Duration,
     ) -> ServerResult<PacketType> {
         let mut control_byte_buff = [0u8; 1];
-        stream.read_exact(&mut control_byte_buff)?;
-        self.process_packet_given_control_byte(control_byte_buff[0], stream, id)
+        match stream.read_exact(&mut control_byte_buff) {
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Err(ServerError::new_kind(
+                    "No hay paquetes para leer",
+                    ServerErrorKind::Idle,
+                ));
+            }
+            result => result?,
+        }
+        // El resto del paquete debe llegar a tiempo aunque las
+        // lecturas no expiren (como con un Keep Alive de 0)
+        stream.alert(packet_timeout)?;
+        let mut stream = DeadlineReader::new(stream, Instant::now() + packet_timeout);
+        self.process_packet_given_control_byte(control_byte_buff[0], &mut stream, id)
     }
 
     #[inline]
     #[doc(hidden)]
     fn _send_publish(
-        self: Arc<Self>,
-        client_id_receiver: ClientId,
+        &self,
+        client_id_receiver: &ClientIdArg,
         publish: Publish,
     ) -> ServerResult<()> {
         self.clients_manager
-            .read()?
-            .client_do(&client_id_receiver, |client| client.send_publish(publish))
+            .read_or_recover()
+            .client_do(client_id_receiver, |client| {
+                // Cada cliente tiene sus propios packet ids
+                let packet_id = client.next_packet_id();
+                client.send_publish(publish.with_packet_id(packet_id))
+            })
     }
 
-    #[instrument(skip(self, threadpool_copy, message), fields(client_id_receiver = %message.client_id))]
+    #[instrument(skip(self, message), fields(client_id_receiver = %message.client_id))]
     #[inline]
-    fn publish_dispatch(
-        self: &Arc<Self>,
-        threadpool_copy: &ThreadPool,
-        message: Message,
-    ) -> ServerResult<()> {
-        let client_id_receiver = message.client_id;
-        let publish = message.packet;
+    fn publish_dispatch(&self, message: Message) {
         debug!("Enviando PUBLISH");
-        let sv_copy = self.clone();
-        threadpool_copy
-            .execute(move || {
-                sv_copy
-                    ._send_publish(client_id_receiver, publish)
-                    .unwrap_or_else(|e| {
-                        if e.kind() != ServerErrorKind::ClientNotFound
-                            && e.kind() != ServerErrorKind::ClientDisconnected
-                        {
-                            error!("Error enviando PUBLISH: {}", e);
-                        }
-                    });
-            })
-            .map_err(ServerError::from)
+        self._send_publish(&message.client_id, message.packet)
             .unwrap_or_else(|e| {
-                if e.kind() != ServerErrorKind::ClientNotFound
-                    && e.kind() != ServerErrorKind::ClientDisconnected
-                {
-                    error!("{}", e);
+                if e.kind() != ServerErrorKind::ClientDisconnected {
+                    error!("Error enviando PUBLISH: {}", e);
                 }
             });
-
-        Ok(())
     }
 
-    /// Receives through the channel the packets to be published, and
-    /// publishes them
-    fn publish_dispatcher_loop(self: &Arc<Self>, receiver: Receiver<Message>) -> ServerResult<()> {
-        let lock = self.pool.lock()?;
-        let threadpool_copy = lock.clone();
-        drop(lock);
-
-        for message in receiver {
-            self.publish_dispatch(&threadpool_copy, message)?;
+    /// Takes from the dispatch queue the packets to be published,
+    /// and publishes them
+    ///
+    /// The packets are sent from this same thread, so that a slow
+    /// subscriber slows down the dispatch instead of piling up packets
+    /// in the [`ThreadPool`] queue. Since the queue is bounded, the
+    /// publication is then slowed down or messages are dropped (see
+    /// [`Config::overload_policy`]). It only keeps a weak reference
+    /// to the server, and ends when the server is dropped
+    fn publish_dispatcher_loop(server: Weak<Self>, queue: Arc<DispatchQueue>) {
+        loop {
+            match queue.pop_timeout(DISPATCH_CHECK) {
+                Some(message) => match server.upgrade() {
+                    Some(server) => server.publish_dispatch(message),
+                    None => break,
+                },
+                None if server.strong_count() == 0 => break,
+                None => (),
+            }
         }
+        debug!("Finalizando despachador de PUBLISH");
+    }
+
+    /// Spawns the thread that sends to the subscribers the packets
+    /// pushed into `queue` (see [`Config::dispatch_queue_len`])
+    ///
+    /// The dispatcher runs on its own thread, and not on the
+    /// [`ThreadPool`], so that it can always make progress even if
+    /// every thread of the pool is blocked publishing
+    pub(super) fn start_publish_dispatcher(
+        self: &Arc<Self>,
+        queue: Arc<DispatchQueue>,
+    ) -> ServerResult<()> {
+        let server = Arc::downgrade(self);
+        thread::Builder::new()
+            .name("publish_dispatcher".to_owned())
+            .spawn(move || Self::publish_dispatcher_loop(server, queue))?;
         Ok(())
     }
 
     /// Send [`Publish`] to all clients that are subscribed to the topic
-    fn broadcast_publish(self: &Arc<Self>, publish: Publish) -> ServerResult<()> {
-        let (sender, receiver) = mpsc::channel();
-        let sv_copy = self.clone();
-        self.pool.lock()?.execute(move || {
-            sv_copy
-                .publish_dispatcher_loop(receiver)
-                .unwrap_or_else(|e| error!("Error despachando el PUBLISH: {}", e));
-        })?;
-
-        self.topic_handler.publish(&publish, sender)?;
+    ///
+    /// The messages go through a bounded queue, so this method
+    /// may block while the dispatcher is behind (see
+    /// [`Config::overload_policy`])
+    ///
+    /// If [`Config::no_local`] is set, or the `origin` client is a
+    /// bridge (see [`Config::bridges`]), the message is not sent back
+    /// to it
+    pub(super) fn broadcast_publish(
+        &self,
+        publish: Publish,
+        origin: Option<&ClientIdArg>,
+    ) -> ServerResult<()> {
+        match origin {
+            Some(origin) if self.config.no_local() || bridge::is_bridge_id(origin) => {
+                self.topic_handler.publish(
+                    &publish,
+                    SkipOrigin {
+                        sink: &*self.dispatch_queue,
+                        origin,
+                    },
+                )?
+            }
+            _ => self
+                .topic_handler
+                .publish(&publish, &*self.dispatch_queue)?,
+        }
         Ok(())
     }
 
     /// Publish the packet so that all clients subscribed
     /// to the topics can receive them
+    ///
+    /// The Puback of a QoS 1 packet is sent before the packet is
+    /// published, which releases its packet identifier [MQTT-2.3.1-6].
+    /// Since the packets of a client are handled in order, it is sent
+    /// before the next packet of the client is handled
+    ///
+    /// If the client has a mount point (see [`Config::mount_point`]),
+    /// the packet is published inside it
     pub fn handle_publish(
         self: &Arc<Self>,
         mut publish: Publish,
         id: &ClientIdArg,
     ) -> ServerResult<()> {
-        publish.set_max_qos(QoSLevel::QoSLevel1);
-        let packet_id = publish.packet_id();
-        self.broadcast_publish(publish)?;
-        if let Some(packet_id) = packet_id {
+        publish.set_max_qos(MAX_QOS);
+        // El Puback se envia antes de publicar, ya que la publicacion
+        // puede bloquearse si la cola de despacho esta llena
+        if let Some(packet_id) = publish.packet_id() {
             self.clients_manager
-                .read()?
+                .read_or_recover()
                 .client_do(id, |client| client.send_packet(&Puback::new(packet_id)?))?;
         }
+        if is_admin_topic(publish.topic_name()) {
+            return self.handle_admin_command(&publish, id);
+        }
+        let publish = match self.clients_manager.read_or_recover().mount_point(id)? {
+            Some(mount_point) => {
+                let topic = mount_point::mount(&mount_point, publish.topic_name());
+                publish.with_topic_name(&topic)?
+            }
+            None => publish,
+        };
+        self.broadcast_publish(publish, Some(id))
+    }
+
+    /// Returns the topic filters inside the mount point of the
+    /// client (see [`Config::mount_point`])
+    fn mount_filters(
+        &self,
+        filters: Vec<TopicFilter>,
+        id: &ClientIdArg,
+    ) -> ServerResult<Vec<TopicFilter>> {
+        let mount_point = match self.clients_manager.read_or_recover().mount_point(id)? {
+            Some(mount_point) => mount_point,
+            None => return Ok(filters),
+        };
+        let mut mounted = Vec::with_capacity(filters.len());
+        for filter in filters {
+            let name = mount_point::mount(&mount_point, filter.name());
+            mounted.push(TopicFilter::new(name, filter.qos())?);
+        }
+        Ok(mounted)
+    }
+
+    /// Removes the packet acknowledged by the [`Puback`] from the
+    /// unacknowledged packets of the client. If it is unknown and
+    /// [`Config::strict_protocol`] is set, it returns an error of
+    /// kind [`ServerErrorKind::ProtocolViolation`]
+    fn handle_puback(&self, puback: Puback, id: &ClientIdArg) -> ServerResult<()> {
+        let packet_id = puback.packet_id();
+        let known = self
+            .clients_manager
+            .read_or_recover()
+            .client_do(id, |client| client.acknowledge(puback))?;
+        if !known {
+            warn!("<{}>: Puback con packet id desconocido ({})", id, packet_id);
+            if self.config.strict_protocol() {
+                return Err(ServerError::new_kind(
+                    &format!("<{}>: Puback con packet id desconocido ({})", id, packet_id),
+                    ServerErrorKind::ProtocolViolation,
+                ));
+            }
+        }
         Ok(())
     }
 
     /// Subscribes the client to all the topics specified in the
     /// [`Subscribe`] packet
     /// Send the corresponding Suback
+    ///
+    /// Only the clients that can issue admin commands can subscribe
+    /// to the topics of their responses. Otherwise, none of the topic
+    /// filters is subscribed to
+    ///
+    /// If the client has a mount point (see [`Config::mount_point`]),
+    /// it only subscribes to the topics inside it
     fn handle_subscribe(&self, mut subscribe: Subscribe, id: &ClientIdArg) -> ServerResult<()> {
-        subscribe.set_max_qos(QoSLevel::QoSLevel1);
+        subscribe.set_max_qos(MAX_QOS);
+        let admin_filters = subscribe
+            .topics()
+            .iter()
+            .any(|filter| is_admin_topic(filter.name()));
+        if admin_filters && !self.clients_manager.read_or_recover().is_admin(id)? {
+            warn!("Suscripcion rechazada: el cliente no puede usar los topics de administracion");
+            return self
+                .clients_manager
+                .read_or_recover()
+                .client_do(id, |client| {
+                    client.send_packet(&subscribe.failure_response()?)
+                });
+        }
+        let subscribe = Subscribe::new(
+            self.mount_filters(subscribe.topics(), id)?,
+            subscribe.packet_identifier(),
+        );
         let retained_messages = self.topic_handler.subscribe(&subscribe, id)?;
+        for listener in self.subscription_listeners.read()?.iter() {
+            for filter in subscribe.topics() {
+                listener.on_subscribe(id, filter.name(), filter.qos());
+            }
+        }
         self.clients_manager
-            .read()?
+            .read_or_recover()
             .client_do(id, |client| client.send_packet(&subscribe.response()?))?;
-        if !retained_messages.is_empty() {
-            self.clients_manager.read()?.client_do(id, |client| {
+        if retained_messages.is_empty() {
+            return Ok(());
+        }
+        if let Some(window) = self.config.retained_delivery_window() {
+            let topics = retained_messages
+                .iter()
+                .map(|retained| retained.topic_name().to_string())
+                .collect();
+            return self.retained_scheduler.schedule(id, topics, window);
+        }
+        self.clients_manager
+            .read_or_recover()
+            .client_do(id, |client| {
                 for retained in retained_messages {
-                    client.send_publish(retained)?;
+                    let packet_id = client.next_packet_id();
+                    client.send_publish(retained.with_packet_id(packet_id))?;
                 }
                 Ok(())
-            })?;
+            })
+    }
+
+    /// Sends to the client the retained message currently stored in
+    /// the topic, whose delivery was spread (see
+    /// [`Config::retained_delivery_window`]). Nothing is sent if the
+    /// message was cleared, or if the client is no longer subscribed
+    /// to the topic. If it was replaced, the new one is sent instead
+    pub(super) fn deliver_retained(&self, id: &ClientIdArg, topic: &str) -> ServerResult<()> {
+        let qos = self
+            .topic_handler
+            .matching_subscribers(topic)?
+            .into_iter()
+            .filter(|(subscriber, _)| subscriber == id)
+            .map(|(_, qos)| qos)
+            .max_by_key(|qos| *qos as u8);
+        let retained = match qos {
+            Some(qos) => self.topic_handler.retained_message(topic, qos)?,
+            None => None,
+        };
+        match retained {
+            Some(retained) => self
+                .clients_manager
+                .read_or_recover()
+                .client_do(id, |client| {
+                    let packet_id = client.next_packet_id();
+                    client.send_publish(retained.with_packet_id(packet_id))
+                }),
+            None => Ok(()),
         }
-        Ok(())
     }
 
     /// Unsubscribe the client from the topics specified in the
@@ -206,11 +485,27 @@ impl<C: Config> Server<C> {
     /// Send the corresponding [`Unsuback`]
     fn handle_unsubscribe(&self, unsubscribe: Unsubscribe, id: &ClientIdArg) -> ServerResult<()> {
         let packet_id = unsubscribe.packet_id();
+        let unsubscribe = Unsubscribe::new(
+            packet_id,
+            self.mount_filters(unsubscribe.topic_filters(), id)?,
+        )?;
+        let filters: Vec<String> = unsubscribe
+            .topic_filters()
+            .iter()
+            .map(|filter| filter.name().to_string())
+            .collect();
         self.topic_handler.unsubscribe(unsubscribe, id)?;
-        self.clients_manager.read()?.client_do(id, |client| {
-            client.send_packet(&Unsuback::new(packet_id)?)?;
-            Ok(())
-        })?;
+        for listener in self.subscription_listeners.read()?.iter() {
+            for filter in &filters {
+                listener.on_unsubscribe(id, filter);
+            }
+        }
+        self.clients_manager
+            .read_or_recover()
+            .client_do(id, |client| {
+                client.send_packet(&Unsuback::new(packet_id)?)?;
+                Ok(())
+            })?;
         Ok(())
     }
 
@@ -223,20 +518,29 @@ impl<C: Config> Server<C> {
         id: &ClientIdArg,
     ) -> ServerResult<()> {
         debug!("Enviando LAST WILL");
-        last_will.set_max_qos(QoSLevel::QoSLevel1);
+        last_will.set_max_qos(MAX_QOS);
 
-        self.broadcast_publish(last_will)
+        self.broadcast_publish(last_will, None)
     }
 
-    /// Waits until it receives the [`Connect`] packet. In case the
-    /// read fails due to timeout, it returns an error of kind
-    /// [`ServerErrorKind::Timeout`]
+    /// Waits until it receives the [`Connect`] packet. If it is not
+    /// received completely within [`Config::connect_timeout`], it
+    /// returns an error of kind [`ServerErrorKind::Timeout`]
+    ///
+    /// Clients using MQTT 3.1 are only accepted if
+    /// [`Config::allow_mqtt_31`] is set.
+    ///
+    /// If the first packet sent by the client is not a [`Connect`],
+    /// it returns an error of kind [`ServerErrorKind::ProtocolViolation`]
+    /// (see [MQTT-3.1.0-1])
     #[instrument(skip(self, network_connection))]
     pub fn wait_for_connect(
         &self,
         network_connection: &mut NetworkConnection<TcpStream, SocketAddr>,
     ) -> ServerResult<Connect> {
-        match Connect::new_from_zero(network_connection) {
+        let deadline = Instant::now() + self.config.connect_timeout();
+        let mut reader = DeadlineReader::new(network_connection, deadline);
+        match Connect::new_from_zero_with_compat(&mut reader, self.config.allow_mqtt_31()) {
             Ok(connect) => {
                 debug!("Recibido CONNECT");
                 Ok(connect)
@@ -252,6 +556,12 @@ impl<C: Config> Server<C> {
                     ),
                 ))
             }
+            Err(err) if err.kind() == ErrorKind::InvalidControlPacketType => {
+                Err(ServerError::new_kind(
+                    "El primer paquete recibido no es un CONNECT",
+                    ServerErrorKind::ProtocolViolation,
+                ))
+            }
             Err(err) => Err(ServerError::from(err)),
         }
     }
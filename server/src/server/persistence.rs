@@ -0,0 +1,133 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::MAIN_SEPARATOR,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use tracing::debug;
+
+use crate::traits::PersistenceBackend;
+
+use super::{server_error::ServerErrorKind, ServerError, ServerResult};
+
+/// Suffix of the temporary file in which the dump is
+/// written before replacing the previous one
+const TMP_SUFFIX: &str = ".tmp";
+/// Dump files with this extension are compressed
+const GZIP_EXTENSION: &str = ".gz";
+/// First bytes of every gzip file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// State of the server that is persisted between executions
+/// (sessions of the clients, subscriptions and retained messages).
+///
+/// Its content is a JSON value, so that any backend can store it
+/// (see [`PersistenceBackend`])
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpState {
+    json: serde_json::Value,
+}
+
+impl DumpState {
+    /// Creates a [`DumpState`] from its JSON value
+    pub fn new(json: serde_json::Value) -> Self {
+        Self { json }
+    }
+
+    /// Parses a [`DumpState`] from a JSON string. If it is not
+    /// valid JSON, it returns an error of kind
+    /// [`ServerErrorKind::DumpError`]
+    pub fn parse(json_str: &str) -> ServerResult<Self> {
+        serde_json::from_str(json_str)
+            .map(Self::new)
+            .map_err(|err| ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError))
+    }
+
+    /// Returns the JSON value of the state
+    pub fn json(&self) -> &serde_json::Value {
+        &self.json
+    }
+
+    /// Consumes the state, returning its JSON value
+    pub fn into_json(self) -> serde_json::Value {
+        self.json
+    }
+}
+
+/// Persists the state of the server as a JSON file, optionally
+/// compressed with gzip. This is the backend used by default
+/// (see [`Config::persistence_backend`](crate::Config::persistence_backend))
+#[derive(Debug, Clone)]
+pub struct JsonFileBackend {
+    path: String,
+    compress: bool,
+}
+
+impl JsonFileBackend {
+    /// Creates a backend that stores the state in `path`. It is
+    /// compressed with gzip if `compress` is true or the path ends
+    /// with `.gz`
+    pub fn new(path: &str, compress: bool) -> Self {
+        Self {
+            path: path.to_string(),
+            compress: compress || path.ends_with(GZIP_EXTENSION),
+        }
+    }
+
+    /// Returns the JSON of a dump file, decompressing it
+    /// if it is gzipped (regardless of its extension)
+    pub(crate) fn decode(bytes: Vec<u8>) -> ServerResult<String> {
+        let json_str = if bytes.starts_with(&GZIP_MAGIC) {
+            let mut json_str = String::new();
+            GzDecoder::new(bytes.as_slice())
+                .read_to_string(&mut json_str)
+                .map(|_| json_str)
+        } else {
+            String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        };
+        json_str.map_err(|err| ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError))
+    }
+}
+
+impl PersistenceBackend for JsonFileBackend {
+    /// The previous dump is replaced atomically, so it is never
+    /// left partially written
+    fn save(&self, state: &DumpState) -> ServerResult<()> {
+        debug!("DUMP");
+        if let Some((folder, _)) = self.path.rsplit_once(MAIN_SEPARATOR) {
+            fs::create_dir_all(folder)?;
+        }
+        // Se escribe en un archivo temporal y luego se renombra, para
+        // que un corte a mitad de la escritura no corrompa el DUMP
+        let tmp_path = format!("{}{}", self.path, TMP_SUFFIX);
+        let file = if self.compress {
+            // Comprimido no tiene sentido gastar tiempo en el formato
+            let file = BufWriter::new(File::create(&tmp_path)?);
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            serde_json::to_writer(&mut encoder, state.json())?;
+            encoder.finish()?.into_inner().map_err(|e| e.into_error())?
+        } else {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(serde_json::to_string_pretty(state.json())?.as_bytes())?;
+            file
+        };
+        // Sin esto, un corte de energia luego del rename podria
+        // dejar el DUMP vacio, ya que el contenido aun no llego al disco
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Compressed dumps are decompressed transparently. If the
+    /// file does not exist, it returns None
+    fn load(&self) -> ServerResult<Option<DumpState>> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(ServerError::from(err)),
+        };
+        let json_str = JsonFileBackend::decode(bytes)?;
+        Ok(Some(DumpState::parse(&json_str)?))
+    }
+}
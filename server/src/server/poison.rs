@@ -0,0 +1,82 @@
+use std::{
+    any::type_name,
+    sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use tracing::warn;
+
+/// Takes a [`Mutex`] even if it was poisoned.
+///
+/// A lock is poisoned when a thread panics while holding it. The
+/// server keeps working with the state as it was left, instead of
+/// making every following client fail because of a single job
+pub trait LockOrRecover<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+/// Takes a [`RwLock`] even if it was poisoned (see [`LockOrRecover`])
+pub trait RwLockOrRecover<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+fn recover<G, T>(err: PoisonError<G>) -> G {
+    warn!("Lock envenenado de {} - Se recupera", type_name::<T>());
+    err.into_inner()
+}
+
+impl<T> LockOrRecover<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(recover::<_, T>)
+    }
+}
+
+impl<T> RwLockOrRecover<T> for RwLock<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(recover::<_, T>)
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(recover::<_, T>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex, RwLock},
+        thread,
+    };
+
+    use super::{LockOrRecover, RwLockOrRecover};
+
+    #[test]
+    fn test_poisoned_mutex_is_recovered() {
+        let mutex = Arc::new(Mutex::new(1));
+        let mutex_copy = mutex.clone();
+        let _ = thread::spawn(move || {
+            let mut value = mutex_copy.lock().unwrap();
+            *value = 2;
+            panic!("Panic con el lock tomado");
+        })
+        .join();
+
+        assert!(mutex.is_poisoned());
+        assert_eq!(*mutex.lock_or_recover(), 2);
+    }
+
+    #[test]
+    fn test_poisoned_rwlock_is_recovered() {
+        let rwlock = Arc::new(RwLock::new(1));
+        let rwlock_copy = rwlock.clone();
+        let _ = thread::spawn(move || {
+            let _value = rwlock_copy.write().unwrap();
+            panic!("Panic con el lock tomado");
+        })
+        .join();
+
+        assert!(rwlock.is_poisoned());
+        *rwlock.write_or_recover() += 1;
+        assert_eq!(*rwlock.read_or_recover(), 2);
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use super::{deadline_timer::DeadlineTimer, ClientId, ClientIdArg, ServerResult};
+
+/// Spreads the delivery of the retained messages that match a
+/// subscription over a time window (see
+/// [`Config::retained_delivery_window`](crate::traits::Config::retained_delivery_window)),
+/// so that many clients subscribing at once do not receive all
+/// of them in a burst.
+///
+/// The timer runs on its own thread, which sleeps until the
+/// next retained message must be delivered (or until new ones
+/// are scheduled).
+///
+/// Only the topic of each retained message is kept: the message
+/// itself is read when it is delivered, so that the client gets
+/// the latest value
+#[derive(Default)]
+pub struct RetainedScheduler {
+    timer: DeadlineTimer<(ClientId, String)>,
+}
+
+impl RetainedScheduler {
+    /// Creates a new [`RetainedScheduler`], without any delivery
+    /// scheduled. The timer does not run until `start()` is called
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns the timer thread. When a delivery is due, `on_due`
+    /// is called with the id of the client and the topic of the
+    /// retained message
+    pub fn start<F>(&self, on_due: F) -> io::Result<()>
+    where
+        F: Fn(&ClientIdArg, &str) + Send + 'static,
+    {
+        self.timer
+            .start("retained_scheduler", move |(id, topic)| on_due(&id, &topic))
+    }
+
+    /// Schedules the delivery of the retained messages of the given
+    /// topics to a client, evenly spread over `window`. The first one
+    /// is due right away, and they are delivered in the given order
+    pub fn schedule(
+        &self,
+        id: &ClientIdArg,
+        topics: Vec<String>,
+        window: Duration,
+    ) -> ServerResult<()> {
+        let start = Instant::now();
+        let count = topics.len() as u32;
+        self.timer.schedule(
+            topics
+                .into_iter()
+                .enumerate()
+                .map(|(i, topic)| (start + window * i as u32 / count, (id.to_owned(), topic))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc::channel,
+        time::{Duration, Instant},
+    };
+
+    use super::RetainedScheduler;
+
+    #[test]
+    fn test_deliveries_are_spread_over_the_window() {
+        let scheduler = RetainedScheduler::new();
+        let (sender, receiver) = channel();
+        scheduler
+            .start(move |id, topic| {
+                sender
+                    .send((id.to_string(), topic.to_string(), Instant::now()))
+                    .unwrap()
+            })
+            .unwrap();
+
+        let start = Instant::now();
+        let topics: Vec<String> = (0..4).map(|i| format!("topic/{}", i)).collect();
+        scheduler
+            .schedule("id", topics.clone(), Duration::from_millis(400))
+            .unwrap();
+
+        for (i, topic) in topics.iter().enumerate() {
+            let (id, received, at) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!(id, "id");
+            assert_eq!(&received, topic);
+            assert!(at - start >= Duration::from_millis(100) * i as u32);
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use std::{
+    io,
+    net::SocketAddr,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -19,26 +21,98 @@ pub struct ServerController {
     /// Handle of the main server thread (the one
     /// that executes the server loop)
     handle: Option<JoinHandle<()>>,
+    /// Addresses on which the server is listening, one
+    /// for each of its listeners
+    local_addrs: Vec<SocketAddr>,
+    /// Address on which the server is listening for
+    /// bridges, if it is
+    bridge_addr: Option<SocketAddr>,
 }
 
 impl ServerController {
     /// Create a new [`ServerController`] for the server that
     /// runs on the thread associated with the *handle* received
-    pub fn new(shutdown_bool: Arc<AtomicBool>, handle: JoinHandle<()>) -> ServerController {
+    pub fn new(
+        shutdown_bool: Arc<AtomicBool>,
+        handle: JoinHandle<()>,
+        local_addrs: Vec<SocketAddr>,
+        bridge_addr: Option<SocketAddr>,
+    ) -> ServerController {
         ServerController {
             shutdown_bool,
             handle: Some(handle),
+            local_addrs,
+            bridge_addr,
         }
     }
-}
 
-impl Drop for ServerController {
-    fn drop(&mut self) {
-        self.shutdown_bool.store(true, Ordering::Relaxed);
-        let handle = self
-            .handle
-            .take()
-            .expect("Server tried to shut down but it was already turned off");
+    /// Returns the address on which the server is listening. If
+    /// it has several listeners, it is the one of the first one.
+    ///
+    /// Useful when the server was configured with port 0,
+    /// in which case the OS assigns the port
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    /// Returns the addresses on which the server is listening, in
+    /// the order its listeners were given (see [`Server::run_on_listeners`](crate::Server::run_on_listeners))
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Returns the address on which the server is listening for
+    /// bridges (see [`Config::bridge_port`](crate::Config::bridge_port)),
+    /// if it is
+    pub fn bridge_addr(&self) -> Option<SocketAddr> {
+        self.bridge_addr
+    }
+
+    /// Returns true once the server was signaled to shut down,
+    /// either by dropping a controller or by a signal (see
+    /// [`ServerController::shutdown_on_signals`])
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_bool.load(Ordering::Relaxed)
+    }
+
+    /// Makes SIGINT and SIGTERM shut down the server gracefully, the
+    /// same way as dropping the controller: the clients are disconnected,
+    /// the state is dumped and the threads are joined. The server loop
+    /// notices it on its next iteration, since its accept does not block.
+    ///
+    /// Since the signals no longer terminate the process, whoever calls
+    /// this should wait for the server (see [`ServerController::wait`]).
+    /// A second signal, received while shutting down, terminates the
+    /// process right away.
+    ///
+    /// It does nothing on platforms other than Unix
+    pub fn shutdown_on_signals(&self) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use signal_hook::{
+                consts::{SIGINT, SIGTERM},
+                flag,
+            };
+            for signal in [SIGINT, SIGTERM] {
+                // El orden importa: si ya se estaba apagando,
+                // se termina el proceso sin esperar
+                flag::register_conditional_shutdown(signal, 1, self.shutdown_bool.clone())?;
+                flag::register(signal, self.shutdown_bool.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the server stops by itself, without signaling it
+    /// to shut down. Used along with [`Server::drain`](crate::Server::drain)
+    pub fn wait(mut self) {
+        if let Some(handle) = self.handle.take() {
+            Self::join(handle);
+        }
+    }
+
+    #[doc(hidden)]
+    fn join(handle: JoinHandle<()>) {
         let id = handle.thread().id();
         if let Err(e) = handle.join() {
             error!("{:?}: Thread joineado con panic: {:?}", id, e);
@@ -47,3 +121,13 @@ impl Drop for ServerController {
         }
     }
 }
+
+impl Drop for ServerController {
+    fn drop(&mut self) {
+        self.shutdown_bool.store(true, Ordering::Relaxed);
+        // Si ya se espero con wait(), el servidor esta apagado
+        if let Some(handle) = self.handle.take() {
+            Self::join(handle);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    error::Error,
     fmt, io,
     sync::{mpsc::SendError, PoisonError},
     time::SystemTimeError,
@@ -11,12 +12,14 @@ use packets::{
 use threadpool::ThreadPoolError;
 use tracing::error;
 
-use crate::topic_handler::topic_handler_error::TopicHandlerError;
+use crate::{topic_handler::topic_handler_error::TopicHandlerError, traits::DisconnectReason};
 
 #[derive(Debug)]
 pub struct ServerError {
     msg: String,
     kind: ServerErrorKind,
+    /// Underlying error that caused this one, if any
+    source: Option<Box<dyn Error + Send + Sync>>,
 }
 
 #[non_exhaustive]
@@ -24,13 +27,13 @@ pub struct ServerError {
 pub enum ServerErrorKind {
     ProtocolViolation,
     ClientDisconnected,
-    ClientNotFound,
     ConnectionRefused(ConnackReturnCode),
     DumpError,
     Timeout,
     PoisonedLock,
     Irrecoverable,
     Idle,
+    InvalidConfig,
     Other,
 }
 
@@ -40,15 +43,21 @@ impl fmt::Display for ServerError {
     }
 }
 
-impl std::error::Error for ServerError {
+impl Error for ServerError {
     fn description(&self) -> &str {
         &self.msg
     }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn Error + 'static))
+    }
 }
 
 impl From<io::Error> for ServerError {
     fn from(error: io::Error) -> Self {
-        match error.kind() {
+        let server_error = match error.kind() {
             io::ErrorKind::UnexpectedEof
             | io::ErrorKind::NotConnected
             | io::ErrorKind::BrokenPipe
@@ -56,18 +65,19 @@ impl From<io::Error> for ServerError {
                 "Se desconecto sin avisar",
                 ServerErrorKind::ClientDisconnected,
             ),
-            io::ErrorKind::WouldBlock => {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
                 ServerError::new_kind("Connection timeout", ServerErrorKind::Timeout)
             }
             _ => ServerError::new_msg(format!("{:?}", error)),
-        }
+        };
+        server_error.with_source(error)
     }
 }
 
 impl From<PacketError> for ServerError {
     fn from(packet_error: PacketError) -> Self {
-        match packet_error.kind() {
-            ErrorKind::WouldBlock => {
+        let server_error = match packet_error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::Timeout => {
                 ServerError::new_kind(&packet_error.to_string(), ServerErrorKind::Timeout)
             }
             ErrorKind::UnexpectedEof => ServerError::new_kind(
@@ -75,7 +85,8 @@ impl From<PacketError> for ServerError {
                 ServerErrorKind::ClientDisconnected,
             ),
             _ => ServerError::new_msg(&format!("packet_error: {:?}", packet_error)),
-        }
+        };
+        server_error.with_source(packet_error)
     }
 }
 
@@ -132,6 +143,7 @@ impl ServerError {
         ServerError {
             msg: msg.into(),
             kind: ServerErrorKind::Other,
+            source: None,
         }
     }
 
@@ -139,10 +151,95 @@ impl ServerError {
         ServerError {
             msg: msg.into(),
             kind,
+            source: None,
         }
     }
 
+    /// Sets the underlying error that caused this one
+    /// (see [`Error::source`])
+    pub fn with_source<E: Error + Send + Sync + 'static>(mut self, source: E) -> ServerError {
+        self.source = Some(Box::new(source));
+        self
+    }
+
     pub fn kind(&self) -> ServerErrorKind {
         self.kind
     }
+
+    /// Returns the reason to disconnect a client whose packets
+    /// could not be processed due to this error
+    pub fn disconnect_reason(&self) -> DisconnectReason {
+        match self.kind {
+            ServerErrorKind::Timeout => DisconnectReason::PacketTimeout,
+            ServerErrorKind::ProtocolViolation => DisconnectReason::ProtocolViolation,
+            _ => DisconnectReason::ConnectionLost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, io};
+
+    use packets::packet_error::PacketError;
+
+    use super::{ServerError, ServerErrorKind};
+    use crate::traits::DisconnectReason;
+
+    #[test]
+    fn test_io_error_is_the_source() {
+        let err = ServerError::from(io::Error::new(io::ErrorKind::BrokenPipe, "broken"));
+
+        assert_eq!(err.kind(), ServerErrorKind::ClientDisconnected);
+        let source = err.source().unwrap();
+        let io_error = source.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(io_error.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(io_error.to_string(), "broken");
+    }
+
+    #[test]
+    fn test_packet_error_chain_is_traversable() {
+        let packet_error = PacketError::from(io::Error::new(io::ErrorKind::InvalidData, "io"));
+        let err = ServerError::from(packet_error);
+
+        let source = err.source().unwrap();
+        assert!(source.downcast_ref::<PacketError>().is_some());
+    }
+
+    #[test]
+    fn test_new_error_has_no_source() {
+        assert!(ServerError::new_msg("msg").source().is_none());
+        assert!(ServerError::new_kind("msg", ServerErrorKind::Other)
+            .source()
+            .is_none());
+    }
+
+    #[test]
+    fn test_disconnect_reason_of_each_error_kind() {
+        let reason = |kind| ServerError::new_kind("msg", kind).disconnect_reason();
+
+        assert_eq!(
+            reason(ServerErrorKind::ClientDisconnected),
+            DisconnectReason::ConnectionLost
+        );
+        assert_eq!(
+            reason(ServerErrorKind::Timeout),
+            DisconnectReason::PacketTimeout
+        );
+        assert_eq!(
+            reason(ServerErrorKind::ProtocolViolation),
+            DisconnectReason::ProtocolViolation
+        );
+        assert_eq!(
+            reason(ServerErrorKind::Other),
+            DisconnectReason::ConnectionLost
+        );
+    }
+
+    #[test]
+    fn test_closed_connection_is_connection_lost() {
+        let err = ServerError::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+
+        assert_eq!(err.disconnect_reason(), DisconnectReason::ConnectionLost);
+    }
 }
@@ -0,0 +1,69 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use packets::publish::Publish;
+use tracing::debug;
+
+use super::{deadline_timer::DeadlineTimer, ClientId, ClientIdArg, ServerResult};
+
+/// Delays the publication of the Last Will of the clients
+/// that disconnect ungracefully, so that a brief reconnection
+/// of the client does not trigger it.
+///
+/// The timer runs on its own thread, which sleeps until
+/// the next Last Will must be published (or until a
+/// new one is scheduled or cancelled)
+#[derive(Default)]
+pub struct WillScheduler {
+    timer: DeadlineTimer<(ClientId, Publish)>,
+}
+
+impl WillScheduler {
+    /// Creates a new [`WillScheduler`], without any Last Will
+    /// scheduled. The timer does not run until `start()` is
+    /// called
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns the timer thread. When the delay of a Last Will
+    /// expires, `on_expire` is called with the id of the client
+    /// and the packet to be published
+    pub fn start<F>(&self, on_expire: F) -> io::Result<()>
+    where
+        F: Fn(&ClientIdArg, Publish) + Send + 'static,
+    {
+        self.timer.start("will_scheduler", move |(id, last_will)| {
+            debug!("<{}>: Vencio el delay del Last Will", id);
+            on_expire(&id, last_will);
+        })
+    }
+
+    /// Schedules the publication of the Last Will of a client
+    /// after the given delay. If the client already had one
+    /// scheduled, it is replaced
+    pub fn schedule(
+        &self,
+        id: &ClientIdArg,
+        last_will: Publish,
+        delay: Duration,
+    ) -> ServerResult<()> {
+        self.cancel(id)?;
+        self.timer
+            .schedule(vec![(Instant::now() + delay, (id.to_owned(), last_will))])
+    }
+
+    /// Cancels the publication of the Last Will of a client.
+    /// Returns true if it had one scheduled
+    pub fn cancel(&self, id: &ClientIdArg) -> ServerResult<bool> {
+        self.timer.cancel(|(pending_id, _)| pending_id == id)
+    }
+
+    /// Removes and returns all the Last Will packets that
+    /// have not been published yet
+    pub fn take_pending(&self) -> ServerResult<Vec<(ClientId, Publish)>> {
+        self.timer.take_pending()
+    }
+}
@@ -1,16 +1,19 @@
 use serde::{Deserialize, Serialize};
 
 use std::{
-    collections::HashMap,
+    collections::{hash_map::RandomState, BTreeMap, HashMap, VecDeque},
     fmt::Debug,
+    hash::BuildHasher,
     ops::Deref,
-    sync::{mpsc::Sender, RwLock},
+    sync::{mpsc::SyncSender, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
 pub mod topic_handler_error;
 
 use packets::qos::QoSLevel;
 use packets::{publish::Publish, subscribe::Subscribe, unsubscribe::Unsubscribe};
+use tracing::warn;
 
 use self::topic_handler_error::TopicHandlerError;
 
@@ -18,17 +21,44 @@ type Subscription = (String, SubscriptionData); // client_id, data
 type Subtopics = HashMap<String, Topic>; // key: subtopic name
 type Subscribers = HashMap<String, SubscriptionData>; // key: client_id
 type Subscriptions = HashMap<String, Subscribers>; // key: topic filter { key: client_id }
+type ClientSubscriptions = BTreeMap<String, Vec<(String, QoSLevel)>>; // key: client_id, (topic filter, qos)
 
 const SEP: &str = "/";
 const MULTI_LEVEL_WILDCARD: &str = "#";
 const SINGLE_LEVEL_WILDCARD: &str = "+";
 const UNMATCH_WILDCARD: &str = "$";
+/// How long a topic without subscribers keeps its publish
+/// count since it was last published to
+const TOPIC_STATS_WINDOW: Duration = Duration::from_secs(300);
+/// Maximum amount of topics whose publish count is tracked
+const MAX_TRACKED_TOPICS: usize = 10_000;
+/// Amount of independent parts of the publish counts, so that
+/// publications to different topics rarely wait for each other
+const TOPIC_STATS_SHARDS: usize = 16;
 
 pub struct Message {
     pub client_id: String,
     pub packet: Publish,
 }
 
+/// Destination of the messages generated by a publication,
+/// one for each subscriber that has to receive it
+pub trait MessageSink {
+    fn send(&self, message: Message) -> Result<(), TopicHandlerError>;
+}
+
+impl MessageSink for SyncSender<Message> {
+    fn send(&self, message: Message) -> Result<(), TopicHandlerError> {
+        Ok(SyncSender::send(self, message)?)
+    }
+}
+
+impl<T: MessageSink + ?Sized> MessageSink for &T {
+    fn send(&self, message: Message) -> Result<(), TopicHandlerError> {
+        (**self).send(message)
+    }
+}
+
 #[doc(hidden)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SubscriptionData {
@@ -38,6 +68,92 @@ struct SubscriptionData {
 #[derive(Serialize, Deserialize)]
 pub struct TopicHandler {
     root: Topic,
+    /// Publish counts of each topic. They are not persisted
+    #[serde(skip, default)]
+    stats: TopicStats,
+    /// Order in which the retained messages were stored, used to
+    /// keep them within their budget. It is rebuilt on restore
+    #[serde(skip, default)]
+    retained: Mutex<RetainedIndex>,
+}
+
+#[doc(hidden)]
+#[derive(Debug, Default)]
+/// Topics with a retained message, from the least to the most
+/// recently stored, along with the budget they must fit in.
+/// They are only tracked if there is a budget
+struct RetainedIndex {
+    entries: VecDeque<(String, usize)>, // topic name, payload size
+    bytes: usize,
+    max_count: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+impl RetainedIndex {
+    fn is_bounded(&self) -> bool {
+        self.max_count.is_some() || self.max_bytes.is_some()
+    }
+
+    fn exceeded(&self) -> bool {
+        let count_exceeded = matches!(self.max_count, Some(max) if self.entries.len() > max);
+        let bytes_exceeded = matches!(self.max_bytes, Some(max) if self.bytes > max);
+        count_exceeded || bytes_exceeded
+    }
+
+    fn push(&mut self, topic: &str, size: usize) {
+        self.entries.push_back((topic.to_owned(), size));
+        self.bytes += size;
+    }
+
+    fn remove(&mut self, topic: &str) {
+        if let Some(pos) = self.entries.iter().position(|(name, _)| name == topic) {
+            if let Some((_, size)) = self.entries.remove(pos) {
+                self.bytes -= size;
+            }
+        }
+    }
+
+    fn pop_oldest(&mut self) -> Option<(String, usize)> {
+        let (topic, size) = self.entries.pop_front()?;
+        self.bytes -= size;
+        Some((topic, size))
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+/// Publish counts of the topics that have subscribers or were
+/// published to within [`TOPIC_STATS_WINDOW`], split in shards
+/// by the hash of the topic. Each shard tracks up to its part of
+/// [`MAX_TRACKED_TOPICS`]
+struct TopicStats {
+    shards: Vec<Mutex<HashMap<String, TopicCounter>>>,
+    hasher: RandomState,
+}
+
+impl Default for TopicStats {
+    fn default() -> Self {
+        Self {
+            shards: (0..TOPIC_STATS_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl TopicStats {
+    fn shard(&self, topic: &str) -> &Mutex<HashMap<String, TopicCounter>> {
+        let hash = self.hasher.hash_one(topic);
+        &self.shards[hash as usize % self.shards.len()]
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+struct TopicCounter {
+    publishes: u64,
+    last_publish: Instant,
 }
 
 #[doc(hidden)]
@@ -75,52 +191,85 @@ impl Topic {
         }
     }
 
-    /// Sends a Publish packet to the clients who are subscribed into a certain topic
+    /// Sends a Publish packet to the clients who are subscribed into a certain topic.
+    /// It does not store the packet as a retained message (see [`Topic::store_retained`])
     fn publish(
         &self,
         topic_name: Option<&str>,
-        sender: Sender<Message>,
+        sender: &dyn MessageSink,
         packet: &Publish,
         is_root: bool,
     ) -> Result<(), TopicHandlerError> {
         let matching = self.current_matching_subs(topic_name, is_root)?;
         let mut packet_no_retain = packet.clone();
         packet_no_retain.set_retain_flag(false);
-        TopicHandler::send_publish(&sender, &packet_no_retain, &matching)?;
-        match topic_name {
-            Some(topic) => {
-                let (current, rest) = Self::split(topic);
-                let subtopics = self.subtopics.read()?;
-                match subtopics.get(current) {
-                    None if packet.retain_flag() && !packet.payload().is_empty() => {
-                        // No hay suscriptores pero el mensaje es retained y tiene payload
-                        drop(subtopics);
-                        self.subtopics
-                            .write()?
-                            .entry(current.to_string())
-                            .or_insert_with(Topic::new)
-                            .publish(rest, sender, packet, false)?;
-                    }
-                    Some(subtopic) => {
-                        // Puede haber suscriptores
-                        subtopic.publish(rest, sender, packet, false)?;
-                        // Si el mensaje era retained sin payload cabe la posibilidad que deje
-                        // un nodo vacío (sacando el retained message), asi que limpiamos
-                        if subtopic.is_empty()? {
-                            drop(subtopics);
-                            self.clean([current])?;
-                        }
-                    }
-                    _ => (),
-                }
-            }
-            None => {
-                self.update_retained_message(packet)?;
+        TopicHandler::send_publish(sender, &packet_no_retain, &matching)?;
+        if let Some(topic) = topic_name {
+            let (current, rest) = Self::split(topic);
+            if let Some(subtopic) = self.subtopics.read()?.get(current) {
+                subtopic.publish(rest, sender, packet, false)?;
             }
         }
         Ok(())
     }
 
+    /// Returns the subscriptions that would receive a Publish packet
+    /// sent to a certain topic, without publishing it
+    fn matching_subscribers(
+        &self,
+        topic_name: Option<&str>,
+        is_root: bool,
+    ) -> Result<Vec<Subscription>, TopicHandlerError> {
+        let mut matching = self.current_matching_subs(topic_name, is_root)?;
+        if let Some(topic) = topic_name {
+            let (current, rest) = Self::split(topic);
+            if let Some(subtopic) = self.subtopics.read()?.get(current) {
+                matching.extend(subtopic.matching_subscribers(rest, false)?);
+            }
+        }
+        Ok(matching)
+    }
+
+    /// Adds to `subscriptions` the subscriptions of this node and its
+    /// subtopics (with their full topic filter), and returns the amount
+    /// of retained messages they have. `path` is the topic name of
+    /// this node (None for the root)
+    fn collect_state(
+        &self,
+        path: Option<&str>,
+        subscriptions: &mut ClientSubscriptions,
+    ) -> Result<usize, TopicHandlerError> {
+        let join = |level: &str| match path {
+            Some(path) => format!("{}{}{}", path, SEP, level),
+            None => level.to_string(),
+        };
+        let mut add = |topic_filter: String, subscribers: &Subscribers| {
+            for (id, data) in subscribers {
+                subscriptions
+                    .entry(id.to_owned())
+                    .or_default()
+                    .push((topic_filter.clone(), data.qos));
+            }
+        };
+
+        if let Some(path) = path {
+            add(path.to_string(), self.subscribers.read()?.deref());
+        }
+        add(
+            join(MULTI_LEVEL_WILDCARD),
+            self.multilevel_subscribers.read()?.deref(),
+        );
+        for (topic_filter, subscribers) in self.singlelevel_subscriptions.read()?.iter() {
+            add(join(topic_filter), subscribers);
+        }
+
+        let mut retained = usize::from(self.retained_message.read()?.is_some());
+        for (name, subtopic) in self.subtopics.read()?.iter() {
+            retained += subtopic.collect_state(Some(&join(name)), subscriptions)?;
+        }
+        Ok(retained)
+    }
+
     /// Subscribe a client id into a topic
     fn subscribe(
         &self,
@@ -196,6 +345,17 @@ impl Topic {
         Ok(())
     }
 
+    #[doc(hidden)]
+    /// Removes every subtopic, subscription and retained message
+    fn clear(&self) -> Result<(), TopicHandlerError> {
+        self.subtopics.write()?.clear();
+        self.subscribers.write()?.clear();
+        self.multilevel_subscribers.write()?.clear();
+        self.singlelevel_subscriptions.write()?.clear();
+        *self.retained_message.write()? = None;
+        Ok(())
+    }
+
     #[doc(hidden)]
     /// Gets the retained message in a Vec
     ///
@@ -336,16 +496,65 @@ impl Topic {
     }
 
     #[doc(hidden)]
-    /// If the packet is a retained message, it either updates the retained message of the topic or
-    /// it removes its retained message if it the packet has a zero-length payload ([MQTT-3.3.1-11])
-    fn update_retained_message(&self, packet: &Publish) -> Result<(), TopicHandlerError> {
-        if packet.retain_flag() {
-            let mut retained = self.retained_message.write()?;
-            if packet.payload().is_empty() {
-                *retained = None;
-            } else {
-                *retained = Some(packet.clone());
+    /// Stores the packet as the retained message of the given topic, or removes
+    /// its retained message if the packet has a zero-length payload ([MQTT-3.3.1-11])
+    fn store_retained(
+        &self,
+        topic_name: Option<&str>,
+        packet: &Publish,
+    ) -> Result<(), TopicHandlerError> {
+        if packet.payload().is_empty() {
+            return self.clear_retained(topic_name);
+        }
+        match topic_name {
+            Some(topic) => {
+                let (current, rest) = Self::split(topic);
+                let subtopics = self.subtopics.read()?;
+                match subtopics.get(current) {
+                    Some(subtopic) => subtopic.store_retained(rest, packet)?,
+                    None => {
+                        drop(subtopics);
+                        self.subtopics
+                            .write()?
+                            .entry(current.to_string())
+                            .or_insert_with(Topic::new)
+                            .store_retained(rest, packet)?;
+                    }
+                }
+            }
+            None => *self.retained_message.write()? = Some(packet.clone()),
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    /// Returns every retained message of the topic and its subtopics,
+    /// including those of the topics that start with `$`
+    fn all_retained(&self) -> Result<Vec<Publish>, TopicHandlerError> {
+        let mut messages: Vec<Publish> = self.retained_message.read()?.iter().cloned().collect();
+        for subtopic in self.subtopics.read()?.values() {
+            messages.extend(subtopic.all_retained()?);
+        }
+        Ok(messages)
+    }
+
+    #[doc(hidden)]
+    /// Removes the retained message of the given topic, cleaning
+    /// the nodes that are left empty
+    fn clear_retained(&self, topic_name: Option<&str>) -> Result<(), TopicHandlerError> {
+        match topic_name {
+            Some(topic) => {
+                let (current, rest) = Self::split(topic);
+                let subtopics = self.subtopics.read()?;
+                if let Some(subtopic) = subtopics.get(current) {
+                    subtopic.clear_retained(rest)?;
+                    if subtopic.is_empty()? {
+                        drop(subtopics);
+                        self.clean([current])?;
+                    }
+                }
             }
+            None => *self.retained_message.write()? = None,
         }
         Ok(())
     }
@@ -491,64 +700,280 @@ impl Topic {
     #[doc(hidden)]
     /// Delete all empty subtopics of this node from a given list
     /// If one of them is not empty, it won't be deleted
+    ///
+    /// Returns how many subtopics were deleted
     fn clean<'a, I: IntoIterator<Item = &'a str>>(
         &self,
         subtopics: I,
-    ) -> Result<(), TopicHandlerError> {
+    ) -> Result<usize, TopicHandlerError> {
         let mut subtopics_dic = self.subtopics.write()?;
+        let mut removed = 0;
 
         for name in subtopics {
             if let Some(node) = subtopics_dic.get_mut(name) {
                 if node.is_empty_mut()? {
                     subtopics_dic.remove(name);
+                    removed += 1;
                 }
             }
         }
 
-        Ok(())
+        Ok(removed)
+    }
+
+    #[doc(hidden)]
+    /// Deletes every empty node below this one, from the deepest
+    /// ones up, so that a branch left without subscriptions nor
+    /// retained messages is removed entirely
+    ///
+    /// Returns how many nodes were deleted
+    fn compact(&self) -> Result<usize, TopicHandlerError> {
+        let subtopics = self.subtopics.read()?;
+        let mut removed = 0;
+        for subtopic in subtopics.values() {
+            removed += subtopic.compact()?;
+        }
+        let names = subtopics.keys().cloned().collect::<Vec<_>>();
+        drop(subtopics);
+
+        Ok(removed + self.clean(names.iter().map(String::as_str))?)
     }
 }
 
 impl TopicHandler {
     /// Creates a new TopicHandler
     pub fn new() -> Self {
-        Self { root: Topic::new() }
+        Self {
+            root: Topic::new(),
+            stats: TopicStats::default(),
+            retained: Mutex::new(RetainedIndex::default()),
+        }
+    }
+
+    /// Sets the maximum amount of retained messages and the maximum
+    /// total size of their payloads. None means no limit.
+    ///
+    /// When storing a retained message exceeds the budget, the least
+    /// recently stored ones are evicted until it fits. The messages
+    /// already stored are evicted right away if they exceed it
+    pub fn set_retained_budget(
+        &self,
+        max_count: Option<usize>,
+        max_bytes: Option<usize>,
+    ) -> Result<(), TopicHandlerError> {
+        let mut retained = self.retained.lock()?;
+        *retained = RetainedIndex {
+            max_count,
+            max_bytes,
+            ..Default::default()
+        };
+        if retained.is_bounded() {
+            // No se sabe en que orden se guardaron los que ya estaban
+            let stored = self.root.all_retained()?;
+            for publish in stored {
+                retained.push(publish.topic_name(), publish.payload().len());
+            }
+            self.evict_retained(&mut retained)?;
+        }
+        Ok(())
     }
 
     /// Subscribe a client id into a set of topics given a Subscribe packet
+    ///
+    /// Returns the retained messages that match each topic filter, in
+    /// the order of the filters. Those of the same filter are sorted
+    /// by topic name, so they are always delivered in the same order
     pub fn subscribe(
         &self,
         packet: &Subscribe,
         client_id: &str,
     ) -> Result<Vec<Publish>, TopicHandlerError> {
-        let topics = packet.topics();
-        let topics: Vec<&packets::topic_filter::TopicFilter> = topics.iter().collect();
         let mut retained = Vec::new();
-        for topic_filter in topics {
+        for topic_filter in packet.topics() {
             let data = SubscriptionData {
                 qos: topic_filter.qos(),
             };
-            retained.extend(self.root.subscribe(
-                Some(topic_filter.name()),
-                client_id,
-                data,
-                true,
-            )?);
+            let mut matching =
+                self.root
+                    .subscribe(Some(topic_filter.name()), client_id, data, true)?;
+            matching.sort_by(|a, b| a.topic_name().cmp(b.topic_name()));
+            retained.extend(matching);
         }
         Ok(retained)
     }
 
     /// Sends a Publish packet to the clients who are subscribed into a certain topic
-    pub fn publish(
+    ///
+    /// If the sink is bounded, this method may block while it is full,
+    /// until the receiving end takes the pending messages
+    pub fn publish<S: MessageSink>(
         &self,
         packet: &Publish,
-        sender: Sender<Message>,
+        sender: S,
     ) -> Result<(), TopicHandlerError> {
         let full_topic = packet.topic_name();
-        self.root.publish(Some(full_topic), sender, packet, true)?;
+        if packet.retain_flag() {
+            // El retained message se guarda con el indice tomado, para que
+            // quede en el mismo orden en el que se guardan. El lock se libera
+            // antes de publicar, ya que enviar puede bloquearse
+            let mut retained = self.retained.lock()?;
+            self.root.store_retained(Some(full_topic), packet)?;
+            if retained.is_bounded() {
+                retained.remove(full_topic);
+                if !packet.payload().is_empty() {
+                    retained.push(full_topic, packet.payload().len());
+                }
+                self.evict_retained(&mut retained)?;
+            }
+        }
+        self.root.publish(Some(full_topic), &sender, packet, true)?;
+        self.record_publish(full_topic)?;
         Ok(())
     }
 
+    #[doc(hidden)]
+    /// Evicts the least recently stored retained messages
+    /// until the rest fit in the budget
+    fn evict_retained(&self, retained: &mut RetainedIndex) -> Result<(), TopicHandlerError> {
+        while retained.exceeded() {
+            match retained.pop_oldest() {
+                Some((topic, size)) => {
+                    warn!(
+                        "Se excedio el limite de retained messages - Se descarta el de <{}> ({} bytes)",
+                        topic, size
+                    );
+                    self.root.clear_retained(Some(&topic))?;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the amount of packets published to each topic.
+    ///
+    /// To bound the memory used, only the topics that have
+    /// subscribers or were published to recently are tracked, up
+    /// to [`MAX_TRACKED_TOPICS`]. The stale ones are pruned here,
+    /// and not when publishing, so that publishing stays cheap
+    pub fn topic_stats(&self) -> Result<HashMap<String, u64>, TopicHandlerError> {
+        self.prune_stats(TOPIC_STATS_WINDOW)?;
+        let mut stats = HashMap::new();
+        for shard in &self.stats.shards {
+            for (topic, counter) in shard.lock()?.iter() {
+                stats.insert(topic.to_owned(), counter.publishes);
+            }
+        }
+        Ok(stats)
+    }
+
+    #[doc(hidden)]
+    /// Counts a publication to the topic. If the shard of the topic
+    /// is full, a topic that is not tracked yet is not counted
+    fn record_publish(&self, topic: &str) -> Result<(), TopicHandlerError> {
+        let mut shard = self.stats.shard(topic).lock()?;
+        let now = Instant::now();
+        if let Some(counter) = shard.get_mut(topic) {
+            counter.publishes += 1;
+            counter.last_publish = now;
+        } else if shard.len() < MAX_TRACKED_TOPICS / TOPIC_STATS_SHARDS {
+            shard.insert(
+                topic.to_owned(),
+                TopicCounter {
+                    publishes: 1,
+                    last_publish: now,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    /// Removes the counters of the topics that were not published
+    /// to within `window` and have no subscribers. The subscribers
+    /// are looked up without holding the lock of the shard
+    fn prune_stats(&self, window: Duration) -> Result<(), TopicHandlerError> {
+        for shard in &self.stats.shards {
+            let now = Instant::now();
+            let expired =
+                |counter: &TopicCounter| now.duration_since(counter.last_publish) > window;
+            let candidates: Vec<String> = shard
+                .lock()?
+                .iter()
+                .filter(|(_, counter)| expired(counter))
+                .map(|(topic, _)| topic.to_owned())
+                .collect();
+            let mut stale = vec![];
+            for topic in candidates {
+                if self
+                    .root
+                    .matching_subscribers(Some(&topic), true)?
+                    .is_empty()
+                {
+                    stale.push(topic);
+                }
+            }
+            let mut shard = shard.lock()?;
+            for topic in stale {
+                // Pudo haber sido publicado mientras tanto
+                if shard.get(&topic).is_some_and(expired) {
+                    shard.remove(&topic);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the clients that would receive a Publish packet sent
+    /// to the given topic (along with the QoS of the subscription),
+    /// without publishing it. The same matching rules of `publish()`
+    /// are applied.
+    ///
+    /// If a client has more than one matching subscription, it appears
+    /// once for each of them, since it would receive a copy of the
+    /// packet for each one
+    pub fn matching_subscribers(
+        &self,
+        topic: &str,
+    ) -> Result<Vec<(String, QoSLevel)>, TopicHandlerError> {
+        let mut matching: Vec<(String, QoSLevel)> = self
+            .root
+            .matching_subscribers(Some(topic), true)?
+            .into_iter()
+            .map(|(id, data)| (id, data.qos))
+            .collect();
+        matching.sort_by(|(id_a, qos_a), (id_b, qos_b)| {
+            id_a.cmp(id_b).then((*qos_a as u8).cmp(&(*qos_b as u8)))
+        });
+        Ok(matching)
+    }
+
+    /// Returns the retained message currently stored in the given
+    /// topic (which must not contain wildcards), if any, with its
+    /// QoS downgraded to `max_qos`
+    pub fn retained_message(
+        &self,
+        topic: &str,
+        max_qos: QoSLevel,
+    ) -> Result<Option<Publish>, TopicHandlerError> {
+        Ok(self
+            .root
+            .get_retained_messages(Some(topic), max_qos, false)?
+            .pop())
+    }
+
+    /// Returns the subscriptions of each client (topic filter and
+    /// QoS, sorted by topic filter) and the amount of retained
+    /// messages stored
+    pub fn state(&self) -> Result<(ClientSubscriptions, usize), TopicHandlerError> {
+        let mut subscriptions = BTreeMap::new();
+        let retained = self.root.collect_state(None, &mut subscriptions)?;
+        for client_subscriptions in subscriptions.values_mut() {
+            client_subscriptions.sort_by(|(filter_a, _), (filter_b, _)| filter_a.cmp(filter_b));
+        }
+        Ok((subscriptions, retained))
+    }
+
     /// Unsubscribe a client_id from a set of topics given a Unsubscribe packet
     pub fn unsubscribe(
         &self,
@@ -567,10 +992,43 @@ impl TopicHandler {
         Ok(())
     }
 
+    /// Removes all the subscriptions of a client. The retained
+    /// messages are kept, even those it published
+    pub fn clear_client(&self, client_id: &str) -> Result<(), TopicHandlerError> {
+        self.remove_client(client_id)
+    }
+
+    /// Removes every subscription and retained message. The budget
+    /// of the retained messages is kept (see [`TopicHandler::set_retained_budget`]).
+    ///
+    /// It is safe to call while other threads publish, although a
+    /// publication made at the same time may still reach some of
+    /// the subscriptions being removed
+    pub fn clear(&self) -> Result<(), TopicHandlerError> {
+        // El indice se toma primero, en el mismo orden que en publish(),
+        // para que no se guarde un retained message a mitad del borrado
+        let mut retained = self.retained.lock()?;
+        self.root.clear()?;
+        retained.entries.clear();
+        retained.bytes = 0;
+        Ok(())
+    }
+
+    /// Removes the nodes of the topic tree that have no subscriptions,
+    /// no retained message and no subtopics, and returns how many were
+    /// removed.
+    ///
+    /// Unsubscribing, disconnecting a clean session client and removing
+    /// a retained message already prune the nodes they leave empty, so
+    /// this is only needed for trees restored from a previous state
+    pub fn compact(&self) -> Result<usize, TopicHandlerError> {
+        self.root.compact()
+    }
+
     #[doc(hidden)]
     /// Sends a publish packet to the given subscribers, adjusting the QoS if needed
     fn send_publish(
-        sender: &Sender<Message>,
+        sender: &dyn MessageSink,
         packet: &Publish,
         subscribers: &[Subscription],
     ) -> Result<(), TopicHandlerError> {
@@ -588,9 +1046,18 @@ impl TopicHandler {
 
 #[cfg(test)]
 mod tests {
-    use super::{Topic, TopicHandler};
-
-    use std::{collections::HashSet, sync::mpsc::channel, vec};
+    use super::{Message, Topic, TopicHandler, MAX_TRACKED_TOPICS};
+
+    use std::{
+        collections::HashSet,
+        sync::{
+            mpsc::{sync_channel, Receiver, SyncSender},
+            Arc,
+        },
+        thread,
+        time::Duration,
+        vec,
+    };
 
     use packets::publish::Publish;
     use packets::qos::QoSLevel;
@@ -598,6 +1065,12 @@ mod tests {
     use packets::topic_filter::TopicFilter;
     use packets::unsubscribe::Unsubscribe;
 
+    // Los tests leen los mensajes recien despues de publicar, por
+    // lo que el canal debe tener lugar para todos ellos
+    fn channel() -> (SyncSender<Message>, Receiver<Message>) {
+        sync_channel(10_000)
+    }
+
     fn build_publish(topic: &str, message: &str) -> Publish {
         Publish::new(false, QoSLevel::QoSLevel1, false, topic, message, Some(123)).unwrap()
     }
@@ -1348,6 +1821,46 @@ mod tests {
         assert_eq!(retained_messages[0].topic_name(), "topic");
     }
 
+    #[test]
+    fn test_retained_messages_sorted_by_topic_name() {
+        let handler = TopicHandler::new();
+        for topic in ["a/c", "a/a", "a/b/x", "a/b"] {
+            let publish =
+                Publish::new(false, QoSLevel::QoSLevel1, true, topic, "msg", Some(123)).unwrap();
+            let (sender, _r) = channel();
+            handler.publish(&publish, sender).unwrap();
+        }
+
+        let retained_messages = handler.subscribe(&build_subscribe("a/#"), "user").unwrap();
+        let topics: Vec<&str> = retained_messages
+            .iter()
+            .map(|publish| publish.topic_name())
+            .collect();
+        assert_eq!(topics, vec!["a/a", "a/b", "a/b/x", "a/c"]);
+    }
+
+    #[test]
+    fn test_retained_message_of_topic() {
+        let handler = TopicHandler::new();
+        for payload in ["old", "new"] {
+            let publish =
+                Publish::new(false, QoSLevel::QoSLevel1, true, "a/b", payload, Some(1)).unwrap();
+            let (sender, _r) = channel();
+            handler.publish(&publish, sender).unwrap();
+        }
+
+        let retained = handler
+            .retained_message("a/b", QoSLevel::QoSLevel0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(retained.payload(), "new");
+        assert_eq!(retained.qos(), QoSLevel::QoSLevel0);
+        assert!(handler
+            .retained_message("a", QoSLevel::QoSLevel1)
+            .unwrap()
+            .is_none());
+    }
+
     #[test]
     fn test_retained_messages_not_on_siblings() {
         let subscribe = build_subscribe("other_topic");
@@ -1730,4 +2243,361 @@ mod tests {
             assert_eq!(msg.packet.topic_name(), "topic/auto/casa");
         }
     }
+
+    #[test]
+    fn test_matching_subscribers_matches_delivery() {
+        let subscriptions = [
+            ("user1", "topic/auto/casa", QoSLevel::QoSLevel1),
+            ("user2", "topic/+/casa", QoSLevel::QoSLevel0),
+            ("user3", "topic/#", QoSLevel::QoSLevel1),
+            ("user4", "#", QoSLevel::QoSLevel0),
+            ("user5", "+/+", QoSLevel::QoSLevel1),
+            ("user6", "otro/#", QoSLevel::QoSLevel1),
+            ("user7", "+/auto/#", QoSLevel::QoSLevel0),
+        ];
+        let handler = TopicHandler::new();
+        for (id, topic, qos) in subscriptions {
+            let subscribe = Subscribe::new(vec![TopicFilter::new(topic, qos).unwrap()], 123);
+            handler.subscribe(&subscribe, id).unwrap();
+        }
+
+        for topic in [
+            "topic/auto/casa",
+            "topic/moto",
+            "topic",
+            "otro/a/b",
+            "$SYS/x",
+        ] {
+            let matching = handler.matching_subscribers(topic).unwrap();
+
+            let (sender, receiver) = channel();
+            handler
+                .publish(&build_publish(topic, "msg"), sender)
+                .unwrap();
+            let mut delivered: Vec<(String, QoSLevel)> = receiver
+                .into_iter()
+                .map(|msg| (msg.client_id, msg.packet.qos()))
+                .collect();
+            delivered.sort_by(|(id_a, qos_a), (id_b, qos_b)| {
+                id_a.cmp(id_b).then((*qos_a as u8).cmp(&(*qos_b as u8)))
+            });
+
+            assert_eq!(matching, delivered, "topic: {}", topic);
+        }
+    }
+
+    #[test]
+    fn test_matching_subscribers_wildcards() {
+        let handler = TopicHandler::new();
+        handler
+            .subscribe(&build_subscribe("a/+/c"), "single")
+            .unwrap();
+        handler.subscribe(&build_subscribe("a/#"), "multi").unwrap();
+
+        let ids = |topic| -> Vec<String> {
+            handler
+                .matching_subscribers(topic)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect()
+        };
+        assert_eq!(ids("a/b/c"), vec!["multi", "single"]);
+        assert_eq!(ids("a/b/d"), vec!["multi"]);
+        assert_eq!(ids("a"), vec!["multi"]);
+        assert!(ids("b").is_empty());
+    }
+
+    #[test]
+    fn test_topic_stats_count_publishes() {
+        let handler = TopicHandler::new();
+        let (sender, _receiver) = channel();
+        for _ in 0..3 {
+            handler
+                .publish(&build_publish("a", "msg"), sender.clone())
+                .unwrap();
+        }
+        handler
+            .publish(&build_publish("b/c", "msg"), sender)
+            .unwrap();
+
+        let stats = handler.topic_stats().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats["a"], 3);
+        assert_eq!(stats["b/c"], 1);
+    }
+
+    #[test]
+    fn test_topic_stats_prune_only_topics_without_subscribers() {
+        let handler = TopicHandler::new();
+        let (sender, _receiver) = channel();
+        handler.subscribe(&build_subscribe("a/+"), "user").unwrap();
+        handler
+            .publish(&build_publish("a/b", "msg"), sender.clone())
+            .unwrap();
+        handler.publish(&build_publish("c", "msg"), sender).unwrap();
+
+        handler.prune_stats(Duration::ZERO).unwrap();
+        let stats = handler.topic_stats().unwrap();
+        assert!(stats.contains_key("a/b"));
+        assert!(!stats.contains_key("c"));
+    }
+
+    #[test]
+    fn test_topic_stats_track_a_bounded_amount_of_topics() {
+        let handler = TopicHandler::new();
+        let (sender, _receiver) = channel();
+        for i in 0..MAX_TRACKED_TOPICS + 100 {
+            handler
+                .publish(&build_publish(&format!("t/{}", i), "msg"), &sender)
+                .unwrap();
+        }
+        assert!(handler.topic_stats().unwrap().len() <= MAX_TRACKED_TOPICS);
+    }
+
+    fn retained_topics(handler: &TopicHandler) -> Vec<String> {
+        handler
+            .subscribe(&build_subscribe("#"), "user")
+            .unwrap()
+            .iter()
+            .map(|publish| publish.topic_name().to_string())
+            .collect()
+    }
+
+    fn publish_retained(handler: &TopicHandler, topic: &str, message: &str) {
+        let (sender, _receiver) = channel();
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel1, true, topic, message, Some(123)).unwrap();
+        handler.publish(&publish, sender).unwrap();
+    }
+
+    #[test]
+    fn test_retained_count_budget_evicts_least_recently_stored() {
+        let handler = TopicHandler::new();
+        handler.set_retained_budget(Some(3), None).unwrap();
+        publish_retained(&handler, "a", "1");
+        publish_retained(&handler, "b", "1");
+        publish_retained(&handler, "c/d", "1");
+        // Volver a guardar "a" lo convierte en el mas reciente
+        publish_retained(&handler, "a", "2");
+        publish_retained(&handler, "e", "1");
+
+        assert_eq!(retained_topics(&handler), vec!["a", "c/d", "e"]);
+        publish_retained(&handler, "f", "1");
+        assert_eq!(retained_topics(&handler), vec!["a", "e", "f"]);
+    }
+
+    #[test]
+    fn test_retained_bytes_budget_evicts_until_it_fits() {
+        let handler = TopicHandler::new();
+        handler.set_retained_budget(None, Some(10)).unwrap();
+        publish_retained(&handler, "a", "1234");
+        publish_retained(&handler, "b", "1234");
+        publish_retained(&handler, "c", "12");
+        publish_retained(&handler, "d", "123456");
+
+        assert_eq!(retained_topics(&handler), vec!["c", "d"]);
+    }
+
+    #[test]
+    fn test_cleared_retained_message_frees_budget() {
+        let handler = TopicHandler::new();
+        handler.set_retained_budget(Some(2), None).unwrap();
+        publish_retained(&handler, "a", "1");
+        publish_retained(&handler, "b", "1");
+        publish_retained(&handler, "a", "");
+        publish_retained(&handler, "c", "1");
+
+        assert_eq!(retained_topics(&handler), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_retained_budget_applies_to_stored_messages() {
+        let handler = TopicHandler::new();
+        publish_retained(&handler, "a", "1");
+        publish_retained(&handler, "b", "1");
+        publish_retained(&handler, "c", "1");
+        handler.set_retained_budget(Some(1), None).unwrap();
+
+        assert_eq!(retained_topics(&handler).len(), 1);
+        assert_eq!(handler.state().unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_retained_budget_counts_topics_starting_with_dollar() {
+        let handler = TopicHandler::new();
+        publish_retained(&handler, "$SYS/a", "1");
+        publish_retained(&handler, "b", "1");
+        publish_retained(&handler, "c", "1");
+        handler.set_retained_budget(Some(1), None).unwrap();
+
+        assert_eq!(handler.state().unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_blocked_retained_publish_does_not_block_the_budget() {
+        let handler = Arc::new(TopicHandler::new());
+        handler.subscribe(&build_subscribe("a"), "user").unwrap();
+        let (sender, receiver) = sync_channel(0);
+        let publisher = {
+            let handler = handler.clone();
+            thread::spawn(move || {
+                let publish =
+                    Publish::new(false, QoSLevel::QoSLevel0, true, "a", "1", None).unwrap();
+                handler.publish(&publish, sender).unwrap();
+            })
+        };
+        // El publisher queda bloqueado hasta que se lea el mensaje
+        thread::sleep(Duration::from_millis(100));
+        handler.set_retained_budget(Some(1), None).unwrap();
+        publish_retained(&handler, "a", "2");
+
+        receiver.recv().unwrap();
+        publisher.join().unwrap();
+        let retained = handler.retained_message("a", QoSLevel::QoSLevel0).unwrap();
+        assert_eq!(retained.unwrap().payload(), "2");
+    }
+
+    #[test]
+    fn test_clear_stops_every_delivery_and_drops_retained() {
+        let handler = TopicHandler::new();
+        handler.set_retained_budget(Some(2), None).unwrap();
+        publish_retained(&handler, "a", "1");
+        handler.subscribe(&build_subscribe("a"), "user1").unwrap();
+        handler.subscribe(&build_subscribe("b/+"), "user2").unwrap();
+        handler.subscribe(&build_subscribe("#"), "user3").unwrap();
+
+        handler.clear().unwrap();
+
+        let (sender, receiver) = channel();
+        handler.publish(&build_publish("a", "x"), &sender).unwrap();
+        handler
+            .publish(&build_publish("b/c", "x"), &sender)
+            .unwrap();
+        drop(sender);
+        assert!(receiver.recv().is_err());
+        assert_eq!(handler.state().unwrap(), (Default::default(), 0));
+        assert!(retained_topics(&handler).is_empty());
+
+        // El presupuesto de retained messages se mantiene
+        publish_retained(&handler, "a", "1");
+        publish_retained(&handler, "b", "1");
+        publish_retained(&handler, "c", "1");
+        assert_eq!(retained_topics(&handler), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_clear_client_only_stops_that_client() {
+        let handler = TopicHandler::new();
+        publish_retained(&handler, "a/b", "1");
+        handler.subscribe(&build_subscribe("a/#"), "user1").unwrap();
+        handler.subscribe(&build_subscribe("a/+"), "user2").unwrap();
+
+        handler.clear_client("user1").unwrap();
+
+        let (sender, receiver) = channel();
+        handler.publish(&build_publish("a/c", "x"), sender).unwrap();
+        let message = receiver.recv().unwrap();
+        assert_eq!(message.client_id, "user2");
+        assert!(receiver.recv().is_err());
+        // Los retained messages se mantienen
+        assert_eq!(retained_topics(&handler), vec!["a/b"]);
+    }
+
+    #[test]
+    fn test_clear_while_publishing() {
+        let handler = Arc::new(TopicHandler::new());
+        let (sender, _receiver) = channel();
+        let publishers: Vec<_> = (0..4)
+            .map(|i| {
+                let handler = handler.clone();
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    for j in 0..100 {
+                        let topic = format!("topic/{}/{}", i, j % 5);
+                        handler
+                            .subscribe(&build_subscribe(&topic), &format!("user{}", i))
+                            .unwrap();
+                        handler
+                            .publish(&build_publish(&topic, "x"), &sender)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..20 {
+            handler.clear().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        for publisher in publishers {
+            publisher.join().unwrap();
+        }
+
+        handler.clear().unwrap();
+        assert_eq!(handler.state().unwrap(), (Default::default(), 0));
+    }
+
+    // Cantidad de nodos del arbol, sin contar la raiz
+    fn node_count(topic: &Topic) -> usize {
+        topic
+            .subtopics
+            .read()
+            .unwrap()
+            .values()
+            .map(|subtopic| 1 + node_count(subtopic))
+            .sum()
+    }
+
+    #[test]
+    fn test_unsubscribe_deep_filter_removes_intermediate_nodes() {
+        let handler = TopicHandler::new();
+        handler.subscribe(&build_subscribe("a/x"), "user2").unwrap();
+        for filter in ["a/b/c/d/e", "a/b/c/+/e", "a/b/c/#"] {
+            handler.subscribe(&build_subscribe(filter), "user").unwrap();
+            assert!(node_count(&handler.root) > 2);
+
+            handler
+                .unsubscribe(build_unsubscribe(filter), "user")
+                .unwrap();
+            // Solo quedan los nodos de la otra suscripcion
+            assert_eq!(node_count(&handler.root), 2);
+            let subtopics = handler.root.subtopics.read().unwrap();
+            let a = subtopics.get("a").unwrap().subtopics.read().unwrap();
+            assert_eq!(a.keys().collect::<Vec<_>>(), vec!["x"]);
+        }
+        assert_eq!(handler.compact().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_compact_removes_empty_nodes() {
+        let handler = TopicHandler::new();
+        publish_retained(&handler, "a/b/retained", "1");
+        handler
+            .subscribe(&build_subscribe("a/c/+"), "user")
+            .unwrap();
+        // Ramas vacias, como las que podria tener un estado restaurado
+        let empty = Topic::new();
+        empty
+            .subtopics
+            .write()
+            .unwrap()
+            .insert("e".to_string(), Topic::new());
+        let mut subtopics = handler.root.subtopics.write().unwrap();
+        subtopics
+            .get("a")
+            .unwrap()
+            .subtopics
+            .write()
+            .unwrap()
+            .insert("empty".to_string(), empty);
+        subtopics.insert("d".to_string(), Topic::new());
+        drop(subtopics);
+        // a, a/b, a/b/retained, a/c, a/empty, a/empty/e y d
+        assert_eq!(node_count(&handler.root), 7);
+
+        assert_eq!(handler.compact().unwrap(), 3);
+        assert_eq!(node_count(&handler.root), 4);
+        assert_eq!(handler.matching_subscribers("a/c/x").unwrap().len(), 1);
+        assert_eq!(retained_topics(&handler), vec!["a/b/retained"]);
+    }
 }
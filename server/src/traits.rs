@@ -1,10 +1,22 @@
 use std::{
     fmt, io,
-    net::{Shutdown, TcpStream},
+    net::{Shutdown, SocketAddr, TcpStream},
+    path::PathBuf,
+    str::FromStr,
     time::Duration,
 };
 
-pub trait Close {
+pub use logger::LogFormat;
+use packets::qos::QoSLevel;
+use serde::Deserialize;
+
+use crate::server::{DumpState, JsonFileBackend, ServerResult};
+
+/// A stream that can be closed from any of its copies.
+///
+/// Since it is also a writer, whoever closes it can first
+/// flush the bytes that are still pending
+pub trait Close: io::Write {
     fn close(&mut self) -> io::Result<()>;
 }
 
@@ -15,8 +27,11 @@ pub trait TryClone {
 }
 
 pub trait Interrupt {
+    /// Makes the reads time out after `when`
     fn alert(&mut self, when: Duration) -> io::Result<()>;
 
+    /// Makes the reads block until there is something
+    /// to read, without any timeout
     fn sleep(&mut self) -> io::Result<()>;
 }
 
@@ -29,6 +44,143 @@ pub enum LoginResult {
 
 pub trait Login: fmt::Debug + Send + Sync + 'static {
     fn login(&mut self, user_name: &str, password: &str) -> io::Result<LoginResult>;
+
+    /// Returns true if the user can issue admin commands (see
+    /// [`Server`](crate::Server)). Only asked for the users that
+    /// logged in successfully. By default, no user is an admin
+    fn is_admin(&mut self, _user_name: &str) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Generates the ids assigned to the clients that connect
+/// with an empty client id (see [`Config::client_id_generator`])
+pub trait ClientIdGenerator: fmt::Debug + Send + Sync + 'static {
+    fn generate(&self) -> String;
+}
+
+/// Default [`ClientIdGenerator`], which generates random
+/// (version 4) UUIDs, such as `3f2b8c1e-9d4a-4f6b-8e2d-7a1c5b9e0f34`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidGenerator;
+
+impl ClientIdGenerator for UuidGenerator {
+    fn generate(&self) -> String {
+        let mut bytes: [u8; 16] = rand::random();
+        // Version 4 y variante RFC 4122
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+/// What the server does with a publication when the queue of
+/// messages waiting to be sent to the subscribers is full
+/// (see [`Config::overload_policy`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverloadPolicy {
+    /// The publication waits until there is room in the queue,
+    /// which slows down the publishing client
+    #[default]
+    Backpressure,
+    /// The oldest message in the queue is dropped to make room
+    DropOldest,
+    /// The new message is dropped
+    DropNewest,
+}
+
+impl FromStr for OverloadPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "backpressure" => Ok(OverloadPolicy::Backpressure),
+            "drop_oldest" => Ok(OverloadPolicy::DropOldest),
+            "drop_newest" => Ok(OverloadPolicy::DropNewest),
+            _ => Err(format!("Politica de sobrecarga desconocida: {}", s)),
+        }
+    }
+}
+
+/// A bridge that federates the server with a remote broker
+/// (see [`Config::bridges`])
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BridgeConfig {
+    /// Address of the remote broker, as `host:port`
+    pub remote: String,
+    /// Name of the bridge, used in the client ids of its connections,
+    /// so it must be unique among the federated brokers. If None, the
+    /// remote address is used
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Topic filters whose messages are brought from the remote broker
+    #[serde(default)]
+    pub topics_in: Vec<String>,
+    /// Topic filters whose messages are sent to the remote broker
+    #[serde(default)]
+    pub topics_out: Vec<String>,
+    /// Credentials used to connect to both brokers
+    #[serde(default)]
+    pub user_name: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// Reason why a client was disconnected from the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client sent a [`Disconnect`](packets::disconnect::Disconnect) packet
+    Gracefully,
+    /// The client did not send any packet during its Keep Alive
+    KeepAliveTimeout,
+    /// The client did not send the whole packet in time
+    /// (see [`Config::packet_read_timeout`])
+    PacketTimeout,
+    /// The client closed the connection without sending a
+    /// [`Disconnect`](packets::disconnect::Disconnect), or
+    /// the connection failed
+    ConnectionLost,
+    /// The client violated the protocol, so the server
+    /// closed the connection
+    ProtocolViolation,
+    /// The client did not acknowledge a packet after all the
+    /// retries (see [`Config::max_retries`])
+    RetriesExhausted,
+}
+
+/// Receives the connection events of the clients of a
+/// [`Server`](crate::Server)
+/// (see [`Server::add_connection_listener`](crate::Server::add_connection_listener))
+pub trait ConnectionListener {
+    /// Called when a client is accepted, after the Connack
+    /// is sent
+    fn on_connect(&self, id: &str, addr: SocketAddr);
+
+    /// Called when a client is disconnected, after its session
+    /// is updated
+    fn on_disconnect(&self, id: &str, reason: DisconnectReason);
+}
+
+/// Receives the subscription changes of the clients of a
+/// [`Server`](crate::Server)
+/// (see [`Server::add_subscription_listener`](crate::Server::add_subscription_listener))
+pub trait SubscriptionListener {
+    /// Called for every topic filter a client subscribes to,
+    /// with the QoS granted by the server, before the Suback is sent
+    fn on_subscribe(&self, id: &str, filter: &str, qos: QoSLevel);
+
+    /// Called for every topic filter a client unsubscribes from,
+    /// before the Unsuback is sent
+    fn on_unsubscribe(&self, id: &str, filter: &str);
 }
 
 impl TryClone for TcpStream {
@@ -48,26 +200,91 @@ impl Interrupt for TcpStream {
     }
     #[inline(always)]
     fn sleep(&mut self) -> io::Result<()> {
-        self.set_nonblocking(true)?;
+        // Un socket no bloqueante haria que el thread del cliente
+        // quede girando sin leer nada
+        self.set_nonblocking(false)?;
         self.set_read_timeout(None)
     }
 }
 
 impl Close for TcpStream {
+    /// The write half is shut down first, so the peer receives
+    /// everything that was sent before the FIN
     fn close(&mut self) -> io::Result<()> {
-        self.shutdown(Shutdown::Both)
+        io::Write::flush(self)?;
+        self.shutdown(Shutdown::Write)?;
+        match self.shutdown(Shutdown::Both) {
+            // Si el otro extremo ya habia cerrado, el FIN termina
+            // de cerrar el socket antes de llegar aca
+            Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(()),
+            result => result,
+        }
     }
 }
 
+/// Stores the state of the server between executions
+/// (see [`Config::persistence_backend`])
+pub trait PersistenceBackend: Send + Sync + 'static {
+    /// Persists the state, replacing the previously stored one
+    fn save(&self, state: &DumpState) -> ServerResult<()>;
+
+    /// Returns the last persisted state, or None if there
+    /// is none. If it is corrupt, it should return an error
+    /// of kind [`ServerErrorKind::DumpError`](crate::ServerErrorKind::DumpError)
+    fn load(&self) -> ServerResult<Option<DumpState>>;
+}
+
+/// Default value of [`Config::dispatch_queue_len`]
+pub const DEFAULT_DISPATCH_QUEUE_LEN: usize = 1024;
+
+/// Default value of [`Config::listen_backlog`] (the same
+/// value used by the standard library)
+pub const DEFAULT_LISTEN_BACKLOG: u32 = 128;
+
+/// Default value of [`Config::connect_timeout`]
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default value of [`Config::write_timeout`]
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default value of [`Config::max_pending_connections`]
+pub const DEFAULT_MAX_PENDING_CONNECTIONS: usize = 1024;
+
+/// Default value of [`Config::max_topic_len`] (the maximum
+/// length of a UTF-8 field)
+pub const DEFAULT_MAX_TOPIC_LEN: usize = 65535;
+
+/// Default value of [`Config::retry_interval`]
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Default value of [`Config::max_retries`]
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// Default value of [`Config::packet_read_timeout`]
+pub const DEFAULT_PACKET_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where and how the state of the server is persisted
+/// (see [`Config::dump_info`])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpConfig {
+    /// Path of the dump file
+    pub path: PathBuf,
+    /// How often the state is persisted
+    pub interval: Duration,
+    /// True if the dump file is compressed with gzip. Dump
+    /// paths ending with `.gz` are always compressed
+    pub compress: bool,
+}
+
 /// Config trait for the server
 pub trait Config: Send + Sync + Clone + 'static {
     /// Returns the port to be connected
     fn port(&self) -> u16;
 
-    /// Returns dump info, if specified. This info is
-    /// a tuple with the dump path and time interval.
+    /// Returns dump info, if specified: the dump path, the
+    /// time interval and whether it is compressed.
     /// Otherwise, it returns None
-    fn dump_info(&self) -> Option<(&str, Duration)>;
+    fn dump_info(&self) -> Option<DumpConfig>;
 
     /// Returns the path to the logs directory
     fn log_path(&self) -> &str;
@@ -75,5 +292,312 @@ pub trait Config: Send + Sync + Clone + 'static {
     /// Returns the IP address of the server
     fn ip(&self) -> &str;
 
+    /// Returns the address the server listens on. It can be
+    /// an IPv4 or IPv6 literal (for example, `0.0.0.0` to
+    /// listen on all interfaces) or a host name.
+    ///
+    /// By default, the server only listens on `127.0.0.1`
+    fn bind_address(&self) -> &str {
+        "127.0.0.1"
+    }
+
+    /// Returns true if the server should accept both IPv4 and
+    /// IPv6 connections when listening on an IPv6 address (for
+    /// example, `::`). IPv4 clients are seen as IPv4-mapped
+    /// IPv6 addresses.
+    ///
+    /// It has no effect when listening on an IPv4 address
+    fn dual_stack(&self) -> bool {
+        false
+    }
+
+    /// Returns the maximum number of messages of a publication
+    /// waiting to be sent to its subscribers. What happens when
+    /// the queue is full depends on `overload_policy()`
+    fn dispatch_queue_len(&self) -> usize {
+        DEFAULT_DISPATCH_QUEUE_LEN
+    }
+
+    /// Returns what to do with a message when the dispatch queue
+    /// is full. By default, the publication is blocked until there
+    /// is room in it ([`OverloadPolicy::Backpressure`])
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::default()
+    }
+
+    /// Returns how often the server persists its state to
+    /// the dump file. A zero interval disables the periodic
+    /// dump (the state is still persisted on shutdown).
+    ///
+    /// By default, it is the interval of `dump_info()`
+    fn dump_interval(&self) -> Duration {
+        self.dump_info()
+            .map(|dump_info| dump_info.interval)
+            .unwrap_or_default()
+    }
+
+    /// Returns the backend in which the state of the server is
+    /// persisted. If None, the state is not persisted.
+    ///
+    /// By default, it is a [`JsonFileBackend`] that writes to the
+    /// path of `dump_info()` (compressed if it says so)
+    fn persistence_backend(&self) -> Option<Box<dyn PersistenceBackend>> {
+        self.dump_info().map(|dump_info| {
+            Box::new(JsonFileBackend::new(
+                &dump_info.path.to_string_lossy(),
+                dump_info.compress,
+            )) as Box<dyn PersistenceBackend>
+        })
+    }
+
+    /// Returns the maximum time the server waits for the rest of a
+    /// packet once its first byte arrived. If the client has a Keep
+    /// Alive shorter than this, the Keep Alive is used instead.
+    ///
+    /// A client that does not send the whole packet in time is
+    /// disconnected
+    fn packet_read_timeout(&self) -> Duration {
+        DEFAULT_PACKET_READ_TIMEOUT
+    }
+
+    /// Returns how long the server waits before publishing the
+    /// Last Will of a client that disconnected ungracefully. If the
+    /// client reconnects in the meantime, it is not published.
+    ///
+    /// If None (the default), the Last Will is published immediately
+    fn will_delay(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the time window over which the retained messages that
+    /// match a new subscription are delivered, evenly spread, instead
+    /// of all at once. This smooths the load when many clients
+    /// subscribe at the same time (for example, after a restart).
+    ///
+    /// Each message is read right before it is delivered, so the
+    /// client always gets the latest retained value of each topic.
+    ///
+    /// If None (the default), they are delivered right after the Suback
+    fn retained_delivery_window(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the mount point of the listener of the server: a prefix
+    /// added to the topics of its clients, so that several tenants can
+    /// share a server without reaching each other's topics. A client
+    /// that publishes to `data` with the mount point `tenantA` actually
+    /// publishes to `tenantA/data`, and the same goes for its
+    /// subscriptions and its Last Will. The mount point is removed from
+    /// the topics of the messages delivered to it, so the clients never
+    /// see it. The clients of each mount point have their own sessions,
+    /// even if they use the same client id, and their admin commands
+    /// only refer to the clients and topics of their mount point.
+    ///
+    /// It must not be empty, nor have wildcards. To serve each tenant on
+    /// its own port, see [`Server::run_on_listeners`](crate::Server::run_on_listeners),
+    /// in which case no mount point can be nested inside another one.
+    ///
+    /// If None (the default), the topics are used as they are
+    fn mount_point(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns true if the server should disconnect the clients
+    /// that do not follow the protocol strictly, such as those
+    /// acknowledging a packet id that was never sent to them.
+    ///
+    /// If false (the default), said situations are only logged
+    fn strict_protocol(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the server should also accept the clients
+    /// that connect with the protocol name "MQIsdp" and protocol
+    /// level 3 (MQTT 3.1). They are handled as MQTT 3.1.1 clients.
+    ///
+    /// If false (the default), they are refused with the return
+    /// code 0x01 (Unacceptable protocol version)
+    fn allow_mqtt_31(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the server should not send a message back to
+    /// the client that published it, even if it is subscribed to its
+    /// topic. It is a server-wide approximation of the No Local option
+    /// of MQTT 5 subscriptions.
+    ///
+    /// False by default
+    fn no_local(&self) -> bool {
+        false
+    }
+
+    /// Returns the maximum amount of clients connected at the same
+    /// time. New clients that exceed it are refused with the
+    /// return code 0x03 (Server unavailable).
+    ///
+    /// If None (the default), there is no limit
+    fn max_clients(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the maximum number of pending TCP connections
+    /// (those not yet accepted by the server). Deployments with a
+    /// high connection rate may need to raise it, so that the
+    /// operating system does not drop the incoming connections.
+    ///
+    /// Note that the operating system may cap it (for example, with
+    /// `net.core.somaxconn` on Linux)
+    fn listen_backlog(&self) -> u32 {
+        DEFAULT_LISTEN_BACKLOG
+    }
+
+    /// Returns the maximum time the server waits for the
+    /// [`Connect`](packets::connect::Connect) packet of a new
+    /// connection. If it does not arrive on time, the connection
+    /// is closed
+    fn connect_timeout(&self) -> Duration {
+        DEFAULT_CONNECT_TIMEOUT
+    }
+
+    /// Returns the maximum time a write to a client may block. If a
+    /// client does not read what the server sends (for example, a slow
+    /// subscriber whose TCP window is full), the write fails after this
+    /// time and the client is disconnected as unresponsive, instead of
+    /// blocking the thread that was writing
+    fn write_timeout(&self) -> Duration {
+        DEFAULT_WRITE_TIMEOUT
+    }
+
+    /// Returns the maximum number of threads handling clients at the
+    /// same time. Each accepted connection is handled by its own thread
+    /// until it is closed. While the limit is reached, the server stops
+    /// accepting new connections, which wait in the listen backlog until
+    /// a client disconnects and its thread ends.
+    ///
+    /// If None (the default), there is no limit
+    fn max_client_threads(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the maximum number of connections that did not send
+    /// their [`Connect`](packets::connect::Connect) packet yet. While
+    /// it is reached, the server stops accepting new connections,
+    /// which wait in the listen backlog until a slot is freed
+    fn max_pending_connections(&self) -> usize {
+        DEFAULT_MAX_PENDING_CONNECTIONS
+    }
+
+    /// Returns the maximum length, in bytes, of the topic names
+    /// of the published packets and the topic filters of the
+    /// subscriptions. Clients that exceed it are disconnected
+    fn max_topic_len(&self) -> usize {
+        DEFAULT_MAX_TOPIC_LEN
+    }
+
+    /// Returns the maximum size, in bytes, of the payload of the
+    /// published packets, independently of the size of other packets.
+    /// A payload that exceeds it is a protocol violation: it is not
+    /// read, and the client is disconnected.
+    ///
+    /// If None (the default), there is no limit
+    fn max_payload_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns how long the server waits for the Puback of a QoS 1
+    /// [`Publish`](packets::publish::Publish) before sending it again
+    /// (with the DUP flag set). The wait doubles on every retry of
+    /// the same packet
+    fn retry_interval(&self) -> Duration {
+        DEFAULT_RETRY_INTERVAL
+    }
+
+    /// Returns how many times a QoS 1 [`Publish`](packets::publish::Publish)
+    /// is sent again before giving up. If it is still not acknowledged,
+    /// the client is disconnected
+    fn max_retries(&self) -> u32 {
+        DEFAULT_MAX_RETRIES
+    }
+
+    /// Returns the maximum Keep Alive, in seconds, that the server
+    /// accepts. Clients that request a larger one have it clamped
+    /// to this value, which is the one the server enforces. Since
+    /// the Connack of MQTT 3.1.1 has no field for it, the client
+    /// is not told about the override.
+    ///
+    /// If None (the default), the Keep Alive of the clients is
+    /// used as is. A Keep Alive of 0 (disabled) is never clamped
+    fn max_keep_alive(&self) -> Option<u16> {
+        None
+    }
+
+    /// Returns the maximum amount of retained messages stored. When
+    /// storing a new one exceeds it, the least recently stored ones
+    /// are evicted until it fits. Clearing a retained message (with
+    /// an empty payload) frees its place.
+    ///
+    /// If None (the default), there is no limit
+    fn max_retained(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the maximum total size, in bytes, of the payloads of
+    /// the retained messages stored. It is enforced the same way as
+    /// `max_retained()`
+    ///
+    /// If None (the default), there is no limit
+    fn max_retained_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the format of the logs written to the standard output
+    /// by [`init`](crate::init). With [`LogFormat::Json`], each line
+    /// is a JSON object that includes the fields of the span of the
+    /// event, such as the id of the client being handled.
+    ///
+    /// [`LogFormat::Pretty`] by default
+    fn log_format(&self) -> LogFormat {
+        LogFormat::default()
+    }
+
+    /// Returns the bridges to remote brokers that the server
+    /// starts when it runs.
+    ///
+    /// Each bridge connects as a client to both brokers, subscribing
+    /// with QoS 1, and republishes on each one the messages of the
+    /// other, with the QoS they were delivered with. A message is
+    /// never delivered back to the bridge that published it, so the
+    /// messages do not loop between the brokers.
+    ///
+    /// The bridges connect to this server through the listener of the
+    /// bridges (see [`Config::bridge_port`]). If the remote broker is
+    /// also this server, they must connect to its listener of the bridges.
+    ///
+    /// Empty by default
+    fn bridges(&self) -> &[BridgeConfig] {
+        &[]
+    }
+
+    /// Returns the port of the listener through which the bridges
+    /// connect, both the ones of this server (see [`Config::bridges`])
+    /// and the ones of remote brokers. The client ids starting with
+    /// `$bridge/` are only accepted through it.
+    ///
+    /// If None, the listener is only bound if the server has bridges,
+    /// on the loopback address and a port assigned by the OS. None by
+    /// default
+    fn bridge_port(&self) -> Option<u16> {
+        None
+    }
+
+    /// Returns the generator of the ids assigned to the clients
+    /// that connect with an empty client id. If a generated id is
+    /// already in use, another one is generated.
+    ///
+    /// By default, a random UUID is used (see [`UuidGenerator`])
+    fn client_id_generator(&self) -> Box<dyn ClientIdGenerator> {
+        Box::new(UuidGenerator)
+    }
+
     fn authenticator(&self) -> Option<Box<dyn Login>>;
 }
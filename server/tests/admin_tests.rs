@@ -0,0 +1,116 @@
+mod common;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use packets::{
+    connect::ConnectBuilder,
+    puback::Puback,
+    publish::Publish,
+    qos::QoSLevel::*,
+    suback::Suback,
+    subscribe::Subscribe,
+    traits::{MQTTDecoding, MQTTEncoding},
+};
+use serde_json::Value;
+
+use crate::common::*;
+
+fn start_admin_server() -> (server::ServerController, u16) {
+    let config =
+        ConfigMock::new(0, None, usr![("root", "toor"), ("user", "pass")]).with_admins(&["root"]);
+    let controller = start_server_with_config(config).unwrap();
+    let port = controller.local_addr().port();
+    (controller, port)
+}
+
+fn login(id: &str, user_name: &str, password: &str, port: u16) -> TcpStream {
+    let builder = ConnectBuilder::new(id, 0, true)
+        .unwrap()
+        .with_user_name(user_name)
+        .unwrap()
+        .with_password(password)
+        .unwrap();
+    connect_client(builder, port, true)
+}
+
+fn subscribe(stream: &mut TcpStream, filter: &str) -> Suback {
+    let subscribe = Subscribe::new(tpc![(filter, QoSLevel1)], 1);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    Suback::read_from(stream, control[0]).unwrap()
+}
+
+fn publish(stream: &mut TcpStream, topic: &str, payload: &str) {
+    let publish = Publish::new(false, QoSLevel0, false, topic, payload, None).unwrap();
+    stream.write_all(&publish.encode().unwrap()).unwrap();
+}
+
+#[test]
+fn test_admin_lists_clients() {
+    let (_s, port) = start_admin_server();
+    let _user = login("user-client", "user", "pass", port);
+    let mut admin = login("admin-client", "root", "toor", port);
+    subscribe(&mut admin, "$admin/list_clients/response/+");
+
+    publish(&mut admin, "$admin/list_clients", r#"{"request_id": "42"}"#);
+
+    let mut control = [0u8];
+    admin.read_exact(&mut control).unwrap();
+    let response = Publish::read_from(&mut admin, control[0]).unwrap();
+    assert_eq!(response.topic_name(), "$admin/list_clients/response/42");
+    let puback = Puback::new(response.packet_id().unwrap()).unwrap();
+    admin.write_all(&puback.encode().unwrap()).unwrap();
+
+    let clients: Value = serde_json::from_str(response.payload()).unwrap();
+    let ids: Vec<&str> = clients
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|client| client["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, vec!["admin-client", "user-client"]);
+}
+
+#[test]
+fn test_admin_disconnects_client() {
+    let (_s, port) = start_admin_server();
+    let mut user = login("user-client", "user", "pass", port);
+    let mut admin = login("admin-client", "root", "toor", port);
+    subscribe(&mut admin, "$admin/disconnect/response/1");
+
+    publish(
+        &mut admin,
+        "$admin/disconnect",
+        r#"{"request_id": "1", "client_id": "user-client"}"#,
+    );
+
+    let mut control = [0u8];
+    admin.read_exact(&mut control).unwrap();
+    let response = Publish::read_from(&mut admin, control[0]).unwrap();
+    assert_eq!(response.payload(), r#"{"disconnected":true}"#);
+    user.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(user.read(&mut control).unwrap(), 0);
+}
+
+#[test]
+fn test_non_admin_can_not_use_admin_topics() {
+    let (_s, port) = start_admin_server();
+    let mut user = login("user-client", "user", "pass", port);
+    let mut admin = login("admin-client", "root", "toor", port);
+
+    let suback = subscribe(&mut user, "$admin/#");
+    assert_eq!(suback.granted_qos(), vec![None]);
+
+    // El comando se descarta: el administrador no recibe nada
+    subscribe(&mut admin, "$admin/#");
+    publish(&mut user, "$admin/list_clients", r#"{"request_id": "1"}"#);
+    admin
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let mut control = [0u8];
+    assert!(admin.read_exact(&mut control).is_err());
+}
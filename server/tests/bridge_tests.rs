@@ -0,0 +1,246 @@
+mod common;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use packets::{
+    connack::Connack,
+    connect::ConnectBuilder,
+    packet_error::ErrorKind,
+    publish::Publish,
+    qos::QoSLevel::*,
+    suback::Suback,
+    subscribe::Subscribe,
+    traits::{MQTTDecoding, MQTTEncoding},
+};
+use server::traits::BridgeConfig;
+
+use crate::common::*;
+
+fn subscribe(stream: &mut TcpStream, topic: &str) {
+    let subscribe = Subscribe::new(tpc![(topic, QoSLevel0)], 1);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    Suback::read_from(stream, control[0]).unwrap();
+}
+
+fn publish(stream: &mut TcpStream, topic: &str, payload: &str) {
+    let publish = Publish::new(false, QoSLevel0, false, topic, payload, None).unwrap();
+    stream.write_all(&publish.encode().unwrap()).unwrap();
+}
+
+fn read_publish(stream: &mut TcpStream) -> Publish {
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    Publish::read_from(stream, control[0]).unwrap()
+}
+
+#[test]
+fn test_bridge_forwards_messages_in_both_directions_without_loops() {
+    let remote =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge_port(0)).unwrap();
+    let remote_port = remote.local_addr().port();
+    let remote_bridge_port = remote.bridge_addr().unwrap().port();
+    let local =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge(BridgeConfig {
+            remote: format!("localhost:{}", remote_bridge_port),
+            name: Some("test".to_string()),
+            topics_in: vec!["bridged/#".to_string()],
+            topics_out: vec!["bridged/#".to_string()],
+            user_name: None,
+            password: None,
+        }))
+        .unwrap();
+    let local_port = local.local_addr().port();
+    // Se espera a que el bridge se conecte a ambos brokers
+    thread::sleep(Duration::from_millis(500));
+
+    let mut local_client = connect_client(
+        ConnectBuilder::new("local", 0, true).unwrap(),
+        local_port,
+        true,
+    );
+    let mut remote_client = connect_client(
+        ConnectBuilder::new("remote", 0, true).unwrap(),
+        remote_port,
+        true,
+    );
+    subscribe(&mut local_client, "bridged/#");
+    subscribe(&mut remote_client, "bridged/#");
+
+    // Del broker remoto al local
+    publish(&mut remote_client, "bridged/remote", "hola");
+    assert_eq!(read_publish(&mut remote_client).payload(), "hola");
+    let received = read_publish(&mut local_client);
+    assert_eq!(received.topic_name(), "bridged/remote");
+    assert_eq!(received.payload(), "hola");
+
+    // Del broker local al remoto
+    publish(&mut local_client, "bridged/local", "chau");
+    assert_eq!(read_publish(&mut local_client).payload(), "chau");
+    let received = read_publish(&mut remote_client);
+    assert_eq!(received.topic_name(), "bridged/local");
+    assert_eq!(received.payload(), "chau");
+
+    // Los mensajes no vuelven a pasar por el bridge
+    let mut control = [0u8];
+    for stream in [&mut local_client, &mut remote_client] {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        assert!(stream.read_exact(&mut control).is_err());
+    }
+}
+
+#[test]
+fn test_bridge_does_not_forward_other_topics() {
+    let remote =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge_port(0)).unwrap();
+    let remote_port = remote.local_addr().port();
+    let remote_bridge_port = remote.bridge_addr().unwrap().port();
+    let local =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge(BridgeConfig {
+            remote: format!("localhost:{}", remote_bridge_port),
+            name: None,
+            topics_in: vec!["bridged".to_string()],
+            topics_out: vec![],
+            user_name: None,
+            password: None,
+        }))
+        .unwrap();
+    let local_port = local.local_addr().port();
+    thread::sleep(Duration::from_millis(500));
+
+    let mut local_client = connect_client(
+        ConnectBuilder::new("local", 0, true).unwrap(),
+        local_port,
+        true,
+    );
+    let mut remote_client = connect_client(
+        ConnectBuilder::new("remote", 0, true).unwrap(),
+        remote_port,
+        true,
+    );
+    subscribe(&mut local_client, "#");
+
+    publish(&mut remote_client, "other", "no");
+    publish(&mut remote_client, "bridged", "si");
+
+    let received = read_publish(&mut local_client);
+    assert_eq!(received.topic_name(), "bridged");
+    assert_eq!(received.payload(), "si");
+}
+
+#[test]
+fn test_bridge_keeps_the_qos_and_payload_of_the_messages() {
+    let remote =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge_port(0)).unwrap();
+    let remote_port = remote.local_addr().port();
+    let remote_bridge_port = remote.bridge_addr().unwrap().port();
+    let local =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge(BridgeConfig {
+            remote: format!("localhost:{}", remote_bridge_port),
+            name: Some("test".to_string()),
+            topics_in: vec!["bridged".to_string()],
+            topics_out: vec![],
+            user_name: None,
+            password: None,
+        }))
+        .unwrap();
+    let local_port = local.local_addr().port();
+    thread::sleep(Duration::from_millis(500));
+
+    let mut local_client = connect_client(
+        ConnectBuilder::new("local", 0, true).unwrap(),
+        local_port,
+        true,
+    );
+    let mut remote_client = connect_client(
+        ConnectBuilder::new("remote", 0, true).unwrap(),
+        remote_port,
+        true,
+    );
+    let subscribe = Subscribe::new(tpc![("bridged", QoSLevel1)], 1);
+    local_client
+        .write_all(&subscribe.encode().unwrap())
+        .unwrap();
+    let mut control = [0u8];
+    local_client.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut local_client, control[0]).unwrap();
+
+    // El payload llega como se publico, aunque parezca tener metadatos
+    let payload = "\u{1}4:test;hola";
+    let publish = Publish::new(false, QoSLevel1, false, "bridged", payload, Some(1)).unwrap();
+    remote_client.write_all(&publish.encode().unwrap()).unwrap();
+
+    let received = read_publish(&mut local_client);
+    assert_eq!(received.qos(), QoSLevel1);
+    assert_eq!(received.payload(), payload);
+}
+
+#[test]
+fn test_bridge_ids_are_only_accepted_through_the_bridge_listener() {
+    let server =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge_port(0)).unwrap();
+    let port = server.local_addr().port();
+    let bridge_port = server.bridge_addr().unwrap().port();
+
+    let builder = ConnectBuilder::new("$bridge/falso", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, false);
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let err = Connack::read_from(&mut stream, control[0]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::IdentifierRejected);
+
+    let builder = ConnectBuilder::new("$bridge/verdadero", 0, true).unwrap();
+    connect_client(builder, bridge_port, true);
+}
+
+#[test]
+fn test_bridge_reconnects_to_the_remote() {
+    let remote =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge_port(0)).unwrap();
+    let remote_bridge_port = remote.bridge_addr().unwrap().port();
+    let local =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bridge(BridgeConfig {
+            remote: format!("localhost:{}", remote_bridge_port),
+            name: Some("test".to_string()),
+            topics_in: vec!["bridged".to_string()],
+            topics_out: vec![],
+            user_name: None,
+            password: None,
+        }))
+        .unwrap();
+    let local_port = local.local_addr().port();
+    thread::sleep(Duration::from_millis(500));
+
+    // El broker remoto se reinicia en el mismo puerto
+    drop(remote);
+    let remote = start_server_with_config(
+        ConfigMock::new(0, None, None).with_bridge_port(remote_bridge_port),
+    )
+    .unwrap();
+    let remote_port = remote.local_addr().port();
+    thread::sleep(Duration::from_secs(2));
+
+    let mut local_client = connect_client(
+        ConnectBuilder::new("local", 0, true).unwrap(),
+        local_port,
+        true,
+    );
+    let mut remote_client = connect_client(
+        ConnectBuilder::new("remote", 0, true).unwrap(),
+        remote_port,
+        true,
+    );
+    subscribe(&mut local_client, "bridged");
+    publish(&mut remote_client, "bridged", "de nuevo");
+
+    let received = read_publish(&mut local_client);
+    assert_eq!(received.topic_name(), "bridged");
+    assert_eq!(received.payload(), "de nuevo");
+}
@@ -5,13 +5,20 @@ use packets::{
 };
 use rand::Rng;
 use server::{
-    traits::{Login, LoginResult},
-    Config, Server, ServerController,
+    traits::{
+        BridgeConfig, DumpConfig, Login, LoginResult, OverloadPolicy, PersistenceBackend,
+        DEFAULT_CONNECT_TIMEOUT, DEFAULT_LISTEN_BACKLOG, DEFAULT_MAX_PENDING_CONNECTIONS,
+        DEFAULT_MAX_RETRIES, DEFAULT_MAX_TOPIC_LEN, DEFAULT_PACKET_READ_TIMEOUT,
+        DEFAULT_RETRY_INTERVAL, DEFAULT_WRITE_TIMEOUT,
+    },
+    Config, DumpState, JsonFileBackend, Server, ServerController, ServerError,
 };
 use std::{
     collections::HashMap,
     io::{Read, Write},
     net::TcpStream,
+    path::PathBuf,
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
@@ -40,6 +47,7 @@ macro_rules! tpc {
 #[derive(Debug, Clone)]
 struct AuthMock {
     users: HashMap<String, String>,
+    admins: Vec<String>,
 }
 
 impl Login for AuthMock {
@@ -58,15 +66,76 @@ impl Login for AuthMock {
             Ok(LoginResult::UsernameNotFound)
         }
     }
+
+    fn is_admin(&mut self, user_name: &str) -> std::io::Result<bool> {
+        Ok(self.admins.iter().any(|admin| admin == user_name))
+    }
+}
+
+// Guarda el estado del servidor en memoria. Las copias
+// comparten el mismo estado
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    state: Arc<Mutex<Option<DumpState>>>,
+}
+
+impl MemoryBackend {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(dead_code)]
+    pub fn state(&self) -> Option<DumpState> {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+impl PersistenceBackend for MemoryBackend {
+    fn save(&self, state: &DumpState) -> Result<(), ServerError> {
+        *self.state.lock().unwrap() = Some(state.clone());
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Option<DumpState>, ServerError> {
+        Ok(self.state.lock().unwrap().clone())
+    }
 }
 
 #[derive(Clone)]
-struct ConfigMock {
+pub struct ConfigMock {
     port: u16,
-    dump_info: Option<(String, Duration)>,
+    dump_info: Option<DumpConfig>,
     log_path: String,
     auth: Option<Box<AuthMock>>,
     ip: String,
+    bind_address: String,
+    dual_stack: bool,
+    dispatch_queue_len: usize,
+    overload_policy: OverloadPolicy,
+    will_delay: Option<Duration>,
+    retained_delivery_window: Option<Duration>,
+    mount_point: Option<String>,
+    packet_read_timeout: Duration,
+    strict_protocol: bool,
+    allow_mqtt_31: bool,
+    no_local: bool,
+    memory_backend: Option<MemoryBackend>,
+    max_clients: Option<usize>,
+    max_client_threads: Option<usize>,
+    listen_backlog: u32,
+    connect_timeout: Duration,
+    write_timeout: Duration,
+    max_pending_connections: usize,
+    max_topic_len: usize,
+    max_payload_size: Option<usize>,
+    retry_interval: Duration,
+    max_retries: u32,
+    max_keep_alive: Option<u16>,
+    max_retained: Option<usize>,
+    max_retained_bytes: Option<usize>,
+    bridges: Vec<BridgeConfig>,
+    bridge_port: Option<u16>,
 }
 
 impl Config for ConfigMock {
@@ -74,10 +143,8 @@ impl Config for ConfigMock {
         self.port
     }
 
-    fn dump_info(&self) -> Option<(&str, Duration)> {
-        self.dump_info
-            .as_ref()
-            .map(|(path, duration)| (path.as_str(), *duration))
+    fn dump_info(&self) -> Option<DumpConfig> {
+        self.dump_info.clone()
     }
 
     fn log_path(&self) -> &str {
@@ -88,6 +155,121 @@ impl Config for ConfigMock {
         &self.ip
     }
 
+    fn bind_address(&self) -> &str {
+        &self.bind_address
+    }
+
+    fn dual_stack(&self) -> bool {
+        self.dual_stack
+    }
+
+    fn dispatch_queue_len(&self) -> usize {
+        self.dispatch_queue_len
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        self.overload_policy
+    }
+
+    fn will_delay(&self) -> Option<Duration> {
+        self.will_delay
+    }
+
+    fn retained_delivery_window(&self) -> Option<Duration> {
+        self.retained_delivery_window
+    }
+
+    fn mount_point(&self) -> Option<&str> {
+        self.mount_point.as_deref()
+    }
+
+    fn packet_read_timeout(&self) -> Duration {
+        self.packet_read_timeout
+    }
+
+    fn strict_protocol(&self) -> bool {
+        self.strict_protocol
+    }
+
+    fn allow_mqtt_31(&self) -> bool {
+        self.allow_mqtt_31
+    }
+
+    fn no_local(&self) -> bool {
+        self.no_local
+    }
+
+    fn max_clients(&self) -> Option<usize> {
+        self.max_clients
+    }
+
+    fn max_client_threads(&self) -> Option<usize> {
+        self.max_client_threads
+    }
+
+    fn listen_backlog(&self) -> u32 {
+        self.listen_backlog
+    }
+
+    fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    fn write_timeout(&self) -> Duration {
+        self.write_timeout
+    }
+
+    fn max_pending_connections(&self) -> usize {
+        self.max_pending_connections
+    }
+
+    fn max_topic_len(&self) -> usize {
+        self.max_topic_len
+    }
+
+    fn max_payload_size(&self) -> Option<usize> {
+        self.max_payload_size
+    }
+
+    fn retry_interval(&self) -> Duration {
+        self.retry_interval
+    }
+
+    fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    fn max_keep_alive(&self) -> Option<u16> {
+        self.max_keep_alive
+    }
+
+    fn max_retained(&self) -> Option<usize> {
+        self.max_retained
+    }
+
+    fn max_retained_bytes(&self) -> Option<usize> {
+        self.max_retained_bytes
+    }
+
+    fn bridges(&self) -> &[BridgeConfig] {
+        &self.bridges
+    }
+
+    fn bridge_port(&self) -> Option<u16> {
+        self.bridge_port
+    }
+
+    fn persistence_backend(&self) -> Option<Box<dyn PersistenceBackend>> {
+        match (&self.memory_backend, &self.dump_info) {
+            (Some(backend), _) => Some(Box::new(backend.clone())),
+            (None, Some(dump_info)) => Some(Box::new(JsonFileBackend::new(
+                &dump_info.path.to_string_lossy(),
+                dump_info.compress,
+            ))),
+            (None, None) => None,
+        }
+    }
+
     fn authenticator(&self) -> Option<Box<dyn Login>> {
         let authenticator = self.auth.clone()?;
         Some(authenticator)
@@ -102,14 +284,213 @@ impl ConfigMock {
     ) -> ConfigMock {
         ConfigMock {
             port,
-            dump_info: dump_info.map(|(str, dur)| (str.to_string(), dur)),
+            dump_info: dump_info.map(|(path, interval)| DumpConfig {
+                path: PathBuf::from(path),
+                interval,
+                compress: false,
+            }),
             log_path: "tests/files/logs".to_string(),
-            auth: users.map(|u| Box::new(AuthMock { users: u })),
+            auth: users.map(|u| {
+                Box::new(AuthMock {
+                    users: u,
+                    admins: vec![],
+                })
+            }),
             ip: "localhost".to_string(),
+            bind_address: "localhost".to_string(),
+            dual_stack: false,
+            dispatch_queue_len: 1024,
+            overload_policy: OverloadPolicy::Backpressure,
+            will_delay: None,
+            retained_delivery_window: None,
+            mount_point: None,
+            packet_read_timeout: DEFAULT_PACKET_READ_TIMEOUT,
+            strict_protocol: false,
+            allow_mqtt_31: false,
+            no_local: false,
+            memory_backend: None,
+            max_clients: None,
+            max_client_threads: None,
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            max_pending_connections: DEFAULT_MAX_PENDING_CONNECTIONS,
+            max_topic_len: DEFAULT_MAX_TOPIC_LEN,
+            max_payload_size: None,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_keep_alive: None,
+            max_retained: None,
+            max_retained_bytes: None,
+            bridges: vec![],
+            bridge_port: None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_bind_address(mut self, bind_address: &str) -> ConfigMock {
+        self.bind_address = bind_address.to_string();
+        self
+    }
+
+    // Los usuarios dados pueden enviar comandos de administracion.
+    // Requiere que la configuracion tenga usuarios
+    #[allow(dead_code)]
+    pub fn with_admins(mut self, admins: &[&str]) -> ConfigMock {
+        if let Some(auth) = &mut self.auth {
+            auth.admins = admins.iter().map(|admin| admin.to_string()).collect();
         }
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_dual_stack(mut self, dual_stack: bool) -> ConfigMock {
+        self.dual_stack = dual_stack;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_dispatch_queue_len(mut self, dispatch_queue_len: usize) -> ConfigMock {
+        self.dispatch_queue_len = dispatch_queue_len;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_overload_policy(mut self, overload_policy: OverloadPolicy) -> ConfigMock {
+        self.overload_policy = overload_policy;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_will_delay(mut self, will_delay: Duration) -> ConfigMock {
+        self.will_delay = Some(will_delay);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_retained_delivery_window(mut self, window: Duration) -> ConfigMock {
+        self.retained_delivery_window = Some(window);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_mount_point(mut self, mount_point: &str) -> ConfigMock {
+        self.mount_point = Some(mount_point.to_string());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_packet_read_timeout(mut self, packet_read_timeout: Duration) -> ConfigMock {
+        self.packet_read_timeout = packet_read_timeout;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_strict_protocol(mut self, strict_protocol: bool) -> ConfigMock {
+        self.strict_protocol = strict_protocol;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_allow_mqtt_31(mut self, allow_mqtt_31: bool) -> ConfigMock {
+        self.allow_mqtt_31 = allow_mqtt_31;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_no_local(mut self, no_local: bool) -> ConfigMock {
+        self.no_local = no_local;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_memory_backend(mut self, backend: MemoryBackend) -> ConfigMock {
+        self.memory_backend = Some(backend);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_max_clients(mut self, max_clients: usize) -> ConfigMock {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_max_client_threads(mut self, max_client_threads: usize) -> ConfigMock {
+        self.max_client_threads = Some(max_client_threads);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_listen_backlog(mut self, listen_backlog: u32) -> ConfigMock {
+        self.listen_backlog = listen_backlog;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> ConfigMock {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> ConfigMock {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_max_pending_connections(mut self, max_pending_connections: usize) -> ConfigMock {
+        self.max_pending_connections = max_pending_connections;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_max_topic_len(mut self, max_topic_len: usize) -> ConfigMock {
+        self.max_topic_len = max_topic_len;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_max_payload_size(mut self, max_payload_size: usize) -> ConfigMock {
+        self.max_payload_size = Some(max_payload_size);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_retries(mut self, retry_interval: Duration, max_retries: u32) -> ConfigMock {
+        self.retry_interval = retry_interval;
+        self.max_retries = max_retries;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_max_keep_alive(mut self, max_keep_alive: u16) -> ConfigMock {
+        self.max_keep_alive = Some(max_keep_alive);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_max_retained(mut self, max_retained: usize, max_bytes: usize) -> ConfigMock {
+        self.max_retained = Some(max_retained);
+        self.max_retained_bytes = Some(max_bytes);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_bridge(mut self, bridge: BridgeConfig) -> ConfigMock {
+        self.bridges.push(bridge);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_bridge_port(mut self, port: u16) -> ConfigMock {
+        self.bridge_port = Some(port);
+        self
     }
 }
 
+#[allow(dead_code)]
 pub fn start_server(
     dump_info: Option<(&str, Duration)>,
     users: Option<HashMap<String, String>>,
@@ -128,6 +509,14 @@ pub fn start_server(
     panic!("No se pudo crear servidor para ejecutar el test");
 }
 
+#[allow(dead_code)]
+// Inicia un servidor con la configuracion dada, sin reintentar
+// con otros puertos si falla
+pub fn start_server_with_config(config: ConfigMock) -> Result<ServerController, ServerError> {
+    Server::new(config, 20).unwrap().run()
+}
+
+#[allow(dead_code)]
 fn random_port() -> u16 {
     // Esos números salen de esta información
     // https://en.wikipedia.org/wiki/List_of_TCP_and_UDP_port_numbers#Dynamic,_private_or_ephemeral_ports
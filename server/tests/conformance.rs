@@ -0,0 +1,326 @@
+//! Tests de conformidad con MQTT v3.1.1.
+//!
+//! Cada chequeo levanta su propio servidor en un puerto efimero y le
+//! habla por un socket real, usando los encoders del crate `packets`.
+//! Los paquetes recibidos se comparan byte a byte con los esperados.
+//! Como cada chequeo es un test independiente, se puede correr uno
+//! solo con, por ejemplo, `cargo test --test conformance check_retained`
+mod common;
+use std::{
+    io::{Cursor, Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use packets::{
+    connack::{Connack, ConnackReturnCode},
+    connect::ConnectBuilder,
+    packet_reader::{encode_remaining_length, RemainingLength},
+    pingreq::PingReq,
+    pingresp::PingResp,
+    puback::Puback,
+    publish::Publish,
+    qos::QoSLevel::{self, *},
+    suback::Suback,
+    subscribe::Subscribe,
+    topic_filter::TopicFilter,
+    traits::{MQTTDecoding, MQTTEncoding},
+    unsuback::Unsuback,
+    unsubscribe::Unsubscribe,
+};
+use server::{Server, ServerController};
+
+use crate::common::*;
+
+/// Tiempo que se espera para asegurar que no llega ningun paquete
+const SILENCE: Duration = Duration::from_millis(300);
+
+/// Cliente minimo para los chequeos: manda paquetes codificados con
+/// [`MQTTEncoding`] y lee los paquetes completos (header fijo incluido)
+struct TestClient {
+    stream: TcpStream,
+}
+
+impl TestClient {
+    /// Se conecta con el Connect del builder, sin leer el Connack
+    fn connect(port: u16, builder: ConnectBuilder) -> Self {
+        Self {
+            stream: connect_client(builder, port, false),
+        }
+    }
+
+    /// Se conecta con una sesion limpia y verifica que el Connack
+    /// sea exactamente el de una conexion aceptada
+    fn connect_accepted(port: u16, id: &str, keep_alive: u16) -> Self {
+        let mut client = Self::connect(port, ConnectBuilder::new(id, keep_alive, true).unwrap());
+        client.expect(&Connack::new(false, ConnackReturnCode::Accepted));
+        client
+    }
+
+    fn send<T: MQTTEncoding>(&mut self, packet: &T) {
+        self.stream.write_all(&packet.encode().unwrap()).unwrap();
+    }
+
+    /// Lee un paquete completo, tal cual llego
+    fn read_bytes(&mut self) -> Vec<u8> {
+        let mut control = [0u8];
+        self.stream.read_exact(&mut control).unwrap();
+        let remaining_length = RemainingLength::from_encoded(&mut self.stream)
+            .unwrap()
+            .decode() as usize;
+        let mut bytes = vec![control[0]];
+        bytes.append(&mut encode_remaining_length(remaining_length));
+        let mut body = vec![0u8; remaining_length];
+        self.stream.read_exact(&mut body).unwrap();
+        bytes.append(&mut body);
+        bytes
+    }
+
+    /// Lee un paquete y lo decodifica, verificando que al volver a
+    /// codificarlo se obtengan exactamente los mismos bytes
+    fn read<T: MQTTDecoding + MQTTEncoding>(&mut self) -> T {
+        let bytes = self.read_bytes();
+        let mut stream = Cursor::new(&bytes[1..]);
+        let packet = T::read_from(&mut stream, bytes[0]).unwrap();
+        assert_eq!(packet.encode().unwrap(), bytes);
+        packet
+    }
+
+    /// Verifica que el proximo paquete sea exactamente `expected`
+    fn expect<T: MQTTEncoding>(&mut self, expected: &T) {
+        assert_eq!(self.read_bytes(), expected.encode().unwrap());
+    }
+
+    /// Verifica que llegue un Publish igual a `expected`, salvo el
+    /// packet id (que lo elige el servidor). Si es QoS 1, lo confirma
+    fn expect_publish(&mut self, expected: Publish) {
+        let publish: Publish = self.read();
+        let expected = match publish.packet_id() {
+            Some(packet_id) => {
+                self.send(&Puback::new(packet_id).unwrap());
+                expected.with_packet_id(packet_id)
+            }
+            None => expected,
+        };
+        assert_eq!(publish.encode().unwrap(), expected.encode().unwrap());
+    }
+
+    /// Verifica que no llegue nada durante [`SILENCE`]
+    fn expect_nothing(&mut self) {
+        self.stream.set_read_timeout(Some(SILENCE)).unwrap();
+        let mut control = [0u8];
+        assert!(self.stream.read_exact(&mut control).is_err());
+        self.stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+    }
+
+    /// Verifica que el servidor haya cerrado la conexion
+    fn expect_closed(&mut self) {
+        let mut control = [0u8];
+        assert_eq!(self.stream.read(&mut control).unwrap(), 0);
+    }
+
+    fn subscribe(&mut self, filters: Vec<TopicFilter>, packet_id: u16) {
+        let return_codes = filters
+            .iter()
+            .map(|filter| u8::from(filter.qos()))
+            .collect();
+        self.send(&Subscribe::new(filters, packet_id));
+        self.expect(&Suback::new_from_vec(return_codes, packet_id).unwrap());
+    }
+}
+
+fn start() -> (ServerController, u16) {
+    let controller = Server::new(ConfigMock::new(0, None, None), 20)
+        .unwrap()
+        .run()
+        .unwrap();
+    let port = controller.local_addr().port();
+    (controller, port)
+}
+
+fn publish(topic: &str, payload: &str, qos: QoSLevel, retain: bool) -> Publish {
+    let packet_id = match qos {
+        QoSLevel0 => None,
+        _ => Some(1),
+    };
+    Publish::new(false, qos, retain, topic, payload, packet_id).unwrap()
+}
+
+#[test]
+fn check_connect() {
+    let (_s, port) = start();
+    let mut client = TestClient::connect(port, ConnectBuilder::new("id", 0, true).unwrap());
+
+    assert_eq!(client.read_bytes(), vec![0b00100000, 2, 0, 0]);
+}
+
+#[test]
+fn check_connect_session_present() {
+    let (_s, port) = start();
+    let mut client = TestClient::connect(port, ConnectBuilder::new("id", 0, false).unwrap());
+    client.expect(&Connack::new(false, ConnackReturnCode::Accepted));
+    drop(client);
+    thread::sleep(Duration::from_millis(100));
+
+    let mut client = TestClient::connect(port, ConnectBuilder::new("id", 0, false).unwrap());
+    client.expect(&Connack::new(true, ConnackReturnCode::Accepted));
+}
+
+#[test]
+fn check_subscribe() {
+    let (_s, port) = start();
+    let mut client = TestClient::connect_accepted(port, "id", 0);
+
+    client.send(&Subscribe::new(
+        tpc![("a", QoSLevel0), ("b", QoSLevel1)],
+        10,
+    ));
+    assert_eq!(client.read_bytes(), vec![0b10010000, 4, 0, 10, 0, 1]);
+}
+
+#[test]
+fn check_publish_qos_0() {
+    let (_s, port) = start();
+    let mut subscriber = TestClient::connect_accepted(port, "sub", 0);
+    let mut publisher = TestClient::connect_accepted(port, "pub", 0);
+    subscriber.subscribe(tpc![("topic", QoSLevel0)], 1);
+
+    publisher.send(&publish("topic", "hola", QoSLevel0, false));
+    subscriber.expect(&publish("topic", "hola", QoSLevel0, false));
+    publisher.expect_nothing();
+}
+
+#[test]
+fn check_publish_qos_1() {
+    let (_s, port) = start();
+    let mut subscriber = TestClient::connect_accepted(port, "sub", 0);
+    let mut publisher = TestClient::connect_accepted(port, "pub", 0);
+    subscriber.subscribe(tpc![("topic", QoSLevel1)], 1);
+
+    publisher.send(&publish("topic", "hola", QoSLevel1, false).with_packet_id(7));
+    publisher.expect(&Puback::new(7).unwrap());
+    subscriber.expect_publish(publish("topic", "hola", QoSLevel1, false));
+}
+
+#[test]
+fn check_publish_is_downgraded_to_subscription_qos() {
+    let (_s, port) = start();
+    let mut subscriber = TestClient::connect_accepted(port, "sub", 0);
+    let mut publisher = TestClient::connect_accepted(port, "pub", 0);
+    subscriber.subscribe(tpc![("topic", QoSLevel0)], 1);
+
+    publisher.send(&publish("topic", "hola", QoSLevel1, false));
+    publisher.expect(&Puback::new(1).unwrap());
+    subscriber.expect(&publish("topic", "hola", QoSLevel0, false));
+}
+
+#[test]
+fn check_retained() {
+    let (_s, port) = start();
+    let mut publisher = TestClient::connect_accepted(port, "pub", 0);
+    publisher.send(&publish("topic", "retenido", QoSLevel0, true));
+    thread::sleep(Duration::from_millis(100));
+
+    // Al suscribirse, el retenido llega con el flag de retain
+    let mut subscriber = TestClient::connect_accepted(port, "sub", 0);
+    subscriber.subscribe(tpc![("topic", QoSLevel0)], 1);
+    subscriber.expect(&publish("topic", "retenido", QoSLevel0, true));
+
+    // A los suscriptores existentes les llega sin el flag
+    publisher.send(&publish("topic", "nuevo", QoSLevel0, true));
+    subscriber.expect(&publish("topic", "nuevo", QoSLevel0, false));
+
+    // Un payload vacio borra el mensaje retenido
+    publisher.send(&publish("topic", "", QoSLevel0, true));
+    subscriber.expect(&publish("topic", "", QoSLevel0, false));
+    let mut late = TestClient::connect_accepted(port, "late", 0);
+    late.subscribe(tpc![("topic", QoSLevel0)], 1);
+    late.expect_nothing();
+}
+
+#[test]
+fn check_wildcards() {
+    let (_s, port) = start();
+    let mut single_level = TestClient::connect_accepted(port, "single", 0);
+    let mut multi_level = TestClient::connect_accepted(port, "multi", 0);
+    let mut publisher = TestClient::connect_accepted(port, "pub", 0);
+    single_level.subscribe(tpc![("sport/+/player", QoSLevel0)], 1);
+    multi_level.subscribe(tpc![("sport/#", QoSLevel0)], 1);
+
+    publisher.send(&publish("sport/tennis/player", "1", QoSLevel0, false));
+    publisher.send(&publish(
+        "sport/tennis/player/ranking",
+        "2",
+        QoSLevel0,
+        false,
+    ));
+    publisher.send(&publish("sport", "3", QoSLevel0, false));
+
+    single_level.expect(&publish("sport/tennis/player", "1", QoSLevel0, false));
+    single_level.expect_nothing();
+    multi_level.expect(&publish("sport/tennis/player", "1", QoSLevel0, false));
+    multi_level.expect(&publish(
+        "sport/tennis/player/ranking",
+        "2",
+        QoSLevel0,
+        false,
+    ));
+    multi_level.expect(&publish("sport", "3", QoSLevel0, false));
+}
+
+#[test]
+fn check_unsubscribe() {
+    let (_s, port) = start();
+    let mut subscriber = TestClient::connect_accepted(port, "sub", 0);
+    let mut publisher = TestClient::connect_accepted(port, "pub", 0);
+    subscriber.subscribe(tpc![("topic", QoSLevel0)], 1);
+
+    subscriber.send(&Unsubscribe::new(2, tpc![("topic", QoSLevel0)]).unwrap());
+    subscriber.expect(&Unsuback::new(2).unwrap());
+
+    publisher.send(&publish("topic", "hola", QoSLevel0, false));
+    subscriber.expect_nothing();
+}
+
+#[test]
+fn check_ping() {
+    let (_s, port) = start();
+    let mut client = TestClient::connect_accepted(port, "id", 0);
+
+    client.send(&PingReq::new());
+    client.expect(&PingResp::new());
+}
+
+#[test]
+fn check_keep_alive_timeout() {
+    let (_s, port) = start();
+    let mut client = TestClient::connect_accepted(port, "id", 1);
+
+    // Sin actividad durante 1,5 veces el keep alive, se desconecta
+    thread::sleep(Duration::from_millis(1600));
+    client.expect_closed();
+}
+
+#[test]
+fn check_duplicate_id_takeover() {
+    let (_s, port) = start();
+    let mut first = TestClient::connect_accepted(port, "id", 0);
+    // Con clean_session no hay sesion presente, aunque la hubiera
+    let mut second = TestClient::connect_accepted(port, "id", 0);
+
+    first.expect_closed();
+    second.send(&PingReq::new());
+    second.expect(&PingResp::new());
+
+    // Sin clean_session, se retoma la sesion anterior
+    let mut persistent =
+        TestClient::connect(port, ConnectBuilder::new("persistent", 0, false).unwrap());
+    persistent.expect(&Connack::new(false, ConnackReturnCode::Accepted));
+    let mut takeover =
+        TestClient::connect(port, ConnectBuilder::new("persistent", 0, false).unwrap());
+    takeover.expect(&Connack::new(true, ConnackReturnCode::Accepted));
+    persistent.expect_closed();
+}
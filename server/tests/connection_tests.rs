@@ -6,11 +6,21 @@ use packets::disconnect::Disconnect;
 use packets::packet_error::ErrorKind;
 use packets::pingreq::PingReq;
 use packets::pingresp::PingResp;
+use packets::puback::Puback;
+use packets::publish::Publish;
+use packets::qos::QoSLevel;
+use packets::suback::Suback;
+use packets::subscribe::Subscribe;
+use packets::topic_filter::TopicFilter;
 use packets::traits::{MQTTDecoding, MQTTEncoding};
+use server::traits::{ConnectionListener, DisconnectReason};
+use server::{Server, ServerErrorKind};
 use std::fs;
 use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[test]
 fn test_connect_clean_session_true() {
@@ -148,6 +158,28 @@ fn test_takeover_should_close_previous_connection() {
     assert_eq!(stream_1.read(&mut control).unwrap(), 0);
 }
 
+#[test]
+fn test_takeover_should_deliver_pending_puback_before_closing() {
+    let (_s, port) = start_server(None, None);
+    let builder_1 = ConnectBuilder::new("id", 0, true).unwrap();
+    let builder_2 = ConnectBuilder::new("id", 0, true).unwrap();
+
+    let mut control = [0u8];
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let publish = Publish::new(false, QoSLevel::QoSLevel1, false, "topic", "msg", Some(7)).unwrap();
+    stream_1.write_all(&publish.encode().unwrap()).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    // El Puback no se lee hasta que el servidor cierra la conexion
+    connect_client(builder_2, port, true);
+    thread::sleep(Duration::from_millis(100));
+
+    stream_1.read_exact(&mut control).unwrap();
+    let puback = Puback::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(puback.packet_id(), 7);
+    assert_eq!(stream_1.read(&mut control).unwrap(), 0);
+}
+
 #[test]
 fn test_takeover_should_change_keep_alive() {
     let (_s, port) = start_server(None, None);
@@ -219,3 +251,743 @@ fn test_session_present_dump() {
     let connack = Connack::read_from(&mut stream, control[0]).unwrap();
     assert!(connack.session_present());
 }
+
+#[test]
+fn test_bind_all_interfaces() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bind_address("0.0.0.0"))
+            .unwrap();
+    let local_addr = controller.local_addr();
+    assert!(local_addr.ip().is_unspecified());
+    assert_ne!(local_addr.port(), 0);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", local_addr.port())).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+    let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+    stream.write_all(&connect.encode().unwrap()).unwrap();
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 2);
+    assert!(Connack::read_from(&mut stream, control[0]).is_ok());
+}
+
+#[test]
+fn test_run_on_pre_bound_listener() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // El puerto de la configuracion se ignora
+    let server = Server::new(ConfigMock::new(1, None, None), 20).unwrap();
+    let controller = server.run_on(listener).unwrap();
+    assert_eq!(controller.local_addr(), addr);
+
+    let mut stream = connect_client(
+        ConnectBuilder::new("id", 0, true).unwrap(),
+        addr.port(),
+        false,
+    );
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(connack.return_code(), ConnackReturnCode::Accepted);
+}
+
+#[test]
+fn test_bind_ipv6_literal() {
+    // Puede que el entorno no soporte IPv6
+    if std::net::TcpListener::bind("[::1]:0").is_err() {
+        return;
+    }
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bind_address("::1")).unwrap();
+    let local_addr = controller.local_addr();
+    assert!(local_addr.is_ipv6());
+
+    assert!(TcpStream::connect(("::1", local_addr.port())).is_ok());
+}
+
+#[test]
+fn test_bind_invalid_address_should_be_error() {
+    let result = start_server_with_config(
+        ConfigMock::new(0, None, None).with_bind_address("direccion invalida"),
+    );
+
+    assert_eq!(result.err().unwrap().kind(), ServerErrorKind::InvalidConfig);
+}
+
+#[test]
+fn test_bind_dual_stack() {
+    if std::net::TcpListener::bind("[::1]:0").is_err() {
+        return;
+    }
+    let controller = start_server_with_config(
+        ConfigMock::new(0, None, None)
+            .with_bind_address("::")
+            .with_dual_stack(true),
+    )
+    .unwrap();
+    let port = controller.local_addr().port();
+
+    for address in ["127.0.0.1", "::1"] {
+        let mut stream = TcpStream::connect((address, port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+        let connect = ConnectBuilder::new(address, 0, true)
+            .unwrap()
+            .build()
+            .unwrap();
+        stream.write_all(&connect.encode().unwrap()).unwrap();
+
+        let mut control = [0u8];
+        stream.read_exact(&mut control).unwrap();
+        assert_eq!(control[0] >> 4, 2);
+        assert!(Connack::read_from(&mut stream, control[0]).is_ok());
+    }
+}
+
+#[test]
+fn test_bind_ipv6_only_rejects_ipv4() {
+    if std::net::TcpListener::bind("[::1]:0").is_err() {
+        return;
+    }
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_bind_address("::")).unwrap();
+    let port = controller.local_addr().port();
+
+    assert!(TcpStream::connect(("::1", port)).is_ok());
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+}
+
+#[test]
+fn test_periodic_dump() {
+    let path = "tests/files/dumps/dump4.json";
+    let _ = fs::remove_file(path);
+    let controller = start_server_with_config(ConfigMock::new(
+        0,
+        Some((path, Duration::from_millis(200))),
+        None,
+    ))
+    .unwrap();
+    let port = controller.local_addr().port();
+
+    // Sin apagar el servidor, el dump deberia aparecer
+    thread::sleep(Duration::from_millis(600));
+    let first_dump = fs::read_to_string(path).unwrap();
+    assert!(!first_dump.contains("periodic_dump_id"));
+
+    // Y actualizarse con el nuevo estado
+    let builder = ConnectBuilder::new("periodic_dump_id", 0, false).unwrap();
+    let _stream = connect_client(builder, port, true);
+    thread::sleep(Duration::from_millis(600));
+    let second_dump = fs::read_to_string(path).unwrap();
+    assert!(second_dump.contains("periodic_dump_id"));
+}
+
+#[test]
+fn test_corrupt_dump_starts_fresh() {
+    let path = "tests/files/dumps/dump5.json";
+    fs::create_dir_all("tests/files/dumps").unwrap();
+    fs::write(path, "{\"topic_handler\": {\"subscri").unwrap();
+
+    let controller = start_server_with_config(ConfigMock::new(
+        0,
+        Some((path, Duration::from_secs(10))),
+        None,
+    ))
+    .unwrap();
+    let port = controller.local_addr().port();
+
+    let builder = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream = connect_client(builder, port, false);
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert!(!connack.session_present());
+
+    // Al apagarse, se reemplaza el DUMP corrupto sin dejar el temporal
+    drop(controller);
+    thread::sleep(Duration::from_millis(50));
+    let dump = fs::read_to_string(path).unwrap();
+    assert!(serde_json::from_str::<serde_json::Value>(&dump).is_ok());
+    assert!(fs::metadata(format!("{}.tmp", path)).is_err());
+}
+
+#[test]
+fn test_compressed_dump_round_trip() {
+    let path = "tests/files/dumps/dump6.json.gz";
+    let _ = fs::remove_file(path);
+    let config = ConfigMock::new(0, Some((path, Duration::from_secs(10))), None);
+    let controller = start_server_with_config(config.clone()).unwrap();
+    let builder = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream = connect_client(builder, controller.local_addr().port(), true);
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+
+    // Apago server: deberia dumpear comprimido
+    drop(controller);
+    thread::sleep(Duration::from_millis(50));
+    let dump = fs::read(path).unwrap();
+    assert_eq!(dump[..2], [0x1f, 0x8b]);
+
+    let controller = start_server_with_config(config).unwrap();
+    let builder = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream = connect_client(builder, controller.local_addr().port(), false);
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert!(connack.session_present());
+}
+
+#[test]
+fn test_incomplete_packet_should_disconnect() {
+    let controller = start_server_with_config(
+        ConfigMock::new(0, None, None).with_packet_read_timeout(Duration::from_millis(300)),
+    )
+    .unwrap();
+    let port = controller.local_addr().port();
+    let connect_builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(connect_builder, port, true);
+
+    // Header de un Publish con remaining length 10, pero solo
+    // se envian 2 bytes del resto del paquete
+    stream.write_all(&[0x30, 10, 0, 1]).unwrap();
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+    let mut buf = [0u8];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn test_disconnect_all_closes_every_connection() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+
+    let mut streams: Vec<TcpStream> = ["a", "b", "c"]
+        .iter()
+        .map(|id| connect_client(ConnectBuilder::new(id, 0, true).unwrap(), port, true))
+        .collect();
+    thread::sleep(Duration::from_millis(100));
+
+    server.disconnect_all("mantenimiento").unwrap();
+
+    for stream in streams.iter_mut() {
+        let mut control = [0u8];
+        stream.read_exact(&mut control).unwrap();
+        assert!(Disconnect::read_from(stream, control[0]).is_ok());
+        assert_eq!(stream.read(&mut control).unwrap(), 0);
+    }
+}
+
+#[test]
+fn test_trickling_client_should_be_disconnected() {
+    let (_s, port) = start_server(None, None);
+    let connect_builder = ConnectBuilder::new("id", 2, true).unwrap();
+    let mut stream = connect_client(connect_builder, port, true);
+
+    // Se envia un Publish de a un byte por segundo, de forma que
+    // las lecturas del servidor nunca se quedan sin datos
+    let mut writer = stream.try_clone().unwrap();
+    thread::spawn(move || {
+        for byte in [0x30, 100].iter().chain([b'a'; 100].iter()) {
+            if writer.write_all(&[*byte]).is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+
+    let start = std::time::Instant::now();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(6)))
+        .unwrap();
+    let mut buf = [0u8];
+    match stream.read(&mut buf) {
+        Ok(read) => assert_eq!(read, 0),
+        Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+    }
+    // Keep Alive de 2 segundos, el servidor lo desconecta a los 3
+    assert!(start.elapsed() < Duration::from_millis(4500));
+}
+
+#[test]
+fn test_first_packet_not_connect_should_close_connection() {
+    let (_s, port) = start_server(None, None);
+    let mut stream = TcpStream::connect(format!("localhost:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let topic = TopicFilter::new("topic", QoSLevel::QoSLevel0).unwrap();
+    let subscribe = Subscribe::new(vec![topic], 1);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+
+    // No se envia Connack, solamente se cierra la conexion
+    let mut buf = [0u8];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn test_first_packet_connect_should_be_accepted() {
+    let (_s, port) = start_server(None, None);
+    let mut stream = TcpStream::connect(format!("localhost:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+    stream.write_all(&connect.encode().unwrap()).unwrap();
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(connack.return_code(), ConnackReturnCode::Accepted);
+}
+
+#[test]
+fn test_subscribe_pipelined_with_connect_should_be_honored() {
+    let (_s, port) = start_server(None, None);
+    let mut stream = TcpStream::connect(format!("localhost:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    // El Subscribe se envia en la misma escritura, sin esperar el Connack
+    let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+    let subscribe = Subscribe::new(
+        vec![TopicFilter::new("topic", QoSLevel::QoSLevel0).unwrap()],
+        7,
+    );
+    let mut bytes = connect.encode().unwrap();
+    bytes.append(&mut subscribe.encode().unwrap());
+    stream.write_all(&bytes).unwrap();
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(connack.return_code(), ConnackReturnCode::Accepted);
+    stream.read_exact(&mut control).unwrap();
+    let suback = Suback::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(suback.packet_id(), 7);
+
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    let publish = Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "msg", None).unwrap();
+    publisher.write_all(&publish.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    let received = Publish::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(received.payload(), "msg");
+}
+
+#[derive(Debug, PartialEq)]
+enum ConnectionEvent {
+    Connected(String, SocketAddr),
+    Disconnected(String, DisconnectReason),
+}
+
+#[derive(Clone, Default)]
+struct RecordingListener {
+    events: Arc<Mutex<Vec<ConnectionEvent>>>,
+}
+
+impl ConnectionListener for RecordingListener {
+    fn on_connect(&self, id: &str, addr: SocketAddr) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(ConnectionEvent::Connected(id.to_string(), addr));
+    }
+
+    fn on_disconnect(&self, id: &str, reason: DisconnectReason) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(ConnectionEvent::Disconnected(id.to_string(), reason));
+    }
+}
+
+#[test]
+fn test_connection_listener_is_notified() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let listener = RecordingListener::default();
+    server
+        .add_connection_listener(Box::new(listener.clone()))
+        .unwrap();
+    let controller = server.run().unwrap();
+    let port = controller.local_addr().port();
+
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+    let addr = stream.local_addr().unwrap();
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+
+    let lost = connect_client(ConnectBuilder::new("lost", 0, true).unwrap(), port, true);
+    let lost_addr = lost.local_addr().unwrap();
+    drop(lost);
+    thread::sleep(Duration::from_millis(500));
+
+    assert_eq!(
+        *listener.events.lock().unwrap(),
+        vec![
+            ConnectionEvent::Connected("id".to_string(), addr),
+            ConnectionEvent::Disconnected("id".to_string(), DisconnectReason::Gracefully),
+            ConnectionEvent::Connected("lost".to_string(), lost_addr),
+            ConnectionEvent::Disconnected("lost".to_string(), DisconnectReason::ConnectionLost),
+        ]
+    );
+}
+
+#[test]
+fn test_protocol_violation_is_notified() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let listener = RecordingListener::default();
+    server
+        .add_connection_listener(Box::new(listener.clone()))
+        .unwrap();
+    let controller = server.run().unwrap();
+    let port = controller.local_addr().port();
+
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+    // Un cliente no puede enviar un Connack
+    stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+    let mut control = [0u8];
+    stream
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    assert_eq!(stream.read(&mut control).unwrap(), 0);
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(
+        listener.events.lock().unwrap().last(),
+        Some(&ConnectionEvent::Disconnected(
+            "id".to_string(),
+            DisconnectReason::ProtocolViolation
+        ))
+    );
+}
+
+#[test]
+fn test_publish_with_topic_longer_than_the_packet_is_a_protocol_violation() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let listener = RecordingListener::default();
+    server
+        .add_connection_listener(Box::new(listener.clone()))
+        .unwrap();
+    let controller = server.run().unwrap();
+    let port = controller.local_addr().port();
+
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+    // El largo del topic (10) excede el Remaining Length (4)
+    stream.write_all(&[0x30, 4, 0, 10, b'a', b'b']).unwrap();
+    let mut control = [0u8];
+    stream
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    assert_eq!(stream.read(&mut control).unwrap(), 0);
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(
+        listener.events.lock().unwrap().last(),
+        Some(&ConnectionEvent::Disconnected(
+            "id".to_string(),
+            DisconnectReason::ProtocolViolation
+        ))
+    );
+}
+
+#[test]
+fn test_keep_alive_0_should_not_disconnect_idle_client() {
+    let (_s, port) = start_server(None, None);
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    thread::sleep(Duration::from_secs(3));
+
+    stream.write_all(&PingReq::new().encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    assert!(PingResp::read_from(&mut stream, control[0]).is_ok());
+}
+
+#[test]
+fn test_connect_past_max_clients_should_be_refused() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_clients(1)).unwrap();
+    let port = controller.local_addr().port();
+    let _first = connect_client(ConnectBuilder::new("a", 0, true).unwrap(), port, true);
+
+    let mut stream = connect_client(ConnectBuilder::new("b", 0, true).unwrap(), port, false);
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]);
+    assert_eq!(connack.unwrap_err().kind(), ErrorKind::ServerUnavailable);
+    assert_eq!(stream.read(&mut control).unwrap(), 0);
+
+    // Un takeover no cuenta como un cliente nuevo
+    let mut stream = connect_client(ConnectBuilder::new("a", 0, true).unwrap(), port, false);
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(connack.return_code(), ConnackReturnCode::Accepted);
+}
+
+#[test]
+fn test_connections_past_max_client_threads_wait_in_backlog() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_client_threads(1))
+            .unwrap();
+    let port = controller.local_addr().port();
+    let mut first = connect_client(ConnectBuilder::new("a", 0, true).unwrap(), port, true);
+
+    // El segundo no es atendido mientras el primero siga conectado
+    let mut second = connect_client(ConnectBuilder::new("b", 0, true).unwrap(), port, false);
+    let mut control = [0u8];
+    second
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    assert!(second.read_exact(&mut control).is_err());
+
+    first
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    assert_eq!(first.read(&mut control).unwrap(), 0);
+    second
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    second.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut second, control[0]).unwrap();
+    assert_eq!(connack.return_code(), ConnackReturnCode::Accepted);
+}
+
+#[test]
+fn test_connect_with_large_listen_backlog() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_listen_backlog(65536))
+            .unwrap();
+    let port = controller.local_addr().port();
+
+    let streams: Vec<TcpStream> = (0..10)
+        .map(|i| {
+            let builder = ConnectBuilder::new(&format!("id{}", i), 0, true).unwrap();
+            connect_client(builder, port, true)
+        })
+        .collect();
+    assert_eq!(streams.len(), 10);
+}
+
+#[test]
+fn test_connection_without_connect_should_be_closed_after_timeout() {
+    let controller = start_server_with_config(
+        ConfigMock::new(0, None, None).with_connect_timeout(Duration::from_millis(500)),
+    )
+    .unwrap();
+    let port = controller.local_addr().port();
+    let mut stream = TcpStream::connect(format!("localhost:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let start = Instant::now();
+    let mut buf = [0u8];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn test_connections_past_max_pending_should_wait_in_backlog() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_pending_connections(1))
+            .unwrap();
+    let port = controller.local_addr().port();
+    let silent = TcpStream::connect(format!("localhost:{}", port)).unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    let mut stream = TcpStream::connect(format!("localhost:{}", port)).unwrap();
+    let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+    stream.write_all(&connect.encode().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let mut control = [0u8];
+    assert!(stream.read_exact(&mut control).is_err());
+
+    // Al cerrarse la conexion pendiente, se acepta la siguiente
+    drop(silent);
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(connack.return_code(), ConnackReturnCode::Accepted);
+}
+
+#[test]
+fn test_connect_during_shutdown_should_be_refused() {
+    let (controller, port) = start_server(None, None);
+    let mut stream = TcpStream::connect(format!("localhost:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    // El apagado espera a que termine el thread de la conexion,
+    // que todavia no envio el CONNECT
+    let shutdown = thread::spawn(move || drop(controller));
+    thread::sleep(Duration::from_millis(500));
+
+    let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+    stream.write_all(&connect.encode().unwrap()).unwrap();
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]);
+    assert_eq!(connack.unwrap_err().kind(), ErrorKind::ServerUnavailable);
+    shutdown.join().unwrap();
+}
+
+#[test]
+fn test_connected_clients_snapshot() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let mut stream_a = connect_client(ConnectBuilder::new("a", 30, true).unwrap(), port, true);
+    let subscribe = Subscribe::new(
+        vec![
+            TopicFilter::new("x", QoSLevel::QoSLevel0).unwrap(),
+            TopicFilter::new("y/#", QoSLevel::QoSLevel1).unwrap(),
+        ],
+        1,
+    );
+    stream_a.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_a.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream_a, control[0]).unwrap();
+    let stream_b = connect_client(ConnectBuilder::new("b", 0, false).unwrap(), port, true);
+    // Una sesion persistente desconectada no deberia aparecer
+    let stream_c = connect_client(ConnectBuilder::new("c", 0, false).unwrap(), port, true);
+    drop(stream_c);
+    thread::sleep(Duration::from_millis(200));
+
+    let clients = server.connected_clients().unwrap();
+    assert_eq!(clients.len(), 2);
+    assert_eq!(clients[0].id, "a");
+    assert_eq!(clients[0].address, stream_a.local_addr().unwrap());
+    assert_eq!(clients[0].keep_alive, 30);
+    assert!(clients[0].clean_session);
+    assert_eq!(clients[0].subscriptions, 2);
+    assert_eq!(clients[1].id, "b");
+    assert_eq!(clients[1].address, stream_b.local_addr().unwrap());
+    assert_eq!(clients[1].keep_alive, 0);
+    assert!(!clients[1].clean_session);
+    assert_eq!(clients[1].subscriptions, 0);
+}
+
+#[test]
+fn test_keep_alive_is_clamped_to_max_keep_alive() {
+    let server = Server::new(ConfigMock::new(0, None, None).with_max_keep_alive(60), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+
+    let _stream = connect_client(ConnectBuilder::new("id", 300, true).unwrap(), port, true);
+    thread::sleep(Duration::from_millis(100));
+
+    let clients = server.connected_clients().unwrap();
+    assert_eq!(clients[0].keep_alive, 60);
+}
+
+#[test]
+fn test_clamped_keep_alive_should_disconnect() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_keep_alive(1)).unwrap();
+    let port = controller.local_addr().port();
+    let mut stream = connect_client(ConnectBuilder::new("id", 300, true).unwrap(), port, true);
+
+    let mut control = [0u8];
+    // Se usa el Keep Alive de 1 segundo, no el pedido por el cliente
+    thread::sleep(Duration::from_millis(1600));
+    assert_eq!(stream.read(&mut control).unwrap(), 0);
+}
+
+// CONNECT de MQTT 3.1, con clean session y client id "id"
+const MQTT_31_CONNECT: [u8; 18] = [
+    0x10, 16, 0, 6, b'M', b'Q', b'I', b's', b'd', b'p', 3, 0x02, 0, 0, 0, 2, b'i', b'd',
+];
+
+#[test]
+fn test_mqtt_31_client_accepted_when_allowed() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_allow_mqtt_31(true)).unwrap();
+    let mut stream = TcpStream::connect(controller.local_addr()).unwrap();
+    stream.write_all(&MQTT_31_CONNECT).unwrap();
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert!(!connack.session_present());
+}
+
+#[test]
+fn test_mqtt_31_client_refused_by_default() {
+    let controller = start_server_with_config(ConfigMock::new(0, None, None)).unwrap();
+    let mut stream = TcpStream::connect(controller.local_addr()).unwrap();
+    stream.write_all(&MQTT_31_CONNECT).unwrap();
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let err = Connack::read_from(&mut stream, control[0]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnacceptableProtocolVersion);
+}
+
+#[test]
+fn test_drain_refuses_new_clients_and_waits_for_connected_ones() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let mut stream = connect_client(ConnectBuilder::new("a", 0, true).unwrap(), port, true);
+    server.drain(Duration::from_secs(10));
+
+    let mut new_stream = connect_client(ConnectBuilder::new("b", 0, true).unwrap(), port, false);
+    new_stream.read_exact(&mut control).unwrap();
+    let err = Connack::read_from(&mut new_stream, control[0]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ServerUnavailable);
+
+    // El cliente que ya estaba conectado sigue funcionando
+    stream.write_all(&PingReq::new().encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    PingResp::read_from(&mut stream, control[0]).unwrap();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        controller.wait();
+        sender.send(()).unwrap();
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+}
+
+#[test]
+fn test_drain_disconnects_remaining_clients_after_timeout() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let mut stream = connect_client(ConnectBuilder::new("a", 0, true).unwrap(), port, true);
+    let start = Instant::now();
+    server.drain(Duration::from_millis(300));
+    controller.wait();
+
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert_eq!(stream.read(&mut control).unwrap(), 0);
+}
@@ -0,0 +1,83 @@
+mod common;
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use logger::{format_layer, LogFormat};
+use packets::{connect::ConnectBuilder, disconnect::Disconnect, traits::MQTTEncoding};
+use serde_json::Value;
+use tracing_subscriber::prelude::*;
+
+use crate::common::*;
+
+/// Writer that keeps everything written to it in memory
+#[derive(Clone, Default)]
+struct SharedBuffer {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SharedBuffer {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.bytes.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_json_log_lines_include_client_id() {
+    let buffer = SharedBuffer::default();
+    let writer = buffer.clone();
+    tracing_subscriber::registry()
+        .with(format_layer(LogFormat::Json, move || writer.clone()))
+        .init();
+    let (_s, port) = start_server(None, None);
+
+    let mut stream = connect_client(
+        ConnectBuilder::new("json_client", 0, true).unwrap(),
+        port,
+        true,
+    );
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let lines: Vec<Value> = buffer
+        .lines()
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(!lines.is_empty());
+    for line in &lines {
+        assert!(line["timestamp"].is_string());
+        assert!(line["level"].is_string());
+        assert!(line["fields"].is_object());
+    }
+    let client_lines: Vec<&Value> = lines
+        .iter()
+        .filter(|line| line["span"]["client_id"] == "json_client")
+        .collect();
+    assert!(!client_lines.is_empty());
+    for line in client_lines {
+        let spans = line["spans"].as_array().unwrap();
+        assert!(spans.iter().any(|span| span["name"] == "client"));
+        assert!(line["threadName"].as_str().unwrap().starts_with("client "));
+    }
+}
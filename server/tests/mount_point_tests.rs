@@ -0,0 +1,262 @@
+mod common;
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use packets::{
+    connect::{ConnectBuilder, LastWill},
+    publish::Publish,
+    qos::QoSLevel::*,
+    suback::Suback,
+    subscribe::Subscribe,
+    topic_filter::TopicFilter,
+    traits::{MQTTDecoding, MQTTEncoding},
+};
+use serde_json::Value;
+use server::{Server, ServerController, ServerErrorKind};
+
+use crate::common::*;
+
+/// Starts a server with a listener for each mount point, returning
+/// the port of each of them
+fn start_tenants_server(mount_points: &[Option<&str>]) -> (ServerController, Vec<u16>) {
+    start_tenants_server_with_config(ConfigMock::new(0, None, None), mount_points)
+}
+
+fn start_tenants_server_with_config(
+    config: ConfigMock,
+    mount_points: &[Option<&str>],
+) -> (ServerController, Vec<u16>) {
+    let server = Server::new(config, 20).unwrap();
+    let listeners = mount_points
+        .iter()
+        .map(|mount_point| {
+            let listener = TcpListener::bind("localhost:0").unwrap();
+            (listener, mount_point.map(str::to_string))
+        })
+        .collect();
+    let controller = server.run_on_listeners(listeners).unwrap();
+    let ports = controller
+        .local_addrs()
+        .iter()
+        .map(|addr| addr.port())
+        .collect();
+    (controller, ports)
+}
+
+fn connect(id: &str, port: u16) -> TcpStream {
+    connect_client(ConnectBuilder::new(id, 0, true).unwrap(), port, true)
+}
+
+fn subscribe(stream: &mut TcpStream, filter: &str) -> Suback {
+    let subscribe = Subscribe::new(tpc![(filter, QoSLevel0)], 1);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    Suback::read_from(stream, control[0]).unwrap()
+}
+
+fn publish(stream: &mut TcpStream, topic: &str, payload: &str, retain: bool) {
+    let publish = Publish::new(false, QoSLevel0, retain, topic, payload, None).unwrap();
+    stream.write_all(&publish.encode().unwrap()).unwrap();
+}
+
+fn read_publish(stream: &mut TcpStream) -> Publish {
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    Publish::read_from(stream, control[0]).unwrap()
+}
+
+fn assert_nothing_received(stream: &mut TcpStream) {
+    stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let mut control = [0u8];
+    assert!(stream.read_exact(&mut control).is_err());
+}
+
+#[test]
+fn test_tenants_do_not_receive_each_others_publications() {
+    let (_s, ports) = start_tenants_server(&[Some("tenantA"), Some("tenantB")]);
+    let mut publisher_a = connect("publisher-a", ports[0]);
+    let mut subscriber_a = connect("subscriber-a", ports[0]);
+    let mut subscriber_b = connect("subscriber-b", ports[1]);
+    subscribe(&mut subscriber_a, "data");
+    subscribe(&mut subscriber_b, "data");
+
+    publish(&mut publisher_a, "data", "21.5", false);
+
+    // El topic llega sin el mount point
+    let received = read_publish(&mut subscriber_a);
+    assert_eq!(received.topic_name(), "data");
+    assert_eq!(received.payload(), "21.5");
+    assert_nothing_received(&mut subscriber_b);
+}
+
+#[test]
+fn test_tenant_wildcards_do_not_reach_other_tenants() {
+    let (_s, ports) = start_tenants_server(&[Some("tenantA"), Some("tenantB")]);
+    let mut publisher_b = connect("publisher-b", ports[1]);
+    let mut subscriber_a = connect("subscriber-a", ports[0]);
+    subscribe(&mut subscriber_a, "#");
+    subscribe(&mut subscriber_a, "+/tenantB/#");
+
+    publish(&mut publisher_b, "data", "B", true);
+    assert_nothing_received(&mut subscriber_a);
+
+    // Tampoco recibe el retained message del otro tenant
+    let mut late_subscriber_a = connect("late-subscriber-a", ports[0]);
+    subscribe(&mut late_subscriber_a, "data");
+    assert_nothing_received(&mut late_subscriber_a);
+}
+
+#[test]
+fn test_publications_land_inside_the_mount_point() {
+    let (_s, ports) = start_tenants_server(&[Some("tenantA"), None]);
+    let mut publisher_a = connect("publisher-a", ports[0]);
+    let mut root_subscriber = connect("root-subscriber", ports[1]);
+    subscribe(&mut root_subscriber, "tenantA/data");
+
+    publish(&mut publisher_a, "data", "21.5", false);
+
+    let received = read_publish(&mut root_subscriber);
+    assert_eq!(received.topic_name(), "tenantA/data");
+}
+
+#[test]
+fn test_mount_point_of_the_config() {
+    let config = ConfigMock::new(0, None, None).with_mount_point("tenantA");
+    let controller = start_server_with_config(config).unwrap();
+    let port = controller.local_addr().port();
+    let mut subscriber = connect("subscriber", port);
+    subscribe(&mut subscriber, "#");
+
+    let last_will = LastWill::new(
+        TopicFilter::new("status", QoSLevel0).unwrap(),
+        "offline".to_string(),
+        false,
+    );
+    let builder = ConnectBuilder::new("publisher", 0, true)
+        .unwrap()
+        .with_last_will(last_will);
+    let publisher = connect_client(builder, port, true);
+    drop(publisher);
+
+    // El Last Will tambien se publica dentro del mount point
+    let received = read_publish(&mut subscriber);
+    assert_eq!(received.topic_name(), "status");
+    assert_eq!(received.payload(), "offline");
+}
+
+#[test]
+fn test_tenants_can_use_the_same_client_id() {
+    let (_s, ports) = start_tenants_server(&[Some("tenantA"), Some("tenantB")]);
+    let builder = ConnectBuilder::new("sensor", 0, false).unwrap();
+    let mut sensor_a = connect_client(builder, ports[0], true);
+    subscribe(&mut sensor_a, "data");
+
+    // No toma la sesion del cliente de tenantA, ni sus suscripciones
+    let builder = ConnectBuilder::new("sensor", 0, false).unwrap();
+    let mut sensor_b = connect_client(builder, ports[1], true);
+
+    let mut publisher_a = connect("publisher", ports[0]);
+    publish(&mut publisher_a, "data", "A", false);
+
+    let received = read_publish(&mut sensor_a);
+    assert_eq!(received.topic_name(), "data");
+    assert_eq!(received.payload(), "A");
+    assert_nothing_received(&mut sensor_b);
+}
+
+#[test]
+fn test_unmounted_clients_cannot_take_over_mounted_sessions() {
+    let (_s, ports) = start_tenants_server(&[Some("tenantA"), None]);
+    let builder = ConnectBuilder::new("sensor", 0, false).unwrap();
+    let mut sensor = connect_client(builder, ports[0], true);
+    subscribe(&mut sensor, "data");
+
+    // El id coincide con el del mount point y el cliente, pero es
+    // otra sesion
+    let builder = ConnectBuilder::new("tenantA/sensor", 0, false).unwrap();
+    let mut intruder = connect_client(builder, ports[1], true);
+
+    let mut publisher = connect("publisher", ports[0]);
+    publish(&mut publisher, "data", "A", false);
+
+    let received = read_publish(&mut sensor);
+    assert_eq!(received.payload(), "A");
+    assert_nothing_received(&mut intruder);
+}
+
+/// Publishes an admin command and returns the payload of its response
+fn admin_command(admin: &mut TcpStream, command: &str, payload: &str) -> Value {
+    subscribe(admin, &format!("$admin/{}/response/+", command));
+    publish(admin, &format!("$admin/{}", command), payload, false);
+    let response = read_publish(admin);
+    assert_eq!(
+        response.topic_name(),
+        format!("$admin/{}/response/1", command)
+    );
+    serde_json::from_str(response.payload()).unwrap()
+}
+
+#[test]
+fn test_admin_commands_are_scoped_to_the_mount_point() {
+    let config = ConfigMock::new(0, None, usr![("root", "toor")]).with_admins(&["root"]);
+    let (_s, ports) = start_tenants_server_with_config(config, &[Some("tenantA"), Some("tenantB")]);
+    let login = |id: &str, port: u16| {
+        let builder = ConnectBuilder::new(id, 0, true)
+            .unwrap()
+            .with_user_name("root")
+            .unwrap()
+            .with_password("toor")
+            .unwrap();
+        connect_client(builder, port, true)
+    };
+    let mut admin_a = login("admin", ports[0]);
+    let mut client_b = login("client-b", ports[1]);
+
+    let clients = admin_command(&mut admin_a, "list_clients", r#"{"request_id": "1"}"#);
+    let ids: Vec<&str> = clients
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|client| client["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, vec!["admin"]);
+
+    let response = admin_command(
+        &mut admin_a,
+        "disconnect",
+        r#"{"request_id": "1", "client_id": "client-b"}"#,
+    );
+    assert_eq!(response["disconnected"], false);
+    assert_nothing_received(&mut client_b);
+}
+
+#[test]
+fn test_invalid_mount_point() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let err = server
+        .run_on_listeners(vec![(listener, Some("tenant/#".to_string()))])
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ServerErrorKind::InvalidConfig);
+}
+
+#[test]
+fn test_nested_mount_points_are_invalid() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let listeners = ["tenants", "tenants/a"]
+        .iter()
+        .map(|mount_point| {
+            let listener = TcpListener::bind("localhost:0").unwrap();
+            (listener, Some(mount_point.to_string()))
+        })
+        .collect();
+    let err = server.run_on_listeners(listeners).err().unwrap();
+    assert_eq!(err.kind(), ServerErrorKind::InvalidConfig);
+}
@@ -2,23 +2,33 @@ mod common;
 use std::{
     fs,
     io::{Read, Write},
+    panic,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use packets::{
     connect::{ConnectBuilder, LastWill},
     disconnect::Disconnect,
+    pingreq::PingReq,
+    pingresp::PingResp,
     puback::Puback,
     publish::Publish,
-    qos::QoSLevel::*,
+    qos::{QoSLevel, QoSLevel::*},
     suback::Suback,
     subscribe::Subscribe,
     topic_filter::TopicFilter,
     traits::{MQTTDecoding, MQTTEncoding},
+    unsuback::Unsuback,
+    unsubscribe::Unsubscribe,
 };
 
 use crate::common::*;
+use server::{traits::SubscriptionListener, Server, ServerController, SubscriptionSummary};
 
 #[test]
 fn test_subscription_qos0() {
@@ -81,6 +91,8 @@ fn test_subscription_qos1() {
     // Recibo publish
     stream.read_exact(&mut control).unwrap();
     let recv_publish = Publish::read_from(&mut stream, control[0]).unwrap();
+    // El servidor asigna su propio packet id
+    let publish = publish.with_packet_id(recv_publish.packet_id().unwrap());
     assert_eq!(recv_publish.encode().unwrap(), publish.encode().unwrap());
 }
 
@@ -129,6 +141,75 @@ fn test_subscription_lowers_qos() {
     }
 }
 
+#[test]
+fn test_publish_is_downgraded_per_subscriber() {
+    let (_s, port) = start_server(None, None);
+    let mut stream_qos0 = connect_client(ConnectBuilder::new("id0", 0, true).unwrap(), port, true);
+    let mut stream_qos1 = connect_client(ConnectBuilder::new("id1", 0, true).unwrap(), port, true);
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    for (stream, qos) in [(&mut stream_qos0, QoSLevel0), (&mut stream_qos1, QoSLevel1)] {
+        let subscribe = Subscribe::new(tpc![("topic", qos)], 123);
+        stream.write_all(&subscribe.encode().unwrap()).unwrap();
+        stream.read_exact(&mut control).unwrap();
+        assert!(Suback::read_from(stream, control[0]).is_ok());
+    }
+
+    let publish = Publish::new(false, QoSLevel1, false, "topic", "message", Some(10)).unwrap();
+    publisher.write_all(&publish.encode().unwrap()).unwrap();
+
+    // Se suscribio con QoS 0: recibe el mensaje con QoS 0 y sin packet id
+    stream_qos0.read_exact(&mut control).unwrap();
+    let recv_publish = Publish::read_from(&mut stream_qos0, control[0]).unwrap();
+    assert_eq!(recv_publish.qos(), QoSLevel0);
+    assert!(recv_publish.packet_id().is_none());
+    assert_eq!(recv_publish.payload(), "message");
+
+    // Se suscribio con QoS 1: recibe el mensaje con QoS 1
+    stream_qos1.read_exact(&mut control).unwrap();
+    let recv_publish = Publish::read_from(&mut stream_qos1, control[0]).unwrap();
+    assert_eq!(recv_publish.qos(), QoSLevel1);
+    assert!(recv_publish.packet_id().is_some());
+    assert_eq!(recv_publish.payload(), "message");
+}
+
+#[test]
+fn test_each_subscriber_gets_its_own_packet_id() {
+    let (_s, port) = start_server(None, None);
+    let mut stream_1 = connect_client(ConnectBuilder::new("id1", 0, true).unwrap(), port, true);
+    let mut stream_2 = connect_client(ConnectBuilder::new("id2", 0, true).unwrap(), port, true);
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 123);
+
+    // El primer suscriptor recibe un mensaje antes de que se
+    // suscriba el segundo, por lo que sus packet ids avanzan
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream_1, control[0]).unwrap();
+    let first = Publish::new(false, QoSLevel1, false, "topic", "first", Some(10)).unwrap();
+    publisher.write_all(&first.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    let recv_first = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    let puback = Puback::new(recv_first.packet_id().unwrap()).unwrap();
+    stream_1.write_all(&puback.encode().unwrap()).unwrap();
+
+    stream_2.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_2.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream_2, control[0]).unwrap();
+    let second = Publish::new(false, QoSLevel1, false, "topic", "second", Some(10)).unwrap();
+    publisher.write_all(&second.encode().unwrap()).unwrap();
+
+    stream_1.read_exact(&mut control).unwrap();
+    let recv_1 = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    stream_2.read_exact(&mut control).unwrap();
+    let recv_2 = Publish::read_from(&mut stream_2, control[0]).unwrap();
+    assert_eq!(recv_1.payload(), "second");
+    assert_eq!(recv_2.payload(), "second");
+    assert_ne!(recv_1.packet_id(), recv_2.packet_id());
+}
+
 #[test]
 fn test_subscription_different_clients() {
     let (_s, port) = start_server(None, None);
@@ -201,6 +282,7 @@ fn test_subscription_different_clients_persistent_session() {
     stream_1.read_exact(&mut control).unwrap();
     assert_eq!(control[0] >> 4, 3);
     let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    let publish = publish.with_packet_id(recv_publish.packet_id().unwrap());
     // ignoro el primer byte por si le ponen la dup flag
     assert_eq!(
         recv_publish.encode().unwrap()[1..],
@@ -289,6 +371,40 @@ fn test_gracefully_disconnection_should_not_send_last_will() {
     );
 }
 
+#[test]
+fn test_malformed_disconnect_should_send_last_will() {
+    let (_s, port) = start_server(None, None);
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 1);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    // Reserved bits distintos de 0 y remaining length distinta de 0
+    for (i, disconnect) in [[0b11100010, 0], [0b11100000, 1]].iter().enumerate() {
+        let builder = ConnectBuilder::new(&format!("id{}", i), 0, true)
+            .unwrap()
+            .with_last_will(LastWill::new(
+                TopicFilter::new("topic", QoSLevel0).unwrap(),
+                format!("will {}", i),
+                false,
+            ));
+        let mut stream = connect_client(builder, port, true);
+        stream.write_all(disconnect).unwrap();
+
+        // Es una violacion del protocolo: se cierra la conexion y se
+        // publica el last will
+        stream
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(stream.read(&mut control).unwrap(), 0);
+        subscriber.read_exact(&mut control).unwrap();
+        let publish = Publish::read_from(&mut subscriber, control[0]).unwrap();
+        assert_eq!(publish.payload(), format!("will {}", i));
+    }
+}
+
 #[test]
 fn test_takeover_should_change_clean_session() {
     let (_s, port) = start_server(None, None);
@@ -320,8 +436,10 @@ fn test_takeover_should_change_clean_session() {
     drop(stream_2);
 
     let mut stream_3 = connect_client(builder_3, port, true);
+    // Menos que 1.5 veces el Keep Alive, para que el servidor no
+    // cierre la conexion antes
     stream_3
-        .set_read_timeout(Some(Duration::from_millis(1500)))
+        .set_read_timeout(Some(Duration::from_millis(1000)))
         .unwrap();
     assert_eq!(
         stream_3.read_exact(&mut control).unwrap_err().kind(),
@@ -547,3 +665,1201 @@ fn test_subscription_dump_qos1() {
     assert_eq!(publish.topic_name(), "topic");
     assert_eq!(publish.qos(), QoSLevel1);
 }
+
+#[test]
+fn test_slow_subscriber_throttles_publisher() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_dispatch_queue_len(1))
+            .unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    // El suscriptor nunca lee lo que le llega
+    let builder = ConnectBuilder::new("slow", 60, true).unwrap();
+    let mut subscriber = connect_client(builder, port, true);
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    let builder = ConnectBuilder::new("fast", 0, true).unwrap();
+    let mut publisher = connect_client(builder, port, true);
+    publisher
+        .set_write_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let publish = Publish::new(
+        false,
+        QoSLevel0,
+        false,
+        "topic",
+        &"a".repeat(64 * 1024),
+        None,
+    )
+    .unwrap()
+    .encode()
+    .unwrap();
+
+    // Son 128MB, muchisimo mas de lo que entra en los buffers de los sockets
+    let throttled = (0..2000).any(|_| publisher.write_all(&publish).is_err());
+    assert!(throttled);
+
+    // Al desconectarse el suscriptor lento, el servidor debe seguir funcionando
+    drop(subscriber);
+    drop(publisher);
+    let builder = ConnectBuilder::new("other", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    stream.write_all(&PingReq::new().encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    PingResp::read_from(&mut stream, control[0]).unwrap();
+}
+
+// Conecta un cliente que se suscribe a "topic", y un cliente
+// con id "id1" y un last will en "topic"
+fn connect_with_last_will(port: u16) -> (std::net::TcpStream, std::net::TcpStream) {
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream_2.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_2.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    Suback::read_from(&mut stream_2, control[0]).unwrap();
+
+    let builder_1 = ConnectBuilder::new("id1", 0, false)
+        .unwrap()
+        .with_last_will(LastWill::new(
+            TopicFilter::new("topic", QoSLevel0).unwrap(),
+            "message".to_string(),
+            false,
+        ));
+    let stream_1 = connect_client(builder_1, port, true);
+    (stream_1, stream_2)
+}
+
+#[test]
+fn test_last_will_delay_reconnect_suppresses_will() {
+    let controller = start_server_with_config(
+        ConfigMock::new(0, None, None).with_will_delay(Duration::from_secs(1)),
+    )
+    .unwrap();
+    let port = controller.local_addr().port();
+    let (stream_1, mut stream_2) = connect_with_last_will(port);
+
+    // Me desconecto sin mandar disconnect y me reconecto antes del delay
+    drop(stream_1);
+    thread::sleep(Duration::from_millis(300));
+    let _stream_1 = connect_client(ConnectBuilder::new("id1", 0, false).unwrap(), port, true);
+
+    // El otro no deberia recibir el last will
+    stream_2
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let mut control = [0u8];
+    assert!(stream_2.read_exact(&mut control).is_err());
+}
+
+#[test]
+fn test_last_will_delay_without_reconnect() {
+    let controller = start_server_with_config(
+        ConfigMock::new(0, None, None).with_will_delay(Duration::from_secs(1)),
+    )
+    .unwrap();
+    let port = controller.local_addr().port();
+    let (stream_1, mut stream_2) = connect_with_last_will(port);
+
+    // Me desconecto sin mandar disconnect
+    drop(stream_1);
+
+    // Antes del delay no deberia llegar nada
+    stream_2
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let mut control = [0u8];
+    assert!(stream_2.read_exact(&mut control).is_err());
+
+    // Despues del delay llega el last will
+    stream_2
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream_2.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let publish = Publish::read_from(&mut stream_2, control[0]).unwrap();
+    assert_eq!(publish.topic_name(), "topic");
+    assert_eq!(publish.payload(), "message");
+}
+
+#[test]
+fn test_client_stats_count_bytes() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+
+    let connect_len = ConnectBuilder::new("id", 0, false)
+        .unwrap()
+        .build()
+        .unwrap()
+        .encode()
+        .unwrap()
+        .len();
+    let builder = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream = connect_client(builder, port, true);
+
+    let payload = "x".repeat(100);
+    let publish = Publish::new(false, QoSLevel0, false, "topic", &payload, None).unwrap();
+    let publish_bytes = publish.encode().unwrap();
+    for _ in 0..3 {
+        stream.write_all(&publish_bytes).unwrap();
+    }
+
+    // Espero el PingResp para asegurarme de que el servidor leyo todo
+    stream.write_all(&PingReq::new().encode().unwrap()).unwrap();
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    PingResp::read_from(&mut stream, control[0]).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let stats = server.client_stats().unwrap()["id"];
+    // Connect + 3 Publish + PingReq
+    assert_eq!(
+        stats.bytes_read,
+        (connect_len + 3 * publish_bytes.len() + 2) as u64
+    );
+    // Connack + PingResp
+    assert_eq!(stats.bytes_written, 4 + 2);
+
+    // Al reconectarse con clean session en true, los contadores se reinician
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let connect_len = ConnectBuilder::new("id", 0, true)
+        .unwrap()
+        .build()
+        .unwrap()
+        .encode()
+        .unwrap()
+        .len();
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let _stream = connect_client(builder, port, true);
+    thread::sleep(Duration::from_millis(100));
+
+    let stats = server.client_stats().unwrap()["id"];
+    assert_eq!(stats.bytes_read, connect_len as u64);
+    assert_eq!(stats.bytes_written, 4);
+}
+
+/// Delays the handling of every subscription, so that a packet
+/// handled out of order overtakes it
+struct SlowSubscriptions;
+
+impl SubscriptionListener for SlowSubscriptions {
+    fn on_subscribe(&self, _id: &str, _filter: &str, _qos: QoSLevel) {
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    fn on_unsubscribe(&self, _id: &str, _filter: &str) {
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Starts a server whose subscriptions are handled slowly
+fn start_slow_subscriptions_server() -> (Arc<Server<ConfigMock>>, ServerController, u16) {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    server
+        .add_subscription_listener(Box::new(SlowSubscriptions))
+        .unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    (server, controller, port)
+}
+
+#[test]
+fn test_packets_of_a_client_are_handled_in_order() {
+    let (_server, _s, port) = start_slow_subscriptions_server();
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+
+    // Se envia todo junto, sin esperar las respuestas
+    let mut bytes = vec![];
+    let publish = |payload: &str| {
+        Publish::new(false, QoSLevel0, false, "topic", payload, None)
+            .unwrap()
+            .encode()
+            .unwrap()
+    };
+    bytes.append(
+        &mut Subscribe::new(tpc![("topic", QoSLevel0)], 1)
+            .encode()
+            .unwrap(),
+    );
+    bytes.append(&mut publish("1"));
+    bytes.append(
+        &mut Unsubscribe::new(2, tpc![("topic", QoSLevel0)])
+            .unwrap()
+            .encode()
+            .unwrap(),
+    );
+    bytes.append(&mut publish("2"));
+    bytes.append(
+        &mut Subscribe::new(tpc![("topic", QoSLevel0)], 3)
+            .encode()
+            .unwrap(),
+    );
+    bytes.append(&mut publish("3"));
+    stream.write_all(&bytes).unwrap();
+
+    // Las publicaciones se despachan desde otro thread, por lo que
+    // pueden intercalarse con los acks de distinta forma
+    let mut acks = vec![];
+    let mut payloads = vec![];
+    let mut control = [0u8];
+    while acks.len() < 3 || payloads.len() < 2 {
+        stream.read_exact(&mut control).unwrap();
+        match control[0] >> 4 {
+            3 => payloads.push(
+                Publish::read_from(&mut stream, control[0])
+                    .unwrap()
+                    .payload()
+                    .to_string(),
+            ),
+            9 => acks.push(
+                Suback::read_from(&mut stream, control[0])
+                    .unwrap()
+                    .packet_id(),
+            ),
+            11 => acks.push(
+                Unsuback::read_from(&mut stream, control[0])
+                    .unwrap()
+                    .packet_id(),
+            ),
+            other => panic!("Paquete inesperado: {}", other),
+        }
+    }
+    assert_eq!(acks, vec![1, 2, 3]);
+    assert_eq!(payloads, vec!["1", "3"]);
+
+    stream
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    assert!(stream.read_exact(&mut control).is_err());
+}
+
+#[test]
+fn test_disconnect_waits_for_pending_packets() {
+    let (server, _s, port) = start_slow_subscriptions_server();
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+
+    let mut bytes = Subscribe::new(tpc![("topic", QoSLevel0)], 1)
+        .encode()
+        .unwrap();
+    bytes.append(&mut Disconnect::new().encode().unwrap());
+    stream.write_all(&bytes).unwrap();
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(stream.read(&mut control).unwrap(), 0);
+    // Con clean session, la suscripcion se elimina al desconectarse
+    thread::sleep(Duration::from_millis(100));
+    assert!(server.matching_subscribers("topic").unwrap().is_empty());
+}
+
+#[test]
+fn test_matching_subscribers_does_not_publish() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    let subscribe = Subscribe::new(tpc![("topic/+", QoSLevel1)], 123);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream, control[0]).unwrap();
+
+    assert_eq!(
+        server.matching_subscribers("topic/a").unwrap(),
+        vec![("id".to_owned(), QoSLevel1)]
+    );
+    assert!(server.matching_subscribers("topic").unwrap().is_empty());
+
+    stream
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    assert!(stream.read_exact(&mut control).is_err());
+}
+
+#[test]
+fn test_export_state_from_dump() {
+    let path = "tests/files/dumps/dump7.json";
+    let _ = fs::remove_file(path);
+    let config = ConfigMock::new(0, Some((path, Duration::from_secs(10))), None);
+    let server = Server::new(config, 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let builder = ConnectBuilder::new("sub", 0, false).unwrap();
+    let mut subscriber = connect_client(builder, port, true);
+    let subscribe = Subscribe::new(tpc![("a/+/c", QoSLevel1), ("a/#", QoSLevel0)], 123);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    let builder = ConnectBuilder::new("pub", 0, false).unwrap();
+    let mut publisher = connect_client(builder, port, true);
+    for topic in ["x/y", "z"] {
+        let publish = Publish::new(false, QoSLevel0, true, topic, "retained", None).unwrap();
+        publisher.write_all(&publish.encode().unwrap()).unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+    server.dump().unwrap();
+
+    let summary = Server::<ConfigMock>::export_state(&fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(summary.clients, vec!["pub".to_owned(), "sub".to_owned()]);
+    assert_eq!(summary.subscriptions.len(), 1);
+    assert_eq!(
+        summary.subscriptions["sub"],
+        vec![
+            SubscriptionSummary {
+                topic_filter: "a/#".to_owned(),
+                qos: QoSLevel0
+            },
+            SubscriptionSummary {
+                topic_filter: "a/+/c".to_owned(),
+                qos: QoSLevel1
+            },
+        ]
+    );
+    assert_eq!(summary.retained_messages, 2);
+}
+
+#[test]
+fn test_dump_lists_subscriptions_of_each_client() {
+    let path = "tests/files/dumps/dump8.json";
+    let _ = fs::remove_file(path);
+    let config = ConfigMock::new(0, Some((path, Duration::from_secs(10))), None);
+    let server = Server::new(config, 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let builder = ConnectBuilder::new("sub", 0, false).unwrap();
+    let mut subscriber = connect_client(builder, port, true);
+    let subscribe = Subscribe::new(tpc![("a/+/c", QoSLevel1), ("b", QoSLevel0)], 123);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+    server.dump().unwrap();
+
+    let dump: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    let filters: Vec<&str> = dump["subscriptions"]["sub"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|subscription| subscription["topic_filter"].as_str().unwrap())
+        .collect();
+    assert_eq!(filters, vec!["a/+/c", "b"]);
+    assert_eq!(
+        dump["subscriptions"]["sub"][0]["qos"],
+        serde_json::to_value(QoSLevel1).unwrap()
+    );
+}
+
+#[test]
+fn test_persistence_backend_round_trips_state() {
+    let backend = MemoryBackend::new();
+    let config = ConfigMock::new(0, None, None).with_memory_backend(backend.clone());
+    let server = Server::new(config.clone(), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let builder = ConnectBuilder::new("sub", 0, false).unwrap();
+    let mut subscriber = connect_client(builder, port, true);
+    let subscribe = Subscribe::new(tpc![("a/+/c", QoSLevel1)], 123);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+    let publish = Publish::new(false, QoSLevel0, true, "x/y", "retained", None).unwrap();
+    subscriber.write_all(&publish.encode().unwrap()).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    server.dump().unwrap();
+    let saved = backend.state().unwrap();
+
+    // Un servidor nuevo se restaura a partir del estado guardado
+    // y, al volver a guardarlo, se obtiene el mismo estado
+    let restored = Server::new(config, 20).unwrap();
+    restored.dump().unwrap();
+    assert_eq!(backend.state().unwrap(), saved);
+
+    let summary = Server::<ConfigMock>::export_state(&saved.json().to_string()).unwrap();
+    assert_eq!(summary.clients, vec!["sub".to_owned()]);
+    assert_eq!(summary.subscriptions["sub"].len(), 1);
+    assert_eq!(summary.retained_messages, 1);
+}
+
+#[test]
+fn test_publish_reusing_packet_id_after_puback_is_accepted() {
+    let (_s, port) = start_server(None, None);
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 1);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream, control[0]).unwrap();
+
+    // Ambos Publish se envian juntos: el servidor confirma el primero
+    // antes de leer el segundo, por lo que el packet id ya esta libre
+    let mut bytes = vec![];
+    for payload in ["first", "second"] {
+        let publish = Publish::new(false, QoSLevel1, false, "topic", payload, Some(10)).unwrap();
+        bytes.append(&mut publish.encode().unwrap());
+    }
+    stream.write_all(&bytes).unwrap();
+
+    let mut pubacks = 0;
+    let mut payloads = vec![];
+    while pubacks < 2 || payloads.len() < 2 {
+        stream.read_exact(&mut control).unwrap();
+        if control[0] >> 4 == 4 {
+            let puback = Puback::read_from(&mut stream, control[0]).unwrap();
+            assert_eq!(puback.packet_id(), 10);
+            pubacks += 1;
+        } else {
+            let publish = Publish::read_from(&mut stream, control[0]).unwrap();
+            payloads.push(publish.payload().to_string());
+        }
+    }
+    assert_eq!(payloads, ["first", "second"]);
+}
+
+#[test]
+fn test_puback_with_unknown_packet_id_should_be_ignored() {
+    let (_s, port) = start_server(None, None);
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+
+    // Nunca se le envio un Publish con ese packet id
+    stream
+        .write_all(&Puback::new(123).unwrap().encode().unwrap())
+        .unwrap();
+
+    // La conexion sigue abierta
+    stream.write_all(&PingReq::new().encode().unwrap()).unwrap();
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    assert!(PingResp::read_from(&mut stream, control[0]).is_ok());
+}
+
+#[test]
+fn test_puback_with_unknown_packet_id_should_disconnect_if_strict() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_strict_protocol(true))
+            .unwrap();
+    let port = controller.local_addr().port();
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+
+    stream
+        .write_all(&Puback::new(123).unwrap().encode().unwrap())
+        .unwrap();
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+    let mut buf = [0u8];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn test_publish_topic_at_max_len_should_be_accepted() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_topic_len(10)).unwrap();
+    let port = controller.local_addr().port();
+    // 10 bytes, pero 8 caracteres
+    let topic = "ñña/bcde";
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    let subscribe = Subscribe::new(vec![TopicFilter::new(topic, QoSLevel0).unwrap()], 1);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    let mut control = [0u8];
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    let publish = Publish::new(false, QoSLevel0, false, topic, "msg", None).unwrap();
+    publisher.write_all(&publish.encode().unwrap()).unwrap();
+
+    subscriber.read_exact(&mut control).unwrap();
+    let received = Publish::read_from(&mut subscriber, control[0]).unwrap();
+    assert_eq!(received.topic_name(), topic);
+}
+
+#[test]
+fn test_publish_topic_over_max_len_should_disconnect() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_topic_len(10)).unwrap();
+    let port = controller.local_addr().port();
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+
+    // 11 bytes, pero 9 caracteres
+    let publish = Publish::new(false, QoSLevel0, false, "ñña/bcdef", "msg", None).unwrap();
+    stream.write_all(&publish.encode().unwrap()).unwrap();
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+    let mut buf = [0u8];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn test_publish_payload_at_max_size_should_be_accepted() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_payload_size(200))
+            .unwrap();
+    let port = controller.local_addr().port();
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    // El limite no aplica a otros paquetes
+    let topics = (0..20)
+        .map(|i| TopicFilter::new(&format!("topic/{:0>20}", i), QoSLevel0).unwrap())
+        .collect();
+    let subscribe = Subscribe::new(topics, 1);
+    assert!(subscribe.encode().unwrap().len() > 200);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    let mut control = [0u8];
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    let payload = "x".repeat(200);
+    let topic = format!("topic/{:0>20}", 0);
+    let publish = Publish::new(false, QoSLevel1, false, &topic, &payload, Some(1)).unwrap();
+    publisher.write_all(&publish.encode().unwrap()).unwrap();
+    publisher.read_exact(&mut control).unwrap();
+    assert_eq!(
+        Puback::read_from(&mut publisher, control[0])
+            .unwrap()
+            .packet_id(),
+        1
+    );
+
+    subscriber.read_exact(&mut control).unwrap();
+    let received = Publish::read_from(&mut subscriber, control[0]).unwrap();
+    assert_eq!(received.payload(), payload);
+}
+
+#[test]
+fn test_publish_payload_over_max_size_should_disconnect() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_payload_size(200))
+            .unwrap();
+    let port = controller.local_addr().port();
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+
+    let publish =
+        Publish::new(false, QoSLevel1, false, "topic", &"x".repeat(201), Some(1)).unwrap();
+    stream.write_all(&publish.encode().unwrap()).unwrap();
+
+    // No se lee el payload, por lo que el cierre puede llegar como un reset
+    stream
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+    let mut buf = [0u8];
+    match stream.read(&mut buf) {
+        Ok(read) => assert_eq!(read, 0),
+        Err(err) => assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset),
+    }
+}
+
+#[test]
+fn test_subscribe_topic_over_max_len_should_disconnect() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_topic_len(10)).unwrap();
+    let port = controller.local_addr().port();
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+
+    let topics = vec![TopicFilter::new("ñña/bcdef/#", QoSLevel0).unwrap()];
+    stream
+        .write_all(&Subscribe::new(topics, 1).encode().unwrap())
+        .unwrap();
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+    let mut buf = [0u8];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn test_clean_session_reconnect_discards_queued_messages_and_subscriptions() {
+    let (_s, port) = start_server(None, None);
+    let mut control = [0u8];
+    let mut stream_1 = connect_client(ConnectBuilder::new("id1", 0, false).unwrap(), port, true);
+    let mut stream_2 = connect_client(ConnectBuilder::new("id2", 0, true).unwrap(), port, true);
+
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream_1, control[0]).unwrap();
+    stream_1
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    drop(stream_1);
+    thread::sleep(Duration::from_millis(100));
+
+    // Queda encolado para la sesion de id1
+    let publish = Publish::new(false, QoSLevel1, false, "topic", "message", Some(10)).unwrap();
+    stream_2.write_all(&publish.encode().unwrap()).unwrap();
+    stream_2.read_exact(&mut control).unwrap();
+    Puback::read_from(&mut stream_2, control[0]).unwrap();
+
+    let mut stream_1 = connect_client(ConnectBuilder::new("id1", 0, true).unwrap(), port, true);
+    // La suscripcion anterior tampoco deberia existir
+    let publish = Publish::new(false, QoSLevel1, false, "topic", "message", Some(11)).unwrap();
+    stream_2.write_all(&publish.encode().unwrap()).unwrap();
+
+    stream_1
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    assert_eq!(
+        stream_1.read_exact(&mut control).unwrap_err().kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+}
+
+#[test]
+fn test_unacknowledged_publish_is_retried_with_dup() {
+    let config = ConfigMock::new(0, None, None).with_retries(Duration::from_millis(200), 1);
+    let controller = start_server_with_config(config).unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 1);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    let publish = Publish::new(false, QoSLevel1, false, "topic", "message", Some(10)).unwrap();
+    publisher.write_all(&publish.encode().unwrap()).unwrap();
+
+    // Se ignora la primera entrega
+    subscriber.read_exact(&mut control).unwrap();
+    let first = Publish::read_from(&mut subscriber, control[0]).unwrap();
+    assert!(!first.dup_flag());
+
+    subscriber
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    let retry = Publish::read_from(&mut subscriber, control[0]).unwrap();
+    assert!(retry.dup_flag());
+    assert_eq!(retry.packet_id(), first.packet_id());
+
+    // Agotados los reintentos, se desconecta al cliente
+    assert_eq!(subscriber.read(&mut control).unwrap(), 0);
+}
+
+#[test]
+fn test_publish_after_an_acknowledged_one_is_retried() {
+    let retry_interval = Duration::from_millis(500);
+    let config = ConfigMock::new(0, None, None).with_retries(retry_interval, 1);
+    let controller = start_server_with_config(config).unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 1);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    for packet_id in [10, 11] {
+        let publish =
+            Publish::new(false, QoSLevel1, false, "topic", "message", Some(packet_id)).unwrap();
+        publisher.write_all(&publish.encode().unwrap()).unwrap();
+        publisher.read_exact(&mut control).unwrap();
+        Puback::read_from(&mut publisher, control[0]).unwrap();
+    }
+
+    // Se confirma la primera entrega y se ignora la segunda
+    subscriber.read_exact(&mut control).unwrap();
+    let first = Publish::read_from(&mut subscriber, control[0]).unwrap();
+    let puback = Puback::new(first.packet_id().unwrap()).unwrap();
+    subscriber.write_all(&puback.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    let second = Publish::read_from(&mut subscriber, control[0]).unwrap();
+    assert!(!second.dup_flag());
+
+    // Se deja margen sobre el intervalo de los reintentos
+    subscriber
+        .set_read_timeout(Some(retry_interval * 3))
+        .unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    let retry = Publish::read_from(&mut subscriber, control[0]).unwrap();
+    assert!(retry.dup_flag());
+    assert_eq!(retry.packet_id(), second.packet_id());
+}
+
+#[test]
+fn test_topic_stats_count_publishes_per_topic() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+    for (topic, times) in [("busy", 3), ("quiet", 1)] {
+        for i in 0..times {
+            let publish = Publish::new(false, QoSLevel1, false, topic, "msg", Some(i + 1)).unwrap();
+            stream.write_all(&publish.encode().unwrap()).unwrap();
+            stream.read_exact(&mut control).unwrap();
+            Puback::read_from(&mut stream, control[0]).unwrap();
+        }
+    }
+    // El Puback se envia antes de que se termine de publicar
+    thread::sleep(Duration::from_millis(100));
+
+    let stats = server.topic_stats().unwrap();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats["busy"], 3);
+    assert_eq!(stats["quiet"], 1);
+}
+
+#[test]
+fn test_retained_budget_evicts_oldest_retained_message() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_max_retained(2, 1024))
+            .unwrap();
+    let port = controller.local_addr().port();
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+
+    for topic in ["a", "b", "c"] {
+        let publish = Publish::new(false, QoSLevel0, true, topic, "message", None).unwrap();
+        stream_2.write_all(&publish.encode().unwrap()).unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    let subscribe = Subscribe::new(tpc![("#", QoSLevel0)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    let mut topics = vec![];
+    for _ in 0..2 {
+        stream_1.read_exact(&mut control).unwrap();
+        let publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+        topics.push(publish.topic_name().to_string());
+    }
+    topics.sort();
+    assert_eq!(topics, vec!["b", "c"]);
+
+    // "a" fue descartado, asi que no llega nada mas
+    stream_1
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    assert!(stream_1.read_exact(&mut control).is_err());
+}
+
+#[test]
+fn test_retained_messages_are_delivered_sorted_by_topic() {
+    let (_s, port) = start_server(None, None);
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    for topic in ["a/z", "a/b", "a/m"] {
+        let publish = Publish::new(false, QoSLevel0, true, topic, "retained", None).unwrap();
+        publisher.write_all(&publish.encode().unwrap()).unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    let subscribe = Subscribe::new(tpc![("a/+", QoSLevel0)], 123);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    let mut topics = vec![];
+    for _ in 0..3 {
+        subscriber.read_exact(&mut control).unwrap();
+        let publish = Publish::read_from(&mut subscriber, control[0]).unwrap();
+        topics.push(publish.topic_name().to_string());
+    }
+    assert_eq!(topics, vec!["a/b", "a/m", "a/z"]);
+}
+
+fn start_server_with_retained(window: Duration, topics: &[String]) -> (ServerController, u16) {
+    let controller = start_server_with_config(
+        ConfigMock::new(0, None, None).with_retained_delivery_window(window),
+    )
+    .unwrap();
+    let port = controller.local_addr().port();
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    for topic in topics {
+        let publish = Publish::new(false, QoSLevel0, true, topic, "retained", None).unwrap();
+        publisher.write_all(&publish.encode().unwrap()).unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+    (controller, port)
+}
+
+#[test]
+fn test_retained_delivery_is_spread_over_window() {
+    let window = Duration::from_secs(1);
+    let topics: Vec<String> = (0..20).map(|i| format!("a/{:02}", i)).collect();
+    let (_s, port) = start_server_with_retained(window, &topics);
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    let subscribe = Subscribe::new(tpc![("a/+", QoSLevel0)], 123);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+    let start = Instant::now();
+
+    let mut received = vec![];
+    for _ in 0..topics.len() {
+        subscriber.read_exact(&mut control).unwrap();
+        let publish = Publish::read_from(&mut subscriber, control[0]).unwrap();
+        assert!(publish.retain_flag());
+        received.push((publish.topic_name().to_string(), start.elapsed()));
+    }
+
+    let received_topics: Vec<String> = received.iter().map(|(topic, _)| topic.clone()).collect();
+    assert_eq!(received_topics, topics);
+    // El primero se envia enseguida y el ultimo cerca del final de la ventana
+    assert!(received[0].1 < window / 4);
+    assert!(received[topics.len() - 1].1 >= window * 3 / 4);
+    assert!(received[topics.len() - 1].1 < window + Duration::from_millis(500));
+}
+
+#[test]
+fn test_spread_retained_delivery_sends_latest_value() {
+    let topics = vec!["a/0".to_string(), "a/1".to_string()];
+    let (_s, port) = start_server_with_retained(Duration::from_secs(1), &topics);
+    let mut publisher = connect_client(ConnectBuilder::new("pub2", 0, true).unwrap(), port, true);
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    let subscribe = Subscribe::new(tpc![("a/+", QoSLevel0)], 123);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut subscriber, control[0]).unwrap();
+    // Se reemplaza el retained de a/1 antes de que se entregue
+    let publish = Publish::new(false, QoSLevel0, true, "a/1", "new", None).unwrap();
+    publisher.write_all(&publish.encode().unwrap()).unwrap();
+
+    let mut received = vec![];
+    for _ in 0..3 {
+        subscriber.read_exact(&mut control).unwrap();
+        let publish = Publish::read_from(&mut subscriber, control[0]).unwrap();
+        received.push((
+            publish.topic_name().to_string(),
+            publish.payload().to_string(),
+        ));
+    }
+    received.sort();
+    assert_eq!(
+        received,
+        vec![
+            ("a/0".to_string(), "retained".to_string()),
+            ("a/1".to_string(), "new".to_string()),
+            ("a/1".to_string(), "new".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_no_local_does_not_echo_publish_to_its_publisher() {
+    let controller =
+        start_server_with_config(ConfigMock::new(0, None, None).with_no_local(true)).unwrap();
+    let port = controller.local_addr().port();
+    let mut stream_1 = connect_client(ConnectBuilder::new("id1", 0, true).unwrap(), port, true);
+    let mut stream_2 = connect_client(ConnectBuilder::new("id2", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    // Ambos se suscriben al mismo topic
+    for stream in [&mut stream_1, &mut stream_2] {
+        let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+        stream.write_all(&subscribe.encode().unwrap()).unwrap();
+        stream.read_exact(&mut control).unwrap();
+        Suback::read_from(stream, control[0]).unwrap();
+    }
+
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "message", None).unwrap();
+    stream_1.write_all(&publish.encode().unwrap()).unwrap();
+
+    // El otro suscriptor recibe el mensaje
+    stream_2.read_exact(&mut control).unwrap();
+    let received = Publish::read_from(&mut stream_2, control[0]).unwrap();
+    assert_eq!(received.payload(), "message");
+
+    // Pero el que lo publico no
+    stream_1
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    assert!(stream_1.read_exact(&mut control).is_err());
+}
+
+#[test]
+fn test_disconnecting_while_publish_is_dispatched_does_not_panic() {
+    // Cuenta los panics de los threads del servidor (los de
+    // los tests se llaman como el test)
+    let panics = Arc::new(AtomicUsize::new(0));
+    let panics_copy = panics.clone();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !matches!(thread::current().name(), Some(name) if name.starts_with("test_")) {
+            panics_copy.fetch_add(1, Ordering::SeqCst);
+        }
+        default_hook(info);
+    }));
+
+    let (_s, port) = start_server(None, None);
+    let mut control = [0u8];
+    let mut subscribers = vec![];
+    for i in 0..10 {
+        let builder = ConnectBuilder::new(&format!("sub{}", i), 0, true).unwrap();
+        let mut stream = connect_client(builder, port, true);
+        let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 123);
+        stream.write_all(&subscribe.encode().unwrap()).unwrap();
+        stream.read_exact(&mut control).unwrap();
+        Suback::read_from(&mut stream, control[0]).unwrap();
+        subscribers.push(stream);
+    }
+
+    let publisher = thread::spawn(move || {
+        let mut stream = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+        let publish = Publish::new(false, QoSLevel0, false, "topic", "message", None).unwrap();
+        for _ in 0..200 {
+            if stream.write_all(&publish.encode().unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Los suscriptores se desconectan mientras se despachan los mensajes
+    for (i, mut stream) in subscribers.into_iter().enumerate() {
+        if i % 2 == 0 {
+            let _ = stream.write_all(&Disconnect::new().encode().unwrap());
+        }
+        drop(stream);
+    }
+    publisher.join().unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    // El servidor sigue funcionando
+    let mut stream = connect_client(ConnectBuilder::new("last", 0, true).unwrap(), port, true);
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream, control[0]).unwrap();
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "last", None).unwrap();
+    stream.write_all(&publish.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    let received = Publish::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(received.payload(), "last");
+
+    assert_eq!(panics.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_last_will_with_qos_2_is_clamped_to_server_max() {
+    let path = "tests/files/dumps/dump9.json";
+    let _ = fs::remove_file(path);
+    let config = ConfigMock::new(0, Some((path, Duration::from_secs(10))), None);
+    let server = Server::new(config, 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+
+    // El servidor acepta el Last Will con QoS 2
+    let builder = ConnectBuilder::new("id", 0, false)
+        .unwrap()
+        .with_last_will(LastWill::new(
+            TopicFilter::new("topic", QoSLevel2).unwrap(),
+            "message".to_string(),
+            false,
+        ));
+    let _stream = connect_client(builder, port, true);
+    server.dump().unwrap();
+
+    // Pero lo guarda con QoS 1
+    let dump: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(
+        dump["clients_manager"]["clients"]["id"]["connect"]["last_will"]["topic"]["qos"],
+        serde_json::to_value(QoSLevel1).unwrap()
+    );
+}
+
+#[test]
+fn test_slow_consumer_is_disconnected_after_write_timeout() {
+    let config = ConfigMock::new(0, None, None).with_write_timeout(Duration::from_secs(1));
+    let server = Server::new(config, 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let mut slow = connect_client(ConnectBuilder::new("slow", 0, true).unwrap(), port, true);
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 1);
+    slow.write_all(&subscribe.encode().unwrap()).unwrap();
+    slow.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut slow, control[0]).unwrap();
+
+    // El suscriptor no lee nada mas, por lo que se llenan los
+    // buffers de la conexion y las escrituras del servidor se bloquean
+    let publisher = thread::spawn(move || {
+        let mut publisher = connect_client(
+            ConnectBuilder::new("publisher", 0, true).unwrap(),
+            port,
+            true,
+        );
+        publisher
+            .set_write_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let payload = "a".repeat(64 * 1024);
+        let publish = Publish::new(false, QoSLevel0, false, "topic", &payload, None)
+            .unwrap()
+            .encode()
+            .unwrap();
+        for _ in 0..400 {
+            if publisher.write_all(&publish).is_err() {
+                break;
+            }
+        }
+    });
+
+    let start = Instant::now();
+    while server
+        .connected_clients()
+        .unwrap()
+        .iter()
+        .any(|client| client.id == "slow")
+    {
+        assert!(start.elapsed() < Duration::from_secs(20));
+        thread::sleep(Duration::from_millis(100));
+    }
+    publisher.join().unwrap();
+
+    // El servidor sigue atendiendo a los demas clientes
+    let mut stream = connect_client(ConnectBuilder::new("other", 0, true).unwrap(), port, true);
+    stream.write_all(&PingReq::new().encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    PingResp::read_from(&mut stream, control[0]).unwrap();
+}
+
+#[test]
+fn test_clear_client_subscriptions_and_clear_topics() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let port = controller.local_addr().port();
+    let mut control = [0u8];
+
+    let mut streams = vec![];
+    for id in ["a", "b"] {
+        let mut stream = connect_client(ConnectBuilder::new(id, 0, true).unwrap(), port, true);
+        let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 1);
+        stream.write_all(&subscribe.encode().unwrap()).unwrap();
+        stream.read_exact(&mut control).unwrap();
+        Suback::read_from(&mut stream, control[0]).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        streams.push(stream);
+    }
+    assert_eq!(server.matching_subscribers("topic").unwrap().len(), 2);
+
+    server.clear_client_subscriptions("a").unwrap();
+    assert_eq!(
+        server.matching_subscribers("topic").unwrap(),
+        vec![("b".to_owned(), QoSLevel0)]
+    );
+
+    server.clear_topics().unwrap();
+    assert!(server.matching_subscribers("topic").unwrap().is_empty());
+    // Los clientes siguen conectados, pero no reciben nada
+    let mut publisher = connect_client(ConnectBuilder::new("p", 0, true).unwrap(), port, true);
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "x", None).unwrap();
+    publisher.write_all(&publish.encode().unwrap()).unwrap();
+    for stream in &mut streams {
+        assert!(stream.read_exact(&mut control).is_err());
+    }
+    assert_eq!(server.connected_clients().unwrap().len(), 3);
+}
+
+#[derive(Debug, PartialEq)]
+enum SubscriptionEvent {
+    Subscribed(String, String, QoSLevel),
+    Unsubscribed(String, String),
+}
+
+#[derive(Clone, Default)]
+struct RecordingListener {
+    events: Arc<Mutex<Vec<SubscriptionEvent>>>,
+}
+
+impl SubscriptionListener for RecordingListener {
+    fn on_subscribe(&self, id: &str, filter: &str, qos: QoSLevel) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(SubscriptionEvent::Subscribed(
+                id.to_string(),
+                filter.to_string(),
+                qos,
+            ));
+    }
+
+    fn on_unsubscribe(&self, id: &str, filter: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(SubscriptionEvent::Unsubscribed(
+                id.to_string(),
+                filter.to_string(),
+            ));
+    }
+}
+
+#[test]
+fn test_subscription_listeners_are_notified() {
+    let server = Server::new(ConfigMock::new(0, None, None), 20).unwrap();
+    let listeners = [RecordingListener::default(), RecordingListener::default()];
+    for listener in &listeners {
+        server
+            .add_subscription_listener(Box::new(listener.clone()))
+            .unwrap();
+    }
+    let controller = server.run().unwrap();
+    let port = controller.local_addr().port();
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    let subscribe = Subscribe::new(tpc![("a/+", QoSLevel0), ("b/#", QoSLevel2)], 1);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream, control[0]).unwrap();
+    let unsubscribe = Unsubscribe::new(2, tpc![("a/+", QoSLevel0)]).unwrap();
+    stream.write_all(&unsubscribe.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    Unsuback::read_from(&mut stream, control[0]).unwrap();
+
+    for listener in &listeners {
+        assert_eq!(
+            *listener.events.lock().unwrap(),
+            vec![
+                SubscriptionEvent::Subscribed("id".to_string(), "a/+".to_string(), QoSLevel0),
+                // Se informa el QoS otorgado por el servidor
+                SubscriptionEvent::Subscribed("id".to_string(), "b/#".to_string(), QoSLevel1),
+                SubscriptionEvent::Unsubscribed("id".to_string(), "a/+".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+#![cfg(unix)]
+mod common;
+use std::{
+    fs,
+    io::Write,
+    net::{TcpListener, TcpStream},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use packets::{connect::ConnectBuilder, disconnect::Disconnect, traits::MQTTEncoding};
+
+use crate::common::*;
+
+const DUMP_DIR: &str = "tests/files/dumps";
+
+/// Returns a port that was free when it was called
+fn free_port() -> u16 {
+    TcpListener::bind("localhost:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_until_listening(port: u16) {
+    let start = Instant::now();
+    while TcpStream::connect(format!("localhost:{}", port)).is_err() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_sigint_dumps_state_before_exiting() {
+    fs::create_dir_all(DUMP_DIR).unwrap();
+    let dump_path = format!("{}/signal_dump.json", DUMP_DIR);
+    let config_path = format!("{}/signal_config.txt", DUMP_DIR);
+    let _ = fs::remove_file(&dump_path);
+    let port = free_port();
+    fs::write(
+        &config_path,
+        format!(
+            "port={}
+dump_path={}
+dump_time=3600
+log_path={}/signal_logs
+ip=localhost
+log_file_level=info
+log_stdout_level=error",
+            port, dump_path, DUMP_DIR
+        ),
+    )
+    .unwrap();
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_server"))
+        .arg(&config_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    wait_until_listening(port);
+
+    // Un cliente con sesion persistente, que debe quedar en el dump
+    let mut stream = connect_client(
+        ConnectBuilder::new("persistent", 0, false).unwrap(),
+        port,
+        true,
+    );
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert!(server.try_wait().unwrap().is_none());
+
+    let status = Command::new("kill")
+        .args(["-INT", &server.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let start = Instant::now();
+    let exit_status = loop {
+        if let Some(exit_status) = server.try_wait().unwrap() {
+            break exit_status;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert!(exit_status.success());
+
+    let dump: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&dump_path).unwrap()).unwrap();
+    assert!(dump["clients_manager"]["clients"]["persistent"].is_object());
+}
@@ -0,0 +1,98 @@
+mod common;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use packets::{connect::ConnectBuilder, disconnect::Disconnect, traits::MQTTEncoding};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+use crate::common::*;
+
+/// Fields of a span, along with the name of the
+/// thread in which it was created
+#[derive(Debug, Default, Clone)]
+struct SpanInfo {
+    name: String,
+    thread: Option<String>,
+    fields: HashMap<String, String>,
+}
+
+impl Visit for SpanInfo {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields
+            .insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+/// Layer that stores the fields of every span
+#[derive(Default, Clone)]
+struct SpanRecorder {
+    spans: Arc<Mutex<HashMap<u64, SpanInfo>>>,
+}
+
+impl SpanRecorder {
+    fn spans_named(&self, name: &str) -> Vec<SpanInfo> {
+        self.spans
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|span| span.name == name)
+            .cloned()
+            .collect()
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SpanRecorder {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        let mut info = SpanInfo {
+            name: attrs.metadata().name().to_string(),
+            thread: thread::current().name().map(str::to_string),
+            ..Default::default()
+        };
+        attrs.record(&mut info);
+        self.spans.lock().unwrap().insert(id.into_u64(), info);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some(info) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            values.record(info);
+        }
+    }
+}
+
+#[test]
+fn test_client_span_has_peer_addr_and_client_id() {
+    let recorder = SpanRecorder::default();
+    tracing_subscriber::registry().with(recorder.clone()).init();
+    let (_s, port) = start_server(None, None);
+
+    let mut stream = connect_client(ConnectBuilder::new("id", 0, true).unwrap(), port, true);
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let peer_addr = stream.local_addr().unwrap().to_string();
+    let spans = recorder.spans_named("client");
+    let span = spans
+        .iter()
+        .find(|span| span.fields.get("peer_addr") == Some(&peer_addr))
+        .unwrap();
+    assert_eq!(span.fields.get("client_id").unwrap(), "id");
+    assert_eq!(span.thread, Some(format!("client {}", peer_addr)));
+}